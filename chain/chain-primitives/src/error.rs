@@ -188,6 +188,9 @@ pub enum Error {
     /// Invalid block merkle root.
     #[error("Invalid Block Merkle Root")]
     InvalidBlockMerkleRoot,
+    /// A batch of block headers doesn't form a chain, the break is at the given index.
+    #[error("Invalid Block Header Chain: break at header {0}")]
+    InvalidBlockHeaderChain(usize),
     /// Someone is not a validator. Usually happens in signature verification
     #[error("Not A Validator")]
     NotAValidator,
@@ -284,6 +287,7 @@ impl Error {
             | Error::InvalidStateRequest(_)
             | Error::InvalidRandomnessBeaconOutput
             | Error::InvalidBlockMerkleRoot
+            | Error::InvalidBlockHeaderChain(_)
             | Error::InvalidProtocolVersion
             | Error::NotAValidator
             | Error::InvalidChallengeRoot => true,
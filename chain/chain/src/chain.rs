@@ -68,7 +68,7 @@ use crate::types::{
 };
 use crate::validate::{
     validate_challenge, validate_chunk_proofs, validate_chunk_with_chunk_extra,
-    validate_transactions_order,
+    validate_transactions_order, verify_headers_contiguous,
 };
 use crate::{byzantine_assert, create_light_client_block_view, Doomslug};
 use crate::{metrics, DoomslugThresholdMode};
@@ -351,6 +351,22 @@ pub struct OrphanMissingChunks {
     pub ancestor_hash: CryptoHash,
 }
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum StateRequestError {
+    #[error("part_id {part_id} out of range, the state has {num_parts} parts")]
+    PartOutOfRange { part_id: u64, num_parts: u64 },
+}
+
+/// Checks that the requested state part exists, given the number of parts
+/// the state is split into. Part ids come from peers, so they have to be
+/// validated before any work is done to compute the part.
+pub fn validate_state_part_id(part_id: u64, num_parts: u64) -> Result<(), StateRequestError> {
+    if part_id >= num_parts {
+        return Err(StateRequestError::PartOutOfRange { part_id, num_parts });
+    }
+    Ok(())
+}
+
 /// Check if block header is known
 /// Returns Err(Error) if any error occurs when checking store
 ///         Ok(Err(BlockKnownError)) if the block header is known
@@ -1678,6 +1694,9 @@ impl Chain {
     ) -> Result<(), Error> {
         // Sort headers by heights if they are out of order.
         headers.sort_by_key(|left| left.height());
+        // Reject batches which don't form a chain before doing any expensive validation.
+        verify_headers_contiguous(&headers)
+            .map_err(|err| Error::InvalidBlockHeaderChain(err.index()))?;
 
         if let Some(header) = headers.first() {
             debug!(target: "chain", "Sync block headers: {} headers from {} at {}", headers.len(), header.hash(), header.height());
@@ -2787,10 +2806,10 @@ impl Chain {
             .get_state_root_node(shard_id, &sync_prev_hash, &state_root)
             .log_storage_error("get_state_root_node fail")?;
         let num_parts = get_num_state_parts(state_root_node.memory_usage);
-
-        if part_id >= num_parts {
-            return Err(Error::InvalidStateRequest("part_id out of bound".to_string()));
-        }
+        validate_state_part_id(part_id, num_parts).map_err(|err| {
+            metrics::STATE_PART_REQUEST_OUT_OF_RANGE.inc();
+            Error::InvalidStateRequest(err.to_string())
+        })?;
         let state_part = self
             .runtime_adapter
             .obtain_state_part(
@@ -5391,4 +5410,19 @@ mod tests {
         );
         assert_eq!(receipt_proofs, vec![2, 3, 1, 4, 0, 5, 6],);
     }
+
+    #[test]
+    fn state_part_id_validation() {
+        use crate::chain::{validate_state_part_id, StateRequestError};
+        assert_eq!(Ok(()), validate_state_part_id(0, 3));
+        assert_eq!(Ok(()), validate_state_part_id(2, 3));
+        assert_eq!(
+            Err(StateRequestError::PartOutOfRange { part_id: 3, num_parts: 3 }),
+            validate_state_part_id(3, 3)
+        );
+        assert_eq!(
+            Err(StateRequestError::PartOutOfRange { part_id: u64::MAX, num_parts: 3 }),
+            validate_state_part_id(u64::MAX, 3)
+        );
+    }
 }
@@ -98,3 +98,10 @@ pub static BLOCK_MISSING_CHUNKS_DELAY: Lazy<Histogram> = Lazy::new(|| {
     )
     .unwrap()
 });
+pub static STATE_PART_REQUEST_OUT_OF_RANGE: Lazy<IntCounter> = Lazy::new(|| {
+    try_create_int_counter(
+        "near_state_part_request_out_of_range_total",
+        "Number of state part requests rejected, because the requested part id was out of range",
+    )
+    .unwrap()
+});
@@ -34,9 +34,9 @@ use near_primitives::types::{
 use near_primitives::utils::{get_block_shard_id, index_to_bytes, to_timestamp};
 use near_primitives::views::LightClientBlockView;
 use near_store::{
-    DBCol, KeyForStateChanges, ShardTries, Store, StoreUpdate, WrappedTrieChanges, CHUNK_TAIL_KEY,
-    FINAL_HEAD_KEY, FORK_TAIL_KEY, HEADER_HEAD_KEY, HEAD_KEY, LARGEST_TARGET_HEIGHT_KEY,
-    LATEST_KNOWN_KEY, TAIL_KEY,
+    DBCol, KeyForStateChanges, ShardTries, StagedStateRootBlock, Store, StoreUpdate,
+    WrappedTrieChanges, CHUNK_TAIL_KEY, FINAL_HEAD_KEY, FORK_TAIL_KEY, HEADER_HEAD_KEY, HEAD_KEY,
+    LARGEST_TARGET_HEIGHT_KEY, LATEST_KNOWN_KEY, TAIL_KEY,
 };
 
 use crate::chunks_store::ReadOnlyChunksStore;
@@ -658,17 +658,18 @@ impl ChainStore {
         &self,
         block_hash: &CryptoHash,
     ) -> Result<StateChangesKinds, Error> {
-        // We store the trie changes under a compound key: `block_hash + trie_key`, so when we
-        // query the changes, we reverse the process by splitting the key using simple slicing of an
-        // array of bytes, essentially, extracting `trie_key`.
+        // We store the trie changes under a compound key: `block_hash + trie_key + shard_uid`
+        // (rows written by older versions have no `shard_uid` suffix), see `KeyForStateChanges`.
+        // The trie key has a variable length, so the key can't be split by simple slicing;
+        // instead, the trie key is taken from the stored value, which contains it as well.
         //
         // Example: data changes are stored under a key:
         //
-        //     block_hash + (col::ACCOUNT + account_id + ACCOUNT_DATA_SEPARATOR + user_specified_key)
+        //     block_hash + (col::ACCOUNT + account_id + ACCOUNT_DATA_SEPARATOR + user_specified_key) + shard_uid
         //
         // Thus, to query the list of touched accounts we do the following:
         // 1. Query RocksDB for `block_hash` prefix.
-        // 2. Extract the original Trie key out of the keys returned by RocksDB
+        // 2. Take the original Trie key out of the values returned by RocksDB
         // 3. Try extracting `account_id` from the key using KeyFor* implementations
 
         let storage_key = KeyForStateChanges::for_block(block_hash);
@@ -700,26 +701,25 @@ impl ChainStore {
         block_hash: &CryptoHash,
         state_changes_request: &StateChangesRequest,
     ) -> Result<StateChanges, Error> {
-        // We store the trie changes under a compound key: `block_hash + trie_key`, so when we
-        // query the changes, we reverse the process by splitting the key using simple slicing of an
-        // array of bytes, essentially, extracting `trie_key`.
+        // We store the trie changes under a compound key: `block_hash + trie_key + shard_uid`
+        // (rows written by older versions have no `shard_uid` suffix), see `KeyForStateChanges`.
+        // The trie key has a variable length, so the key can't be split by simple slicing;
+        // instead, the trie key is taken from the stored value, which contains it as well.
         //
         // Example: data changes are stored under a key:
         //
-        //     block_hash + (col::ACCOUNT + account_id + ACCOUNT_DATA_SEPARATOR + user_specified_key)
+        //     block_hash + (col::ACCOUNT + account_id + ACCOUNT_DATA_SEPARATOR + user_specified_key) + shard_uid
         //
         // Thus, to query all the changes by a user-specified key prefix, we do the following:
         // 1. Query RocksDB for
         //     block_hash + (col::ACCOUNT + account_id + ACCOUNT_DATA_SEPARATOR + user_specified_key_prefix)
         //
-        // 2. In the simplest case, to extract the full key we need to slice the RocksDB key by a length of
-        //     block_hash + (col::ACCOUNT + account_id + ACCOUNT_DATA_SEPARATOR)
+        // 2. Take the trie key of every row out of its value and parse it with a relevant KeyFor*
+        //    implementation to ensure consistency
         //
-        //    In this implementation, however, we decoupled this process into two steps:
-        //
-        //    2.1. Split off the `block_hash` (internally in `KeyForStateChanges`), thus we are
-        //         left working with a key that was used in the trie.
-        //    2.2. Parse the trie key with a relevant KeyFor* implementation to ensure consistency
+        // The shard is not a part of the queried prefix, so the results for a data key prefix
+        // include the matching rows from every shard: a key changed in several shards of the
+        // block (e.g. during resharding) is returned once per shard.
 
         Ok(match state_changes_request {
             StateChangesRequest::AccountChanges { account_ids } => {
@@ -1153,6 +1153,9 @@ pub struct ChainStoreUpdate<'a> {
     final_head: Option<Tip>,
     largest_target_height: Option<BlockHeight>,
     trie_changes: Vec<WrappedTrieChanges>,
+    /// State root -> block mappings of the saved trie changes, recorded once
+    /// the update is committed.
+    state_root_blocks: Vec<StagedStateRootBlock>,
     // All state changes made by a chunk, this is only used for splitting states
     add_state_changes_for_split_states: HashMap<(CryptoHash, ShardId), StateChangesForSplitStates>,
     remove_state_changes_for_split_states: HashSet<(CryptoHash, ShardId)>,
@@ -1180,6 +1183,7 @@ impl<'a> ChainStoreUpdate<'a> {
             final_head: None,
             largest_target_height: None,
             trie_changes: vec![],
+            state_root_blocks: vec![],
             add_state_changes_for_split_states: HashMap::new(),
             remove_state_changes_for_split_states: HashSet::new(),
             add_blocks_to_catchup: vec![],
@@ -2036,7 +2040,12 @@ impl<'a> ChainStoreUpdate<'a> {
                             &get_block_shard_uid(&block_hash, &shard_uid),
                         )?;
                         if let Some(trie_changes) = trie_changes {
-                            tries.revert_insertions(&trie_changes, shard_uid, &mut store_update);
+                            tries.revert_insertions(
+                                &trie_changes,
+                                shard_uid,
+                                &block_hash,
+                                &mut store_update,
+                            );
                             self.gc_col(
                                 DBCol::TrieChanges,
                                 &get_block_shard_uid(&block_hash, &shard_uid),
@@ -2052,7 +2061,12 @@ impl<'a> ChainStoreUpdate<'a> {
                             &get_block_shard_uid(&block_hash, &shard_uid),
                         )?;
                         if let Some(trie_changes) = trie_changes {
-                            tries.apply_deletions(&trie_changes, shard_uid, &mut store_update);
+                            tries.apply_deletions(
+                                &trie_changes,
+                                shard_uid,
+                                &block_hash,
+                                &mut store_update,
+                            );
                             self.gc_col(
                                 DBCol::TrieChanges,
                                 &get_block_shard_uid(&block_hash, &shard_uid),
@@ -2377,6 +2391,9 @@ impl<'a> ChainStoreUpdate<'a> {
             | DBCol::PeerComponent
             | DBCol::LastComponentNonce
             | DBCol::ComponentEdges
+            | DBCol::PeerEncodings
+            | DBCol::StateRefcountHistory
+            | DBCol::TrieChangesDebug
             // https://github.com/nearprotocol/nearcore/pull/2952
             | DBCol::EpochInfo
             | DBCol::EpochStart
@@ -2718,15 +2735,15 @@ impl<'a> ChainStoreUpdate<'a> {
         // Create separate store update for deletions, because we want to update cache and don't want to remove nodes
         // from the store.
         let mut deletions_store_update = self.store().store_update();
-        for mut wrapped_trie_changes in self.trie_changes.drain(..) {
-            wrapped_trie_changes.insertions_into(&mut store_update);
+        for wrapped_trie_changes in self.trie_changes.drain(..) {
             wrapped_trie_changes.deletions_into(&mut deletions_store_update);
-            wrapped_trie_changes.state_changes_into(&mut store_update);
-
             if self.chain_store.save_trie_changes {
+                self.state_root_blocks.push(wrapped_trie_changes.staged_state_root_block());
                 wrapped_trie_changes
-                    .trie_changes_into(&mut store_update)
+                    .persist(&mut store_update)
                     .map_err(|err| Error::Other(err.to_string()))?;
+            } else {
+                wrapped_trie_changes.persist_without_trie_changes(&mut store_update);
             }
         }
         deletions_store_update.update_cache()?;
@@ -2833,6 +2850,9 @@ impl<'a> ChainStoreUpdate<'a> {
     pub fn commit(mut self) -> Result<(), Error> {
         let store_update = self.finalize()?;
         store_update.commit()?;
+        for staged in self.state_root_blocks.drain(..) {
+            staged.record();
+        }
         let ChainStoreCacheUpdate {
             blocks,
             headers,
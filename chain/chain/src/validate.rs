@@ -117,6 +117,42 @@ pub fn validate_transactions_order(transactions: &[SignedTransaction]) -> bool {
     true
 }
 
+/// Reason why a batch of block headers doesn't form a chain.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum HeaderChainError {
+    #[error("prev_hash of header {index} is not the hash of the previous header")]
+    PrevHashMismatch { index: usize },
+    #[error("height of header {index} is not greater than the height of the previous header")]
+    HeightNotIncreasing { index: usize },
+}
+
+impl HeaderChainError {
+    /// Index of the first header which doesn't extend the previous one.
+    pub fn index(&self) -> usize {
+        match self {
+            HeaderChainError::PrevHashMismatch { index }
+            | HeaderChainError::HeightNotIncreasing { index } => *index,
+        }
+    }
+}
+
+/// Checks that every header points to the previous one and has a greater height.
+/// Only looks at the headers themselves, so it is cheap enough to reject malformed
+/// batches received from peers before validating each header against the chain.
+pub fn verify_headers_contiguous(headers: &[BlockHeader]) -> Result<(), HeaderChainError> {
+    for (index, pair) in headers.windows(2).enumerate() {
+        let (prev, header) = (&pair[0], &pair[1]);
+        let index = index + 1;
+        if header.prev_hash() != prev.hash() {
+            return Err(HeaderChainError::PrevHashMismatch { index });
+        }
+        if header.height() <= prev.height() {
+            return Err(HeaderChainError::HeightNotIncreasing { index });
+        }
+    }
+    Ok(())
+}
+
 /// Validate that all next chunk information matches previous chunk extra.
 pub fn validate_chunk_with_chunk_extra(
     chain_store: &ChainStore,
@@ -416,6 +452,7 @@ mod tests {
     use near_crypto::{InMemorySigner, KeyType};
 
     use super::*;
+    use crate::test_utils::setup;
 
     fn make_tx(account_id: &str, seed: &str, nonce: Nonce) -> SignedTransaction {
         let account_id: AccountId = account_id.parse().unwrap();
@@ -496,4 +533,50 @@ mod tests {
         ];
         assert!(!validate_transactions_order(&transactions));
     }
+
+    #[test]
+    fn test_verify_headers_contiguous() {
+        let (chain, _, signer) = setup();
+        let mut blocks = vec![chain.get_block(chain.genesis().hash()).unwrap()];
+        for _ in 0..4 {
+            blocks.push(Block::empty(blocks.last().unwrap(), &*signer));
+        }
+        let headers: Vec<_> = blocks.iter().map(|b| b.header().clone()).collect();
+
+        assert_eq!(Ok(()), verify_headers_contiguous(&[]));
+        assert_eq!(Ok(()), verify_headers_contiguous(&headers[..1]));
+        assert_eq!(Ok(()), verify_headers_contiguous(&headers));
+
+        // A header is missing.
+        let gap = vec![headers[0].clone(), headers[1].clone(), headers[3].clone()];
+        assert_eq!(
+            Err(HeaderChainError::PrevHashMismatch { index: 2 }),
+            verify_headers_contiguous(&gap)
+        );
+
+        // Headers out of order.
+        let mut reversed = headers.clone();
+        reversed.reverse();
+        assert_eq!(
+            Err(HeaderChainError::PrevHashMismatch { index: 1 }),
+            verify_headers_contiguous(&reversed)
+        );
+
+        // A header from a fork.
+        let fork = Block::empty_with_height(&blocks[1], 3, &*signer);
+        let forked = vec![headers[1].clone(), headers[2].clone(), fork.header().clone()];
+        assert_eq!(
+            Err(HeaderChainError::PrevHashMismatch { index: 2 }),
+            verify_headers_contiguous(&forked)
+        );
+
+        // A header pointing to the previous one, but without a greater height.
+        let mut same_height = Block::empty(&blocks[2], &*signer);
+        same_height.mut_header().get_mut().inner_lite.height = blocks[2].header().height();
+        same_height.mut_header().resign(&*signer);
+        let stalled = vec![headers[1].clone(), headers[2].clone(), same_height.header().clone()];
+        let err = verify_headers_contiguous(&stalled).unwrap_err();
+        assert_eq!(HeaderChainError::HeightNotIncreasing { index: 2 }, err);
+        assert_eq!(2, err.index());
+    }
 }
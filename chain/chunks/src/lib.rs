@@ -123,8 +123,8 @@ use crate::logic::cares_about_shard_this_or_next_epoch;
 use near_chain::near_chain_primitives::error::Error::DBNotFoundErr;
 pub use near_chunks_primitives::Error;
 use near_network::types::{
-    AccountIdOrPeerTrackingShard, PartialEncodedChunkForwardMsg, PartialEncodedChunkRequestMsg,
-    PartialEncodedChunkResponseMsg,
+    AccountIdOrPeerTrackingShard, ForwardValidationError, PartialEncodedChunkForwardMsg,
+    PartialEncodedChunkRequestMsg, PartialEncodedChunkResponseMsg,
 };
 use near_primitives::epoch_manager::RngSeed;
 use rand::Rng;
@@ -1360,9 +1360,10 @@ impl ShardsManager {
         &mut self,
         forward: &PartialEncodedChunkForwardMsg,
     ) -> Result<(), Error> {
-        let valid_hash = forward.is_valid_hash(); // check hash
-
-        if !valid_hash {
+        // The hash is checked (again, by `validate` below) before the prev block
+        // is looked up, so that forwards with an unknown prev block are only
+        // cached if their hash is valid.
+        if !forward.is_valid_hash() {
             return Err(Error::InvalidPartMessage);
         }
 
@@ -1372,9 +1373,20 @@ impl ShardsManager {
             self.validate_part(forward.merkle_root, part_info, num_total_parts)?;
         }
 
-        // check signature
+        // check shard id
         let epoch_id =
             self.runtime_adapter.get_epoch_id_from_prev_block(&forward.prev_block_hash)?;
+        let shard_layout = self.runtime_adapter.get_shard_layout(&epoch_id)?;
+        match forward.validate(&shard_layout) {
+            Ok(()) => {}
+            Err(ForwardValidationError::InvalidHash) => return Err(Error::InvalidPartMessage),
+            Err(ForwardValidationError::InvalidShardId { .. }) => {
+                metrics::PARTIAL_ENCODED_CHUNK_FORWARD_INVALID_SHARD_ID.inc();
+                return Err(Error::InvalidChunkShardId);
+            }
+        }
+
+        // check signature
         let valid_signature = self.runtime_adapter.verify_chunk_signature_with_header_parts(
             &forward.chunk_hash,
             &forward.signature,
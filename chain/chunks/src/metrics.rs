@@ -33,3 +33,12 @@ pub static DISTRIBUTE_ENCODED_CHUNK_TIME: Lazy<near_o11y::metrics::HistogramVec>
         )
         .unwrap()
     });
+
+pub static PARTIAL_ENCODED_CHUNK_FORWARD_INVALID_SHARD_ID: Lazy<near_o11y::metrics::IntCounter> =
+    Lazy::new(|| {
+        near_o11y::metrics::try_create_int_counter(
+            "near_partial_encoded_chunk_forward_invalid_shard_id",
+            "Number of partial encoded chunk forwards rejected because of a shard id which doesn't exist in the shard layout",
+        )
+        .unwrap()
+    });
@@ -793,6 +793,18 @@ impl Message for GetExecutionOutcome {
     type Result = Result<GetExecutionOutcomeResponse, GetExecutionOutcomeError>;
 }
 
+/// Proof that the given transaction is included in the chain.
+/// If the node doesn't track the shard of the signer, the proof is requested from
+/// a validator tracking it and `None` is returned until the response arrives.
+pub struct GetTxInclusionProof {
+    pub tx_hash: CryptoHash,
+    pub signer_account_id: AccountId,
+}
+
+impl Message for GetTxInclusionProof {
+    type Result = Result<Option<GetExecutionOutcomeResponse>, TxStatusError>;
+}
+
 pub struct GetExecutionOutcomesForBlock {
     pub block_hash: CryptoHash,
 }
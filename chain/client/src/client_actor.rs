@@ -450,6 +450,17 @@ impl ClientActor {
             NetworkClientMessages::StateResponse(state_response_info) => {
                 let shard_id = state_response_info.shard_id();
                 let hash = state_response_info.sync_hash();
+                // If the layout is unknown, the response won't match any download anyway.
+                if let Ok(shard_layout) =
+                    self.client.runtime_adapter.get_epoch_id(&hash).and_then(|epoch_id| {
+                        self.client.runtime_adapter.get_shard_layout(&epoch_id)
+                    })
+                {
+                    if let Err(err) = state_response_info.validate_against_layout(&shard_layout) {
+                        error!(target: "sync", "State sync received response for hash {:?}, potential malicious peer: {}", hash, err);
+                        return NetworkClientResponses::NoResponse;
+                    }
+                }
                 let state_response = state_response_info.take_state_response();
 
                 trace!(target: "sync", "Received state response shard_id: {} sync_hash: {:?} part(id/size): {:?}",
@@ -614,6 +625,16 @@ impl ClientActor {
                 }
                 NetworkClientResponses::NoResponse
             }
+            NetworkClientMessages::EncryptedBlob(blob, author) => {
+                let me = self.client.validator_signer.as_ref().map(|vs| vs.validator_id());
+                if me != Some(&blob.recipient) {
+                    warn!(target: "client", ?author, recipient = %blob.recipient, "Received encrypted blob addressed to another account");
+                } else {
+                    // Nothing consumes validator-to-validator data yet.
+                    debug!(target: "client", ?author, len = blob.ciphertext.len(), "Received encrypted blob");
+                }
+                NetworkClientResponses::NoResponse
+            }
             NetworkClientMessages::NetworkInfo(network_info) => {
                 self.network_info = network_info;
                 NetworkClientResponses::NoResponse
@@ -1829,6 +1850,9 @@ impl ClientActor {
         } else {
             None
         };
+        // Exports near_shard_cache_total_bytes. It locks all the trie caches,
+        // so it is done here rather than on every cache update.
+        self.client.runtime_adapter.get_tries().total_cache_bytes();
         self.info_helper.info(
             &head,
             &self.client.sync_status,
@@ -3,8 +3,9 @@ pub use near_client_primitives::types::{
     GetExecutionOutcome, GetExecutionOutcomeResponse, GetExecutionOutcomesForBlock, GetGasPrice,
     GetNetworkInfo, GetNextLightClientBlock, GetProtocolConfig, GetReceipt, GetStateChanges,
     GetStateChangesInBlock, GetStateChangesWithCauseInBlock,
-    GetStateChangesWithCauseInBlockForTrackedShards, GetValidatorInfo, GetValidatorOrdered, Query,
-    QueryError, Status, StatusResponse, SyncStatus, TxStatus, TxStatusError,
+    GetStateChangesWithCauseInBlockForTrackedShards, GetTxInclusionProof, GetValidatorInfo,
+    GetValidatorOrdered, Query, QueryError, Status, StatusResponse, SyncStatus, TxStatus,
+    TxStatusError,
 };
 
 pub use near_client_primitives::debug::DebugStatus;
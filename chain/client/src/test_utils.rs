@@ -1021,6 +1021,7 @@ pub fn setup_mock_all_validators(
                         NetworkRequests::ForwardTx(_, _)
                         | NetworkRequests::BanPeer { .. }
                         | NetworkRequests::TxStatus(_, _, _)
+                        | NetworkRequests::TxInclusionProof(_, _)
                         | NetworkRequests::Challenge(_) => {}
                     };
                 }
@@ -23,14 +23,14 @@ use near_client_primitives::types::{
     GetExecutionOutcomesForBlock, GetGasPrice, GetGasPriceError, GetNextLightClientBlockError,
     GetProtocolConfig, GetProtocolConfigError, GetReceipt, GetReceiptError, GetStateChangesError,
     GetStateChangesWithCauseInBlock, GetStateChangesWithCauseInBlockForTrackedShards,
-    GetValidatorInfoError, Query, QueryError, TxStatus, TxStatusError,
+    GetTxInclusionProof, GetValidatorInfoError, Query, QueryError, TxStatus, TxStatusError,
 };
 #[cfg(feature = "test_features")]
 use near_network::types::NetworkAdversarialMessage;
 use near_network::types::{
     NetworkRequests, NetworkViewClientMessages, NetworkViewClientResponses, PeerManagerAdapter,
     PeerManagerMessageRequest, ReasonForBan, StateResponseInfo, StateResponseInfoV1,
-    StateResponseInfoV2,
+    StateResponseInfoV2, TxInclusionProof,
 };
 use near_performance_metrics_macros::{perf, perf_with_debug};
 use near_primitives::block::{Block, BlockHeader};
@@ -77,6 +77,10 @@ pub struct ViewClientRequestManager {
     pub query_responses: lru::LruCache<String, Result<QueryResponse, String>>,
     /// Receipt outcome requests
     pub receipt_outcome_requests: lru::LruCache<CryptoHash, Instant>,
+    /// Transaction inclusion proof requests that need to be forwarded to other shards
+    pub tx_inclusion_proof_requests: lru::LruCache<CryptoHash, Instant>,
+    /// Transaction inclusion proof responses from other nodes
+    pub tx_inclusion_proof_responses: lru::LruCache<CryptoHash, TxInclusionProof>,
 }
 
 /// View client provides currently committed (to the storage) view of the current chain and state.
@@ -101,6 +105,8 @@ impl ViewClientRequestManager {
             query_requests: lru::LruCache::new(QUERY_REQUEST_LIMIT),
             query_responses: lru::LruCache::new(QUERY_REQUEST_LIMIT),
             receipt_outcome_requests: lru::LruCache::new(QUERY_REQUEST_LIMIT),
+            tx_inclusion_proof_requests: lru::LruCache::new(QUERY_REQUEST_LIMIT),
+            tx_inclusion_proof_responses: lru::LruCache::new(QUERY_REQUEST_LIMIT),
         }
     }
 }
@@ -429,6 +435,65 @@ impl ViewClientActor {
         }
     }
 
+    /// Builds the inclusion proof of the given transaction from the local chain.
+    /// Returns `None` if the transaction is unknown or not confirmed yet.
+    fn build_tx_inclusion_proof(&self, tx_hash: CryptoHash) -> Option<TxInclusionProof> {
+        // The outcome of a transaction is executed by its signer, whose shard
+        // determines the chunk the proof has to be built from.
+        let sender_id =
+            self.chain.get_execution_outcome(&tx_hash).ok()?.outcome_with_id.outcome.executor_id;
+        let id = TransactionOrReceiptId::Transaction { transaction_hash: tx_hash, sender_id };
+        match self.get_execution_outcome(id) {
+            Ok(response) => Some(TxInclusionProof {
+                outcome_proof: response.outcome_proof,
+                outcome_root_proof: response.outcome_root_proof,
+            }),
+            Err(e) => {
+                debug!(target: "view_client", "Failed to build inclusion proof of {}: {}", tx_hash, e);
+                None
+            }
+        }
+    }
+
+    fn get_tx_inclusion_proof(
+        &mut self,
+        tx_hash: CryptoHash,
+        signer_account_id: AccountId,
+    ) -> Result<Option<TxInclusionProof>, TxStatusError> {
+        {
+            let mut request_manager = self.request_manager.write().expect(POISONED_LOCK_ERR);
+            if let Some(proof) = request_manager.tx_inclusion_proof_responses.pop(&tx_hash) {
+                request_manager.tx_inclusion_proof_requests.pop(&tx_hash);
+                return Ok(Some(proof));
+            }
+        }
+
+        let head = self.chain.head().map_err(|e| TxStatusError::ChainError(e))?;
+        let target_shard_id = self
+            .runtime_adapter
+            .account_id_to_shard_id(&signer_account_id, &head.epoch_id)
+            .map_err(|err| TxStatusError::InternalError(err.to_string()))?;
+        if self.runtime_adapter.cares_about_shard(
+            self.validator_account_id.as_ref(),
+            &head.prev_block_hash,
+            target_shard_id,
+            true,
+        ) {
+            return Ok(self.build_tx_inclusion_proof(tx_hash));
+        }
+        let mut request_manager = self.request_manager.write().expect(POISONED_LOCK_ERR);
+        if Self::need_request(tx_hash, &mut request_manager.tx_inclusion_proof_requests) {
+            let validator = self
+                .chain
+                .find_validator_for_forwarding(target_shard_id)
+                .map_err(|e| TxStatusError::ChainError(e))?;
+            self.network_adapter.do_send(PeerManagerMessageRequest::NetworkRequests(
+                NetworkRequests::TxInclusionProof(validator, tx_hash),
+            ));
+        }
+        Ok(None)
+    }
+
     fn retrieve_headers(
         &mut self,
         hashes: Vec<CryptoHash>,
@@ -469,6 +534,85 @@ impl ViewClientActor {
         cache.push_back(now);
         true
     }
+
+    fn get_execution_outcome(
+        &self,
+        id: TransactionOrReceiptId,
+    ) -> Result<GetExecutionOutcomeResponse, GetExecutionOutcomeError> {
+        let (id, account_id) = match id {
+            TransactionOrReceiptId::Transaction { transaction_hash, sender_id } => {
+                (transaction_hash, sender_id)
+            }
+            TransactionOrReceiptId::Receipt { receipt_id, receiver_id } => {
+                (receipt_id, receiver_id)
+            }
+        };
+        match self.chain.get_execution_outcome(&id) {
+            Ok(outcome) => {
+                let mut outcome_proof = outcome;
+                let epoch_id =
+                    self.chain.get_block(&outcome_proof.block_hash)?.header().epoch_id().clone();
+                let target_shard_id =
+                    self.runtime_adapter.account_id_to_shard_id(&account_id, &epoch_id)?;
+                let res = self.chain.get_next_block_hash_with_new_chunk(
+                    &outcome_proof.block_hash,
+                    target_shard_id,
+                )?;
+                match res {
+                    Some((h, target_shard_id)) => {
+                        outcome_proof.block_hash = h;
+                        // Here we assume the number of shards is small so this reconstruction
+                        // should be fast
+                        let outcome_roots = self
+                            .chain
+                            .get_block(&h)?
+                            .chunks()
+                            .iter()
+                            .map(|header| header.outcome_root())
+                            .collect::<Vec<_>>();
+                        if target_shard_id >= (outcome_roots.len() as u64) {
+                            return Err(GetExecutionOutcomeError::InconsistentState {
+                                number_or_shards: outcome_roots.len(),
+                                execution_outcome_shard_id: target_shard_id,
+                            });
+                        }
+                        Ok(GetExecutionOutcomeResponse {
+                            outcome_proof: outcome_proof.into(),
+                            outcome_root_proof: merklize(&outcome_roots).1
+                                [target_shard_id as usize]
+                                .clone(),
+                        })
+                    }
+                    None => Err(GetExecutionOutcomeError::NotConfirmed {
+                        transaction_or_receipt_id: id,
+                    }),
+                }
+            }
+            Err(e) => match e {
+                near_chain::Error::DBNotFoundErr(_) => {
+                    let head = self.chain.head().map_err(|e| TxStatusError::ChainError(e))?;
+                    let target_shard_id =
+                        self.runtime_adapter.account_id_to_shard_id(&account_id, &head.epoch_id)?;
+                    if self.runtime_adapter.cares_about_shard(
+                        self.validator_account_id.as_ref(),
+                        &head.last_block_hash,
+                        target_shard_id,
+                        true,
+                    ) {
+                        Err(GetExecutionOutcomeError::UnknownTransactionOrReceipt {
+                            transaction_or_receipt_id: id,
+                        })
+                    } else {
+                        Err(GetExecutionOutcomeError::UnavailableShard {
+                            transaction_or_receipt_id: id,
+                            shard_id: target_shard_id,
+                        })
+                    }
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
 }
 
 impl Actor for ViewClientActor {
@@ -587,6 +731,23 @@ impl Handler<TxStatus> for ViewClientActor {
     }
 }
 
+impl Handler<GetTxInclusionProof> for ViewClientActor {
+    type Result = Result<Option<GetExecutionOutcomeResponse>, TxStatusError>;
+
+    #[perf]
+    fn handle(&mut self, msg: GetTxInclusionProof, _: &mut Self::Context) -> Self::Result {
+        let _timer = metrics::VIEW_CLIENT_MESSAGE_TIME
+            .with_label_values(&["GetTxInclusionProof"])
+            .start_timer();
+        Ok(self.get_tx_inclusion_proof(msg.tx_hash, msg.signer_account_id)?.map(|proof| {
+            GetExecutionOutcomeResponse {
+                outcome_proof: proof.outcome_proof,
+                outcome_root_proof: proof.outcome_root_proof,
+            }
+        }))
+    }
+}
+
 impl Handler<GetValidatorInfo> for ViewClientActor {
     type Result = Result<EpochValidatorInfo, GetValidatorInfoError>;
 
@@ -809,79 +970,7 @@ impl Handler<GetExecutionOutcome> for ViewClientActor {
         let _timer = metrics::VIEW_CLIENT_MESSAGE_TIME
             .with_label_values(&["GetExecutionOutcome"])
             .start_timer();
-        let (id, account_id) = match msg.id {
-            TransactionOrReceiptId::Transaction { transaction_hash, sender_id } => {
-                (transaction_hash, sender_id)
-            }
-            TransactionOrReceiptId::Receipt { receipt_id, receiver_id } => {
-                (receipt_id, receiver_id)
-            }
-        };
-        match self.chain.get_execution_outcome(&id) {
-            Ok(outcome) => {
-                let mut outcome_proof = outcome;
-                let epoch_id =
-                    self.chain.get_block(&outcome_proof.block_hash)?.header().epoch_id().clone();
-                let target_shard_id =
-                    self.runtime_adapter.account_id_to_shard_id(&account_id, &epoch_id)?;
-                let res = self.chain.get_next_block_hash_with_new_chunk(
-                    &outcome_proof.block_hash,
-                    target_shard_id,
-                )?;
-                match res {
-                    Some((h, target_shard_id)) => {
-                        outcome_proof.block_hash = h;
-                        // Here we assume the number of shards is small so this reconstruction
-                        // should be fast
-                        let outcome_roots = self
-                            .chain
-                            .get_block(&h)?
-                            .chunks()
-                            .iter()
-                            .map(|header| header.outcome_root())
-                            .collect::<Vec<_>>();
-                        if target_shard_id >= (outcome_roots.len() as u64) {
-                            return Err(GetExecutionOutcomeError::InconsistentState {
-                                number_or_shards: outcome_roots.len(),
-                                execution_outcome_shard_id: target_shard_id,
-                            });
-                        }
-                        Ok(GetExecutionOutcomeResponse {
-                            outcome_proof: outcome_proof.into(),
-                            outcome_root_proof: merklize(&outcome_roots).1
-                                [target_shard_id as usize]
-                                .clone(),
-                        })
-                    }
-                    None => Err(GetExecutionOutcomeError::NotConfirmed {
-                        transaction_or_receipt_id: id,
-                    }),
-                }
-            }
-            Err(e) => match e {
-                near_chain::Error::DBNotFoundErr(_) => {
-                    let head = self.chain.head().map_err(|e| TxStatusError::ChainError(e))?;
-                    let target_shard_id =
-                        self.runtime_adapter.account_id_to_shard_id(&account_id, &head.epoch_id)?;
-                    if self.runtime_adapter.cares_about_shard(
-                        self.validator_account_id.as_ref(),
-                        &head.last_block_hash,
-                        target_shard_id,
-                        true,
-                    ) {
-                        Err(GetExecutionOutcomeError::UnknownTransactionOrReceipt {
-                            transaction_or_receipt_id: id,
-                        })
-                    } else {
-                        Err(GetExecutionOutcomeError::UnavailableShard {
-                            transaction_or_receipt_id: id,
-                            shard_id: target_shard_id,
-                        })
-                    }
-                }
-                _ => Err(e.into()),
-            },
-        }
+        self.get_execution_outcome(msg.id)
     }
 }
 
@@ -1198,6 +1287,20 @@ impl Handler<NetworkViewClientMessages> for ViewClientActor {
                 // TODO #3488
                 NetworkViewClientResponses::NoResponse
             }
+            NetworkViewClientMessages::TxInclusionProofRequest { tx_hash } => {
+                match self.build_tx_inclusion_proof(tx_hash) {
+                    Some(proof) => NetworkViewClientResponses::TxInclusionProof(Box::new(proof)),
+                    None => NetworkViewClientResponses::NoResponse,
+                }
+            }
+            NetworkViewClientMessages::TxInclusionProofResponse(proof) => {
+                let tx_hash = proof.outcome_proof.id;
+                let mut request_manager = self.request_manager.write().expect(POISONED_LOCK_ERR);
+                if request_manager.tx_inclusion_proof_requests.pop(&tx_hash).is_some() {
+                    request_manager.tx_inclusion_proof_responses.put(tx_hash, *proof);
+                }
+                NetworkViewClientResponses::NoResponse
+            }
         }
     }
 }
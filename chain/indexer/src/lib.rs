@@ -107,6 +107,11 @@ impl Indexer {
             ",
             indexer_config.home_dir.join("config.json").display()
         );
+        anyhow::ensure!(
+            !near_config.config.store.skip_state_changes,
+            "Indexer needs the state changes, please remove `store.skip_state_changes` from {}",
+            indexer_config.home_dir.join("config.json").display()
+        );
         let nearcore::NearNode { client, view_client, .. } =
             nearcore::start_with_config(&indexer_config.home_dir, near_config.clone())
                 .with_context(|| "start_with_config")?;
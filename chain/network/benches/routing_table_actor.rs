@@ -5,7 +5,7 @@ use criterion::{black_box, Criterion};
 use near_crypto::{KeyType, SecretKey, Signature};
 use near_network::routing;
 use near_network::test_utils::random_peer_id;
-use near_network::types::Edge;
+use near_network::types::{Edge, Nonce};
 use near_primitives::network::PeerId;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -18,7 +18,7 @@ fn build_graph(depth: usize, size: usize) -> routing::GraphWithCache {
 
     let mut edges: Vec<Edge> = Vec::new();
     for node in &nodes[..size] {
-        edges.push(Edge::make_fake_edge(source.clone(), node.clone(), 1));
+        edges.push(Edge::make_fake_edge(source.clone(), node.clone(), Nonce(1)));
     }
 
     for layer in 0..depth - 1 {
@@ -26,7 +26,7 @@ fn build_graph(depth: usize, size: usize) -> routing::GraphWithCache {
             for v in 0..size {
                 let peer0 = nodes[layer * size + u].clone();
                 let peer1 = nodes[(layer + 1) * size + v].clone();
-                edges.push(Edge::make_fake_edge(peer0, peer1, (layer + u + v) as u64));
+                edges.push(Edge::make_fake_edge(peer0, peer1, Nonce((layer + u + v) as u64)));
             }
         }
     }
@@ -111,7 +111,7 @@ fn benchmark_sign_edge(c: &mut Criterion) {
 
     c.bench_function("benchmark_sign_edge", |bench| {
         bench.iter(|| {
-            let ei = Edge::build_hash(&p0, &p1, 123);
+            let ei = Edge::build_hash(&p0, &p1, Nonce(123));
             black_box(ei);
         })
     });
@@ -139,7 +139,7 @@ pub struct EdgeNew {
     pub key: Arc<(PeerId, PeerId)>,
     /// Nonce to keep tracking of the last update on this edge.
     /// It must be even
-    pub nonce: u64,
+    pub nonce: Nonce,
     /// Signature from parties validating the edge. These are signature of the added edge.
     #[allow(unused)]
     signature0: Signature,
@@ -157,7 +157,7 @@ pub struct EdgeNew2 {
     pub key: (Arc<PeerId>, Arc<PeerId>),
     /// Nonce to keep tracking of the last update on this edge.
     /// It must be even
-    pub nonce: u64,
+    pub nonce: Nonce,
     /// Signature from parties validating the edge. These are signature of the added edge.
     #[allow(unused)]
     signature0: Signature,
@@ -31,7 +31,7 @@ use crate::types::AccountKeys;
 use near_o11y::log_assert;
 use near_primitives::types::{AccountId, EpochId};
 use rayon::iter::{ParallelBridge, ParallelIterator};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -124,10 +124,10 @@ pub struct CacheSnapshot {
 
 impl CacheSnapshot {
     fn is_new(&self, d: &SignedAccountData) -> bool {
-        let id = (d.epoch_id.clone(), d.account_id.clone());
+        let id = (d.epoch_id().clone(), d.account_id().clone());
         self.keys.contains_key(&id)
             && match self.data.get(&id) {
-                Some(old) if old.timestamp >= d.timestamp => false,
+                Some(old) if old.timestamp() >= d.timestamp() => false,
                 _ => true,
             }
     }
@@ -135,7 +135,7 @@ impl CacheSnapshot {
         if !self.is_new(&d) {
             return None;
         }
-        let id = (d.epoch_id.clone(), d.account_id.clone());
+        let id = (d.epoch_id().clone(), d.account_id().clone());
         self.data.insert(id, d.clone());
         Some(d)
     }
@@ -171,6 +171,30 @@ impl Cache {
         })
     }
 
+    /// Removes from `data` the entries whose epoch is not one of the epochs we have account keys
+    /// for. A peer may mix entries from arbitrary epochs into a single SyncAccountsData message,
+    /// and those would otherwise just be skipped silently.
+    /// Returns the number of entries removed.
+    pub fn retain_active_epochs(&self, data: &mut Vec<Arc<SignedAccountData>>) -> usize {
+        let inner = self.0.load();
+        let active: HashSet<&EpochId> = inner.keys.keys().map(|(epoch_id, _)| epoch_id).collect();
+        let len = data.len();
+        data.retain(|d| active.contains(d.epoch_id()));
+        len - data.len()
+    }
+
+    /// Returns the accounts which we have verified AccountData for, sorted and deduplicated
+    /// (an account may have data for multiple epochs). The cache keeps only the newest data
+    /// for each (epoch_id,account_id) and drops the data of accounts which are no longer
+    /// important in `set_keys`, so all the data is fresh.
+    pub fn known_validator_accounts(&self) -> Vec<AccountId> {
+        let mut accounts: Vec<_> =
+            self.0.load().data.keys().map(|(_, account_id)| account_id.clone()).collect();
+        accounts.sort();
+        accounts.dedup();
+        accounts
+    }
+
     /// Selects new data and verifies the signatures.
     /// Returns the verified new data and an optional error.
     /// Note that even if error has been returned the partially validated output is returned
@@ -190,7 +214,7 @@ impl Cache {
             if d.payload().len() > network_protocol::MAX_ACCOUNT_DATA_SIZE_BYTES {
                 return (vec![], Some(Error::DataTooLarge));
             }
-            let id = (d.epoch_id.clone(), d.account_id.clone());
+            let id = (d.epoch_id().clone(), d.account_id().clone());
             // We want the communication needed for broadcasting per-account data to be minimal.
             // Therefore broadcasting multiple datasets per account is considered malicious
             // behavior, since all but one are obviously outdated.
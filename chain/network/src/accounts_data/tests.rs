@@ -4,7 +4,7 @@ use crate::network_protocol::SignedAccountData;
 use crate::testonly::{assert_is_superset, make_rng, AsSet as _, Rng};
 use crate::time;
 use crate::types::AccountKeys;
-use near_primitives::types::EpochId;
+use near_primitives::types::{AccountId, EpochId};
 use near_primitives::validator_signer::{InMemoryValidatorSigner, ValidatorSigner as _};
 use pretty_assertions::assert_eq;
 use std::sync::Arc;
@@ -228,3 +228,77 @@ async fn single_account_multiple_data() {
     // entries has been applied.
     assert_eq!(res.0.as_set(), cache.load().data.values().collect());
 }
+
+#[tokio::test]
+async fn known_validator_accounts() {
+    let mut rng = make_rng(2947294234);
+    let rng = &mut rng;
+    let clock = time::FakeClock::default();
+    let now = clock.now_utc();
+
+    let mut signers = make_signers(rng, 5);
+    // signers[4] is the same account as signers[0], but in another epoch.
+    signers[4].signer = signers[0].signer.clone();
+    let account_id = |i: usize| signers[i].signer.validator_id().clone();
+    let sorted = |mut accounts: Vec<AccountId>| {
+        accounts.sort();
+        accounts
+    };
+
+    let cache = Arc::new(Cache::new());
+    assert!(cache.known_validator_accounts().is_empty());
+    cache.set_keys(make_account_keys(&signers));
+    // No data yet.
+    assert!(cache.known_validator_accounts().is_empty());
+
+    // signers[3] has no data.
+    let res = cache
+        .clone()
+        .insert(
+            [0, 1, 2, 4]
+                .iter()
+                .map(|i| Arc::new(signers[*i].make_account_data(rng, now)))
+                .collect(),
+        )
+        .await;
+    assert_eq!(4, unwrap(&res).len());
+    assert_eq!(
+        sorted(vec![account_id(0), account_id(1), account_id(2)]),
+        cache.known_validator_accounts()
+    );
+
+    // Data of accounts which are no longer important doesn't count.
+    cache.set_keys(make_account_keys(&signers[1..4]));
+    assert_eq!(sorted(vec![account_id(1), account_id(2)]), cache.known_validator_accounts());
+}
+
+#[tokio::test]
+async fn retain_active_epochs() {
+    let mut rng = make_rng(2947294234);
+    let rng = &mut rng;
+    let clock = time::FakeClock::default();
+    let now = clock.now_utc();
+
+    let signers = make_signers(rng, 5);
+    let cache = Arc::new(Cache::new());
+    cache.set_keys(make_account_keys(&signers[0..3]));
+    // An account which is not important, but in an active epoch.
+    let other =
+        Signer { epoch_id: signers[0].epoch_id.clone(), signer: data::make_validator_signer(rng) };
+
+    let a0 = Arc::new(signers[0].make_account_data(rng, now));
+    let a1 = Arc::new(signers[1].make_account_data(rng, now));
+    let a3 = Arc::new(signers[3].make_account_data(rng, now));
+    let a4 = Arc::new(signers[4].make_account_data(rng, now));
+    let b = Arc::new(other.make_account_data(rng, now));
+    let mut batch = vec![a0.clone(), a3.clone(), a1.clone(), b.clone(), a4.clone()];
+    // a3 and a4 belong to inactive epochs => dropped.
+    assert_eq!(2, cache.retain_active_epochs(&mut batch));
+    assert_eq!(vec![a0.clone(), a1.clone(), b.clone()], batch);
+    // Nothing to drop anymore.
+    assert_eq!(0, cache.retain_active_epochs(&mut batch));
+
+    // The remaining entries are handled as usual.
+    let res = cache.clone().insert(batch).await;
+    assert_eq!([&a0, &a1].as_set(), unwrap(&res).as_set());
+}
@@ -1,6 +1,7 @@
 pub mod arc_mutex;
 pub mod atomic_cell;
 pub mod demux;
+pub mod rate;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,35 @@
+use crate::concurrency::demux::RateLimit;
+use crate::time;
+
+/// Token bucket implementing the `RateLimit` algorithm for events which are
+/// dropped, rather than delayed (as in `demux::Demux`), when they exceed the limit.
+/// The bucket holds up to `burst` tokens and gets `qps` new tokens per second.
+/// Every event takes one token, and is rejected if the bucket is empty.
+pub struct TokenBucket {
+    rl: RateLimit,
+    tokens: f64,
+    last_refill: time::Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket. Panics if `rl` is not valid.
+    pub fn new(rl: RateLimit, now: time::Instant) -> Self {
+        rl.validate().unwrap();
+        Self { rl, tokens: rl.burst as f64, last_refill: now }
+    }
+
+    /// Takes a token for an event happening at `now`.
+    /// Returns false iff the bucket is empty, i.e. the event exceeds the rate limit.
+    pub fn try_acquire(&mut self, now: time::Instant) -> bool {
+        if now > self.last_refill {
+            let refill = (now - self.last_refill).as_seconds_f64() * self.rl.qps;
+            self.tokens = (self.tokens + refill).min(self.rl.burst as f64);
+            self.last_refill = now;
+        }
+        if self.tokens < 1. {
+            return false;
+        }
+        self.tokens -= 1.;
+        true
+    }
+}
@@ -1,5 +1,7 @@
 use crate::concurrency::arc_mutex::ArcMutex;
 use crate::concurrency::demux;
+use crate::concurrency::rate::TokenBucket;
+use crate::time;
 
 #[tokio::test]
 async fn test_demux() {
@@ -69,3 +71,32 @@ fn test_arc_mutex() {
     // After update, load() should return the new value.
     assert_eq!(v2, *m.load());
 }
+
+#[test]
+fn token_bucket() {
+    let clock = time::FakeClock::default();
+    let mut bucket = TokenBucket::new(demux::RateLimit { qps: 2., burst: 5 }, clock.now());
+    // A burst is let through at once, the excess is dropped.
+    let passed = (0..10).filter(|_| bucket.try_acquire(clock.now())).count();
+    assert_eq!(5, passed);
+
+    // Events at the rate limit pass.
+    for _ in 0..10 {
+        clock.advance(time::Duration::milliseconds(500));
+        assert!(bucket.try_acquire(clock.now()));
+        assert!(!bucket.try_acquire(clock.now()));
+    }
+
+    // Events exceeding the rate limit are dropped: at 4 events per second, only half pass.
+    let mut passed = 0;
+    for _ in 0..40 {
+        clock.advance(time::Duration::milliseconds(250));
+        passed += bucket.try_acquire(clock.now()) as usize;
+    }
+    assert_eq!(20, passed);
+
+    // The bucket refills while idle, but up to `burst` only.
+    clock.advance(time::Duration::seconds(100));
+    let passed = (0..10).filter(|_| bucket.try_acquire(clock.now())).count();
+    assert_eq!(5, passed);
+}
@@ -1,8 +1,10 @@
 use crate::blacklist;
 use crate::concurrency::demux;
+use crate::network_protocol;
 use crate::network_protocol::PeerAddr;
 use crate::network_protocol::PeerInfo;
 use crate::peer_manager::peer_manager_actor::Event;
+use crate::routing::auditor::{NoopAuditor, RoutedMessageAuditor};
 use crate::sink::Sink;
 use crate::time;
 use crate::types::ROUTED_MESSAGE_TTL;
@@ -11,6 +13,9 @@ use near_crypto::{KeyType, SecretKey};
 use near_primitives::network::PeerId;
 use near_primitives::types::AccountId;
 use near_primitives::validator_signer::{InMemoryValidatorSigner, ValidatorSigner};
+use near_primitives::version::{
+    ProtocolVersion, PEER_MIN_ALLOWED_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::sync::Arc;
 
@@ -23,6 +28,18 @@ pub const MAX_ROUTES_TO_STORE: usize = 5;
 /// Maximum number of PeerAddts in the ValidatorConfig::endpoints field.
 pub const MAX_PEER_ADDRS: usize = 10;
 
+/// Maximum size of a single serialized PeersResponse message.
+pub const MAX_PEERS_RESPONSE_BYTES: usize = 32 * 1024;
+
+/// Maximum number of state parts computed concurrently for a single peer.
+pub const MAX_CONCURRENT_STATE_PART_REQUESTS: usize = 4;
+
+/// Maximum number of routed message hashes remembered for deduplication.
+pub const ROUTED_MESSAGE_DEDUP_CAPACITY: usize = 100_000;
+
+/// Maximum number of StateRequestPart messages tracked as in flight.
+pub const IN_FLIGHT_STATE_PART_REQUESTS_CAPACITY: usize = 10_000;
+
 /// ValidatorEndpoints are the endpoints that peers should connect to, to send messages to this
 /// validator. Validator will sign the endpoints and broadcast them to the network.
 /// For a static setup (a static IP, or a list of relay nodes with static IPs) use PublicAddrs.
@@ -66,6 +83,9 @@ pub struct NetworkConfig {
     pub boot_nodes: Vec<PeerInfo>,
     pub whitelist_nodes: Vec<PeerInfo>,
     pub handshake_timeout: time::Duration,
+    /// Deadline for receiving and parsing the Handshake message of a new connection.
+    /// Bounds how long a peer trickling bytes can keep a connection slot occupied.
+    pub handshake_read_timeout: time::Duration,
     pub reconnect_delay: time::Duration,
 
     /// Maximum time between refreshing the peer list.
@@ -92,8 +112,50 @@ pub struct NetworkConfig {
     pub peer_expiration_duration: time::Duration,
     /// Maximum number of peer addresses we should ever send on PeersRequest.
     pub max_send_peers: u32,
+    /// Peers sent on PeersRequest are split into multiple PeersResponse messages,
+    /// each of at most this size in bytes.
+    pub max_peers_response_bytes: usize,
+    /// Maximum number of StateRequestPart messages from a single peer which are processed
+    /// concurrently. Further requests are answered with StateRequestPartBusy.
+    pub max_concurrent_state_part_requests: usize,
+    /// Transactions received from peers (directly or in ForwardTx routed messages)
+    /// whose borsh encoding is larger than that are rejected without decoding them.
+    pub max_transaction_bytes: usize,
+    /// RoutingTableUpdates received from peers which contain more accounts than that
+    /// are rejected. Updates we send are split according to the default limit,
+    /// see `RoutingTableUpdate::into_chunks`.
+    pub max_accounts_per_update: usize,
+    /// Blocks received from peers whose borsh encoding is larger than that
+    /// are rejected without decoding them.
+    pub max_block_bytes: usize,
+    /// Block headers received from peers whose borsh encoding is larger than that
+    /// are rejected without decoding them.
+    pub max_block_header_bytes: usize,
+    /// Routed messages received from peers whose borsh-encoded body is larger than that
+    /// are rejected without decoding them.
+    pub max_routed_body_bytes: usize,
+    /// Challenges received from peers whose borsh encoding is larger than that
+    /// are rejected without decoding them.
+    pub max_challenge_bytes: usize,
+    /// Epoch sync responses received from peers whose borsh encoding is larger than that
+    /// are rejected without decoding them.
+    pub max_epoch_sync_response_bytes: usize,
     /// Duration for checking on stats from the peers.
     pub peer_stats_period: time::Duration,
+    /// Lowest protocol version accepted from peers during the handshake.
+    /// Peers below it are refused even if they support our protocol version.
+    pub min_accepted_protocol_version: ProtocolVersion,
+    /// Proposed interval of the idle keepalive pings sent to each connected peer.
+    /// The actual interval is the minimum of the proposals of both sides.
+    pub keep_alive_interval: time::Duration,
+    /// Identical routed messages (with the same hash) received again within this
+    /// window, possibly from a different peer, are dropped. Zero disables it.
+    /// Requests (see `RoutedMessageV2::expect_response`) are never dropped this way.
+    pub routed_message_dedup_window: time::Duration,
+    /// StateRequestPart for a part which has been requested less than this long ago, and
+    /// hasn't been answered yet, is not sent again. Should be shorter than the state sync
+    /// timeout of the client, so that retries after the timeout are not suppressed.
+    pub state_part_request_timeout: time::Duration,
     /// Time to persist Accounts Id in the router without removing them.
     pub ttl_account_id_router: time::Duration,
     /// Number of hops a message is allowed to travel before being dropped.
@@ -121,6 +183,17 @@ pub struct NetworkConfig {
     pub archive: bool,
     /// Maximal rate at which SyncAccountsData can be broadcasted.
     pub accounts_data_broadcast_rate_limit: demux::RateLimit,
+    /// Maximal rate at which SyncRoutingTable messages from a single peer are processed.
+    /// The edges of every update have to be verified, so the excess updates are dropped.
+    /// The content of the dropped incremental updates is lost until the next full sync
+    /// of the routing table with the peer, which is performed only after the handshake.
+    pub sync_routing_table_rate_limit: demux::RateLimit,
+    /// Maximal rate at which RoutingTableSubsetRequest messages from a single peer are served.
+    /// The excess requests are dropped.
+    pub routing_table_subset_request_rate_limit: demux::RateLimit,
+    /// Maximal rate at which a full accounts data sync is requested again from a single peer,
+    /// after it answered a full sync request incompletely.
+    pub full_accounts_data_sync_retry_rate_limit: demux::RateLimit,
     /// features
     pub features: Features,
     /// If true - connect only to the bootnodes.
@@ -132,6 +205,23 @@ pub struct NetworkConfig {
     //   * not broadcasting deleted edges
     //   * ignoring received deleted edges as well
     pub skip_tombstones: Option<time::Duration>,
+    /// Whether to drop the edges received in SyncRoutingTable which are not signed by both of
+    /// their peers and keep validating the remaining edges of the message, rather than stop at
+    /// the first invalid edge. Either way they are counted and the sender gets banned.
+    pub drop_asymmetric_edges: bool,
+    /// Whether to drop the entries of incremental SyncAccountsData messages whose epoch is
+    /// not one of the epochs we have account keys for. The dropped entries are counted in
+    /// near_accounts_data_inactive_epoch_total, which stays at zero if it is not set.
+    pub validate_accounts_data_epochs: bool,
+    /// Whether to answer TelemetryRequest messages with the node's telemetry.
+    /// If not set, the requests are answered with an empty TelemetryData.
+    pub share_telemetry: bool,
+    /// Whether the peer manager and the peer actors log the addresses of peers with
+    /// the host part of the IP zeroed (see `network_protocol::redact_addr`).
+    pub redact_addrs_in_logs: bool,
+
+    /// Auditor invoked for every routed message received from a peer.
+    pub routed_message_auditor: Arc<dyn RoutedMessageAuditor>,
 
     /// TEST-ONLY
     /// TODO(gprusak): make it pub(crate), once all integration tests
@@ -195,6 +285,7 @@ impl NetworkConfig {
                     .context("whitelist_nodes")?
             },
             handshake_timeout: cfg.handshake_timeout.try_into()?,
+            handshake_read_timeout: cfg.handshake_read_timeout.try_into()?,
             reconnect_delay: cfg.reconnect_delay.try_into()?,
             monitor_peers_max_period: cfg.monitor_peers_max_period.try_into()?,
             max_num_peers: cfg.max_num_peers,
@@ -206,8 +297,24 @@ impl NetworkConfig {
             archival_peer_connections_lower_bound: cfg.archival_peer_connections_lower_bound,
             ban_window: cfg.ban_window.try_into()?,
             max_send_peers: 512,
+            max_peers_response_bytes: MAX_PEERS_RESPONSE_BYTES,
+            max_concurrent_state_part_requests: cfg.experimental.max_concurrent_state_part_requests,
+            max_transaction_bytes: cfg.experimental.max_transaction_bytes,
+            max_accounts_per_update: cfg.experimental.max_accounts_per_update,
+            max_block_bytes: cfg.experimental.max_block_bytes,
+            max_block_header_bytes: cfg.experimental.max_block_header_bytes,
+            max_routed_body_bytes: cfg.experimental.max_routed_body_bytes,
+            max_challenge_bytes: cfg.experimental.max_challenge_bytes,
+            max_epoch_sync_response_bytes: cfg.experimental.max_epoch_sync_response_bytes,
             peer_expiration_duration: cfg.peer_expiration_duration.try_into()?,
             peer_stats_period: cfg.peer_stats_period.try_into()?,
+            keep_alive_interval: time::Duration::seconds(60),
+            routed_message_dedup_window: cfg.experimental.routed_message_dedup_window.try_into()?,
+            state_part_request_timeout: cfg.experimental.state_part_request_timeout.try_into()?,
+            min_accepted_protocol_version: std::cmp::max(
+                PEER_MIN_ALLOWED_PROTOCOL_VERSION,
+                cfg.experimental.min_accepted_protocol_version,
+            ),
             ttl_account_id_router: cfg.ttl_account_id_router.try_into()?,
             routed_message_ttl: ROUTED_MESSAGE_TTL,
             max_routes_to_store: MAX_ROUTES_TO_STORE,
@@ -222,6 +329,12 @@ impl NetworkConfig {
             outbound_disabled: false,
             archive,
             accounts_data_broadcast_rate_limit: demux::RateLimit { qps: 0.1, burst: 1 },
+            sync_routing_table_rate_limit: demux::RateLimit {
+                qps: cfg.experimental.sync_routing_table_rate_limit_qps,
+                burst: cfg.experimental.sync_routing_table_rate_limit_burst,
+            },
+            routing_table_subset_request_rate_limit: demux::RateLimit { qps: 0.1, burst: 5 },
+            full_accounts_data_sync_retry_rate_limit: demux::RateLimit { qps: 0.1, burst: 1 },
             features,
             inbound_disabled: cfg.experimental.inbound_disabled,
             connect_only_to_boot_nodes: cfg.experimental.connect_only_to_boot_nodes,
@@ -230,6 +343,11 @@ impl NetworkConfig {
             } else {
                 None
             },
+            drop_asymmetric_edges: cfg.experimental.drop_asymmetric_edges,
+            validate_accounts_data_epochs: cfg.experimental.validate_accounts_data_epochs,
+            share_telemetry: cfg.experimental.share_telemetry,
+            redact_addrs_in_logs: cfg.experimental.redact_addrs_in_logs,
+            routed_message_auditor: Arc::new(NoopAuditor),
             event_sink: Sink::null(),
         };
         Ok(this)
@@ -239,6 +357,34 @@ impl NetworkConfig {
         PeerId::new(self.node_key.public_key())
     }
 
+    /// Parameters of decoding the messages received from peers.
+    pub(crate) fn decode_config(&self, clock: time::Clock) -> network_protocol::DecodeConfig {
+        network_protocol::DecodeConfig {
+            clock,
+            max_transaction_bytes: self.max_transaction_bytes,
+            max_accounts_per_update: self.max_accounts_per_update,
+            max_block_bytes: self.max_block_bytes,
+            max_block_header_bytes: self.max_block_header_bytes,
+            max_routed_body_bytes: self.max_routed_body_bytes,
+            max_challenge_bytes: self.max_challenge_bytes,
+            max_epoch_sync_response_bytes: self.max_epoch_sync_response_bytes,
+        }
+    }
+
+    /// Returns `addr` in the form in which it should be logged.
+    pub(crate) fn addr_for_logs(&self, addr: SocketAddr) -> SocketAddr {
+        if self.redact_addrs_in_logs {
+            network_protocol::redact_addr(addr)
+        } else {
+            addr
+        }
+    }
+
+    /// Returns `peer_info` in the form in which it should be logged.
+    pub(crate) fn peer_info_for_logs(&self, peer_info: &PeerInfo) -> PeerInfo {
+        PeerInfo { addr: peer_info.addr.map(|addr| self.addr_for_logs(addr)), ..peer_info.clone() }
+    }
+
     /// TEST-ONLY: Returns network config with given seed used for peer id.
     pub fn from_seed(seed: &str, port: u16) -> Self {
         let node_key = SecretKey::from_seed(KeyType::ED25519, seed);
@@ -262,6 +408,7 @@ impl NetworkConfig {
             boot_nodes: vec![],
             whitelist_nodes: vec![],
             handshake_timeout: time::Duration::seconds(60),
+            handshake_read_timeout: time::Duration::seconds(10),
             reconnect_delay: time::Duration::seconds(60),
             monitor_peers_max_period: time::Duration::seconds(100),
             max_num_peers: 40,
@@ -274,7 +421,20 @@ impl NetworkConfig {
             ban_window: time::Duration::seconds(1),
             peer_expiration_duration: time::Duration::seconds(60 * 60),
             max_send_peers: 512,
+            max_peers_response_bytes: MAX_PEERS_RESPONSE_BYTES,
+            max_concurrent_state_part_requests: MAX_CONCURRENT_STATE_PART_REQUESTS,
+            max_transaction_bytes: network_protocol::MAX_TRANSACTION_BYTES,
+            max_accounts_per_update: network_protocol::MAX_ACCOUNTS_PER_UPDATE,
+            max_block_bytes: network_protocol::MAX_BLOCK_BYTES,
+            max_block_header_bytes: network_protocol::MAX_BLOCK_HEADER_BYTES,
+            max_routed_body_bytes: network_protocol::MAX_ROUTED_BODY_BYTES,
+            max_challenge_bytes: network_protocol::MAX_CHALLENGE_BYTES,
+            max_epoch_sync_response_bytes: network_protocol::MAX_EPOCH_SYNC_RESPONSE_BYTES,
             peer_stats_period: time::Duration::seconds(5),
+            keep_alive_interval: time::Duration::seconds(60),
+            routed_message_dedup_window: time::Duration::milliseconds(200),
+            state_part_request_timeout: time::Duration::seconds(2),
+            min_accepted_protocol_version: PEER_MIN_ALLOWED_PROTOCOL_VERSION,
             ttl_account_id_router: time::Duration::seconds(60 * 60),
             routed_message_ttl: ROUTED_MESSAGE_TTL,
             max_routes_to_store: 1,
@@ -286,13 +446,68 @@ impl NetworkConfig {
             connect_only_to_boot_nodes: false,
             archive: false,
             accounts_data_broadcast_rate_limit: demux::RateLimit { qps: 100., burst: 1000000 },
+            sync_routing_table_rate_limit: demux::RateLimit { qps: 100., burst: 1000000 },
+            routing_table_subset_request_rate_limit: demux::RateLimit { qps: 100., burst: 1000000 },
+            full_accounts_data_sync_retry_rate_limit: demux::RateLimit {
+                qps: 100.,
+                burst: 1000000,
+            },
             features: Features { enable_tier1: true },
             skip_tombstones: None,
+            drop_asymmetric_edges: false,
+            validate_accounts_data_epochs: false,
+            share_telemetry: false,
+            redact_addrs_in_logs: false,
+            routed_message_auditor: Arc::new(NoopAuditor),
             event_sink: Sink::null(),
         }
     }
 
     pub fn verify(self) -> anyhow::Result<VerifiedConfig> {
+        if self.routed_message_ttl == 0 {
+            anyhow::bail!("routed_message_ttl has to be positive");
+        }
+        if self.max_concurrent_state_part_requests == 0 {
+            anyhow::bail!("max_concurrent_state_part_requests has to be positive");
+        }
+        if self.max_transaction_bytes == 0 {
+            anyhow::bail!("max_transaction_bytes has to be positive");
+        }
+        if self.max_accounts_per_update == 0 {
+            anyhow::bail!("max_accounts_per_update has to be positive");
+        }
+        if self.max_block_bytes == 0 {
+            anyhow::bail!("max_block_bytes has to be positive");
+        }
+        if self.max_block_header_bytes == 0 {
+            anyhow::bail!("max_block_header_bytes has to be positive");
+        }
+        if self.max_routed_body_bytes == 0 {
+            anyhow::bail!("max_routed_body_bytes has to be positive");
+        }
+        if self.max_challenge_bytes == 0 {
+            anyhow::bail!("max_challenge_bytes has to be positive");
+        }
+        if self.max_epoch_sync_response_bytes == 0 {
+            anyhow::bail!("max_epoch_sync_response_bytes has to be positive");
+        }
+        self.sync_routing_table_rate_limit.validate().context("sync_routing_table_rate_limit")?;
+        self.routing_table_subset_request_rate_limit
+            .validate()
+            .context("routing_table_subset_request_rate_limit")?;
+        self.full_accounts_data_sync_retry_rate_limit
+            .validate()
+            .context("full_accounts_data_sync_retry_rate_limit")?;
+        if !(PEER_MIN_ALLOWED_PROTOCOL_VERSION..=PROTOCOL_VERSION)
+            .contains(&self.min_accepted_protocol_version)
+        {
+            anyhow::bail!(
+                "min_accepted_protocol_version({}) has to be within [{}, {}]",
+                self.min_accepted_protocol_version,
+                PEER_MIN_ALLOWED_PROTOCOL_VERSION,
+                PROTOCOL_VERSION
+            );
+        }
         if !(self.ideal_connections_lo <= self.ideal_connections_hi) {
             anyhow::bail!(
                 "Invalid ideal_connections values. lo({}) > hi({}).",
@@ -396,18 +611,18 @@ mod test {
         let clock = time::FakeClock::default();
         let signer = data::make_validator_signer(&mut rng);
 
-        let ad = AccountData {
-            peers: (0..config::MAX_PEER_ADDRS)
-                .map(|_| {
-                    // Using IPv6 gives maximal size of the resulting config.
-                    let ip = data::make_ipv6(&mut rng);
-                    data::make_peer_addr(&mut rng, ip)
-                })
-                .collect(),
-            account_id: signer.validator_id().clone(),
-            epoch_id: data::make_epoch_id(&mut rng),
-            timestamp: clock.now_utc(),
-        };
+        let epoch_id = data::make_epoch_id(&mut rng);
+        let ad = AccountData::builder(signer.validator_id().clone(), epoch_id, clock.now_utc())
+            .peers(
+                (0..config::MAX_PEER_ADDRS)
+                    .map(|_| {
+                        // Using IPv6 gives maximal size of the resulting config.
+                        let ip = data::make_ipv6(&mut rng);
+                        data::make_peer_addr(&mut rng, ip)
+                    })
+                    .collect(),
+            )
+            .build();
         let sad = ad.sign(&signer).unwrap();
         assert!(sad.payload().len() <= network_protocol::MAX_ACCOUNT_DATA_SIZE_BYTES);
     }
@@ -1,3 +1,5 @@
+use crate::config;
+use crate::network_protocol;
 use crate::network_protocol::PeerAddr;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
@@ -48,6 +50,11 @@ fn default_monitor_peers_max_period() -> Duration {
     Duration::from_secs(60)
 }
 
+/// Time given to a peer to send its Handshake message.
+fn default_handshake_read_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
 /// Remove peers that we didn't hear about for this amount of time.
 fn default_peer_expiration_duration() -> Duration {
     Duration::from_secs(7 * 24 * 60 * 60)
@@ -64,6 +71,48 @@ fn default_skip_tombstones() -> i64 {
     }
 }
 
+/// Average number of SyncRoutingTable messages per second processed from a peer.
+/// Peers send an update whenever their view of the network changes, which is
+/// a few times per second at most.
+fn default_sync_routing_table_rate_limit_qps() -> f64 {
+    5.
+}
+/// Number of SyncRoutingTable messages from a peer which are processed at once,
+/// e.g. after a batch of connections has been established.
+fn default_sync_routing_table_rate_limit_burst() -> u64 {
+    50
+}
+fn default_max_transaction_bytes() -> usize {
+    network_protocol::MAX_TRANSACTION_BYTES
+}
+fn default_max_accounts_per_update() -> usize {
+    network_protocol::MAX_ACCOUNTS_PER_UPDATE
+}
+fn default_max_block_bytes() -> usize {
+    network_protocol::MAX_BLOCK_BYTES
+}
+fn default_max_block_header_bytes() -> usize {
+    network_protocol::MAX_BLOCK_HEADER_BYTES
+}
+fn default_max_routed_body_bytes() -> usize {
+    network_protocol::MAX_ROUTED_BODY_BYTES
+}
+fn default_max_challenge_bytes() -> usize {
+    network_protocol::MAX_CHALLENGE_BYTES
+}
+fn default_max_epoch_sync_response_bytes() -> usize {
+    network_protocol::MAX_EPOCH_SYNC_RESPONSE_BYTES
+}
+fn default_max_concurrent_state_part_requests() -> usize {
+    config::MAX_CONCURRENT_STATE_PART_REQUESTS
+}
+fn default_routed_message_dedup_window() -> Duration {
+    Duration::from_millis(200)
+}
+fn default_state_part_request_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
     /// Local address to listen for incoming connections.
@@ -106,6 +155,11 @@ pub struct Config {
     pub archival_peer_connections_lower_bound: u32,
     /// Handshake timeout.
     pub handshake_timeout: Duration,
+    /// Deadline for receiving and parsing the Handshake message from a peer.
+    /// Unlike `handshake_timeout`, it doesn't include the time spent on
+    /// accepting the connection once the Handshake has arrived.
+    #[serde(default = "default_handshake_read_timeout")]
+    pub handshake_read_timeout: Duration,
     /// Duration before trying to reconnect to a peer.
     pub reconnect_delay: Duration,
     /// Skip waiting for peers before starting node.
@@ -190,6 +244,97 @@ pub struct ExperimentalConfig {
     // compatibility.
     #[serde(default = "default_skip_tombstones")]
     pub skip_sending_tombstones_seconds: i64,
+
+    // Peers with a lower protocol version are refused during the handshake, even if
+    // they support our protocol version. Used to force peers to upgrade.
+    // Values lower than PEER_MIN_ALLOWED_PROTOCOL_VERSION have no effect.
+    #[serde(default)]
+    pub min_accepted_protocol_version: u32,
+
+    // If true, edges received in SyncRoutingTable which are not signed by both of
+    // their peers are dropped and the remaining edges of the message are still
+    // validated. Otherwise validation stops at the first invalid edge. The sender
+    // gets banned either way.
+    #[serde(default)]
+    pub drop_asymmetric_edges: bool,
+
+    // If true, entries of incremental SyncAccountsData messages which belong to
+    // epochs we don't consider active are dropped before signature verification.
+    #[serde(default)]
+    pub validate_accounts_data_epochs: bool,
+
+    // If true, TelemetryRequest messages are answered with the protocol version,
+    // the (rounded) uptime and the tracked shards of the node. Otherwise they are
+    // answered with empty telemetry.
+    #[serde(default)]
+    pub share_telemetry: bool,
+
+    // If true, the addresses of peers are logged with the host part of the IP zeroed.
+    #[serde(default)]
+    pub redact_addrs_in_logs: bool,
+
+    // Rate limit of SyncRoutingTable messages processed per peer: up to `burst`
+    // messages at once and `qps` messages per second on average. The excess
+    // messages are dropped, and the edges and accounts of the dropped incremental
+    // updates are lost until the next full sync, which happens after a reconnection.
+    #[serde(default = "default_sync_routing_table_rate_limit_qps")]
+    pub sync_routing_table_rate_limit_qps: f64,
+    #[serde(default = "default_sync_routing_table_rate_limit_burst")]
+    pub sync_routing_table_rate_limit_burst: u64,
+
+    // Transactions received from peers, whose borsh encoding is larger than that,
+    // are rejected. It shouldn't be lower than max_transaction_size of the runtime
+    // config, otherwise valid transactions are not propagated.
+    #[serde(default = "default_max_transaction_bytes")]
+    pub max_transaction_bytes: usize,
+
+    // RoutingTableUpdates received from peers, which contain more accounts than that,
+    // are rejected. Peers split their updates according to the default value, so
+    // a lower value makes us reject legitimate updates.
+    #[serde(default = "default_max_accounts_per_update")]
+    pub max_accounts_per_update: usize,
+
+    // Blocks received from peers, whose borsh encoding is larger than that, are rejected.
+    // Blocks contain just the chunk headers, so they are much smaller in practice.
+    #[serde(default = "default_max_block_bytes")]
+    pub max_block_bytes: usize,
+
+    // Block headers received from peers, whose borsh encoding is larger than that,
+    // are rejected.
+    #[serde(default = "default_max_block_header_bytes")]
+    pub max_block_header_bytes: usize,
+
+    // Routed messages received from peers, whose borsh encoded body is larger than
+    // that, are rejected. The largest bodies are state parts and chunk parts.
+    #[serde(default = "default_max_routed_body_bytes")]
+    pub max_routed_body_bytes: usize,
+
+    // Challenges received from peers, whose borsh encoding is larger than that,
+    // are rejected. The largest are ChunkState challenges, bounded by the chunk limits.
+    #[serde(default = "default_max_challenge_bytes")]
+    pub max_challenge_bytes: usize,
+
+    // Epoch sync responses received from peers, whose borsh encoding is larger than
+    // that, are rejected. Their size grows linearly with the number of validators.
+    #[serde(default = "default_max_epoch_sync_response_bytes")]
+    pub max_epoch_sync_response_bytes: usize,
+
+    // Maximum number of StateRequestPart messages from a single peer which are
+    // processed concurrently. Computing a state part is expensive, so the further
+    // requests are answered with StateRequestPartBusy.
+    #[serde(default = "default_max_concurrent_state_part_requests")]
+    pub max_concurrent_state_part_requests: usize,
+
+    // Identical routed messages (with the same hash) received again within this
+    // window, possibly from a different peer, are dropped. Zero disables it.
+    #[serde(default = "default_routed_message_dedup_window")]
+    pub routed_message_dedup_window: Duration,
+
+    // StateRequestPart for a part which has been requested less than this long ago,
+    // and hasn't been answered yet, is not sent again. Should be shorter than the
+    // state sync timeout of the client, so that its retries are not suppressed.
+    #[serde(default = "default_state_part_request_timeout")]
+    pub state_part_request_timeout: Duration,
 }
 
 impl Default for ExperimentalConfig {
@@ -198,6 +343,23 @@ impl Default for ExperimentalConfig {
             inbound_disabled: false,
             connect_only_to_boot_nodes: false,
             skip_sending_tombstones_seconds: default_skip_tombstones(),
+            min_accepted_protocol_version: 0,
+            drop_asymmetric_edges: false,
+            validate_accounts_data_epochs: false,
+            share_telemetry: false,
+            redact_addrs_in_logs: false,
+            sync_routing_table_rate_limit_qps: default_sync_routing_table_rate_limit_qps(),
+            sync_routing_table_rate_limit_burst: default_sync_routing_table_rate_limit_burst(),
+            max_transaction_bytes: default_max_transaction_bytes(),
+            max_accounts_per_update: default_max_accounts_per_update(),
+            max_block_bytes: default_max_block_bytes(),
+            max_block_header_bytes: default_max_block_header_bytes(),
+            max_routed_body_bytes: default_max_routed_body_bytes(),
+            max_challenge_bytes: default_max_challenge_bytes(),
+            max_epoch_sync_response_bytes: default_max_epoch_sync_response_bytes(),
+            max_concurrent_state_part_requests: default_max_concurrent_state_part_requests(),
+            routed_message_dedup_window: default_routed_message_dedup_window(),
+            state_part_request_timeout: default_state_part_request_timeout(),
         }
     }
 }
@@ -216,6 +378,7 @@ impl Default for Config {
             safe_set_size: default_safe_set_size(),
             archival_peer_connections_lower_bound: default_archival_peer_connections_lower_bound(),
             handshake_timeout: Duration::from_secs(20),
+            handshake_read_timeout: default_handshake_read_timeout(),
             reconnect_delay: Duration::from_secs(60),
             skip_sync_wait: false,
             ban_window: Duration::from_secs(3 * 60 * 60),
@@ -0,0 +1,123 @@
+//! Serialized size checks and round-trip benchmarks of the heaviest PeerMessage variants.
+//!
+//! Every test serializes a representative message in both encodings and checks that
+//! the serialized size stays within `SIZE_TOLERANCE` of the recorded baseline.
+//! The baselines are meant to be updated by hand, whenever a change of the size is intended.
+//!
+//! The benchmarks measure serialization followed by deserialization. They are ignored by
+//! default, run them with `cargo test --release -p near-network bench_ -- --ignored --nocapture`.
+use crate::network_protocol::testonly as data;
+use crate::network_protocol::{Encoding, PeerMessage, RoutedMessageBody};
+use crate::testonly::make_rng;
+use crate::time;
+use crate::types::PartialEncodedChunkResponseMsg;
+use std::time::Instant;
+
+/// Serialized size of a message in bytes, per encoding.
+struct Baseline {
+    borsh: usize,
+    proto: usize,
+}
+
+const BLOCK: Baseline = Baseline { borsh: 2017, proto: 2025 };
+/// 10 headers.
+const BLOCK_HEADERS: Baseline = Baseline { borsh: 6035, proto: 6093 };
+/// 15 parts of an empty chunk, routed.
+const PARTIAL_ENCODED_CHUNK_RESPONSE: Baseline = Baseline { borsh: 2377, proto: 2383 };
+
+/// Allowed relative deviation of the serialized size from the baseline.
+const SIZE_TOLERANCE: f64 = 0.05;
+const ITERATIONS: u32 = 200;
+
+fn check_size(name: &str, msg: &PeerMessage, baseline: &Baseline) {
+    let cfg = data::make_decode_config();
+    for (enc, want_size) in [(Encoding::Borsh, baseline.borsh), (Encoding::Proto, baseline.proto)] {
+        let buf = msg.serialize(enc);
+        assert_eq!(msg, &PeerMessage::deserialize(enc, &buf, &cfg).unwrap());
+        let deviation = (buf.len() as f64 - want_size as f64).abs() / want_size as f64;
+        assert!(
+            deviation <= SIZE_TOLERANCE,
+            "{name}/{enc:?}: serialized size {} differs from the baseline {want_size} by more than {}%",
+            buf.len(),
+            SIZE_TOLERANCE * 100.,
+        );
+    }
+}
+
+fn bench_round_trip(name: &str, msg: &PeerMessage) {
+    let cfg = data::make_decode_config();
+    for enc in [Encoding::Borsh, Encoding::Proto] {
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            let buf = criterion::black_box(msg.serialize(enc));
+            criterion::black_box(PeerMessage::deserialize(enc, &buf, &cfg).unwrap());
+        }
+        println!("{name}/{enc:?}: {:?} per round trip", start.elapsed() / ITERATIONS);
+    }
+}
+
+fn make_chain() -> data::Chain {
+    let mut rng = make_rng(3874502934);
+    let mut clock = time::FakeClock::default();
+    data::Chain::make(&mut clock, &mut rng, 10)
+}
+
+fn make_block() -> PeerMessage {
+    PeerMessage::Block(make_chain().blocks[5].clone())
+}
+
+fn make_block_headers() -> PeerMessage {
+    PeerMessage::BlockHeaders(make_chain().get_block_headers())
+}
+
+fn make_partial_encoded_chunk_response() -> PeerMessage {
+    let mut rng = make_rng(921853233);
+    let chain = make_chain();
+    let chunk_hash = chain.blocks[3].chunks()[0].chunk_hash();
+    let msg = data::make_routed_message(
+        &mut rng,
+        RoutedMessageBody::PartialEncodedChunkResponse(PartialEncodedChunkResponseMsg {
+            chunk_hash: chunk_hash.clone(),
+            parts: data::make_chunk_parts(chain.chunks[&chunk_hash].clone()),
+            receipts: vec![],
+        }),
+    );
+    PeerMessage::Routed(Box::new(msg))
+}
+
+#[test]
+fn block_size() {
+    check_size("Block", &make_block(), &BLOCK);
+}
+
+#[test]
+fn block_headers_size() {
+    check_size("BlockHeaders", &make_block_headers(), &BLOCK_HEADERS);
+}
+
+#[test]
+fn partial_encoded_chunk_response_size() {
+    check_size(
+        "PartialEncodedChunkResponse",
+        &make_partial_encoded_chunk_response(),
+        &PARTIAL_ENCODED_CHUNK_RESPONSE,
+    );
+}
+
+#[test]
+#[ignore]
+fn bench_block() {
+    bench_round_trip("Block", &make_block());
+}
+
+#[test]
+#[ignore]
+fn bench_block_headers() {
+    bench_round_trip("BlockHeaders", &make_block_headers());
+}
+
+#[test]
+#[ignore]
+fn bench_partial_encoded_chunk_response() {
+    bench_round_trip("PartialEncodedChunkResponse", &make_partial_encoded_chunk_response());
+}
@@ -106,7 +106,16 @@ impl std::error::Error for HandshakeFailureReason {}
 /// Warning, position of each message type in this enum defines the protocol due to serialization.
 /// DO NOT MOVE, REORDER, DELETE items from the list. Only add new items to the end.
 /// If need to remove old items - replace with `None`.
-#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug, strum::AsRefStr)]
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    PartialEq,
+    Eq,
+    Clone,
+    Debug,
+    strum::AsRefStr,
+    strum::EnumVariantNames,
+)]
 // TODO(#1313): Use Box
 #[allow(clippy::large_enum_variant)]
 pub(super) enum PeerMessage {
@@ -144,3 +153,10 @@ pub(super) enum PeerMessage {
 }
 #[cfg(target_arch = "x86_64")] // Non-x86_64 doesn't match this requirement yet but it's not bad as it's not production-ready
 const _: () = assert!(std::mem::size_of::<PeerMessage>() <= 1144, "PeerMessage > 1144 bytes");
+
+impl PeerMessage {
+    /// Borsh tag (variant index) of `PeerMessage::Block`.
+    pub(super) const BLOCK_TAG: u8 = 11;
+    /// Borsh tag (variant index) of `PeerMessage::Challenge`.
+    pub(super) const CHALLENGE_TAG: u8 = 15;
+}
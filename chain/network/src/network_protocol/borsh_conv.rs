@@ -121,9 +121,11 @@ impl TryFrom<&net::PeerMessage> for mem::PeerMessage {
             net::PeerMessage::BlockRequest(bh) => mem::PeerMessage::BlockRequest(bh),
             net::PeerMessage::Block(b) => mem::PeerMessage::Block(b),
             net::PeerMessage::Transaction(t) => mem::PeerMessage::Transaction(t),
-            net::PeerMessage::Routed(r) => {
-                mem::PeerMessage::Routed(Box::new(RoutedMessageV2 { msg: *r, created_at: None }))
-            }
+            net::PeerMessage::Routed(r) => mem::PeerMessage::Routed(Box::new(RoutedMessageV2 {
+                msg: *r,
+                created_at: None,
+                request_id: None,
+            })),
             net::PeerMessage::Disconnect => mem::PeerMessage::Disconnect,
             net::PeerMessage::Challenge(c) => mem::PeerMessage::Challenge(c),
             net::PeerMessage::_HandshakeV2 => return Err(Self::Error::DeprecatedHandshakeV2),
@@ -158,8 +160,11 @@ impl From<&mem::PeerMessage> for net::PeerMessage {
             mem::PeerMessage::RequestUpdateNonce(e) => net::PeerMessage::RequestUpdateNonce(e),
             mem::PeerMessage::ResponseUpdateNonce(e) => net::PeerMessage::ResponseUpdateNonce(e),
 
-            // This message is not supported, we translate it to an empty RoutingTableUpdate.
-            mem::PeerMessage::SyncAccountsData(_) => {
+            // These messages are not supported, we translate them to an empty RoutingTableUpdate.
+            mem::PeerMessage::SyncAccountsData(_)
+            | mem::PeerMessage::KeepAliveConfig { .. }
+            | mem::PeerMessage::RoutingTableSubsetRequest { .. }
+            | mem::PeerMessage::SupportedEncodings(_) => {
                 net::PeerMessage::SyncRoutingTable(net::RoutingTableUpdate::default())
             }
 
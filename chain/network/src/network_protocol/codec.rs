@@ -0,0 +1,91 @@
+//! Wire codecs of PeerMessage.
+use crate::network_protocol::{
+    borsh_, proto, DecodeConfig, Encoding, ParsePeerMessageError, PeerMessage,
+};
+use borsh::{BorshDeserialize as _, BorshSerialize as _};
+use protobuf::Message as _;
+
+/// Serialization of PeerMessage into one of the wire formats.
+/// `PeerMessage::serialize` and `PeerMessage::deserialize` use the codec selected
+/// by the `Encoding` (see `Encoding::codec`). Other codecs can be plugged in through
+/// `PeerMessage::serialize_with` and `PeerMessage::deserialize_with`, for example to
+/// experiment with new wire formats. Connections use only the codecs of `Encoding`,
+/// since that is what peers negotiate, so using a new wire format on the network
+/// requires a new `Encoding` variant, but not changes of PeerMessage.
+pub trait PeerMessageCodec: Send + Sync {
+    fn encode(&self, msg: &PeerMessage, writer: &mut dyn std::io::Write) -> std::io::Result<()>;
+    /// Appends the encoding of `msg` to `buf`. Codecs may override it with an implementation
+    /// faster than going through the generic writer.
+    fn encode_to_vec(&self, msg: &PeerMessage, buf: &mut Vec<u8>) {
+        self.encode(msg, buf).expect("writing to a Vec cannot fail")
+    }
+    fn decode(&self, data: &[u8], cfg: &DecodeConfig)
+        -> Result<PeerMessage, ParsePeerMessageError>;
+}
+
+struct BorshCodec;
+
+impl PeerMessageCodec for BorshCodec {
+    fn encode(
+        &self,
+        msg: &PeerMessage,
+        mut writer: &mut dyn std::io::Write,
+    ) -> std::io::Result<()> {
+        borsh_::PeerMessage::from(msg).serialize(&mut writer)
+    }
+
+    fn decode(
+        &self,
+        data: &[u8],
+        _cfg: &DecodeConfig,
+    ) -> Result<PeerMessage, ParsePeerMessageError> {
+        (&borsh_::PeerMessage::try_from_slice(data).map_err(ParsePeerMessageError::BorshDecode)?)
+            .try_into()
+            .map_err(ParsePeerMessageError::BorshConv)
+    }
+}
+
+struct ProtoCodec;
+
+impl PeerMessageCodec for ProtoCodec {
+    fn encode(&self, msg: &PeerMessage, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        Ok(proto::PeerMessage::from(msg).write_to_writer(writer)?)
+    }
+
+    fn encode_to_vec(&self, msg: &PeerMessage, buf: &mut Vec<u8>) {
+        // Unlike `write_to_writer`, it computes the size upfront and writes without
+        // an intermediate buffer.
+        proto::PeerMessage::from(msg).write_to_vec(buf).unwrap()
+    }
+
+    fn decode(
+        &self,
+        data: &[u8],
+        cfg: &DecodeConfig,
+    ) -> Result<PeerMessage, ParsePeerMessageError> {
+        let msg = proto::PeerMessage::parse_from_bytes(data)
+            .map_err(ParsePeerMessageError::ProtoDecode)?;
+        // Looking for unknown fields requires a walk over the whole message,
+        // so do it only if the result is going to be logged.
+        if !tracing::enabled!(target: "network", tracing::Level::DEBUG) {
+            return PeerMessage::from_proto(&msg, cfg).map_err(ParsePeerMessageError::ProtoConv);
+        }
+        let (msg, saw_unknown_fields) =
+            PeerMessage::try_from_proto_reporting_unknown_fields(&msg, cfg)
+                .map_err(ParsePeerMessageError::ProtoConv)?;
+        if saw_unknown_fields {
+            tracing::debug!(target: "network", "received {} with unknown fields, peer probably runs a newer protocol version", msg.msg_variant());
+        }
+        Ok(msg)
+    }
+}
+
+impl Encoding {
+    /// Codec implementing the encoding.
+    pub(crate) fn codec(self) -> &'static dyn PeerMessageCodec {
+        match self {
+            Encoding::Borsh => &BorshCodec,
+            Encoding::Proto => &ProtoCodec,
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Diagnostics of PeerMessages which failed to decode.
+use crate::network_protocol::routing_header::proto_variant;
+use crate::network_protocol::{borsh_, Encoding, ParsePeerMessageError};
+use borsh::BorshDeserialize as _;
+use protobuf::CodedInputStream;
+use std::fmt;
+
+/// Describes where decoding of a serialized PeerMessage has failed.
+/// It is meant to be logged instead of the payload itself, which might be large
+/// and might contain data which shouldn't end up in the logs: the report
+/// contains only offsets, lengths, names and error descriptions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DecodeFailureReport {
+    pub encoding: Encoding,
+    /// Length of the payload.
+    pub len: usize,
+    /// PeerMessage variant which the payload claims to contain, if it got that far.
+    pub variant: Option<&'static str>,
+    /// Offset of the top-level field (proto) or value (borsh) which failed to decode.
+    /// None if the framing of the message is correct and the failure happened later,
+    /// e.g. when converting the decoded message to a PeerMessage.
+    pub offset: Option<usize>,
+    /// Set iff the payload is truncated: the number of bytes missing to complete
+    /// the field at `offset`.
+    pub missing_bytes: Option<usize>,
+    /// Error at `offset` if the framing is broken, the decoding error otherwise.
+    pub error: String,
+}
+
+impl fmt::Display for DecodeFailureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} message of {} bytes", self.encoding, self.len)?;
+        if let Some(variant) = self.variant {
+            write!(f, ", variant {variant}")?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, ", failed at byte {offset}")?;
+        }
+        if let Some(missing_bytes) = self.missing_bytes {
+            write!(f, ", truncated by {missing_bytes} bytes")?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+
+/// Partially parses `data` (which failed to decode as a PeerMessage in the `enc`
+/// encoding with `err`) to find out how far the decoding got before failing.
+pub(crate) fn debug_decode_failure(
+    enc: Encoding,
+    data: &[u8],
+    err: ParsePeerMessageError,
+) -> DecodeFailureReport {
+    let mut report = DecodeFailureReport {
+        encoding: enc,
+        len: data.len(),
+        variant: None,
+        offset: None,
+        missing_bytes: None,
+        error: String::new(),
+    };
+    match enc {
+        Encoding::Borsh => debug_borsh(data, &mut report),
+        Encoding::Proto => debug_proto(data, &mut report),
+    }
+    if report.offset.is_none() {
+        report.error = format!("{:#}", anyhow::Error::new(err));
+    }
+    report
+}
+
+fn debug_borsh(data: &[u8], report: &mut DecodeFailureReport) {
+    report.variant = data.first().and_then(|tag| {
+        <borsh_::PeerMessage as strum::VariantNames>::VARIANTS.get(*tag as usize).copied()
+    });
+    let mut buf = data;
+    let result = borsh_::PeerMessage::deserialize(&mut buf);
+    let pos = data.len() - buf.len();
+    match result {
+        Err(err) => {
+            report.offset = Some(pos);
+            report.error = err.to_string();
+        }
+        Ok(_) if !buf.is_empty() => {
+            report.offset = Some(pos);
+            report.error = format!("{} trailing bytes", buf.len());
+        }
+        Ok(_) => {}
+    }
+}
+
+fn debug_proto(data: &[u8], report: &mut DecodeFailureReport) {
+    let mut is = CodedInputStream::from_bytes(data);
+    loop {
+        let start = is.pos() as usize;
+        let fail = |report: &mut DecodeFailureReport, err: protobuf::Error| {
+            report.offset = Some(start);
+            report.error = err.to_string();
+        };
+        let tag = match is.read_raw_tag_or_eof() {
+            Ok(Some(tag)) => tag,
+            Ok(None) => return,
+            Err(err) => return fail(report, err),
+        };
+        // As in the regular protobuf decoding, the last field of the oneof wins.
+        if let Some(variant) = proto_variant(tag >> 3) {
+            report.variant = Some(variant);
+        }
+        if tag & 7 != 2 {
+            if let Err(err) = protobuf::rt::skip_field_for_tag(tag, &mut is) {
+                return fail(report, err);
+            }
+            continue;
+        }
+        // Length-delimited field: check the declared length against the payload
+        // length, to tell how many bytes are missing.
+        let len = match is.read_raw_varint64() {
+            Ok(len) => len,
+            Err(err) => return fail(report, err),
+        };
+        let end = (is.pos() as usize).saturating_add(len as usize);
+        if end > data.len() {
+            report.offset = Some(start);
+            report.missing_bytes = Some(end - data.len());
+            report.error = format!("field {} truncated", tag >> 3);
+            return;
+        }
+        if let Err(err) = is.skip_raw_bytes(len as u32) {
+            return fail(report, err);
+        }
+    }
+}
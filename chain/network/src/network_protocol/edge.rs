@@ -14,17 +14,109 @@ use crate::time;
 pub const EDGE_MIN_TIMESTAMP_NONCE: Lazy<time::Utc> =
     Lazy::new(|| time::Utc::from_unix_timestamp(1660000000).unwrap());
 
+/// Version of an edge. Odd nonces are versions in which the edge is `Active`,
+/// even nonces are versions in which it is `Removed`.
+///
+/// There are two kinds of nonces:
+/// - old style nonces, which are counters starting at 1,
+/// - new style nonces, which are unix timestamps (in seconds) of the moment the edge was
+///   created, rounded up to an odd number. They are above `EDGE_MIN_TIMESTAMP_NONCE`,
+///   so they are newer than any old style nonce, and the newer timestamp is the newer version.
+/// In both cases removing an edge increments its nonce, so the removal is newer than
+/// the addition it removes, but older than the next addition.
+///
+/// Therefore nonces of the same edge are ordered by their numeric value. All comparisons
+/// of versions of an edge should go through `Nonce`, rather than through raw integers.
+#[cfg_attr(feature = "deepsize_feature", derive(deepsize::DeepSizeOf))]
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+#[cfg_attr(feature = "test_features", derive(serde::Serialize, serde::Deserialize))]
+pub struct Nonce(pub u64);
+
+impl Nonce {
+    /// Lowest active nonce, with which a new edge starts.
+    pub const FIRST: Nonce = Nonce(1);
+
+    /// New style nonce of an edge created at `t`.
+    pub fn from_utc(t: time::Utc) -> Self {
+        let n = t.unix_timestamp() as u64;
+        Nonce(if n % 2 == 1 { n } else { n + 1 })
+    }
+
+    /// State of the edge at this version.
+    pub fn edge_state(self) -> EdgeState {
+        if self.0 % 2 == 1 {
+            EdgeState::Active
+        } else {
+            EdgeState::Removed
+        }
+    }
+
+    /// Lowest nonce higher than this one, which makes the edge `Active`.
+    pub fn next_active(self) -> Self {
+        match self.edge_state() {
+            EdgeState::Active => Nonce(self.0 + 2),
+            EdgeState::Removed => Nonce(self.0 + 1),
+        }
+    }
+
+    /// Nonce of the removal of this version of the edge.
+    fn removal(self) -> Self {
+        Nonce(self.0 + 1)
+    }
+
+    /// Nonce of the version removed by this one.
+    fn removed(self) -> Self {
+        Nonce(self.0 - 1)
+    }
+
+    /// Time of creation of the edge, for new style nonces.
+    /// Returns None for old style nonces.
+    pub fn to_utc(self) -> Result<Option<time::Utc>, InvalidNonceError> {
+        let nonce = self.0;
+        let nonce_as_i64 =
+            i64::try_from(nonce).map_err(|_| InvalidNonceError::NonceOutOfBoundsError { nonce })?;
+        let nonce_ts = time::Utc::from_unix_timestamp(nonce_as_i64)
+            .map_err(|_| InvalidNonceError::NonceOutOfBoundsError { nonce })?;
+        Ok(if nonce_ts > *EDGE_MIN_TIMESTAMP_NONCE { Some(nonce_ts) } else { None })
+    }
+
+    /// Whether the edge was created before `t`.
+    /// Old style nonces are never considered older than anything.
+    pub fn is_older_than(self, t: time::Utc) -> bool {
+        // Old-style nonce - for now, assume that they are always fresh.
+        matches!(self.to_utc(), Ok(Some(nonce_ts)) if nonce_ts < t)
+    }
+}
+
+impl std::fmt::Display for Nonce {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 /// Information that will be ultimately used to create a new edge.
 /// It contains nonce proposed for the edge with signature from peer.
 #[cfg_attr(feature = "deepsize_feature", derive(deepsize::DeepSizeOf))]
 #[derive(Clone, BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug, Default)]
 pub struct PartialEdgeInfo {
-    pub nonce: u64,
+    pub nonce: Nonce,
     pub signature: Signature,
 }
 
 impl PartialEdgeInfo {
-    pub fn new(peer0: &PeerId, peer1: &PeerId, nonce: u64, secret_key: &SecretKey) -> Self {
+    pub fn new(peer0: &PeerId, peer1: &PeerId, nonce: Nonce, secret_key: &SecretKey) -> Self {
         let data = Edge::build_hash(peer0, peer1, nonce);
         let signature = secret_key.sign(data.as_ref());
         Self { nonce, signature }
@@ -47,7 +139,7 @@ impl Edge {
     pub fn new(
         peer0: PeerId,
         peer1: PeerId,
-        nonce: u64,
+        nonce: Nonce,
         signature0: Signature,
         signature1: Signature,
     ) -> Self {
@@ -63,7 +155,7 @@ impl Edge {
         &self.0.key
     }
 
-    pub fn nonce(&self) -> u64 {
+    pub fn nonce(&self) -> Nonce {
         self.0.nonce
     }
 
@@ -79,7 +171,7 @@ impl Edge {
         self.0.removal_info.as_ref()
     }
 
-    pub fn make_fake_edge(peer0: PeerId, peer1: PeerId, nonce: u64) -> Self {
+    pub fn make_fake_edge(peer0: PeerId, peer1: PeerId, nonce: Nonce) -> Self {
         Self(Arc::new(EdgeInner {
             key: (peer0, peer1),
             nonce,
@@ -93,7 +185,7 @@ impl Edge {
     pub fn build_with_secret_key(
         peer0: PeerId,
         peer1: PeerId,
-        nonce: u64,
+        nonce: Nonce,
         secret_key: &SecretKey,
         signature1: Signature,
     ) -> Self {
@@ -108,7 +200,7 @@ impl Edge {
 
     /// Build the hash of the edge given its content.
     /// It is important that peer0 < peer1 at this point.
-    pub fn build_hash(peer0: &PeerId, peer1: &PeerId, nonce: u64) -> CryptoHash {
+    pub fn build_hash(peer0: &PeerId, peer1: &PeerId, nonce: Nonce) -> CryptoHash {
         let (peer0, peer1) = if peer0 < peer1 { (peer0, peer1) } else { (peer1, peer0) };
         CryptoHash::hash_borsh(&(peer0, peer1, nonce))
     }
@@ -129,20 +221,11 @@ impl Edge {
         edge_info.signature.verify(data.as_ref(), pk)
     }
 
-    /// Next nonce of valid addition edge.
-    pub fn next_nonce(nonce: u64) -> u64 {
-        if nonce % 2 == 1 {
-            nonce + 2
-        } else {
-            nonce + 1
-        }
-    }
-
     /// Create the remove edge change from an added edge change.
     pub fn remove_edge(&self, my_peer_id: PeerId, sk: &SecretKey) -> Edge {
         assert_eq!(self.edge_type(), EdgeState::Active);
         let mut edge = self.0.as_ref().clone();
-        edge.nonce += 1;
+        edge.nonce = edge.nonce.removal();
         let me = edge.key.0 == my_peer_id;
         let hash = edge.hash();
         let signature = sk.sign(hash.as_ref());
@@ -155,7 +238,7 @@ impl Edge {
     }
 
     fn prev_hash(&self) -> CryptoHash {
-        Edge::build_hash(&self.key().0, &self.key().1, self.nonce() - 1)
+        Edge::build_hash(&self.key().0, &self.key().1, self.nonce().removed())
     }
 
     pub fn verify(&self) -> bool {
@@ -173,7 +256,7 @@ impl Edge {
             }
             EdgeState::Removed => {
                 // nonce should be an even positive number
-                if self.nonce() == 0 {
+                if self.nonce() == Nonce(0) {
                     return false;
                 }
 
@@ -196,24 +279,39 @@ impl Edge {
         }
     }
 
+    /// Whether both peers have signed the addition of this edge. For a removed edge
+    /// these are the signatures of the edge it removes. Unlike `verify`, the removal
+    /// signature is not checked.
+    pub fn is_mutually_signed(&self) -> bool {
+        let hash = match self.edge_type() {
+            EdgeState::Active => self.hash(),
+            EdgeState::Removed if self.nonce() == Nonce(0) => return false,
+            EdgeState::Removed => self.prev_hash(),
+        };
+        self.signature0().verify(hash.as_ref(), self.key().0.public_key())
+            && self.signature1().verify(hash.as_ref(), self.key().1.public_key())
+    }
+
     /// It will be considered as a new edge if the nonce is odd, otherwise it is canceling the
     /// previous edge.
     pub fn edge_type(&self) -> EdgeState {
-        if self.nonce() % 2 == 1 {
-            EdgeState::Active
-        } else {
-            EdgeState::Removed
-        }
+        self.nonce().edge_state()
     }
     /// Next nonce of valid addition edge.
-    pub fn next(&self) -> u64 {
-        Edge::next_nonce(self.nonce())
+    pub fn next(&self) -> Nonce {
+        self.nonce().next_active()
     }
 
     pub fn contains_peer(&self, peer_id: &PeerId) -> bool {
         self.key().0 == *peer_id || self.key().1 == *peer_id
     }
 
+    /// Whether this edge is a newer version of `other`, i.e. both edges connect the same
+    /// pair of peers and this one has a higher nonce.
+    pub fn supersedes(&self, other: &Edge) -> bool {
+        self.key() == other.key() && self.nonce() > other.nonce()
+    }
+
     /// Find a peer id in this edge different from `me`.
     pub fn other(&self, me: &PeerId) -> Option<&PeerId> {
         if self.key().0 == *me {
@@ -227,28 +325,7 @@ impl Edge {
 
     // Checks if edge was created before a given timestamp.
     pub fn is_edge_older_than(&self, utc_timestamp: time::Utc) -> bool {
-        Edge::nonce_to_utc(self.nonce()).map_or(false, |maybe_timestamp| {
-            // Old-style nonce - for now, assume that they are always fresh.
-            maybe_timestamp.map_or(false, |nonce_timestamp| nonce_timestamp < utc_timestamp)
-        })
-    }
-
-    pub fn nonce_to_utc(nonce: u64) -> Result<Option<time::Utc>, InvalidNonceError> {
-        if let Ok(nonce_as_i64) = i64::try_from(nonce) {
-            time::Utc::from_unix_timestamp(nonce_as_i64)
-                .map(
-                    |nonce_ts| {
-                        if nonce_ts > *EDGE_MIN_TIMESTAMP_NONCE {
-                            Some(nonce_ts)
-                        } else {
-                            None
-                        }
-                    },
-                )
-                .map_err(|_| InvalidNonceError::NonceOutOfBoundsError { nonce })
-        } else {
-            Err(InvalidNonceError::NonceOutOfBoundsError { nonce })
-        }
+        self.nonce().is_older_than(utc_timestamp)
     }
 }
 
@@ -269,7 +346,7 @@ pub struct EdgeInner {
     /// New edge starts with value of `1`.
     /// We update the edge only if it's `nonce` is higher. All versions of `Edge` with lower
     /// `nonce` will be ignored.
-    nonce: u64,
+    nonce: Nonce,
     /// Each `edge` consists of two signatures, one for each `peer`.
     /// It's generated by signing triple (key.0, key.1, nonce) by each `peer` private key.
     /// `Signature` is generated at the time when edge is added, that is when `nonce` is `odd`.
@@ -293,7 +370,7 @@ impl EdgeInner {
     fn new(
         peer0: PeerId,
         peer1: PeerId,
-        nonce: u64,
+        nonce: Nonce,
         signature0: Signature,
         signature1: Signature,
     ) -> Self {
@@ -2,12 +2,24 @@
 #[path = "borsh.rs"]
 mod borsh_;
 mod borsh_conv;
+mod codec;
+mod debug;
 mod edge;
 mod peer;
+mod peer_ring;
 mod proto_conv;
+mod routing_header;
+mod transcode;
+pub use codec::PeerMessageCodec;
+pub(crate) use debug::debug_decode_failure;
 pub use edge::*;
 pub use peer::*;
+pub use peer_ring::PeerRing;
+pub use routing_header::{peek_routing_header, RoutedHeader, RoutingHeader};
+pub use transcode::{transcode, TranscodeError};
 
+#[cfg(test)]
+mod bench_tests;
 #[cfg(test)]
 pub(crate) mod testonly;
 #[cfg(test)]
@@ -20,14 +32,15 @@ mod _proto {
 pub use _proto::network as proto;
 
 use crate::time;
-use borsh::{BorshDeserialize as _, BorshSerialize as _};
+use borsh::BorshSerialize as _;
 use near_crypto::PublicKey;
 use near_crypto::Signature;
 use near_primitives::block::{Approval, Block, BlockHeader, GenesisId};
 use near_primitives::challenge::Challenge;
 use near_primitives::hash::CryptoHash;
-use near_primitives::merkle::combine_hash;
+use near_primitives::merkle::{combine_hash, verify_path, MerklePath};
 use near_primitives::network::{AnnounceAccount, PeerId};
+use near_primitives::shard_layout::ShardLayout;
 use near_primitives::sharding::{
     ChunkHash, PartialEncodedChunk, PartialEncodedChunkPart, ReceiptProof, ShardChunkHeader,
 };
@@ -35,11 +48,12 @@ use near_primitives::syncing::{EpochSyncFinalizationResponse, EpochSyncResponse}
 use near_primitives::syncing::{ShardStateSyncResponse, ShardStateSyncResponseV1};
 use near_primitives::transaction::SignedTransaction;
 use near_primitives::types::{AccountId, EpochId};
-use near_primitives::types::{BlockHeight, ShardId};
+use near_primitives::types::{BlockHeight, NumShards, ShardId};
 use near_primitives::validator_signer::ValidatorSigner;
-use near_primitives::views::FinalExecutionOutcomeView;
+use near_primitives::version::ProtocolVersion;
+use near_primitives::views::{ExecutionOutcomeWithIdView, FinalExecutionOutcomeView};
 use protobuf::Message as _;
-use std::collections::HashSet;
+use std::collections::{btree_map, BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::sync::Arc;
 
@@ -49,6 +63,30 @@ pub struct PeerAddr {
     pub peer_id: PeerId,
 }
 
+impl PeerAddr {
+    /// Renders `PeerId@SocketAddr` like `Serialize` does, but with the address
+    /// redacted by `redact_addr`.
+    pub fn display_redacted(&self) -> String {
+        format!("{}@{}", self.peer_id, redact_addr(self.addr))
+    }
+}
+
+/// Zeroes the host part of the IP: the last octet of IPv4 and the lower 64 bits of IPv6,
+/// keeping the port. Use it in logs which shouldn't reveal the exact addresses of the peers.
+pub fn redact_addr(addr: std::net::SocketAddr) -> std::net::SocketAddr {
+    let ip: std::net::IpAddr = match addr.ip() {
+        std::net::IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            std::net::Ipv4Addr::new(a, b, c, 0).into()
+        }
+        std::net::IpAddr::V6(ip) => {
+            let [a, b, c, d, ..] = ip.segments();
+            std::net::Ipv6Addr::new(a, b, c, d, 0, 0, 0, 0).into()
+        }
+    };
+    std::net::SocketAddr::new(ip, addr.port())
+}
+
 impl serde::Serialize for PeerAddr {
     fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
         s.serialize_str(&format!("{}@{}", self.peer_id, self.addr))
@@ -85,35 +123,179 @@ impl std::str::FromStr for PeerAddr {
     }
 }
 
+/// Data about a TIER1 account, broadcasted by the validator owning it.
+///
+/// The fields are private, so that the data can't be modified once it has been
+/// signed: SignedAccountData derefs to AccountData, and the signed payload has
+/// to stay in sync with it. Use `AccountData::builder()` to construct it.
+///
+/// ```compile_fail
+/// fn clear_peers(data: &mut near_network::types::AccountData) {
+///     data.peers.clear();
+/// }
+/// ```
 #[derive(PartialEq, Eq, Debug, Hash)]
 pub struct AccountData {
-    pub peers: Vec<PeerAddr>,
-    pub account_id: AccountId,
-    pub epoch_id: EpochId,
-    pub timestamp: time::Utc,
+    peers: Vec<PeerAddr>,
+    account_id: AccountId,
+    epoch_id: EpochId,
+    timestamp: time::Utc,
+    /// Version of the AccountData format, covered by the signature.
+    /// Receivers may apply version-specific validation rules.
+    /// Data which doesn't specify the version is parsed as version 0.
+    version: u32,
+}
+
+/// Builder of AccountData, see `AccountData::builder()`.
+#[derive(Debug)]
+pub struct AccountDataBuilder(AccountData);
+
+impl AccountDataBuilder {
+    /// Sets the addresses of the proxies (or the validator itself) through which
+    /// the account can be reached. Empty by default.
+    pub fn peers(mut self, peers: Vec<PeerAddr>) -> Self {
+        self.0.peers = peers;
+        self
+    }
+
+    pub fn build(self) -> AccountData {
+        self.0
+    }
 }
 
+/// Version of the AccountData format set by `AccountData::sign`.
+pub const ACCOUNT_DATA_VERSION: u32 = 1;
+
 // Limit on the size of the serialized AccountData message.
 // It is important to have such a constraint on the serialized proto,
 // because it may contain many unknown fields (which are dropped during parsing).
 pub const MAX_ACCOUNT_DATA_SIZE_BYTES: usize = 10000; // 10kB
 
+// Default limits on the size of the borsh-encoded Block and BlockHeader accepted in proto
+// messages, see `DecodeConfig::max_block_bytes` and `DecodeConfig::max_block_header_bytes`.
+// Blocks contain just the chunk headers, so even with many shards and validators
+// they are orders of magnitude smaller than that.
+pub const MAX_BLOCK_BYTES: usize = 10_000_000; // 10MB
+pub const MAX_BLOCK_HEADER_BYTES: usize = 1_000_000; // 1MB
+
+// Default limit on the size of the borsh-encoded SignedTransaction accepted in proto
+// messages, both as a Transaction message and as a ForwardTx routed message.
+// It matches max_transaction_size of the runtime config, so that no transaction
+// which could be included in a chunk is rejected. See `DecodeConfig::max_transaction_bytes`.
+pub const MAX_TRANSACTION_BYTES: usize = 4_194_304; // 4MiB
+
+// Default limit on the size of the borsh-encoded body of a RoutedMessage accepted in proto
+// messages, see `DecodeConfig::max_routed_body_bytes`.
+// The largest bodies are state parts and chunk parts with receipts, which are much smaller.
+// Without the limit a body with a crafted length prefix (for example the list of parts in
+// a PartialEncodedChunkResponse) could make us allocate way more memory than the message has.
+pub const MAX_ROUTED_BODY_BYTES: usize = 134_217_728; // 128MiB
+
+// Default limit on the size of the borsh-encoded Challenge accepted in proto messages,
+// see `DecodeConfig::max_challenge_bytes`. The largest challenges are ChunkState challenges,
+// which carry the previous chunk and the partial state needed to re-execute it; both are
+// bounded by the chunk limits.
+pub const MAX_CHALLENGE_BYTES: usize = 33_554_432; // 32MiB
+
+// Default limit on the size of the borsh-encoded EpochSyncResponse accepted in proto
+// messages, see `DecodeConfig::max_epoch_sync_response_bytes`. The response carries a light
+// client block, whose size is dominated by the next block producers and their approvals,
+// i.e. it grows linearly with the number of validators.
+pub const MAX_EPOCH_SYNC_RESPONSE_BYTES: usize = 10_000_000; // 10MB
+
+// Default limit on the number of AnnounceAccounts in a single RoutingTableUpdate accepted in
+// proto messages, see `DecodeConfig::max_accounts_per_update`. Every AnnounceAccount has to be
+// verified, so an unbounded list is an easy way to make us waste CPU. It is way above the number
+// of validators, so the full set of accounts is sent in a single message in practice.
+// The limit configured by the receiver is not known to the sender, so the sender always splits
+// the updates according to this default, see `RoutingTableUpdate::into_chunks`.
+pub const MAX_ACCOUNTS_PER_UPDATE: usize = 1000;
+
+/// Parameters of decoding the PeerMessages received from the network.
+#[derive(Clone)]
+pub struct DecodeConfig {
+    /// Clock used to bound the time spent decoding the large messages,
+    /// see `proto_conv::decode_epoch_sync_response`.
+    pub clock: time::Clock,
+    /// Limit on the size of the borsh-encoded transactions,
+    /// see `NetworkConfig::max_transaction_bytes`.
+    pub max_transaction_bytes: usize,
+    /// Limit on the number of accounts in a RoutingTableUpdate,
+    /// see `NetworkConfig::max_accounts_per_update`.
+    pub max_accounts_per_update: usize,
+    /// Limit on the size of the borsh-encoded blocks,
+    /// see `NetworkConfig::max_block_bytes`.
+    pub max_block_bytes: usize,
+    /// Limit on the size of the borsh-encoded block headers,
+    /// see `NetworkConfig::max_block_header_bytes`.
+    pub max_block_header_bytes: usize,
+    /// Limit on the size of the borsh-encoded bodies of routed messages,
+    /// see `NetworkConfig::max_routed_body_bytes`.
+    pub max_routed_body_bytes: usize,
+    /// Limit on the size of the borsh-encoded challenges,
+    /// see `NetworkConfig::max_challenge_bytes`.
+    pub max_challenge_bytes: usize,
+    /// Limit on the size of the borsh-encoded epoch sync responses,
+    /// see `NetworkConfig::max_epoch_sync_response_bytes`.
+    pub max_epoch_sync_response_bytes: usize,
+}
+
 impl AccountData {
+    /// Starts building AccountData of `account_id` for `epoch_id`, created at `timestamp`.
+    /// The data is built with the ACCOUNT_DATA_VERSION version.
+    pub fn builder(
+        account_id: AccountId,
+        epoch_id: EpochId,
+        timestamp: time::Utc,
+    ) -> AccountDataBuilder {
+        AccountDataBuilder(AccountData {
+            peers: vec![],
+            account_id,
+            epoch_id,
+            timestamp,
+            version: ACCOUNT_DATA_VERSION,
+        })
+    }
+
+    pub fn peers(&self) -> &[PeerAddr] {
+        &self.peers
+    }
+    pub fn account_id(&self) -> &AccountId {
+        &self.account_id
+    }
+    pub fn epoch_id(&self) -> &EpochId {
+        &self.epoch_id
+    }
+    pub fn timestamp(&self) -> time::Utc {
+        self.timestamp
+    }
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
     /// Serializes AccountData to proto and signs it using `signer`.
+    /// Equivalent to `to_payload()`, signing the payload and then `attach_signature()`.
     /// Panics if AccountData.account_id doesn't match signer.validator_id(),
     /// as this would likely be a bug.
     /// Returns an error if the serialized data is too large to be broadcasted.
-    /// TODO(gprusak): consider separating serialization from signing (so introducing an
-    /// intermediate SerializedAccountData type) so that sign() then could fail only
-    /// due to account_id mismatch. Then instead of panicking we could return an error
-    /// and the caller (who constructs the arguments) would do an unwrap(). This would
-    /// consistute a cleaner never-panicking interface.
     pub fn sign(self, signer: &dyn ValidatorSigner) -> anyhow::Result<SignedAccountData> {
         assert_eq!(
             &self.account_id,
             signer.validator_id(),
             "AccountData.account_id doesn't match the signer's account_id"
         );
+        let (data, payload) = self.to_payload()?;
+        let signature = signer.sign_account_key_payload(&payload);
+        Ok(data.attach_signature(signature))
+    }
+
+    /// Serializes AccountData to proto. Returns the serialized data together with the
+    /// payload bytes to be signed with the account key, possibly on another machine
+    /// (see `ValidatorSigner::sign_account_key_payload`).
+    /// The version is overwritten with ACCOUNT_DATA_VERSION before serialization.
+    /// Returns an error if the serialized data is too large to be broadcasted.
+    pub fn to_payload(mut self) -> anyhow::Result<(SerializedAccountData, Vec<u8>)> {
+        self.version = ACCOUNT_DATA_VERSION;
         let payload = proto::AccountKeyPayload::from(&self).write_to_bytes().unwrap();
         if payload.len() > MAX_ACCOUNT_DATA_SIZE_BYTES {
             anyhow::bail!(
@@ -122,11 +304,34 @@ impl AccountData {
                 MAX_ACCOUNT_DATA_SIZE_BYTES
             );
         }
-        let signature = signer.sign_account_key_payload(&payload);
-        Ok(SignedAccountData {
-            account_data: self,
-            payload: AccountKeySignedPayload { payload, signature },
-        })
+        Ok((SerializedAccountData { account_data: self, payload: payload.clone() }, payload))
+    }
+
+    /// Size of the payload that `to_payload()` would produce, which is the size checked
+    /// against MAX_ACCOUNT_DATA_SIZE_BYTES. It is computed without converting the data
+    /// to proto, so that callers can cheaply check the limit for every candidate list of peers.
+    pub fn estimated_serialized_size(&self) -> usize {
+        // to_payload() overwrites the version, which affects the size of the encoding.
+        proto_conv::account_key_payload_size(self, ACCOUNT_DATA_VERSION)
+    }
+}
+
+/// AccountData serialized by `AccountData::to_payload()`, waiting for the signature.
+#[derive(PartialEq, Eq, Debug, Hash)]
+pub struct SerializedAccountData {
+    account_data: AccountData,
+    payload: Vec<u8>,
+}
+
+impl SerializedAccountData {
+    /// Combines the data with the signature of its payload.
+    /// The signature is not verified here: receivers of the SignedAccountData
+    /// verify it against the account key of the validator.
+    pub fn attach_signature(self, signature: near_crypto::Signature) -> SignedAccountData {
+        SignedAccountData {
+            account_data: self.account_data,
+            payload: AccountKeySignedPayload { payload: self.payload, signature },
+        }
     }
 }
 
@@ -174,6 +379,42 @@ impl SignedAccountData {
     }
 }
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum VerifyAccountDataError {
+    #[error("no public key known for the (account_id,epoch_id)")]
+    UnknownAccount,
+    #[error("payload size = {0}, max is {}", MAX_ACCOUNT_DATA_SIZE_BYTES)]
+    DataTooLarge(usize),
+    #[error("invalid signature")]
+    InvalidSignature,
+}
+
+/// Verifies the signatures of a batch of SignedAccountData.
+/// `key_for_account` resolves the public key of an account in the given epoch. Lookups are
+/// memoized within the call, so that an (account_id,epoch_id) pair occurring in multiple
+/// entries is resolved only once.
+/// Returns a verification result for every entry, in the order of `entries`.
+pub fn verify_accounts_data(
+    entries: &[Arc<SignedAccountData>],
+    key_for_account: impl Fn(&AccountId, &EpochId) -> Option<PublicKey>,
+) -> Vec<Result<(), VerifyAccountDataError>> {
+    let mut keys = HashMap::<(&AccountId, &EpochId), Option<PublicKey>>::new();
+    entries
+        .iter()
+        .map(|d| {
+            if d.payload().len() > MAX_ACCOUNT_DATA_SIZE_BYTES {
+                return Err(VerifyAccountDataError::DataTooLarge(d.payload().len()));
+            }
+            let key = keys
+                .entry((&d.account_id, &d.epoch_id))
+                .or_insert_with(|| key_for_account(&d.account_id, &d.epoch_id))
+                .as_ref()
+                .ok_or(VerifyAccountDataError::UnknownAccount)?;
+            d.payload().verify(key).map_err(|()| VerifyAccountDataError::InvalidSignature)
+        })
+        .collect()
+}
+
 #[derive(PartialEq, Eq, Clone, Debug, Default)]
 pub struct RoutingTableUpdate {
     pub edges: Vec<Edge>,
@@ -192,6 +433,66 @@ impl RoutingTableUpdate {
     pub(crate) fn new(edges: Vec<Edge>, accounts: Vec<AnnounceAccount>) -> Self {
         Self { edges, accounts }
     }
+
+    /// Sorts the edges and the accounts by their borsh encoding and removes duplicates,
+    /// so that updates with the same content are equal, whatever order it was collected in.
+    pub(crate) fn canonicalize(&mut self) {
+        self.edges.sort_by_cached_key(|e| e.try_to_vec().unwrap());
+        self.edges.dedup();
+        self.accounts.sort_by_cached_key(|a| a.try_to_vec().unwrap());
+        self.accounts.dedup();
+    }
+
+    /// Hash of the canonicalized content of the update, see `canonicalize`.
+    /// Updates with the same content have the same fingerprint, which allows
+    /// skipping an update which has been seen already without comparing it as a whole.
+    pub fn fingerprint(&self) -> CryptoHash {
+        let mut update = self.clone();
+        update.canonicalize();
+        CryptoHash::hash_borsh(&(update.edges, update.accounts))
+    }
+
+    /// Splits the update into updates with at most `MAX_ACCOUNTS_PER_UPDATE` accounts each,
+    /// so that they are accepted by receivers which use the default limit.
+    /// All the edges are sent in the first update.
+    pub(crate) fn into_chunks(self) -> Vec<RoutingTableUpdate> {
+        if self.accounts.len() <= MAX_ACCOUNTS_PER_UPDATE {
+            return vec![self];
+        }
+        let mut edges = self.edges;
+        self.accounts
+            .chunks(MAX_ACCOUNTS_PER_UPDATE)
+            .map(|accounts| Self::new(std::mem::take(&mut edges), accounts.to_vec()))
+            .collect()
+    }
+
+    /// Merges two updates into a single one without duplicates.
+    /// For every pair of peers only the edge with the highest nonce is kept.
+    /// AnnounceAccount doesn't carry a timestamp, so for an account present in
+    /// both updates the announcement from `other` is considered fresher.
+    /// Edges and accounts of the result are sorted by their keys.
+    pub fn merge(self, other: RoutingTableUpdate) -> RoutingTableUpdate {
+        let mut edges = BTreeMap::<(PeerId, PeerId), Edge>::new();
+        for edge in self.edges.into_iter().chain(other.edges) {
+            match edges.entry(edge.key().clone()) {
+                btree_map::Entry::Vacant(e) => {
+                    e.insert(edge);
+                }
+                btree_map::Entry::Occupied(mut e) => {
+                    if edge.supersedes(e.get()) {
+                        e.insert(edge);
+                    }
+                }
+            }
+        }
+        let accounts: BTreeMap<AccountId, AnnounceAccount> = self
+            .accounts
+            .into_iter()
+            .chain(other.accounts)
+            .map(|a| (a.account_id.clone(), a))
+            .collect();
+        Self { edges: edges.into_values().collect(), accounts: accounts.into_values().collect() }
+    }
 }
 /// Structure representing handshake between peers.
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -225,6 +526,17 @@ pub struct SyncAccountsData {
     pub accounts_data: Vec<Arc<SignedAccountData>>,
     pub requesting_full_sync: bool,
     pub incremental: bool,
+    /// Number of entries the sender holds, 0 if not declared.
+    pub total_count: usize,
+}
+
+impl SyncAccountsData {
+    /// Checks whether the message contains all the entries the sender declared to hold.
+    /// Messages without a declared count (incremental ones, or sent by peers which don't
+    /// set it) are considered complete.
+    pub fn is_complete(&self) -> bool {
+        self.accounts_data.len() >= self.total_count
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Debug, strum::IntoStaticStr, strum::EnumVariantNames)]
@@ -240,6 +552,19 @@ pub enum PeerMessage {
     ResponseUpdateNonce(Edge),
 
     SyncAccountsData(SyncAccountsData),
+    /// Proposed interval of the idle keepalive pings, exchanged after the handshake.
+    /// See KeepAliveConfig in network_protocol/network.proto.
+    KeepAliveConfig {
+        interval_ms: u32,
+    },
+    /// Request for the routing table entries of the accounts with the given prefix,
+    /// see RoutingTableSubsetRequest in network_protocol/network.proto.
+    RoutingTableSubsetRequest {
+        account_prefix: String,
+    },
+    /// Encodings which the sender can decode, exchanged after the handshake.
+    /// See SupportedEncodings in network_protocol/network.proto.
+    SupportedEncodings(Vec<Encoding>),
 
     PeersRequest,
     PeersResponse(Vec<PeerInfo>),
@@ -274,6 +599,21 @@ pub enum Encoding {
     Proto,
 }
 
+/// Encodings of PeerMessage which this node can decode, in the order of preference.
+pub(crate) const SUPPORTED_ENCODINGS: &[Encoding] = &[Encoding::Proto, Encoding::Borsh];
+
+/// Returns the encodings supported by both sides of a connection, in the order
+/// of `ours` (i.e. in the order of our preference), without duplicates.
+pub(crate) fn shared_encodings(ours: &[Encoding], theirs: &[Encoding]) -> Vec<Encoding> {
+    let mut shared = vec![];
+    for e in ours {
+        if theirs.contains(e) && !shared.contains(e) {
+            shared.push(*e);
+        }
+    }
+    shared
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ParsePeerMessageError {
     #[error("BorshDecode")]
@@ -286,28 +626,105 @@ pub enum ParsePeerMessageError {
     ProtoConv(#[source] proto_conv::ParsePeerMessageError),
 }
 
+/// Writer which counts the bytes passed through to `inner`.
+struct CountingWriter<'a, W> {
+    inner: &'a mut W,
+    written: usize,
+}
+
+impl<W: std::io::Write> std::io::Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 impl PeerMessage {
     pub(crate) fn serialize(&self, enc: Encoding) -> Vec<u8> {
-        match enc {
-            Encoding::Borsh => borsh_::PeerMessage::from(self).try_to_vec().unwrap(),
-            Encoding::Proto => proto::PeerMessage::from(self).write_to_bytes().unwrap(),
+        let mut buf = vec![];
+        self.serialize_into(enc, &mut buf);
+        buf
+    }
+
+    /// Serializes the message with `codec`, which doesn't have to be one of the codecs
+    /// selected by `Encoding`, see `PeerMessageCodec`.
+    pub fn serialize_with(&self, codec: &dyn PeerMessageCodec) -> Vec<u8> {
+        let mut buf = vec![];
+        codec.encode_to_vec(self, &mut buf);
+        buf
+    }
+
+    /// Serializes the message into `buf`, replacing its previous content.
+    /// The allocation of `buf` is reused, so that the caller can pool buffers.
+    pub(crate) fn serialize_into(&self, enc: Encoding, buf: &mut Vec<u8>) {
+        buf.clear();
+        enc.codec().encode_to_vec(self, buf);
+    }
+
+    /// Serializes the message directly into `writer`, without buffering the whole
+    /// encoding in memory first. Returns the number of bytes written.
+    pub fn serialize_to(
+        &self,
+        enc: Encoding,
+        writer: &mut impl std::io::Write,
+    ) -> std::io::Result<usize> {
+        let mut writer = CountingWriter { inner: writer, written: 0 };
+        enc.codec().encode(self, &mut writer)?;
+        Ok(writer.written)
+    }
+
+    /// Splits `peers` into `PeersResponse` messages, each of which serializes in encoding `enc`
+    /// to at most `max_bytes` bytes, so that a large peer list is not sent as a single frame.
+    /// A peer which doesn't fit into `max_bytes` even on its own is sent in a separate message.
+    pub(crate) fn peers_response_chunks(
+        enc: Encoding,
+        peers: Vec<PeerInfo>,
+        max_bytes: usize,
+    ) -> Vec<PeerMessage> {
+        let size =
+            |peers: &[PeerInfo]| PeerMessage::PeersResponse(peers.to_vec()).serialize(enc).len();
+        // In proto, the length prefix of the PeersResponse grows with its content,
+        // by at most this many bytes compared to the empty message.
+        const LENGTH_PREFIX_GROWTH: usize = 4;
+        let empty_size = size(&[]);
+        let mut chunks = vec![];
+        let mut chunk = vec![];
+        let mut chunk_size = empty_size + LENGTH_PREFIX_GROWTH;
+        for peer in peers {
+            let peer_size = size(std::slice::from_ref(&peer)) - empty_size;
+            if !chunk.is_empty() && chunk_size + peer_size > max_bytes {
+                chunks.push(PeerMessage::PeersResponse(std::mem::take(&mut chunk)));
+                chunk_size = empty_size + LENGTH_PREFIX_GROWTH;
+            }
+            chunk.push(peer);
+            chunk_size += peer_size;
+        }
+        if !chunk.is_empty() {
+            chunks.push(PeerMessage::PeersResponse(chunk));
         }
+        chunks
     }
 
     pub(crate) fn deserialize(
         enc: Encoding,
         data: &[u8],
+        cfg: &DecodeConfig,
     ) -> Result<PeerMessage, ParsePeerMessageError> {
-        Ok(match enc {
-            Encoding::Borsh => (&borsh_::PeerMessage::try_from_slice(data)
-                .map_err(ParsePeerMessageError::BorshDecode)?)
-                .try_into()
-                .map_err(ParsePeerMessageError::BorshConv)?,
-            Encoding::Proto => (&proto::PeerMessage::parse_from_bytes(data)
-                .map_err(ParsePeerMessageError::ProtoDecode)?)
-                .try_into()
-                .map_err(ParsePeerMessageError::ProtoConv)?,
-        })
+        Self::deserialize_with(enc.codec(), data, cfg)
+    }
+
+    /// Deserializes a message encoded with `codec`, see `serialize_with`.
+    pub fn deserialize_with(
+        codec: &dyn PeerMessageCodec,
+        data: &[u8],
+        cfg: &DecodeConfig,
+    ) -> Result<PeerMessage, ParsePeerMessageError> {
+        codec.decode(data, cfg)
     }
 
     pub(crate) fn msg_variant(&self) -> &'static str {
@@ -335,6 +752,7 @@ impl PeerMessage {
                     | RoutedMessageBody::StateResponse(_)
                     | RoutedMessageBody::VersionedPartialEncodedChunk(_)
                     | RoutedMessageBody::VersionedStateResponse(_)
+                    | RoutedMessageBody::EncryptedBlob(_)
             ),
             _ => false,
         }
@@ -353,6 +771,8 @@ impl PeerMessage {
                     | RoutedMessageBody::StateRequestPart(_, _, _)
                     | RoutedMessageBody::TxStatusRequest(_, _)
                     | RoutedMessageBody::TxStatusResponse(_)
+                    | RoutedMessageBody::TxInclusionProofRequest(_)
+                    | RoutedMessageBody::TxInclusionProofResponse(_)
             ),
             _ => false,
         }
@@ -361,7 +781,13 @@ impl PeerMessage {
 
 // TODO(#1313): Use Box
 #[derive(
-    borsh::BorshSerialize, borsh::BorshDeserialize, PartialEq, Eq, Clone, strum::IntoStaticStr,
+    borsh::BorshSerialize,
+    borsh::BorshDeserialize,
+    PartialEq,
+    Eq,
+    Clone,
+    strum::IntoStaticStr,
+    strum::EnumVariantNames,
 )]
 pub enum RoutedMessageBody {
     BlockApproval(Approval),
@@ -398,9 +824,30 @@ pub enum RoutedMessageBody {
     VersionedPartialEncodedChunk(PartialEncodedChunk),
     VersionedStateResponse(StateResponseInfo),
     PartialEncodedChunkForward(PartialEncodedChunkForwardMsg),
+    /// Liveness check of an account, acked by the network layer of the receiver.
+    Heartbeat(Heartbeat),
+    HeartbeatAck(HeartbeatAck),
+    /// Data exchanged between validators, which is opaque to the network layer.
+    EncryptedBlob(EncryptedBlob),
+    /// Response to a StateRequestPart(shard_id, sync_hash, part_id) which was refused, because
+    /// the node is already computing too many state parts for the requesting peer.
+    StateRequestPartBusy(ShardId, CryptoHash, u64),
+    /// Request for a proof that the transaction with the given hash has been executed.
+    TxInclusionProofRequest(CryptoHash),
+    TxInclusionProofResponse(TxInclusionProof),
+    /// Request for the telemetry of the receiver, answered by its network layer.
+    TelemetryRequest,
+    /// Response to TelemetryRequest. Empty if the receiver declined to share its telemetry.
+    TelemetryResponse(TelemetryData),
 }
 
 impl RoutedMessageBody {
+    /// Names of all the variants, i.e. every value that `RoutedMessage::body_variant()`
+    /// can return. Useful for enumerating metric labels upfront.
+    pub fn all_variant_names() -> &'static [&'static str] {
+        <Self as strum::VariantNames>::VARIANTS
+    }
+
     // Return whether this message is important.
     // In routing logics, we send important messages multiple times to minimize the risk that they are
     // lost
@@ -471,6 +918,26 @@ impl fmt::Debug for RoutedMessageBody {
             ),
             RoutedMessageBody::Ping(_) => write!(f, "Ping"),
             RoutedMessageBody::Pong(_) => write!(f, "Pong"),
+            RoutedMessageBody::Heartbeat(heartbeat) => write!(f, "Heartbeat({})", heartbeat.nonce),
+            RoutedMessageBody::HeartbeatAck(ack) => write!(f, "HeartbeatAck({})", ack.nonce),
+            RoutedMessageBody::EncryptedBlob(blob) => {
+                write!(f, "EncryptedBlob({}, {} bytes)", blob.recipient, blob.ciphertext.len())
+            }
+            RoutedMessageBody::StateRequestPartBusy(shard_id, sync_hash, part_id) => {
+                write!(f, "StateRequestPartBusy({}, {}, {})", shard_id, sync_hash, part_id)
+            }
+            RoutedMessageBody::TxInclusionProofRequest(tx_hash) => {
+                write!(f, "TxInclusionProofRequest({})", tx_hash)
+            }
+            RoutedMessageBody::TxInclusionProofResponse(proof) => write!(
+                f,
+                "TxInclusionProofResponse({}, {})",
+                proof.outcome_proof.id, proof.outcome_proof.block_hash
+            ),
+            RoutedMessageBody::TelemetryRequest => write!(f, "TelemetryRequest"),
+            RoutedMessageBody::TelemetryResponse(data) => {
+                write!(f, "TelemetryResponse({:?})", data)
+            }
         }
     }
 }
@@ -505,6 +972,11 @@ pub struct RoutedMessageV2 {
     pub msg: RoutedMessage,
     /// The time the Routed message was created by `author`.
     pub created_at: Option<time::Utc>,
+    /// Id attached by the author of a request, which the response echoes, so that the
+    /// author can tell which of its (possibly repeated) requests got answered.
+    /// It is not signed and is dropped when the message is sent in borsh encoding,
+    /// so it can only be used as a hint.
+    pub request_id: Option<u64>,
 }
 
 impl std::ops::Deref for RoutedMessageV2 {
@@ -537,6 +1009,11 @@ impl RoutedMessage {
         CryptoHash::hash_borsh(&RoutedMessageNoSignature { target, author: source, body })
     }
 
+    /// Original sender of the message. Note that it is not authenticated until `verify()`.
+    pub fn author(&self) -> &PeerId {
+        &self.author
+    }
+
     pub fn hash(&self) -> CryptoHash {
         RoutedMessage::build_hash(&self.target, &self.author, &self.body)
     }
@@ -549,14 +1026,22 @@ impl RoutedMessage {
         matches!(
             self.body,
             RoutedMessageBody::Ping(_)
+                | RoutedMessageBody::Heartbeat(_)
                 | RoutedMessageBody::TxStatusRequest(_, _)
                 | RoutedMessageBody::StateRequestHeader(_, _)
                 | RoutedMessageBody::StateRequestPart(_, _, _)
                 | RoutedMessageBody::PartialEncodedChunkRequest(_)
                 | RoutedMessageBody::ReceiptOutcomeRequest(_)
+                | RoutedMessageBody::TxInclusionProofRequest(_)
+                | RoutedMessageBody::TelemetryRequest
         )
     }
 
+    /// Whether the message can still be delivered to anyone, i.e. its ttl is positive.
+    pub fn is_deliverable(&self) -> bool {
+        self.ttl > 0
+    }
+
     /// Return true if ttl is positive after decreasing ttl by one, false otherwise.
     pub fn decrease_ttl(&mut self) -> bool {
         self.ttl = self.ttl.saturating_sub(1);
@@ -603,6 +1088,88 @@ pub struct Pong {
     pub source: PeerId,
 }
 
+/// Liveness check sent to an account. In contrast to Ping, it doesn't carry
+/// the source: the ack is routed back along the path of the request.
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize, PartialEq, Eq, Clone, Debug, Hash)]
+pub struct Heartbeat {
+    pub nonce: u64,
+}
+
+/// Response to Heartbeat, echoing its nonce.
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize, PartialEq, Eq, Clone, Debug, Hash)]
+pub struct HeartbeatAck {
+    pub nonce: u64,
+}
+
+/// Time since the node has started, rounded down, so that the telemetry doesn't
+/// reveal when exactly the node has been restarted.
+#[derive(
+    borsh::BorshSerialize, borsh::BorshDeserialize, PartialEq, Eq, Clone, Copy, Debug, Hash,
+)]
+pub enum UptimeBucket {
+    LessThanHour,
+    LessThanDay,
+    LessThanWeek,
+    AtLeastWeek,
+}
+
+impl UptimeBucket {
+    pub fn from_uptime(uptime: time::Duration) -> Self {
+        if uptime < time::Duration::HOUR {
+            Self::LessThanHour
+        } else if uptime < time::Duration::DAY {
+            Self::LessThanDay
+        } else if uptime < time::Duration::WEEK {
+            Self::LessThanWeek
+        } else {
+            Self::AtLeastWeek
+        }
+    }
+}
+
+/// Telemetry reported in TelemetryResponse. A node which doesn't share its telemetry
+/// responds with the default (empty) value.
+#[derive(
+    borsh::BorshSerialize, borsh::BorshDeserialize, PartialEq, Eq, Clone, Debug, Default, Hash,
+)]
+pub struct TelemetryData {
+    pub protocol_version: Option<ProtocolVersion>,
+    pub uptime: Option<UptimeBucket>,
+    pub tracked_shards: Option<Vec<ShardId>>,
+}
+
+impl TelemetryData {
+    /// Whether the responder declined to share its telemetry.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Ciphertext addressed to the validator `recipient`. Relaying peers forward it
+/// without interpreting it; only the recipient is able to decrypt it.
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize, PartialEq, Eq, Clone, Hash)]
+pub struct EncryptedBlob {
+    pub recipient: AccountId,
+    pub ciphertext: Vec<u8>,
+}
+
+impl fmt::Debug for EncryptedBlob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedBlob")
+            .field("recipient", &self.recipient)
+            .field("ciphertext_len", &self.ciphertext.len())
+            .finish()
+    }
+}
+
+/// Execution outcome of a transaction together with the merkle path proving that
+/// the outcome is included in the outcome root of the block `outcome_proof.block_hash`.
+#[derive(borsh::BorshSerialize, borsh::BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub struct TxInclusionProof {
+    pub outcome_proof: ExecutionOutcomeWithIdView,
+    pub outcome_root_proof: MerklePath,
+}
+
 impl PartialEncodedChunkForwardMsg {
     pub fn from_header_and_parts(
         header: &ShardChunkHeader,
@@ -624,6 +1191,69 @@ impl PartialEncodedChunkForwardMsg {
         let correct_hash = combine_hash(&self.inner_header_hash, &self.merkle_root);
         ChunkHash(correct_hash) == self.chunk_hash
     }
+
+    /// Checks that the merkle proof of every part matches `merkle_root`.
+    pub fn verify_parts(&self) -> Result<(), PartsValidationError> {
+        for part in &self.parts {
+            if !verify_path(self.merkle_root, &part.merkle_proof, &part.part) {
+                return Err(PartsValidationError::InvalidMerkleProof { part_ord: part.part_ord });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the fields which can be validated without the chunk header:
+    /// the chunk hash and that `shard_id` exists in `shard_layout`, i.e. the
+    /// layout of the epoch the chunk belongs to.
+    pub fn validate(&self, shard_layout: &ShardLayout) -> Result<(), ForwardValidationError> {
+        if !self.is_valid_hash() {
+            return Err(ForwardValidationError::InvalidHash);
+        }
+        let num_shards = shard_layout.num_shards();
+        if self.shard_id >= num_shards {
+            return Err(ForwardValidationError::InvalidShardId {
+                shard_id: self.shard_id,
+                num_shards,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum PartsValidationError {
+    #[error("merkle proof of part {part_ord} doesn't match the merkle root")]
+    InvalidMerkleProof { part_ord: u64 },
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum ForwardValidationError {
+    #[error("chunk hash doesn't match the inner header hash and the merkle root")]
+    InvalidHash,
+    #[error("shard {shard_id} doesn't exist, the shard layout has {num_shards} shards")]
+    InvalidShardId { shard_id: ShardId, num_shards: NumShards },
+}
+
+/// Checks that `chunks` (e.g. reconstructed from forwarded parts) are the chunks of `block`,
+/// i.e. that for every shard there is a chunk with the chunk hash from the block header.
+/// Returns the ids of the shards whose chunk is missing or doesn't match, in ascending order.
+/// A shard is also reported if `chunks` contains a chunk for it which doesn't match, even if
+/// the matching one is present as well.
+pub fn verify_block_chunks(block: &Block, chunks: &[PartialEncodedChunk]) -> Vec<ShardId> {
+    let want: Vec<ChunkHash> = block.chunks().iter().map(|header| header.chunk_hash()).collect();
+    let mut found = vec![false; want.len()];
+    let mut bad = BTreeSet::new();
+    for chunk in chunks {
+        let shard_id = chunk.shard_id();
+        match want.get(shard_id as usize) {
+            Some(hash) if hash == &chunk.chunk_hash() => found[shard_id as usize] = true,
+            _ => {
+                bad.insert(shard_id);
+            }
+        }
+    }
+    bad.extend((0..want.len()).filter(|i| !found[*i]).map(|i| i as ShardId));
+    bad.into_iter().collect()
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, borsh::BorshSerialize, borsh::BorshDeserialize)]
@@ -681,6 +1311,27 @@ impl StateResponseInfo {
             Self::V2(info) => info.state_response,
         }
     }
+
+    /// Checks that the response is for a shard which exists in `shard_layout`.
+    /// The shard id is set by the sender, so it has to be checked before the response
+    /// is matched against the pending downloads.
+    pub fn validate_against_layout(
+        &self,
+        shard_layout: &ShardLayout,
+    ) -> Result<(), StateResponseError> {
+        let shard_id = self.shard_id();
+        let num_shards = shard_layout.num_shards();
+        if shard_id >= num_shards {
+            return Err(StateResponseError::UnknownShard { shard_id, num_shards });
+        }
+        Ok(())
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum StateResponseError {
+    #[error("shard {shard_id} doesn't exist in a layout of {num_shards} shards")]
+    UnknownShard { shard_id: ShardId, num_shards: NumShards },
 }
 
 #[derive(
@@ -699,6 +1350,12 @@ pub enum AccountOrPeerIdOrHash {
     Hash(CryptoHash),
 }
 
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum RoutingTargetError {
+    #[error("expected a PeerId or a hash as the routing target, got AccountId {0}")]
+    AccountId(AccountId),
+}
+
 pub struct RawRoutedMessage {
     pub target: AccountOrPeerIdOrHash,
     pub body: RoutedMessageBody,
@@ -706,18 +1363,21 @@ pub struct RawRoutedMessage {
 
 impl RawRoutedMessage {
     /// Add signature to the message.
-    /// Panics if the target is an AccountId instead of a PeerId.
+    /// Fails if the target is an AccountId instead of a PeerId or a hash.
+    /// Zero `routed_message_ttl` makes the message undeliverable, so it is
+    /// rejected in debug builds.
     pub fn sign(
         self,
         node_key: &near_crypto::SecretKey,
         routed_message_ttl: u8,
         now: Option<time::Utc>,
-    ) -> RoutedMessageV2 {
+    ) -> Result<RoutedMessageV2, RoutingTargetError> {
+        debug_assert!(routed_message_ttl > 0, "signing a routed message with zero ttl");
         let author = PeerId::new(node_key.public_key());
-        let target = self.target.peer_id_or_hash().unwrap();
+        let target = self.target.try_into_peer_id_or_hash()?;
         let hash = RoutedMessage::build_hash(&target, &author, &self.body);
         let signature = node_key.sign(hash.as_ref());
-        RoutedMessageV2 {
+        Ok(RoutedMessageV2 {
             msg: RoutedMessage {
                 target,
                 author,
@@ -726,6 +1386,7 @@ impl RawRoutedMessage {
                 body: self.body,
             },
             created_at: now,
-        }
+            request_id: None,
+        })
     }
 }
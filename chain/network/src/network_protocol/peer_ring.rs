@@ -0,0 +1,67 @@
+use crate::network_protocol::PeerAddr;
+use borsh::BorshSerialize;
+use near_primitives::hash::CryptoHash;
+use near_primitives::types::AccountId;
+use std::collections::HashSet;
+
+/// Consistent-hash ring assigning accounts to peers.
+///
+/// Every peer occupies `VIRTUAL_NODES` points on the ring, derived from its PeerId,
+/// and an account is assigned to the peer owning the first point at or after the hash
+/// of the account id. The assignment depends only on the set of peers, so nodes which
+/// agree on the set of relays agree on the assignment. Adding or removing a peer moves
+/// only the accounts assigned to that peer.
+#[derive(Clone, Debug)]
+pub struct PeerRing {
+    peers: Vec<PeerAddr>,
+    /// Points of the ring, sorted, together with the index of the owning peer in `peers`.
+    points: Vec<(u64, usize)>,
+}
+
+/// Stable hash of `value`, used as a position on the ring.
+fn ring_point(value: &impl BorshSerialize) -> u64 {
+    let hash = CryptoHash::hash_borsh(value);
+    u64::from_le_bytes(hash.as_ref()[..8].try_into().unwrap())
+}
+
+impl PeerRing {
+    /// Number of points each peer occupies on the ring.
+    /// More points make the distribution of accounts more even.
+    pub const VIRTUAL_NODES: u32 = 128;
+
+    /// Builds the ring out of `peers`. Peers are identified by PeerId: if several
+    /// addresses share a PeerId, only the first one is kept.
+    /// Returns None if `peers` is empty.
+    pub fn new(peers: impl IntoIterator<Item = PeerAddr>) -> Option<Self> {
+        let mut seen = HashSet::new();
+        let peers: Vec<_> = peers.into_iter().filter(|p| seen.insert(p.peer_id.clone())).collect();
+        if peers.is_empty() {
+            return None;
+        }
+        let mut points: Vec<_> = peers
+            .iter()
+            .enumerate()
+            .flat_map(|(i, p)| {
+                (0..Self::VIRTUAL_NODES)
+                    .map(move |vnode| (ring_point(&(p.peer_id.clone(), vnode)), i))
+            })
+            .collect();
+        // Collisions are broken by PeerId, so that the order doesn't depend on the order of `peers`.
+        points.sort_by(|(a, i), (b, j)| (a, &peers[*i].peer_id).cmp(&(b, &peers[*j].peer_id)));
+        Some(Self { peers, points })
+    }
+
+    /// Peers of the ring.
+    pub fn peers(&self) -> &[PeerAddr] {
+        &self.peers
+    }
+
+    /// Returns the peer which `account_id` is assigned to.
+    pub fn route(&self, account_id: &AccountId) -> &PeerAddr {
+        let point = ring_point(account_id);
+        let i = self.points.partition_point(|(p, _)| *p < point);
+        // Wrap around past the last point.
+        let (_, peer) = self.points[if i == self.points.len() { 0 } else { i }];
+        &self.peers[peer]
+    }
+}
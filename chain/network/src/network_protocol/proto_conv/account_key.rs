@@ -33,6 +33,7 @@ impl From<&AccountData> for proto::AccountKeyPayload {
                 peers: x.peers.iter().map(Into::into).collect(),
                 epoch_id: MF::some((&x.epoch_id.0).into()),
                 timestamp: MF::some(utc_to_proto(&x.timestamp)),
+                version: x.version,
                 ..Default::default()
             })),
             ..Self::default()
@@ -40,6 +41,45 @@ impl From<&AccountData> for proto::AccountKeyPayload {
     }
 }
 
+/// Size of the encoding of `proto::AccountKeyPayload::from(x)` with the version replaced by
+/// `version`, computed without building the proto. Has to be kept in sync with the conversion
+/// above (and the conversions of the nested types).
+pub(crate) fn account_key_payload_size(x: &AccountData, version: u32) -> usize {
+    use protobuf::rt;
+    // Size of a length-delimited field (message, bytes or string) with `len` bytes of content.
+    let delimited =
+        |field: u32, len: u64| rt::tag_size(field) + rt::compute_raw_varint64_size(len) + len;
+    let peers: u64 = x
+        .peers
+        .iter()
+        .map(|p| {
+            let ip_len = match p.addr.ip() {
+                std::net::IpAddr::V4(_) => 4,
+                std::net::IpAddr::V6(_) => 16,
+            };
+            let port = match p.addr.port() {
+                0 => 0,
+                port => rt::uint32_size(2, port as u32),
+            };
+            let addr = delimited(1, ip_len) + port;
+            let peer_id = delimited(1, p.peer_id.public_key().len() as u64);
+            delimited(2, delimited(1, addr) + delimited(2, peer_id))
+        })
+        .sum();
+    let epoch_id = delimited(1, x.epoch_id.0.as_ref().len() as u64);
+    let timestamp = utc_to_proto(&x.timestamp).compute_size();
+    let version = match version {
+        0 => 0,
+        version => rt::uint32_size(5, version),
+    };
+    let account_data = rt::string_size(1, x.account_id.as_ref())
+        + peers
+        + delimited(3, epoch_id)
+        + delimited(4, timestamp)
+        + version;
+    delimited(2, account_data) as usize
+}
+
 impl TryFrom<&proto::AccountKeyPayload> for AccountData {
     type Error = ParseAccountDataError;
     fn try_from(x: &proto::AccountKeyPayload) -> Result<Self, Self::Error> {
@@ -54,6 +94,7 @@ impl TryFrom<&proto::AccountKeyPayload> for AccountData {
             epoch_id: EpochId(try_from_required(&x.epoch_id).map_err(Self::Error::EpochId)?),
             timestamp: map_from_required(&x.timestamp, utc_from_proto)
                 .map_err(Self::Error::Timestamp)?,
+            version: x.version,
         })
     }
 }
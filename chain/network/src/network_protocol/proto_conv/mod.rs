@@ -8,7 +8,7 @@ mod time;
 mod util;
 
 use self::time::*;
-use account_key::*;
+pub(crate) use account_key::*;
 use crypto::*;
 use handshake::*;
 use net::*;
@@ -3,15 +3,20 @@ use super::*;
 
 use crate::network_protocol::proto;
 use crate::network_protocol::proto::peer_message::Message_type as ProtoMT;
+use crate::network_protocol::routing_header::RoutedMessagePrefix;
+use crate::network_protocol::{DecodeConfig, Encoding, RoutedMessageBody};
 use crate::network_protocol::{PeerMessage, RoutingTableUpdate, SyncAccountsData};
 use crate::network_protocol::{RoutedMessage, RoutedMessageV2};
 use crate::time::error::ComponentRange;
 use borsh::{BorshDeserialize as _, BorshSerialize as _};
 use near_primitives::block::{Block, BlockHeader};
-use near_primitives::challenge::Challenge;
+use near_primitives::hash::CryptoHash;
 use near_primitives::syncing::{EpochSyncFinalizationResponse, EpochSyncResponse};
-use near_primitives::transaction::SignedTransaction;
 use near_primitives::types::EpochId;
+use near_primitives::views::validator_stake_view::ValidatorStakeView;
+use near_primitives::views::{BlockHeaderInnerLiteView, LightClientBlockView};
+use protobuf::reflect::{ReflectFieldRef, ReflectValueRef};
+use protobuf::MessageDyn;
 use protobuf::MessageField as MF;
 use std::sync::Arc;
 
@@ -21,6 +26,30 @@ pub enum ParseRoutingTableUpdateError {
     Edges(ParseVecError<ParseEdgeError>),
     #[error("accounts {0}")]
     Accounts(ParseVecError<ParseAnnounceAccountError>),
+    #[error("too many accounts: got {got}, max {max}")]
+    TooManyAccounts { got: usize, max: usize },
+}
+
+impl From<&Encoding> for proto::supported_encodings::Encoding {
+    fn from(x: &Encoding) -> Self {
+        match x {
+            Encoding::Borsh => Self::BORSH,
+            Encoding::Proto => Self::PROTO,
+        }
+    }
+}
+
+/// Encodings unknown to this binary (for example introduced by a newer
+/// version of the protocol) are skipped, rather than rejected.
+fn encodings_from_proto(x: &proto::SupportedEncodings) -> Vec<Encoding> {
+    x.encodings
+        .iter()
+        .filter_map(|e| match e.enum_value_or_default() {
+            proto::supported_encodings::Encoding::BORSH => Some(Encoding::Borsh),
+            proto::supported_encodings::Encoding::PROTO => Some(Encoding::Proto),
+            proto::supported_encodings::Encoding::UNKNOWN => None,
+        })
+        .collect()
 }
 
 impl From<&RoutingTableUpdate> for proto::RoutingTableUpdate {
@@ -33,14 +62,22 @@ impl From<&RoutingTableUpdate> for proto::RoutingTableUpdate {
     }
 }
 
-impl TryFrom<&proto::RoutingTableUpdate> for RoutingTableUpdate {
-    type Error = ParseRoutingTableUpdateError;
-    fn try_from(x: &proto::RoutingTableUpdate) -> Result<Self, Self::Error> {
-        Ok(Self {
-            edges: try_from_slice(&x.edges).map_err(Self::Error::Edges)?,
-            accounts: try_from_slice(&x.accounts).map_err(Self::Error::Accounts)?,
-        })
+/// Decodes a RoutingTableUpdate. It is not a `TryFrom`, because the limit
+/// on the number of accounts is taken from `DecodeConfig`.
+pub(crate) fn routing_table_update_from_proto(
+    x: &proto::RoutingTableUpdate,
+    cfg: &DecodeConfig,
+) -> Result<RoutingTableUpdate, ParseRoutingTableUpdateError> {
+    if x.accounts.len() > cfg.max_accounts_per_update {
+        return Err(ParseRoutingTableUpdateError::TooManyAccounts {
+            got: x.accounts.len(),
+            max: cfg.max_accounts_per_update,
+        });
     }
+    Ok(RoutingTableUpdate {
+        edges: try_from_slice(&x.edges).map_err(ParseRoutingTableUpdateError::Edges)?,
+        accounts: try_from_slice(&x.accounts).map_err(ParseRoutingTableUpdateError::Accounts)?,
+    })
 }
 
 //////////////////////////////////////////
@@ -51,13 +88,14 @@ impl From<&BlockHeader> for proto::BlockHeader {
     }
 }
 
-pub type ParseBlockHeaderError = borsh::maybestd::io::Error;
+pub type ParseBlockHeaderError = ParseBorshError;
 
-impl TryFrom<&proto::BlockHeader> for BlockHeader {
-    type Error = ParseBlockHeaderError;
-    fn try_from(x: &proto::BlockHeader) -> Result<Self, Self::Error> {
-        Self::try_from_slice(&x.borsh)
-    }
+/// Decodes a BlockHeader, see `routing_table_update_from_proto`.
+pub(crate) fn block_header_from_proto(
+    x: &proto::BlockHeader,
+    cfg: &DecodeConfig,
+) -> Result<BlockHeader, ParseBlockHeaderError> {
+    try_from_borsh(&x.borsh, cfg.max_block_header_bytes)
 }
 
 //////////////////////////////////////////
@@ -68,13 +106,14 @@ impl From<&Block> for proto::Block {
     }
 }
 
-pub type ParseBlockError = borsh::maybestd::io::Error;
+pub type ParseBlockError = ParseBorshError;
 
-impl TryFrom<&proto::Block> for Block {
-    type Error = ParseBlockError;
-    fn try_from(x: &proto::Block) -> Result<Self, Self::Error> {
-        Self::try_from_slice(&x.borsh)
-    }
+/// Decodes a Block, see `block_header_from_proto`.
+pub(crate) fn block_from_proto(
+    x: &proto::Block,
+    cfg: &DecodeConfig,
+) -> Result<Block, ParseBlockError> {
+    try_from_borsh(&x.borsh, cfg.max_block_bytes)
 }
 
 //////////////////////////////////////////
@@ -113,6 +152,28 @@ impl From<&PeerMessage> for proto::PeerMessage {
                             .collect(),
                         incremental: msg.incremental,
                         requesting_full_sync: msg.requesting_full_sync,
+                        total_count: msg.total_count as u64,
+                        ..Default::default()
+                    })
+                }
+                PeerMessage::KeepAliveConfig { interval_ms } => {
+                    ProtoMT::KeepAliveConfig(proto::KeepAliveConfig {
+                        interval_ms: *interval_ms,
+                        ..Default::default()
+                    })
+                }
+                PeerMessage::RoutingTableSubsetRequest { account_prefix } => {
+                    ProtoMT::RoutingTableSubsetRequest(proto::RoutingTableSubsetRequest {
+                        account_prefix: account_prefix.clone(),
+                        ..Default::default()
+                    })
+                }
+                PeerMessage::SupportedEncodings(encodings) => {
+                    ProtoMT::SupportedEncodings(proto::SupportedEncodings {
+                        encodings: encodings
+                            .iter()
+                            .map(|e| proto::supported_encodings::Encoding::from(e).into())
+                            .collect(),
                         ..Default::default()
                     })
                 }
@@ -148,6 +209,7 @@ impl From<&PeerMessage> for proto::PeerMessage {
                 PeerMessage::Routed(r) => ProtoMT::Routed(proto::RoutedMessage {
                     borsh: r.msg.try_to_vec().unwrap(),
                     created_at: MF::from_option(r.created_at.as_ref().map(utc_to_proto)),
+                    request_id: r.request_id.unwrap_or(0),
                     ..Default::default()
                 }),
                 PeerMessage::Disconnect => ProtoMT::Disconnect(proto::Disconnect::new()),
@@ -185,12 +247,135 @@ impl From<&PeerMessage> for proto::PeerMessage {
     }
 }
 
-pub type ParseTransactionError = borsh::maybestd::io::Error;
-pub type ParseRoutedError = borsh::maybestd::io::Error;
-pub type ParseChallengeError = borsh::maybestd::io::Error;
-pub type ParseEpochSyncResponseError = borsh::maybestd::io::Error;
+pub type ParseTransactionError = ParseBorshError;
+pub type ParseRoutedError = ParseBorshError;
+pub type ParseChallengeError = ParseBorshError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ParseEpochSyncResponseError {
+    #[error("borsh: {0}")]
+    Borsh(#[from] borsh::maybestd::io::Error),
+    #[error("decoding didn't finish before the deadline")]
+    Timeout,
+    #[error("too large: got {got} bytes, max {max}")]
+    TooLarge { got: usize, max: usize },
+}
+
 pub type ParseEpochSyncFinalizationResponseError = borsh::maybestd::io::Error;
 
+/// Equivalent of `RoutedMessage::try_from_slice`, which rejects the message if its body
+/// is larger than `max_body_bytes`, or if it is a ForwardTx with a transaction larger than
+/// `max_transaction_bytes`. The checks are done before the body is decoded.
+/// Length prefixes of the lists in the (untrusted) body don't need to be checked: borsh
+/// preallocates at most 4kB per list and fails once the remaining bytes run out.
+pub(crate) fn decode_routed_message(
+    mut data: &[u8],
+    max_body_bytes: usize,
+    max_transaction_bytes: usize,
+) -> Result<RoutedMessage, ParsePeerMessageError> {
+    let decode_err = |err| ParsePeerMessageError::Routed(ParseBorshError::Decode(err));
+    let buf = &mut data;
+    let RoutedMessagePrefix { target, author, signature, ttl } =
+        RoutedMessagePrefix::deserialize(buf).map_err(decode_err)?;
+    // The body is the last field, so the remaining bytes are exactly the encoded body.
+    if buf.len() > max_body_bytes {
+        return Err(ParsePeerMessageError::Routed(ParseBorshError::TooLarge {
+            got: buf.len(),
+            max: max_body_bytes,
+        }));
+    }
+    if let Some((tag, fields)) = buf.split_first() {
+        let variant = <RoutedMessageBody as strum::VariantNames>::VARIANTS.get(*tag as usize);
+        // Similarly, the transaction is all of the ForwardTx body but the variant tag.
+        if variant == Some(&"ForwardTx") && fields.len() > max_transaction_bytes {
+            return Err(ParsePeerMessageError::ForwardTx(ParseBorshError::TooLarge {
+                got: fields.len(),
+                max: max_transaction_bytes,
+            }));
+        }
+    }
+    let body = RoutedMessageBody::try_from_slice(*buf).map_err(decode_err)?;
+    Ok(RoutedMessage { target, author, signature, ttl, body })
+}
+
+/// Time budget for decoding a single EpochSyncResponse.
+const EPOCH_SYNC_RESPONSE_DECODE_TIMEOUT: crate::time::Duration = crate::time::Duration::seconds(1);
+
+/// Number of vector elements decoded between the subsequent deadline checks.
+const DECODE_DEADLINE_CHECK_INTERVAL: u32 = 1000;
+
+/// Decodes a borsh-encoded vector, checking every few elements whether the deadline has passed.
+/// Memory is not preallocated, since the declared length is controlled by the sender.
+fn decode_vec_with_deadline<T: borsh::BorshDeserialize>(
+    clock: &crate::time::Clock,
+    deadline: crate::time::Instant,
+    buf: &mut &[u8],
+) -> Result<Vec<T>, ParseEpochSyncResponseError> {
+    let len = u32::deserialize(buf)?;
+    let mut res = vec![];
+    for i in 0..len {
+        if i % DECODE_DEADLINE_CHECK_INTERVAL == 0 && clock.now() >= deadline {
+            return Err(ParseEpochSyncResponseError::Timeout);
+        }
+        res.push(T::deserialize(buf)?);
+    }
+    Ok(res)
+}
+
+/// Equivalent of `EpochSyncResponse::try_from_slice`, which gives up with
+/// `ParseEpochSyncResponseError::Timeout` once `deadline` passes.
+/// The response may contain arbitrarily long lists of validators and approvals,
+/// so they are decoded element by element. The decoding order has to match the
+/// borsh layout of EpochSyncResponse.
+/// Responses larger than `max_bytes` are rejected before anything is decoded.
+pub(crate) fn decode_epoch_sync_response(
+    clock: &crate::time::Clock,
+    deadline: crate::time::Instant,
+    mut data: &[u8],
+    max_bytes: usize,
+) -> Result<EpochSyncResponse, ParseEpochSyncResponseError> {
+    if data.len() > max_bytes {
+        return Err(ParseEpochSyncResponseError::TooLarge { got: data.len(), max: max_bytes });
+    }
+    let invalid_data = |msg: String| {
+        ParseEpochSyncResponseError::Borsh(borsh::maybestd::io::Error::new(
+            borsh::maybestd::io::ErrorKind::InvalidData,
+            msg,
+        ))
+    };
+    let buf = &mut data;
+    let resp = match u8::deserialize(buf)? {
+        0 => EpochSyncResponse::UpToDate,
+        1 => {
+            let prev_block_hash = CryptoHash::deserialize(buf)?;
+            let next_block_inner_hash = CryptoHash::deserialize(buf)?;
+            let inner_lite = BlockHeaderInnerLiteView::deserialize(buf)?;
+            let inner_rest_hash = CryptoHash::deserialize(buf)?;
+            let next_bps = match u8::deserialize(buf)? {
+                0 => None,
+                1 => Some(decode_vec_with_deadline::<ValidatorStakeView>(clock, deadline, buf)?),
+                tag => return Err(invalid_data(format!("invalid Option tag: {tag}"))),
+            };
+            let approvals_after_next = decode_vec_with_deadline(clock, deadline, buf)?;
+            EpochSyncResponse::Advance {
+                light_client_block_view: LightClientBlockView {
+                    prev_block_hash,
+                    next_block_inner_hash,
+                    inner_lite,
+                    inner_rest_hash,
+                    next_bps,
+                    approvals_after_next,
+                },
+            }
+        }
+        tag => return Err(invalid_data(format!("invalid EpochSyncResponse tag: {tag}"))),
+    };
+    if !buf.is_empty() {
+        return Err(invalid_data("Not all bytes read".to_string()));
+    }
+    Ok(resp)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ParsePeerMessageError {
     #[error("empty message")]
@@ -221,6 +406,8 @@ pub enum ParsePeerMessageError {
     Transaction(ParseTransactionError),
     #[error("routed: {0}")]
     Routed(ParseRoutedError),
+    #[error("routed forward_tx: {0}")]
+    ForwardTx(ParseTransactionError),
     #[error("challenge: {0}")]
     Challenge(ParseChallengeError),
     #[error("epoch_sync_request: {0}")]
@@ -237,90 +424,158 @@ pub enum ParsePeerMessageError {
     SyncAccountsData(ParseVecError<ParseSignedAccountDataError>),
 }
 
-impl TryFrom<&proto::PeerMessage> for PeerMessage {
-    type Error = ParsePeerMessageError;
-    fn try_from(x: &proto::PeerMessage) -> Result<Self, Self::Error> {
-        Ok(match x.message_type.as_ref().ok_or(Self::Error::Empty)? {
+impl PeerMessage {
+    /// Converts a decoded proto message to a PeerMessage. Unlike the other proto
+    /// conversions it is not a `TryFrom`, because decoding of the embedded borsh
+    /// blobs depends on `cfg`.
+    pub(crate) fn from_proto(
+        x: &proto::PeerMessage,
+        cfg: &DecodeConfig,
+    ) -> Result<Self, ParsePeerMessageError> {
+        Ok(match x.message_type.as_ref().ok_or(ParsePeerMessageError::Empty)? {
             ProtoMT::Handshake(h) => {
-                PeerMessage::Handshake(h.try_into().map_err(Self::Error::Handshake)?)
+                PeerMessage::Handshake(h.try_into().map_err(ParsePeerMessageError::Handshake)?)
             }
             ProtoMT::HandshakeFailure(hf) => {
-                let (pi, hfr) = hf.try_into().map_err(Self::Error::HandshakeFailure)?;
+                let (pi, hfr) = hf.try_into().map_err(ParsePeerMessageError::HandshakeFailure)?;
                 PeerMessage::HandshakeFailure(pi, hfr)
             }
-            ProtoMT::LastEdge(le) => {
-                PeerMessage::LastEdge(try_from_required(&le.edge).map_err(Self::Error::LastEdge)?)
-            }
+            ProtoMT::LastEdge(le) => PeerMessage::LastEdge(
+                try_from_required(&le.edge).map_err(ParsePeerMessageError::LastEdge)?,
+            ),
             ProtoMT::SyncRoutingTable(rtu) => PeerMessage::SyncRoutingTable(
-                rtu.try_into().map_err(Self::Error::SyncRoutingTable)?,
+                routing_table_update_from_proto(rtu, cfg)
+                    .map_err(ParsePeerMessageError::SyncRoutingTable)?,
             ),
             ProtoMT::UpdateNonceRequest(unr) => PeerMessage::RequestUpdateNonce(
                 try_from_required(&unr.partial_edge_info)
-                    .map_err(Self::Error::UpdateNonceRequest)?,
+                    .map_err(ParsePeerMessageError::UpdateNonceRequest)?,
             ),
             ProtoMT::UpdateNonceResponse(unr) => PeerMessage::ResponseUpdateNonce(
-                try_from_required(&unr.edge).map_err(Self::Error::UpdateNonceResponse)?,
+                try_from_required(&unr.edge).map_err(ParsePeerMessageError::UpdateNonceResponse)?,
             ),
             ProtoMT::SyncAccountsData(msg) => PeerMessage::SyncAccountsData(SyncAccountsData {
                 accounts_data: try_from_slice(&msg.accounts_data)
-                    .map_err(Self::Error::SyncAccountsData)?
+                    .map_err(ParsePeerMessageError::SyncAccountsData)?
                     .into_iter()
                     .map(Arc::new)
                     .collect(),
                 incremental: msg.incremental,
                 requesting_full_sync: msg.requesting_full_sync,
+                total_count: msg.total_count as usize,
             }),
+            ProtoMT::KeepAliveConfig(kac) => {
+                PeerMessage::KeepAliveConfig { interval_ms: kac.interval_ms }
+            }
+            ProtoMT::RoutingTableSubsetRequest(rtsr) => PeerMessage::RoutingTableSubsetRequest {
+                account_prefix: rtsr.account_prefix.clone(),
+            },
+            ProtoMT::SupportedEncodings(se) => {
+                PeerMessage::SupportedEncodings(encodings_from_proto(se))
+            }
             ProtoMT::PeersRequest(_) => PeerMessage::PeersRequest,
             ProtoMT::PeersResponse(pr) => PeerMessage::PeersResponse(
-                try_from_slice(&pr.peers).map_err(Self::Error::PeersResponse)?,
+                try_from_slice(&pr.peers).map_err(ParsePeerMessageError::PeersResponse)?,
             ),
             ProtoMT::BlockHeadersRequest(bhr) => PeerMessage::BlockHeadersRequest(
-                try_from_slice(&bhr.block_hashes).map_err(Self::Error::BlockHeadersRequest)?,
+                try_from_slice(&bhr.block_hashes)
+                    .map_err(ParsePeerMessageError::BlockHeadersRequest)?,
             ),
             ProtoMT::BlockHeadersResponse(bhr) => PeerMessage::BlockHeaders(
-                try_from_slice(&bhr.block_headers).map_err(Self::Error::BlockHeadersResponse)?,
+                map_from_slice(&bhr.block_headers, |h| block_header_from_proto(h, cfg))
+                    .map_err(ParsePeerMessageError::BlockHeadersResponse)?,
             ),
             ProtoMT::BlockRequest(br) => PeerMessage::BlockRequest(
-                try_from_required(&br.block_hash).map_err(Self::Error::BlockRequest)?,
+                try_from_required(&br.block_hash).map_err(ParsePeerMessageError::BlockRequest)?,
             ),
             ProtoMT::BlockResponse(br) => PeerMessage::Block(
-                try_from_required(&br.block).map_err(Self::Error::BlockResponse)?,
+                map_from_required(&br.block, |b| block_from_proto(b, cfg))
+                    .map_err(ParsePeerMessageError::BlockResponse)?,
             ),
             ProtoMT::Transaction(t) => PeerMessage::Transaction(
-                SignedTransaction::try_from_slice(&t.borsh).map_err(Self::Error::Transaction)?,
+                try_from_borsh(&t.borsh, cfg.max_transaction_bytes)
+                    .map_err(ParsePeerMessageError::Transaction)?,
             ),
-            ProtoMT::Routed(r) => PeerMessage::Routed(Box::new(RoutedMessageV2 {
-                msg: RoutedMessage::try_from_slice(&r.borsh).map_err(Self::Error::Routed)?,
-                created_at: r
-                    .created_at
-                    .as_ref()
-                    .map(utc_from_proto)
-                    .transpose()
-                    .map_err(Self::Error::RoutedCreatedAtTimestamp)?,
-            })),
+            ProtoMT::Routed(r) => {
+                let msg = decode_routed_message(
+                    &r.borsh,
+                    cfg.max_routed_body_bytes,
+                    cfg.max_transaction_bytes,
+                )?;
+                PeerMessage::Routed(Box::new(RoutedMessageV2 {
+                    msg,
+                    created_at: r
+                        .created_at
+                        .as_ref()
+                        .map(utc_from_proto)
+                        .transpose()
+                        .map_err(ParsePeerMessageError::RoutedCreatedAtTimestamp)?,
+                    request_id: (r.request_id != 0).then_some(r.request_id),
+                }))
+            }
             ProtoMT::Disconnect(_) => PeerMessage::Disconnect,
             ProtoMT::Challenge(c) => PeerMessage::Challenge(
-                Challenge::try_from_slice(&c.borsh).map_err(Self::Error::Challenge)?,
+                try_from_borsh(&c.borsh, cfg.max_challenge_bytes)
+                    .map_err(ParsePeerMessageError::Challenge)?,
             ),
             ProtoMT::EpochSyncRequest(esr) => PeerMessage::EpochSyncRequest(EpochId(
-                try_from_required(&esr.epoch_id).map_err(Self::Error::EpochSyncRequest)?,
-            )),
-            ProtoMT::EpochSyncResponse(esr) => PeerMessage::EpochSyncResponse(Box::new(
-                EpochSyncResponse::try_from_slice(&esr.borsh)
-                    .map_err(Self::Error::EpochSyncResponse)?,
+                try_from_required(&esr.epoch_id)
+                    .map_err(ParsePeerMessageError::EpochSyncRequest)?,
             )),
+            ProtoMT::EpochSyncResponse(esr) => {
+                let deadline = cfg.clock.now() + EPOCH_SYNC_RESPONSE_DECODE_TIMEOUT;
+                PeerMessage::EpochSyncResponse(Box::new(
+                    decode_epoch_sync_response(
+                        &cfg.clock,
+                        deadline,
+                        &esr.borsh,
+                        cfg.max_epoch_sync_response_bytes,
+                    )
+                    .map_err(ParsePeerMessageError::EpochSyncResponse)?,
+                ))
+            }
             ProtoMT::EpochSyncFinalizationRequest(esr) => {
                 PeerMessage::EpochSyncFinalizationRequest(EpochId(
                     try_from_required(&esr.epoch_id)
-                        .map_err(Self::Error::EpochSyncFinalizationRequest)?,
+                        .map_err(ParsePeerMessageError::EpochSyncFinalizationRequest)?,
                 ))
             }
             ProtoMT::EpochSyncFinalizationResponse(esr) => {
                 PeerMessage::EpochSyncFinalizationResponse(Box::new(
                     EpochSyncFinalizationResponse::try_from_slice(&esr.borsh)
-                        .map_err(Self::Error::EpochSyncFinalizationResponse)?,
+                        .map_err(ParsePeerMessageError::EpochSyncFinalizationResponse)?,
                 ))
             }
         })
     }
 }
+
+/// Checks whether `msg`, or any message nested in it, contains fields
+/// unknown to this binary. Protobuf skips such fields silently when parsing.
+fn has_unknown_fields(msg: &dyn MessageDyn) -> bool {
+    if msg.unknown_fields_dyn().iter().next().is_some() {
+        return true;
+    }
+    let is_dirty = |v: ReflectValueRef<'_>| match v {
+        ReflectValueRef::Message(m) => has_unknown_fields(&*m),
+        _ => false,
+    };
+    msg.descriptor_dyn().fields().any(|f| match f.get_reflect(msg) {
+        ReflectFieldRef::Optional(v) => v.value().map_or(false, is_dirty),
+        ReflectFieldRef::Repeated(r) => r.into_iter().any(is_dirty),
+        // network.proto doesn't use maps.
+        ReflectFieldRef::Map(_) => false,
+    })
+}
+
+impl PeerMessage {
+    /// Same as `from_proto`, but additionally reports whether
+    /// `x` contained fields unknown to this binary, which indicates that the
+    /// peer runs a newer version of the protocol.
+    pub(crate) fn try_from_proto_reporting_unknown_fields(
+        x: &proto::PeerMessage,
+        cfg: &DecodeConfig,
+    ) -> Result<(Self, bool), ParsePeerMessageError> {
+        Ok((Self::from_proto(x, cfg)?, has_unknown_fields(x)))
+    }
+}
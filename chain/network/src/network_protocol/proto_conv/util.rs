@@ -1,4 +1,5 @@
 /// Proto conversion utilities.
+use borsh::BorshDeserialize;
 use protobuf::MessageField as MF;
 
 #[derive(thiserror::Error, Debug)]
@@ -19,6 +20,17 @@ pub fn try_from_slice<'a, X, Y: TryFrom<&'a X>>(
     Ok(ys)
 }
 
+pub fn map_from_slice<'a, X, Y, E>(
+    xs: &'a [X],
+    mut f: impl FnMut(&'a X) -> Result<Y, E>,
+) -> Result<Vec<Y>, ParseVecError<E>> {
+    let mut ys = vec![];
+    for (idx, x) in xs.iter().enumerate() {
+        ys.push(f(x).map_err(|source| ParseVecError { idx, source })?);
+    }
+    Ok(ys)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ParseRequiredError<E> {
     #[error("missing, while required")]
@@ -39,3 +51,24 @@ pub fn map_from_required<'a, X, Y, E>(
 ) -> Result<Y, ParseRequiredError<E>> {
     f(x.as_ref().ok_or(ParseRequiredError::Missing)?).map_err(ParseRequiredError::Other)
 }
+
+#[derive(thiserror::Error, Debug)]
+pub enum ParseBorshError {
+    #[error("too large: {got} bytes, max is {max}")]
+    TooLarge { got: usize, max: usize },
+    #[error(transparent)]
+    Decode(borsh::maybestd::io::Error),
+}
+
+/// Decodes a borsh blob embedded in a proto message.
+/// The size is checked before decoding, because borsh allocates memory
+/// according to the length prefixes found in the (untrusted) input.
+pub fn try_from_borsh<T: BorshDeserialize>(
+    data: &[u8],
+    max_bytes: usize,
+) -> Result<T, ParseBorshError> {
+    if data.len() > max_bytes {
+        return Err(ParseBorshError::TooLarge { got: data.len(), max: max_bytes });
+    }
+    T::try_from_slice(data).map_err(ParseBorshError::Decode)
+}
@@ -0,0 +1,190 @@
+/// Partial decoding of serialized PeerMessages, for the routing layer.
+use crate::network_protocol::{
+    borsh_, borsh_conv, proto_conv, Encoding, ParsePeerMessageError, PeerIdOrHash,
+};
+use borsh::BorshDeserialize;
+use near_crypto::Signature;
+use near_primitives::network::PeerId;
+use protobuf::CodedInputStream;
+
+/// The part of a PeerMessage which is needed to decide how to forward it.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RoutingHeader {
+    /// Name of the PeerMessage variant, as returned by `<&'static str>::from(&msg)`.
+    pub variant: &'static str,
+    /// Set iff the message is `PeerMessage::Routed`.
+    pub routed: Option<RoutedHeader>,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RoutedHeader {
+    pub target: PeerIdOrHash,
+    pub ttl: u8,
+}
+
+/// Borsh layout of the fields of RoutedMessage which precede the body.
+#[derive(BorshDeserialize)]
+pub(crate) struct RoutedMessagePrefix {
+    pub target: PeerIdOrHash,
+    pub author: PeerId,
+    pub signature: Signature,
+    pub ttl: u8,
+}
+
+impl RoutedMessagePrefix {
+    /// Upper bound on the borsh-encoded size of RoutedMessagePrefix:
+    /// target (1+65) + author (65) + signature (1+65) + ttl (1) = 198 bytes.
+    const MAX_LEN: u32 = 256;
+
+    /// Decodes the prefix from the beginning of a borsh-encoded RoutedMessage,
+    /// ignoring the remaining bytes.
+    fn decode(mut data: &[u8]) -> std::io::Result<Self> {
+        Self::deserialize(&mut data)
+    }
+}
+
+impl From<RoutedMessagePrefix> for RoutedHeader {
+    fn from(p: RoutedMessagePrefix) -> Self {
+        Self { target: p.target, ttl: p.ttl }
+    }
+}
+
+/// Maps field numbers of the `message_type` oneof of proto::PeerMessage to the
+/// corresponding PeerMessage variant names. See network_protocol/network.proto.
+pub(crate) fn proto_variant(field_number: u32) -> Option<&'static str> {
+    Some(match field_number {
+        4 => "Handshake",
+        5 => "HandshakeFailure",
+        6 => "LastEdge",
+        7 => "SyncRoutingTable",
+        8 => "RequestUpdateNonce",
+        9 => "ResponseUpdateNonce",
+        10 => "PeersRequest",
+        11 => "PeersResponse",
+        12 => "BlockHeadersRequest",
+        13 => "BlockHeaders",
+        14 => "BlockRequest",
+        15 => "Block",
+        16 => "Transaction",
+        17 => "Routed",
+        18 => "Disconnect",
+        19 => "Challenge",
+        20 => "EpochSyncRequest",
+        21 => "EpochSyncResponse",
+        22 => "EpochSyncFinalizationRequest",
+        23 => "EpochSyncFinalizationResponse",
+        25 => "SyncAccountsData",
+        26 => "KeepAliveConfig",
+        27 => "RoutingTableSubsetRequest",
+        28 => "SupportedEncodings",
+        _ => return None,
+    })
+}
+
+/// Field number of `routed` in proto::PeerMessage.
+const PROTO_ROUTED_FIELD: u32 = 17;
+/// Field number of `borsh` in proto::RoutedMessage.
+const PROTO_ROUTED_BORSH_FIELD: u32 = 1;
+
+/// Extracts the RoutingHeader from a serialized PeerMessage, without decoding the
+/// whole message. In particular the body of a Routed message is neither decoded nor
+/// copied, and its signature is not verified.
+///
+/// Succeeds on every input accepted by `PeerMessage::deserialize`, in which case the
+/// result agrees with the decoded message. It may succeed on some malformed inputs as well.
+pub fn peek_routing_header(
+    enc: Encoding,
+    data: &[u8],
+) -> Result<RoutingHeader, ParsePeerMessageError> {
+    match enc {
+        Encoding::Borsh => peek_borsh(data),
+        Encoding::Proto => peek_proto(data),
+    }
+}
+
+fn peek_borsh(data: &[u8]) -> Result<RoutingHeader, ParsePeerMessageError> {
+    let (tag, rest) = data.split_first().ok_or_else(|| {
+        ParsePeerMessageError::BorshDecode(std::io::ErrorKind::UnexpectedEof.into())
+    })?;
+    let variant = match <borsh_::PeerMessage as strum::VariantNames>::VARIANTS.get(*tag as usize) {
+        Some(&"_HandshakeV2") => {
+            return Err(ParsePeerMessageError::BorshConv(
+                borsh_conv::ParsePeerMessageError::DeprecatedHandshakeV2,
+            ))
+        }
+        Some(&"_RoutingTableSyncV2") => {
+            return Err(ParsePeerMessageError::BorshConv(
+                borsh_conv::ParsePeerMessageError::DeprecatedRoutingTableSyncV2,
+            ))
+        }
+        Some(variant) => *variant,
+        None => {
+            return Err(ParsePeerMessageError::BorshDecode(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unexpected variant index: {tag}"),
+            )))
+        }
+    };
+    let routed = match variant {
+        "Routed" => Some(
+            RoutedMessagePrefix::decode(rest).map_err(ParsePeerMessageError::BorshDecode)?.into(),
+        ),
+        _ => None,
+    };
+    Ok(RoutingHeader { variant, routed })
+}
+
+fn peek_proto(data: &[u8]) -> Result<RoutingHeader, ParsePeerMessageError> {
+    let mut is = CodedInputStream::from_bytes(data);
+    let mut header = None;
+    // As in the regular protobuf decoding, the last field of the oneof wins.
+    while let Some(tag) = is.read_raw_tag_or_eof().map_err(ParsePeerMessageError::ProtoDecode)? {
+        let field_number = tag >> 3;
+        let variant = match proto_variant(field_number) {
+            Some(variant) => variant,
+            None => {
+                protobuf::rt::skip_field_for_tag(tag, &mut is)
+                    .map_err(ParsePeerMessageError::ProtoDecode)?;
+                continue;
+            }
+        };
+        let routed = if field_number == PROTO_ROUTED_FIELD {
+            let prefix = peek_proto_routed(&mut is).map_err(ParsePeerMessageError::ProtoDecode)?;
+            Some(
+                RoutedMessagePrefix::decode(&prefix)
+                    .map_err(|err| {
+                        ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::Routed(
+                            proto_conv::ParseRoutedError::Decode(err),
+                        ))
+                    })?
+                    .into(),
+            )
+        } else {
+            protobuf::rt::skip_field_for_tag(tag, &mut is)
+                .map_err(ParsePeerMessageError::ProtoDecode)?;
+            None
+        };
+        header = Some(RoutingHeader { variant, routed });
+    }
+    header.ok_or(ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::Empty))
+}
+
+/// Reads a length-delimited proto::RoutedMessage from `is` and returns
+/// at most RoutedMessagePrefix::MAX_LEN first bytes of its `borsh` field.
+fn peek_proto_routed(is: &mut CodedInputStream) -> protobuf::Result<Vec<u8>> {
+    let len = is.read_raw_varint64()?;
+    let old_limit = is.push_limit(len)?;
+    let mut prefix = vec![];
+    while let Some(tag) = is.read_raw_tag_or_eof()? {
+        if tag >> 3 != PROTO_ROUTED_BORSH_FIELD {
+            protobuf::rt::skip_field_for_tag(tag, is)?;
+            continue;
+        }
+        let len = is.read_raw_varint32()?;
+        let n = len.min(RoutedMessagePrefix::MAX_LEN);
+        prefix = is.read_raw_bytes(n)?;
+        is.skip_raw_bytes(len - n)?;
+    }
+    is.pop_limit(old_limit);
+    Ok(prefix)
+}
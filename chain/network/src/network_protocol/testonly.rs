@@ -125,7 +125,7 @@ pub fn make_partial_edge<R: Rng>(rng: &mut R) -> PartialEdgeInfo {
     PartialEdgeInfo::new(
         &PeerId::new(a.public_key),
         &PeerId::new(b.public_key),
-        rng.gen(),
+        Nonce(rng.gen()),
         &a.secret_key,
     )
 }
@@ -134,7 +134,7 @@ pub fn make_edge(a: &InMemorySigner, b: &InMemorySigner) -> Edge {
     let (a, b) = if a.public_key < b.public_key { (a, b) } else { (b, a) };
     let ap = PeerId::new(a.public_key.clone());
     let bp = PeerId::new(b.public_key.clone());
-    let nonce = 1; // Make it an active edge.
+    let nonce = Nonce::FIRST; // Make it an active edge.
     let hash = Edge::build_hash(&ap, &bp, nonce);
     Edge::new(ap, bp, nonce, a.secret_key.sign(hash.as_ref()), b.secret_key.sign(hash.as_ref()))
 }
@@ -355,11 +355,9 @@ pub fn make_handshake<R: Rng>(rng: &mut R, chain: &Chain) -> Handshake {
 pub fn make_routed_message<R: Rng>(rng: &mut R, body: RoutedMessageBody) -> RoutedMessageV2 {
     let signer = make_signer(rng);
     let peer_id = PeerId::new(signer.public_key);
-    RawRoutedMessage { target: AccountOrPeerIdOrHash::PeerId(peer_id.clone()), body }.sign(
-        &signer.secret_key,
-        /*ttl=*/ 1,
-        None,
-    )
+    RawRoutedMessage { target: AccountOrPeerIdOrHash::PeerId(peer_id.clone()), body }
+        .sign(&signer.secret_key, /*ttl=*/ 1, None)
+        .unwrap()
 }
 pub fn make_ipv4(rng: &mut impl Rng) -> net::IpAddr {
     net::IpAddr::V4(net::Ipv4Addr::from(rng.gen::<[u8; 4]>()))
@@ -403,6 +401,7 @@ pub fn make_account_data(
         account_id,
         epoch_id,
         timestamp,
+        version: ACCOUNT_DATA_VERSION,
     }
 }
 
@@ -423,3 +422,18 @@ impl SignedAccountData {
         &mut self.payload.signature
     }
 }
+
+/// DecodeConfig with a clock which never advances,
+/// so that decoding never runs out of time.
+pub fn make_decode_config() -> DecodeConfig {
+    DecodeConfig {
+        clock: time::FakeClock::default().clock(),
+        max_transaction_bytes: MAX_TRANSACTION_BYTES,
+        max_accounts_per_update: MAX_ACCOUNTS_PER_UPDATE,
+        max_block_bytes: MAX_BLOCK_BYTES,
+        max_block_header_bytes: MAX_BLOCK_HEADER_BYTES,
+        max_routed_body_bytes: MAX_ROUTED_BODY_BYTES,
+        max_challenge_bytes: MAX_CHALLENGE_BYTES,
+        max_epoch_sync_response_bytes: MAX_EPOCH_SYNC_RESPONSE_BYTES,
+    }
+}
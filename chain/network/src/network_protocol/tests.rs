@@ -6,8 +6,22 @@ use crate::time;
 use crate::types::{HandshakeFailureReason, PeerMessage};
 use crate::types::{PartialEncodedChunkRequestMsg, PartialEncodedChunkResponseMsg};
 use anyhow::{bail, Context as _};
+use assert_matches::assert_matches;
+use borsh::BorshSerialize as _;
+use near_crypto::Signer as _;
+use near_primitives::block::genesis_chunks;
+use near_primitives::merkle::{Direction, MerklePathItem};
 use near_primitives::syncing::EpochSyncResponse;
+use near_primitives::transaction::{
+    ExecutionOutcome, ExecutionOutcomeWithId, ExecutionOutcomeWithIdAndProof,
+};
 use near_primitives::types::EpochId;
+use near_primitives::validator_signer::InMemoryValidatorSigner;
+use near_primitives::views::validator_stake_view::ValidatorStakeView;
+use near_primitives::views::{
+    BlockHeaderInnerLiteView, LightClientBlockView, ValidatorStakeViewV1,
+};
+use protobuf::Message as _;
 
 #[test]
 fn bad_account_data_size() {
@@ -26,29 +40,208 @@ fn bad_account_data_size() {
         account_id: signer.validator_id().clone(),
         epoch_id: data::make_epoch_id(&mut rng),
         timestamp: clock.now_utc(),
+        version: ACCOUNT_DATA_VERSION,
     };
     assert!(ad.sign(&signer).is_err());
 }
 
+#[test]
+fn account_data_builder() {
+    let mut rng = make_rng(57394810);
+    let clock = time::FakeClock::default();
+    let signer = data::make_validator_signer(&mut rng);
+    let epoch_id = data::make_epoch_id(&mut rng);
+    let ip = data::make_ipv4(&mut rng);
+    let peers = vec![data::make_peer_addr(&mut rng, ip)];
+    let ad = AccountData::builder(signer.validator_id().clone(), epoch_id.clone(), clock.now_utc())
+        .peers(peers.clone())
+        .build();
+    assert_eq!(signer.validator_id(), ad.account_id());
+    assert_eq!(&epoch_id, ad.epoch_id());
+    assert_eq!(clock.now_utc(), ad.timestamp());
+    assert_eq!(&peers[..], ad.peers());
+    assert_eq!(ACCOUNT_DATA_VERSION, ad.version());
+    // The signed data exposes the same values.
+    let sad = ad.sign(&signer).unwrap();
+    assert_eq!(&peers[..], sad.peers());
+    assert_eq!(&epoch_id, sad.epoch_id());
+}
+
+#[test]
+fn account_data_estimated_serialized_size() {
+    let mut rng = make_rng(2093847561);
+    let clock = time::FakeClock::default();
+    let signer = data::make_validator_signer(&mut rng);
+    let epoch_id = data::make_epoch_id(&mut rng);
+    let make = |peers: Vec<PeerAddr>| AccountData {
+        peers,
+        account_id: signer.validator_id().clone(),
+        epoch_id: epoch_id.clone(),
+        timestamp: clock.now_utc(),
+        // to_payload() overwrites the version, the estimate has to account for that.
+        version: 0,
+    };
+    // Add peers one by one, the way a caller building the peer list would,
+    // until the estimate exceeds the limit.
+    let mut peers = vec![];
+    loop {
+        let ad = make(peers.clone());
+        let size = ad.estimated_serialized_size();
+        if size > MAX_ACCOUNT_DATA_SIZE_BYTES {
+            assert!(ad.to_payload().is_err());
+            break;
+        }
+        let (_, payload) = ad.to_payload().unwrap();
+        assert_eq!(payload.len(), size);
+        let ip = match peers.len() % 2 {
+            0 => data::make_ipv4(&mut rng),
+            _ => data::make_ipv6(&mut rng),
+        };
+        peers.push(data::make_peer_addr(&mut rng, ip));
+    }
+    // The last list which fit within the limit can still be signed.
+    peers.pop();
+    assert!(make(peers).sign(&signer).is_ok());
+}
+
+#[test]
+fn account_data_offline_signing() {
+    let mut rng = make_rng(4920384712);
+    let clock = time::FakeClock::default();
+    let signer = data::make_validator_signer(&mut rng);
+    let epoch_id = data::make_epoch_id(&mut rng);
+    let ad =
+        data::make_account_data(&mut rng, clock.now_utc(), epoch_id, signer.validator_id().clone());
+    let copy = |ad: &AccountData| AccountData {
+        peers: ad.peers.clone(),
+        account_id: ad.account_id.clone(),
+        epoch_id: ad.epoch_id.clone(),
+        timestamp: ad.timestamp,
+        version: ad.version,
+    };
+
+    let want = copy(&ad).sign(&signer).unwrap();
+    // Sign the payload separately, as an air-gapped signer would.
+    let (serialized, payload) = copy(&ad).to_payload().unwrap();
+    let signature = signer.sign_account_key_payload(&payload);
+    let got = serialized.attach_signature(signature);
+    assert_eq!(want, got);
+    assert!(got.payload().verify(&signer.public_key()).is_ok());
+    assert_eq!(
+        want.payload().verify(&signer.public_key()),
+        got.payload().verify(&signer.public_key())
+    );
+
+    // Signature with a different key doesn't verify.
+    let (serialized, payload) = ad.to_payload().unwrap();
+    let other = data::make_validator_signer(&mut rng);
+    let got = serialized.attach_signature(other.sign_account_key_payload(&payload));
+    assert!(got.payload().verify(&signer.public_key()).is_err());
+}
+
+#[test]
+fn account_data_version() {
+    let mut rng = make_rng(8234672981);
+    let clock = time::FakeClock::default();
+    let signer = data::make_validator_signer(&mut rng);
+    let epoch_id = data::make_epoch_id(&mut rng);
+    let ad =
+        data::make_account_data(&mut rng, clock.now_utc(), epoch_id, signer.validator_id().clone());
+
+    // sign() sets the current version.
+    let sad = AccountData { version: 0, ..ad }.sign(&signer).unwrap();
+    assert_eq!(ACCOUNT_DATA_VERSION, sad.version);
+
+    // Signs the payload with an explicitly chosen version, bypassing sign().
+    let sign_with_version = |version: u32| {
+        let mut payload = proto::AccountKeyPayload::from(&*sad);
+        match payload.payload_type.as_mut().unwrap() {
+            proto::account_key_payload::Payload_type::AccountData(a) => a.version = version,
+            #[allow(unreachable_patterns)]
+            _ => unreachable!(),
+        }
+        let payload = payload.write_to_bytes().unwrap();
+        let signature = signer.sign_account_key_payload(&payload);
+        let proto = proto::AccountKeySignedPayload {
+            payload,
+            signature: protobuf::MessageField::some((&signature).into()),
+            ..Default::default()
+        };
+        SignedAccountData::try_from(&proto).unwrap()
+    };
+    // Data without the version field (as produced by older nodes) parses as version 0.
+    // Newer versions are preserved. In both cases the signature still verifies.
+    for version in [0, ACCOUNT_DATA_VERSION + 1] {
+        let got = sign_with_version(version);
+        assert_eq!(version, got.version);
+        assert_eq!(sad.timestamp, got.timestamp);
+        assert_eq!(sad.peers, got.peers);
+        assert!(got.payload().verify(&signer.public_key()).is_ok());
+    }
+}
+
 #[test]
 fn serialize_deserialize_protobuf_only() {
     let mut rng = make_rng(39521947542);
     let clock = time::FakeClock::default();
-    let msgs = [PeerMessage::SyncAccountsData(SyncAccountsData {
-        accounts_data: (0..4)
-            .map(|_| Arc::new(data::make_signed_account_data(&mut rng, &clock.clock())))
-            .collect(),
-        incremental: true,
-        requesting_full_sync: true,
-    })];
+    let msgs = [
+        PeerMessage::SyncAccountsData(SyncAccountsData {
+            accounts_data: (0..4)
+                .map(|_| Arc::new(data::make_signed_account_data(&mut rng, &clock.clock())))
+                .collect(),
+            incremental: true,
+            requesting_full_sync: true,
+            total_count: 0,
+        }),
+        PeerMessage::KeepAliveConfig { interval_ms: 0 },
+        PeerMessage::KeepAliveConfig { interval_ms: 30_000 },
+        PeerMessage::RoutingTableSubsetRequest { account_prefix: String::new() },
+        PeerMessage::RoutingTableSubsetRequest { account_prefix: "validator.".to_string() },
+        PeerMessage::SupportedEncodings(vec![]),
+        PeerMessage::SupportedEncodings(vec![Encoding::Proto, Encoding::Borsh]),
+    ];
     for m in msgs {
-        let m2 = PeerMessage::deserialize(Encoding::Proto, &m.serialize(Encoding::Proto))
-            .with_context(|| m.to_string())
-            .unwrap();
+        let m2 = PeerMessage::deserialize(
+            Encoding::Proto,
+            &m.serialize(Encoding::Proto),
+            &data::make_decode_config(),
+        )
+        .with_context(|| m.to_string())
+        .unwrap();
         assert_eq!(m, m2);
     }
 }
 
+#[test]
+fn proto_unknown_fields() {
+    let mut rng = make_rng(89028037453);
+    let a = data::make_signer(&mut rng);
+    let b = data::make_signer(&mut rng);
+    let msg = PeerMessage::LastEdge(data::make_edge(&a, &b));
+    let parse = |x: &proto::PeerMessage| {
+        let x = proto::PeerMessage::parse_from_bytes(&x.write_to_bytes().unwrap()).unwrap();
+        PeerMessage::try_from_proto_reporting_unknown_fields(&x, &data::make_decode_config())
+            .unwrap()
+    };
+    let x = proto::PeerMessage::from(&msg);
+    assert_eq!((msg.clone(), false), parse(&x));
+
+    // Unknown field at the top level.
+    let mut y = x.clone();
+    y.mut_unknown_fields().add_varint(1000, 7);
+    assert_eq!((msg.clone(), true), parse(&y));
+
+    // Unknown field in a nested message.
+    let mut y = x.clone();
+    match &mut y.message_type {
+        Some(proto::peer_message::Message_type::LastEdge(le)) => {
+            le.mut_unknown_fields().add_length_delimited(1000, b"new field".to_vec())
+        }
+        _ => unreachable!(),
+    }
+    assert_eq!((msg, true), parse(&y));
+}
+
 #[test]
 fn serialize_deserialize() -> anyhow::Result<()> {
     let mut rng = make_rng(89028037453);
@@ -108,8 +301,9 @@ fn serialize_deserialize() -> anyhow::Result<()> {
     for enc in [Encoding::Proto, Encoding::Borsh] {
         for m in &msgs {
             (|| {
-                let m2 = PeerMessage::deserialize(enc, &m.serialize(enc))
-                    .with_context(|| m.to_string())?;
+                let m2 =
+                    PeerMessage::deserialize(enc, &m.serialize(enc), &data::make_decode_config())
+                        .with_context(|| m.to_string())?;
                 if *m != m2 {
                     bail!("deserialize(serialize({m}) = {m2}");
                 }
@@ -128,11 +322,23 @@ fn serialize_deserialize() -> anyhow::Result<()> {
         assert!(y[0] <= 21, "serialize({},BORSH)[0] = {:?}, want <= 21", m, y.get(0));
     }
 
+    // Check that serialize_into produces the same bytes as serialize,
+    // without reallocating a large enough buffer.
+    let mut buf = Vec::with_capacity(1 << 20);
+    for enc in [Encoding::Proto, Encoding::Borsh] {
+        for m in &msgs {
+            let ptr = buf.as_ptr();
+            m.serialize_into(enc, &mut buf);
+            assert_eq!(m.serialize(enc), buf, "serialize_into({m}), encoding={enc:?}");
+            assert_eq!(ptr, buf.as_ptr(), "serialize_into({m}) reallocated, encoding={enc:?}");
+        }
+    }
+
     // Encodings should never be compatible.
     for (from, to) in [(Encoding::Proto, Encoding::Borsh), (Encoding::Borsh, Encoding::Proto)] {
         for m in &msgs {
             let bytes = &m.serialize(from);
-            match PeerMessage::deserialize(to, bytes) {
+            match PeerMessage::deserialize(to, bytes, &data::make_decode_config()) {
                 Err(_) => {}
                 Ok(m2) => {
                     bail!("from={from:?},to={to:?}: deserialize(serialize({m})) = {m2}, want error")
@@ -143,3 +349,1682 @@ fn serialize_deserialize() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn transcode_matches_full_path() -> anyhow::Result<()> {
+    let mut rng = make_rng(5093844109);
+    let mut clock = time::FakeClock::default();
+    let chain = data::Chain::make(&mut clock, &mut rng, 4);
+    let ping_source = data::make_peer_id(&mut rng);
+    let msgs = [
+        PeerMessage::Block(chain.blocks[2].clone()),
+        PeerMessage::Challenge(data::make_challenge(&mut rng)),
+        PeerMessage::BlockRequest(chain.blocks[2].hash().clone()),
+        PeerMessage::BlockHeaders(chain.get_block_headers()),
+        PeerMessage::Transaction(data::make_signed_transaction(&mut rng)),
+        PeerMessage::Routed(Box::new(data::make_routed_message(
+            &mut rng,
+            RoutedMessageBody::Ping(Ping { nonce: 3, source: ping_source }),
+        ))),
+        PeerMessage::Disconnect,
+    ];
+    for (from, to) in [(Encoding::Proto, Encoding::Borsh), (Encoding::Borsh, Encoding::Proto)] {
+        for m in &msgs {
+            let data = m.serialize(from);
+            let want =
+                PeerMessage::deserialize(from, &data, &data::make_decode_config())?.serialize(to);
+            let got = transcode(from, to, &data, &data::make_decode_config())
+                .with_context(|| m.to_string())?;
+            assert_eq!(
+                PeerMessage::deserialize(to, &want, &data::make_decode_config())?,
+                PeerMessage::deserialize(to, &got, &data::make_decode_config())
+                    .with_context(|| m.to_string())?,
+                "from={from:?},to={to:?}"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn transcode_passes_block_through_undecoded() {
+    // An invalid block blob is moved between encodings as is;
+    // the error surfaces only when the receiver deserializes it.
+    let msg = proto::PeerMessage {
+        message_type: Some(proto::peer_message::Message_type::BlockResponse(
+            proto::BlockResponse {
+                block: protobuf::MessageField::some(proto::Block {
+                    borsh: vec![7, 7, 7],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )),
+        ..Default::default()
+    };
+    let data = transcode(
+        Encoding::Proto,
+        Encoding::Borsh,
+        &msg.write_to_bytes().unwrap(),
+        &data::make_decode_config(),
+    )
+    .unwrap();
+    assert_eq!(&data[1..], &[7, 7, 7]);
+    assert!(PeerMessage::deserialize(Encoding::Borsh, &data, &data::make_decode_config()).is_err());
+    let back =
+        transcode(Encoding::Borsh, Encoding::Proto, &data, &data::make_decode_config()).unwrap();
+    assert_eq!(msg, proto::PeerMessage::parse_from_bytes(&back).unwrap());
+}
+
+#[test]
+fn reencode_preserves_message() -> anyhow::Result<()> {
+    let mut rng = make_rng(5093844109);
+    let mut clock = time::FakeClock::default();
+    let chain = data::Chain::make(&mut clock, &mut rng, 4);
+    let ping_source = data::make_peer_id(&mut rng);
+    let msgs = [
+        PeerMessage::Block(chain.blocks[2].clone()),
+        PeerMessage::Challenge(data::make_challenge(&mut rng)),
+        PeerMessage::BlockHeadersRequest(chain.blocks.iter().map(|b| *b.hash()).collect()),
+        PeerMessage::BlockHeaders(chain.get_block_headers()),
+        PeerMessage::PeersResponse((0..3).map(|_| data::make_peer_info(&mut rng)).collect()),
+        PeerMessage::Transaction(data::make_signed_transaction(&mut rng)),
+        PeerMessage::Routed(Box::new(data::make_routed_message(
+            &mut rng,
+            RoutedMessageBody::Ping(Ping { nonce: 3, source: ping_source }),
+        ))),
+        PeerMessage::Disconnect,
+    ];
+    for (from, to) in [
+        (Encoding::Proto, Encoding::Borsh),
+        (Encoding::Borsh, Encoding::Proto),
+        (Encoding::Proto, Encoding::Proto),
+    ] {
+        for m in &msgs {
+            let got =
+                PeerMessage::reencode(&m.serialize(from), from, to, &data::make_decode_config())
+                    .with_context(|| m.to_string())?;
+            assert_eq!(
+                m,
+                &PeerMessage::deserialize(to, &got, &data::make_decode_config())
+                    .with_context(|| m.to_string())?,
+                "from={from:?},to={to:?}"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn reencode_rejects_malformed_block() {
+    let msg = proto::PeerMessage {
+        message_type: Some(proto::peer_message::Message_type::BlockResponse(
+            proto::BlockResponse {
+                block: protobuf::MessageField::some(proto::Block {
+                    borsh: vec![7, 7, 7],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )),
+        ..Default::default()
+    };
+    let data = msg.write_to_bytes().unwrap();
+    // Unlike transcode, reencode decodes the block payload.
+    assert!(transcode(Encoding::Proto, Encoding::Borsh, &data, &data::make_decode_config()).is_ok());
+    assert_matches!(
+        PeerMessage::reencode(&data, Encoding::Proto, Encoding::Borsh, &data::make_decode_config()),
+        Err(TranscodeError::Decode(ParsePeerMessageError::ProtoConv(_)))
+    );
+    assert_matches!(
+        PeerMessage::reencode(&[], Encoding::Borsh, Encoding::Proto, &data::make_decode_config()),
+        Err(TranscodeError::Decode(ParsePeerMessageError::BorshDecode(_)))
+    );
+}
+
+#[test]
+fn supported_encodings_unknown_values() {
+    let msg = proto::PeerMessage::from(&PeerMessage::SupportedEncodings(vec![Encoding::Borsh]));
+    let mut x = msg.clone();
+    match &mut x.message_type {
+        Some(proto::peer_message::Message_type::SupportedEncodings(se)) => {
+            se.encodings.insert(0, protobuf::EnumOrUnknown::from_i32(1000));
+            se.encodings.push(proto::supported_encodings::Encoding::UNKNOWN.into());
+        }
+        _ => unreachable!(),
+    }
+    // Encodings unknown to us are dropped, the known ones are preserved.
+    let x = proto::PeerMessage::parse_from_bytes(&x.write_to_bytes().unwrap()).unwrap();
+    assert_eq!(
+        PeerMessage::SupportedEncodings(vec![Encoding::Borsh]),
+        PeerMessage::from_proto(&x, &data::make_decode_config()).unwrap()
+    );
+}
+
+#[test]
+fn negotiate_shared_encodings() {
+    use Encoding::*;
+    assert_eq!(vec![Proto, Borsh], shared_encodings(&[Proto, Borsh], &[Borsh, Proto]));
+    assert_eq!(vec![Borsh], shared_encodings(&[Proto, Borsh], &[Borsh]));
+    assert_eq!(vec![Proto], shared_encodings(&[Proto, Borsh], &[Proto, Proto]));
+    assert_eq!(Vec::<Encoding>::new(), shared_encodings(&[Proto], &[Borsh]));
+    assert_eq!(Vec::<Encoding>::new(), shared_encodings(&[Proto, Borsh], &[]));
+    // Borsh cannot carry SupportedEncodings.
+    let msg = PeerMessage::SupportedEncodings(SUPPORTED_ENCODINGS.to_vec());
+    let res = transcode(
+        Encoding::Proto,
+        Encoding::Borsh,
+        &msg.serialize(Encoding::Proto),
+        &data::make_decode_config(),
+    );
+    assert!(matches!(res, Err(TranscodeError::Unsupported(..))));
+}
+
+#[test]
+fn transcode_sync_accounts_data_to_borsh() {
+    let mut rng = make_rng(1840294);
+    let clock = time::FakeClock::default();
+    let msg = PeerMessage::SyncAccountsData(SyncAccountsData {
+        accounts_data: vec![Arc::new(data::make_signed_account_data(&mut rng, &clock.clock()))],
+        incremental: false,
+        requesting_full_sync: false,
+        total_count: 1,
+    });
+    let res = transcode(
+        Encoding::Proto,
+        Encoding::Borsh,
+        &msg.serialize(Encoding::Proto),
+        &data::make_decode_config(),
+    );
+    assert!(matches!(res, Err(TranscodeError::Unsupported(..))));
+    let res = PeerMessage::reencode(
+        &msg.serialize(Encoding::Proto),
+        Encoding::Proto,
+        Encoding::Borsh,
+        &data::make_decode_config(),
+    );
+    assert!(matches!(res, Err(TranscodeError::Unsupported(..))));
+}
+
+#[test]
+fn routing_table_update_fingerprint() {
+    let mut rng = make_rng(6019283);
+    let update = data::make_routing_table(&mut rng);
+    let fingerprint = update.fingerprint();
+
+    // Same content in a different order, with duplicates.
+    let mut shuffled = update.clone();
+    shuffled.edges.reverse();
+    shuffled.accounts.reverse();
+    shuffled.accounts.push(update.accounts[0].clone());
+    assert_ne!(update, shuffled);
+    assert_eq!(fingerprint, shuffled.fingerprint());
+
+    // Different content.
+    let mut fewer_edges = update.clone();
+    fewer_edges.edges.pop();
+    assert_ne!(fingerprint, fewer_edges.fingerprint());
+    let mut other_account = update.clone();
+    other_account.accounts[0] = data::make_announce_account(&mut rng);
+    assert_ne!(fingerprint, other_account.fingerprint());
+    // Edges and accounts are not interchangeable.
+    let accounts_only = RoutingTableUpdate::from_accounts(update.accounts.clone());
+    let edges_only = RoutingTableUpdate::from_edges(update.edges.clone());
+    assert_ne!(accounts_only.fingerprint(), edges_only.fingerprint());
+    assert_ne!(fingerprint, accounts_only.fingerprint());
+}
+
+#[test]
+fn sync_accounts_data_complete() {
+    let mut rng = make_rng(2948571);
+    let clock = time::FakeClock::default();
+    let accounts_data: Vec<_> = (0..3)
+        .map(|_| Arc::new(data::make_signed_account_data(&mut rng, &clock.clock())))
+        .collect();
+    let full = SyncAccountsData {
+        total_count: accounts_data.len(),
+        accounts_data,
+        incremental: false,
+        requesting_full_sync: false,
+    };
+    let roundtrip = |msg: &SyncAccountsData| {
+        let msg = PeerMessage::SyncAccountsData(msg.clone());
+        match PeerMessage::deserialize(
+            Encoding::Proto,
+            &msg.serialize(Encoding::Proto),
+            &data::make_decode_config(),
+        )
+        .unwrap()
+        {
+            PeerMessage::SyncAccountsData(msg) => msg,
+            msg => panic!("unexpected message {msg:?}"),
+        }
+    };
+
+    // Complete response.
+    let got = roundtrip(&full);
+    assert_eq!(full, got);
+    assert!(got.is_complete());
+
+    // Truncated response.
+    let mut truncated = full.clone();
+    truncated.accounts_data.pop();
+    let got = roundtrip(&truncated);
+    assert_eq!(3, got.total_count);
+    assert!(!got.is_complete());
+
+    // Without the declared count, the message is considered complete.
+    let undeclared = SyncAccountsData { total_count: 0, ..truncated };
+    assert!(roundtrip(&undeclared).is_complete());
+}
+
+#[test]
+fn epoch_sync_response_decode_deadline() {
+    let mut rng = make_rng(7283412);
+    let clock = time::FakeClock::default();
+    let signer = data::make_signer(&mut rng);
+    let h = |x: &[u8]| near_primitives::hash::hash(x);
+    let make_with_bps = |approvals: usize, with_bps: bool| EpochSyncResponse::Advance {
+        light_client_block_view: LightClientBlockView {
+            prev_block_hash: h(b"prev"),
+            next_block_inner_hash: h(b"next_inner"),
+            inner_lite: BlockHeaderInnerLiteView {
+                height: 74,
+                epoch_id: h(b"epoch"),
+                next_epoch_id: h(b"next_epoch"),
+                prev_state_root: h(b"state_root"),
+                outcome_root: h(b"outcome_root"),
+                timestamp: 1234,
+                timestamp_nanosec: 1234,
+                next_bp_hash: h(b"next_bp"),
+                block_merkle_root: h(b"block_merkle_root"),
+            },
+            inner_rest_hash: h(b"inner_rest"),
+            next_bps: with_bps.then(|| {
+                vec![ValidatorStakeView::V1(ValidatorStakeViewV1 {
+                    account_id: signer.account_id.clone(),
+                    public_key: signer.public_key(),
+                    stake: 1000,
+                })]
+            }),
+            approvals_after_next: (0..approvals)
+                .map(|i| if i % 2 == 0 { Some(signer.sign(b"approval")) } else { None })
+                .collect(),
+        },
+    };
+    let make = |approvals: usize| make_with_bps(approvals, true);
+    let decode = |resp: &EpochSyncResponse, deadline| {
+        proto_conv::decode_epoch_sync_response(
+            &clock.clock(),
+            deadline,
+            &resp.try_to_vec().unwrap(),
+            MAX_EPOCH_SYNC_RESPONSE_BYTES,
+        )
+    };
+
+    // decode_epoch_sync_response follows the borsh layout by hand, so every variant
+    // (including the optional and repeated fields) has to round trip. The match below
+    // stops compiling once a variant is added, so that it gets covered here as well.
+    let deadline = clock.now() + time::Duration::seconds(1);
+    for resp in [EpochSyncResponse::UpToDate, make(0), make(10), make_with_bps(10, false)] {
+        match &resp {
+            EpochSyncResponse::UpToDate | EpochSyncResponse::Advance { .. } => {}
+        }
+        assert_eq!(resp, decode(&resp, deadline).unwrap());
+    }
+    let mut bytes = make(10).try_to_vec().unwrap();
+    bytes.push(0);
+    assert!(matches!(
+        proto_conv::decode_epoch_sync_response(
+            &clock.clock(),
+            deadline,
+            &bytes,
+            MAX_EPOCH_SYNC_RESPONSE_BYTES
+        ),
+        Err(proto_conv::ParseEpochSyncResponseError::Borsh(_))
+    ));
+
+    // Decoding a large payload is aborted once the deadline passes.
+    let deadline = clock.now();
+    assert!(matches!(
+        decode(&make(100_000), deadline),
+        Err(proto_conv::ParseEpochSyncResponseError::Timeout)
+    ));
+
+    // An oversized response is rejected before decoding, even if it is valid.
+    let deadline = clock.now() + time::Duration::seconds(1);
+    let bytes = make(10).try_to_vec().unwrap();
+    let max = bytes.len() - 1;
+    assert!(matches!(
+        proto_conv::decode_epoch_sync_response(&clock.clock(), deadline, &bytes, max),
+        Err(proto_conv::ParseEpochSyncResponseError::TooLarge { got, max: m })
+            if got == bytes.len() && m == max
+    ));
+    // A response of exactly the limit is fully decoded.
+    assert_eq!(
+        make(10),
+        proto_conv::decode_epoch_sync_response(&clock.clock(), deadline, &bytes, bytes.len())
+            .unwrap()
+    );
+}
+
+#[test]
+fn routed_request_id() {
+    let mut rng = make_rng(2093847561);
+    let body = RoutedMessageBody::StateRequestPart(0, CryptoHash::hash_bytes(b"sync_hash"), 3);
+    let mut msg = data::make_routed_message(&mut rng, body);
+    assert_eq!(None, msg.request_id);
+    let hash = msg.hash();
+    msg.request_id = Some(17);
+    // The request id is not signed.
+    assert_eq!(hash, msg.hash());
+    assert!(msg.verify());
+
+    let msg = PeerMessage::Routed(Box::new(msg));
+    let got = PeerMessage::deserialize(
+        Encoding::Proto,
+        &msg.serialize(Encoding::Proto),
+        &data::make_decode_config(),
+    )
+    .unwrap();
+    assert_eq!(msg, got);
+    // Borsh encoding has no place for the request id.
+    let got = PeerMessage::deserialize(
+        Encoding::Borsh,
+        &msg.serialize(Encoding::Borsh),
+        &data::make_decode_config(),
+    )
+    .unwrap();
+    match (&msg, got) {
+        (PeerMessage::Routed(want), PeerMessage::Routed(got)) => {
+            assert_eq!(None, got.request_id);
+            assert_eq!(want.msg, got.msg);
+        }
+        (_, got) => panic!("unexpected message {got}"),
+    }
+}
+
+#[test]
+fn heartbeat() {
+    let mut rng = make_rng(7610293847);
+    let heartbeat =
+        data::make_routed_message(&mut rng, RoutedMessageBody::Heartbeat(Heartbeat { nonce: 17 }));
+    let ack = data::make_routed_message(
+        &mut rng,
+        RoutedMessageBody::HeartbeatAck(HeartbeatAck { nonce: 17 }),
+    );
+    assert!(heartbeat.expect_response());
+    assert!(!ack.expect_response());
+    for enc in [Encoding::Proto, Encoding::Borsh] {
+        for msg in [&heartbeat, &ack] {
+            let msg = PeerMessage::Routed(Box::new(msg.clone()));
+            assert_eq!(
+                msg,
+                PeerMessage::deserialize(enc, &msg.serialize(enc), &data::make_decode_config())
+                    .unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn encrypted_blob() {
+    let mut rng = make_rng(9182736450);
+    let blob = EncryptedBlob {
+        recipient: "alice.near".parse().unwrap(),
+        ciphertext: vec![0xab, 0xcd, 0xef, 0x12, 0x34],
+    };
+    let msg = PeerMessage::Routed(Box::new(data::make_routed_message(
+        &mut rng,
+        RoutedMessageBody::EncryptedBlob(blob.clone()),
+    )));
+    assert!(msg.is_client_message());
+    for enc in [Encoding::Proto, Encoding::Borsh] {
+        assert_eq!(
+            msg,
+            PeerMessage::deserialize(enc, &msg.serialize(enc), &data::make_decode_config())
+                .unwrap()
+        );
+    }
+    // Borsh tags of the existing variants must not change when new variants are added.
+    assert_eq!(21, RoutedMessageBody::EncryptedBlob(blob.clone()).try_to_vec().unwrap()[0]);
+
+    // Debug output contains the length of the ciphertext, but not its content.
+    assert_eq!(
+        format!("EncryptedBlob {{ recipient: {:?}, ciphertext_len: 5 }}", blob.recipient),
+        format!("{:?}", blob)
+    );
+    assert_eq!(
+        "EncryptedBlob(alice.near, 5 bytes)",
+        format!("{:?}", RoutedMessageBody::EncryptedBlob(blob))
+    );
+}
+
+#[test]
+fn tx_inclusion_proof() {
+    let mut rng = make_rng(5029384756);
+    let tx_hash = CryptoHash::hash_bytes(b"tx");
+    let outcome = ExecutionOutcomeWithIdAndProof {
+        proof: vec![],
+        block_hash: CryptoHash::hash_bytes(b"block"),
+        outcome_with_id: ExecutionOutcomeWithId {
+            id: tx_hash,
+            outcome: ExecutionOutcome {
+                executor_id: "alice.near".parse().unwrap(),
+                gas_burnt: 17,
+                ..Default::default()
+            },
+        },
+    };
+    let proof = TxInclusionProof {
+        outcome_proof: outcome.into(),
+        outcome_root_proof: vec![MerklePathItem {
+            hash: CryptoHash::hash_bytes(b"root"),
+            direction: Direction::Left,
+        }],
+    };
+    let request =
+        data::make_routed_message(&mut rng, RoutedMessageBody::TxInclusionProofRequest(tx_hash));
+    let response = data::make_routed_message(
+        &mut rng,
+        RoutedMessageBody::TxInclusionProofResponse(proof.clone()),
+    );
+    assert!(request.expect_response());
+    assert!(!response.expect_response());
+    for enc in [Encoding::Proto, Encoding::Borsh] {
+        for msg in [&request, &response] {
+            let msg = PeerMessage::Routed(Box::new(msg.clone()));
+            assert!(msg.is_view_client_message());
+            assert_eq!(
+                msg,
+                PeerMessage::deserialize(enc, &msg.serialize(enc), &data::make_decode_config())
+                    .unwrap()
+            );
+        }
+    }
+    // New variants are appended, so that the borsh tags of the existing ones don't change.
+    assert_eq!(23, RoutedMessageBody::TxInclusionProofRequest(tx_hash).try_to_vec().unwrap()[0]);
+    assert_eq!(
+        24,
+        RoutedMessageBody::TxInclusionProofResponse(proof.clone()).try_to_vec().unwrap()[0]
+    );
+    assert_eq!(format!("TxInclusionProofRequest({})", tx_hash), format!("{:?}", request.body));
+    assert_eq!(
+        format!("TxInclusionProofResponse({}, {})", tx_hash, proof.outcome_proof.block_hash),
+        format!("{:?}", response.body)
+    );
+}
+
+#[test]
+fn telemetry() {
+    let mut rng = make_rng(4810293756);
+    let telemetry = TelemetryData {
+        protocol_version: Some(57),
+        uptime: Some(UptimeBucket::LessThanDay),
+        tracked_shards: Some(vec![0, 3]),
+    };
+    let request = data::make_routed_message(&mut rng, RoutedMessageBody::TelemetryRequest);
+    let response = data::make_routed_message(
+        &mut rng,
+        RoutedMessageBody::TelemetryResponse(telemetry.clone()),
+    );
+    let declined = data::make_routed_message(
+        &mut rng,
+        RoutedMessageBody::TelemetryResponse(TelemetryData::default()),
+    );
+    assert!(request.expect_response());
+    assert!(!response.expect_response());
+    for enc in [Encoding::Proto, Encoding::Borsh] {
+        for msg in [&request, &response, &declined] {
+            let msg = PeerMessage::Routed(Box::new(msg.clone()));
+            assert_eq!(
+                msg,
+                PeerMessage::deserialize(enc, &msg.serialize(enc), &data::make_decode_config())
+                    .unwrap()
+            );
+        }
+    }
+    // New variants are appended, so that the borsh tags of the existing ones don't change.
+    assert_eq!(25, RoutedMessageBody::TelemetryRequest.try_to_vec().unwrap()[0]);
+    assert_eq!(
+        26,
+        RoutedMessageBody::TelemetryResponse(telemetry.clone()).try_to_vec().unwrap()[0]
+    );
+    assert_eq!("TelemetryRequest", format!("{:?}", request.body));
+    assert_eq!(
+        "TelemetryResponse(TelemetryData { protocol_version: None, uptime: None, tracked_shards: None })",
+        format!("{:?}", declined.body)
+    );
+    assert!(!telemetry.is_empty());
+    assert!(TelemetryData::default().is_empty());
+}
+
+#[test]
+fn uptime_bucket() {
+    let bucket = |uptime| UptimeBucket::from_uptime(uptime);
+    assert_eq!(UptimeBucket::LessThanHour, bucket(time::Duration::ZERO));
+    assert_eq!(UptimeBucket::LessThanHour, bucket(time::Duration::minutes(59)));
+    assert_eq!(UptimeBucket::LessThanDay, bucket(time::Duration::HOUR));
+    assert_eq!(UptimeBucket::LessThanWeek, bucket(time::Duration::DAY));
+    assert_eq!(UptimeBucket::AtLeastWeek, bucket(time::Duration::WEEK));
+    assert_eq!(UptimeBucket::AtLeastWeek, bucket(time::Duration::days(400)));
+}
+
+#[test]
+fn block_chunks() {
+    let mut rng = make_rng(7102938465);
+    let mut clock = time::FakeClock::default();
+    let chain = data::Chain::make(&mut clock, &mut rng, 3);
+    let block = chain.blocks.last().unwrap();
+    let chunks: Vec<_> = block
+        .chunks()
+        .iter()
+        .map(|header| PartialEncodedChunk::new(header.clone(), vec![], vec![]))
+        .collect();
+    assert_eq!(4, chunks.len());
+    // Chunks of the same shards, but of another chain.
+    let other_chunks: Vec<_> = genesis_chunks(
+        vec![CryptoHash::hash_bytes(b"other_state_root")],
+        4,
+        1000,
+        0,
+        near_primitives::version::PROTOCOL_VERSION,
+    )
+    .into_iter()
+    .map(|chunk| PartialEncodedChunk::new(chunk.take_header(), vec![], vec![]))
+    .collect();
+    assert_ne!(chunks[1].chunk_hash(), other_chunks[1].chunk_hash());
+
+    // Complete set, in any order.
+    assert_eq!(Vec::<ShardId>::new(), verify_block_chunks(block, &chunks));
+    let mut reversed = chunks.clone();
+    reversed.reverse();
+    assert_eq!(Vec::<ShardId>::new(), verify_block_chunks(block, &reversed));
+
+    // Missing chunk.
+    let mut missing = chunks.clone();
+    missing.remove(2);
+    assert_eq!(vec![2], verify_block_chunks(block, &missing));
+    assert_eq!(vec![0, 1, 2, 3], verify_block_chunks(block, &[]));
+
+    // Mismatched chunk, either instead of the right one or next to it.
+    let mut mismatched = chunks.clone();
+    mismatched[1] = other_chunks[1].clone();
+    assert_eq!(vec![1], verify_block_chunks(block, &mismatched));
+    let mut extra = chunks.clone();
+    extra.push(other_chunks[3].clone());
+    assert_eq!(vec![3], verify_block_chunks(block, &extra));
+}
+
+#[test]
+fn oversized_block() {
+    let mut rng = make_rng(2039481720);
+    let mut clock = time::FakeClock::default();
+    let chain = data::Chain::make(&mut clock, &mut rng, 2);
+    let block = chain.blocks[1].clone();
+    let header = block.header().clone();
+
+    let cfg = data::make_decode_config();
+
+    // Valid block and header pass.
+    assert_eq!(block, proto_conv::block_from_proto(&(&block).into(), &cfg).unwrap());
+    assert_eq!(header, proto_conv::block_header_from_proto(&(&header).into(), &cfg).unwrap());
+
+    // Oversized blobs are rejected before decoding.
+    let oversized = proto::Block { borsh: vec![0; MAX_BLOCK_BYTES + 1], ..Default::default() };
+    assert!(matches!(
+        proto_conv::block_from_proto(&oversized, &cfg),
+        Err(proto_conv::ParseBlockError::TooLarge { got, max: MAX_BLOCK_BYTES }) if got == MAX_BLOCK_BYTES + 1
+    ));
+    let oversized =
+        proto::BlockHeader { borsh: vec![0; MAX_BLOCK_HEADER_BYTES + 1], ..Default::default() };
+    assert!(matches!(
+        proto_conv::block_header_from_proto(&oversized, &cfg),
+        Err(proto_conv::ParseBlockHeaderError::TooLarge { .. })
+    ));
+
+    // The limits are configurable: with limits lower than the size of the valid
+    // block and header, the messages carrying them get rejected as well.
+    let block_len = block.try_to_vec().unwrap().len();
+    let msg = PeerMessage::Block(block);
+    let cfg = DecodeConfig { max_block_bytes: block_len - 1, ..data::make_decode_config() };
+    assert_matches!(
+        PeerMessage::deserialize(Encoding::Proto, &msg.serialize(Encoding::Proto), &cfg),
+        Err(ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::BlockResponse(_)))
+    );
+    let header_len = header.try_to_vec().unwrap().len();
+    let msg = PeerMessage::BlockHeaders(vec![header]);
+    let cfg = DecodeConfig { max_block_header_bytes: header_len - 1, ..data::make_decode_config() };
+    assert_matches!(
+        PeerMessage::deserialize(Encoding::Proto, &msg.serialize(Encoding::Proto), &cfg),
+        Err(ParsePeerMessageError::ProtoConv(
+            proto_conv::ParsePeerMessageError::BlockHeadersResponse(_)
+        ))
+    );
+}
+
+#[test]
+fn oversized_transaction() {
+    use near_primitives::transaction::{Action, DeployContractAction};
+    let mut rng = make_rng(7731920456);
+    let signer = data::make_signer(&mut rng);
+    let oversized = SignedTransaction::from_actions(
+        1,
+        signer.account_id.clone(),
+        data::make_account_id(&mut rng),
+        &signer,
+        vec![Action::DeployContract(DeployContractAction { code: vec![0; MAX_TRANSACTION_BYTES] })],
+        CryptoHash::default(),
+    );
+    let valid = data::make_signed_transaction(&mut rng);
+    let routed = |tx: &SignedTransaction, rng: &mut _| {
+        PeerMessage::Routed(Box::new(data::make_routed_message(
+            rng,
+            RoutedMessageBody::ForwardTx(tx.clone()),
+        )))
+    };
+
+    // Valid transactions pass.
+    for msg in [PeerMessage::Transaction(valid.clone()), routed(&valid, &mut rng)] {
+        let got = PeerMessage::deserialize(
+            Encoding::Proto,
+            &msg.serialize(Encoding::Proto),
+            &data::make_decode_config(),
+        );
+        assert_eq!(msg, got.unwrap());
+    }
+
+    // Oversized transactions are rejected.
+    let msg = PeerMessage::Transaction(oversized.clone());
+    assert!(matches!(
+        PeerMessage::deserialize(
+            Encoding::Proto,
+            &msg.serialize(Encoding::Proto),
+            &data::make_decode_config()
+        ),
+        Err(ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::Transaction(
+            proto_conv::ParseTransactionError::TooLarge { max: MAX_TRANSACTION_BYTES, .. }
+        )))
+    ));
+    let msg = routed(&oversized, &mut rng);
+    assert!(matches!(
+        PeerMessage::deserialize(
+            Encoding::Proto,
+            &msg.serialize(Encoding::Proto),
+            &data::make_decode_config()
+        ),
+        Err(ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::ForwardTx(
+            proto_conv::ParseTransactionError::TooLarge { max: MAX_TRANSACTION_BYTES, .. }
+        )))
+    ));
+
+    // The limit is configurable: with a limit lower than the size of the valid
+    // transaction, it gets rejected as well.
+    let tx_len = valid.try_to_vec().unwrap().len();
+    let cfg = DecodeConfig { max_transaction_bytes: tx_len - 1, ..data::make_decode_config() };
+    let msg = PeerMessage::Transaction(valid.clone());
+    assert_matches!(
+        PeerMessage::deserialize(Encoding::Proto, &msg.serialize(Encoding::Proto), &cfg),
+        Err(ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::Transaction(
+            proto_conv::ParseTransactionError::TooLarge { got, max }
+        ))) if got == tx_len && max == tx_len - 1
+    );
+    let msg = routed(&valid, &mut rng);
+    assert_matches!(
+        PeerMessage::deserialize(Encoding::Proto, &msg.serialize(Encoding::Proto), &cfg),
+        Err(ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::ForwardTx(
+            proto_conv::ParseTransactionError::TooLarge { got, max }
+        ))) if got == tx_len && max == tx_len - 1
+    );
+}
+
+#[test]
+fn oversized_challenge() {
+    let mut rng = make_rng(4417203958);
+    // Valid challenge passes.
+    let msg = PeerMessage::Challenge(data::make_challenge(&mut rng));
+    let got = PeerMessage::deserialize(
+        Encoding::Proto,
+        &msg.serialize(Encoding::Proto),
+        &data::make_decode_config(),
+    );
+    assert_eq!(msg, got.unwrap());
+
+    // Oversized blob is rejected before decoding.
+    let msg = proto::PeerMessage {
+        message_type: Some(proto::peer_message::Message_type::Challenge(proto::Challenge {
+            borsh: vec![0; MAX_CHALLENGE_BYTES + 1],
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+    assert!(matches!(
+        PeerMessage::from_proto(&msg, &data::make_decode_config()),
+        Err(proto_conv::ParsePeerMessageError::Challenge(
+            proto_conv::ParseChallengeError::TooLarge { got, max: MAX_CHALLENGE_BYTES }
+        )) if got == MAX_CHALLENGE_BYTES + 1
+    ));
+
+    // The limit is configurable: with a limit lower than the size of the valid
+    // challenge, it gets rejected as well.
+    let challenge = data::make_challenge(&mut rng);
+    let challenge_len = challenge.try_to_vec().unwrap().len();
+    let msg = PeerMessage::Challenge(challenge);
+    let cfg = DecodeConfig { max_challenge_bytes: challenge_len - 1, ..data::make_decode_config() };
+    assert_matches!(
+        PeerMessage::deserialize(Encoding::Proto, &msg.serialize(Encoding::Proto), &cfg),
+        Err(ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::Challenge(
+            proto_conv::ParseChallengeError::TooLarge { got, max }
+        ))) if got == challenge_len && max == challenge_len - 1
+    );
+}
+
+#[test]
+fn oversized_epoch_sync_response() {
+    // Valid response passes.
+    let msg = PeerMessage::EpochSyncResponse(Box::new(EpochSyncResponse::UpToDate));
+    let got = PeerMessage::deserialize(
+        Encoding::Proto,
+        &msg.serialize(Encoding::Proto),
+        &data::make_decode_config(),
+    );
+    assert_eq!(msg, got.unwrap());
+
+    // Oversized blob is rejected before decoding.
+    let msg = proto::PeerMessage {
+        message_type: Some(proto::peer_message::Message_type::EpochSyncResponse(
+            proto::EpochSyncResponse {
+                borsh: vec![0; MAX_EPOCH_SYNC_RESPONSE_BYTES + 1],
+                ..Default::default()
+            },
+        )),
+        ..Default::default()
+    };
+    assert!(matches!(
+        PeerMessage::from_proto(&msg, &data::make_decode_config()),
+        Err(proto_conv::ParsePeerMessageError::EpochSyncResponse(
+            proto_conv::ParseEpochSyncResponseError::TooLarge {
+                got,
+                max: MAX_EPOCH_SYNC_RESPONSE_BYTES
+            }
+        )) if got == MAX_EPOCH_SYNC_RESPONSE_BYTES + 1
+    ));
+
+    // The limit is configurable: with a limit lower than the size of the valid
+    // response, it gets rejected as well.
+    let response = EpochSyncResponse::UpToDate;
+    let response_len = response.try_to_vec().unwrap().len();
+    let msg = PeerMessage::EpochSyncResponse(Box::new(response));
+    let cfg = DecodeConfig {
+        max_epoch_sync_response_bytes: response_len - 1,
+        ..data::make_decode_config()
+    };
+    assert_matches!(
+        PeerMessage::deserialize(Encoding::Proto, &msg.serialize(Encoding::Proto), &cfg),
+        Err(ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::EpochSyncResponse(
+            proto_conv::ParseEpochSyncResponseError::TooLarge { got, max }
+        ))) if got == response_len && max == response_len - 1
+    );
+}
+
+#[test]
+fn routed_body_size_limit() {
+    let mut rng = make_rng(5830192741);
+    let mut clock = time::FakeClock::default();
+    let chain = data::Chain::make(&mut clock, &mut rng, 2);
+    let chunk_hash = chain.blocks[1].chunks()[0].chunk_hash();
+    let msg = data::make_routed_message(
+        &mut rng,
+        RoutedMessageBody::PartialEncodedChunkResponse(PartialEncodedChunkResponseMsg {
+            chunk_hash: chunk_hash.clone(),
+            parts: data::make_chunk_parts(chain.chunks[&chunk_hash].clone()),
+            receipts: vec![],
+        }),
+    );
+    let peer_msg = PeerMessage::Routed(Box::new(msg.clone()));
+    let msg = msg.msg;
+    let encoded = msg.try_to_vec().unwrap();
+    let body_len = msg.body.try_to_vec().unwrap().len();
+
+    // Valid message passes, also when the body size is exactly at the limit.
+    assert_eq!(
+        msg,
+        proto_conv::decode_routed_message(&encoded, MAX_ROUTED_BODY_BYTES, MAX_TRANSACTION_BYTES)
+            .unwrap()
+    );
+    assert_eq!(
+        msg,
+        proto_conv::decode_routed_message(&encoded, body_len, MAX_TRANSACTION_BYTES).unwrap()
+    );
+
+    // Oversized body is rejected.
+    assert_matches!(
+        proto_conv::decode_routed_message(&encoded, body_len - 1, MAX_TRANSACTION_BYTES),
+        Err(proto_conv::ParsePeerMessageError::Routed(proto_conv::ParseRoutedError::TooLarge { got, max })) if got == body_len && max == body_len - 1
+    );
+
+    // The list of parts declares way more elements than there are bytes left.
+    // Such a body is rejected, whether it exceeds the limit or not.
+    let header_len = encoded.len() - body_len;
+    let mut hostile = encoded[..header_len].to_vec();
+    hostile.push(msg.body.try_to_vec().unwrap()[0]);
+    hostile.extend(chunk_hash.try_to_vec().unwrap());
+    hostile.extend(u32::MAX.to_le_bytes());
+    hostile.resize(hostile.len() + 1000, 0);
+    assert_matches!(
+        proto_conv::decode_routed_message(&hostile, 1000, MAX_TRANSACTION_BYTES),
+        Err(proto_conv::ParsePeerMessageError::Routed(
+            proto_conv::ParseRoutedError::TooLarge { .. }
+        ))
+    );
+    assert_matches!(
+        proto_conv::decode_routed_message(&hostile, MAX_ROUTED_BODY_BYTES, MAX_TRANSACTION_BYTES),
+        Err(proto_conv::ParsePeerMessageError::Routed(proto_conv::ParseRoutedError::Decode(err))) if err.kind() == std::io::ErrorKind::InvalidInput
+    );
+    // The configured limit applies to the routed messages in proto PeerMessages.
+    let data = peer_msg.serialize(Encoding::Proto);
+    let cfg = DecodeConfig { max_routed_body_bytes: body_len - 1, ..data::make_decode_config() };
+    assert_matches!(
+        PeerMessage::deserialize(Encoding::Proto, &data, &cfg),
+        Err(ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::Routed(
+            proto_conv::ParseRoutedError::TooLarge { .. }
+        )))
+    );
+}
+
+#[test]
+fn routed_message_body_variant_names() {
+    let names = RoutedMessageBody::all_variant_names();
+    for want in [
+        "BlockApproval",
+        "ForwardTx",
+        "TxStatusRequest",
+        "TxStatusResponse",
+        "ReceiptOutcomeRequest",
+        "StateRequestHeader",
+        "StateRequestPart",
+        "StateResponse",
+        "PartialEncodedChunkRequest",
+        "PartialEncodedChunkResponse",
+        "Ping",
+        "Pong",
+        "VersionedPartialEncodedChunk",
+        "VersionedStateResponse",
+        "PartialEncodedChunkForward",
+        "Heartbeat",
+        "HeartbeatAck",
+        "EncryptedBlob",
+        "StateRequestPartBusy",
+        "TxInclusionProofRequest",
+        "TxInclusionProofResponse",
+        "TelemetryRequest",
+        "TelemetryResponse",
+    ] {
+        assert!(names.contains(&want), "{want} missing from {names:?}");
+    }
+    // Labels produced at runtime come from the same set.
+    let mut rng = make_rng(3453489);
+    let msg =
+        data::make_routed_message(&mut rng, RoutedMessageBody::Heartbeat(Heartbeat { nonce: 7 }));
+    assert!(names.contains(&msg.body_variant()));
+}
+
+#[test]
+fn verify_accounts_data_memoizes_keys() {
+    let mut rng = make_rng(9123784502);
+    let clock = time::FakeClock::default();
+    let epoch_id = data::make_epoch_id(&mut rng);
+    let signers: Vec<_> = (0..3).map(|_| data::make_validator_signer(&mut rng)).collect();
+    let mut make = |signer: &InMemoryValidatorSigner| {
+        data::make_account_data(
+            &mut rng,
+            clock.now_utc(),
+            epoch_id.clone(),
+            signer.validator_id().clone(),
+        )
+        .sign(signer)
+        .unwrap()
+    };
+    let mut corrupted = make(&signers[1]);
+    *corrupted.payload_mut() = vec![7; 10];
+    let entries: Vec<_> = [
+        make(&signers[0]),
+        make(&signers[1]),
+        make(&signers[0]),
+        corrupted,
+        // signers[2] is not a known account.
+        make(&signers[2]),
+        make(&signers[2]),
+    ]
+    .into_iter()
+    .map(Arc::new)
+    .collect();
+
+    let lookups = std::cell::Cell::new(0);
+    let got = verify_accounts_data(&entries, |account_id, got_epoch_id| {
+        lookups.set(lookups.get() + 1);
+        assert_eq!(&epoch_id, got_epoch_id);
+        signers[..2].iter().find(|s| s.validator_id() == account_id).map(|s| s.public_key())
+    });
+    assert_eq!(
+        vec![
+            Ok(()),
+            Ok(()),
+            Ok(()),
+            Err(VerifyAccountDataError::InvalidSignature),
+            Err(VerifyAccountDataError::UnknownAccount),
+            Err(VerifyAccountDataError::UnknownAccount),
+        ],
+        got
+    );
+    // One lookup per distinct (account_id,epoch_id).
+    assert_eq!(3, lookups.get());
+}
+
+#[test]
+fn nonce_ordering() {
+    let mut rng = make_rng(8734512);
+    let [a, b] = [0, 1].map(|_| PeerId::new(data::make_signer(&mut rng).public_key));
+    let (a, b) = Edge::make_key(a, b);
+    let t = *EDGE_MIN_TIMESTAMP_NONCE + time::Duration::days(2);
+    let legacy = Nonce(7);
+    let created = Nonce::from_utc(t);
+    let recreated = Nonce::from_utc(t + time::Duration::seconds(10));
+
+    // Timestamp nonces are always active, and round up to the next odd second.
+    assert_eq!(EdgeState::Active, created.edge_state());
+    assert_eq!(created, Nonce::from_utc(t + time::Duration::seconds(1)));
+    assert!(matches!(legacy.to_utc(), Ok(None)));
+    assert!(matches!(created.to_utc(), Ok(Some(_))));
+
+    // Any timestamp nonce is newer than any legacy nonce.
+    assert!(legacy < created);
+    assert!(legacy.next_active() < created);
+    // Timestamp nonces are ordered by time.
+    assert!(created < recreated);
+    assert!(created.is_older_than(t + time::Duration::seconds(5)));
+    assert!(!recreated.is_older_than(t + time::Duration::seconds(5)));
+    // Legacy nonces never expire.
+    assert!(!legacy.is_older_than(t));
+
+    // Removal is newer than the addition it removes, but older than the next addition.
+    for nonce in [legacy, created] {
+        let removal = Nonce(nonce.0 + 1);
+        assert_eq!(EdgeState::Removed, removal.edge_state());
+        assert!(nonce < removal);
+        assert!(removal < removal.next_active());
+        assert_eq!(nonce.next_active(), removal.next_active());
+    }
+    assert!(Nonce(created.0 + 1) < recreated);
+
+    // Edges follow the order of their nonces.
+    let edge = |nonce| Edge::make_fake_edge(a.clone(), b.clone(), nonce);
+    assert!(edge(created).supersedes(&edge(legacy)));
+    assert!(edge(recreated).supersedes(&edge(created)));
+    assert!(!edge(created).supersedes(&edge(recreated)));
+}
+
+#[test]
+fn edge_supersedes() {
+    let mut rng = make_rng(2348923);
+    let [a, b, c] = [0, 1, 2].map(|_| PeerId::new(data::make_signer(&mut rng).public_key));
+    let edge = |p0: &PeerId, p1: &PeerId, nonce| {
+        let (p0, p1) = Edge::make_key(p0.clone(), p1.clone());
+        Edge::make_fake_edge(p0, p1, Nonce(nonce))
+    };
+    let current = edge(&a, &b, 5);
+    // Higher nonce for the same pair of peers, regardless of the order of the peers.
+    assert!(edge(&a, &b, 7).supersedes(&current));
+    assert!(edge(&b, &a, 6).supersedes(&current));
+    // Same or lower nonce.
+    assert!(!edge(&a, &b, 5).supersedes(&current));
+    assert!(!edge(&a, &b, 3).supersedes(&current));
+    // Mismatched endpoints.
+    assert!(!edge(&a, &c, 7).supersedes(&current));
+    assert!(!edge(&c, &b, 7).supersedes(&current));
+}
+
+#[test]
+fn peer_addr_display_redacted() {
+    let mut rng = make_rng(3298742);
+    let peer_id = data::make_peer_id(&mut rng);
+    let redacted = |addr: &str| {
+        PeerAddr { addr: addr.parse().unwrap(), peer_id: peer_id.clone() }.display_redacted()
+    };
+    assert_eq!(format!("{peer_id}@12.34.56.0:24567"), redacted("12.34.56.78:24567"));
+    assert_eq!(format!("{peer_id}@10.0.0.0:1"), redacted("10.0.0.255:1"));
+    assert_eq!(
+        format!("{peer_id}@[2001:db8:85a3:8d3::]:3000"),
+        redacted("[2001:db8:85a3:8d3:1319:8a2e:370:7348]:3000")
+    );
+    assert_eq!(format!("{peer_id}@[::]:80"), redacted("[::1]:80"));
+}
+
+#[test]
+fn peers_response_chunks() {
+    let mut rng = make_rng(2389472);
+    let peers: Vec<_> = (0..100).map(|_| data::make_peer_info(&mut rng)).collect();
+    for enc in [Encoding::Proto, Encoding::Borsh] {
+        let max_bytes = 1000;
+        let chunks = PeerMessage::peers_response_chunks(enc, peers.clone(), max_bytes);
+        assert!(chunks.len() > 1);
+        let mut got = vec![];
+        for chunk in chunks {
+            let data = chunk.serialize(enc);
+            assert!(data.len() <= max_bytes, "{enc:?}: chunk of {} bytes", data.len());
+            match PeerMessage::deserialize(enc, &data, &data::make_decode_config()).unwrap() {
+                PeerMessage::PeersResponse(chunk) => got.extend(chunk),
+                msg => panic!("unexpected message {msg}"),
+            }
+        }
+        assert_eq!(peers, got);
+    }
+}
+
+#[test]
+fn partial_encoded_chunk_forward_verify_parts() {
+    let parts: Vec<Box<[u8]>> = (0..4u8).map(|i| vec![i; 10].into_boxed_slice()).collect();
+    let (merkle_root, proofs) = near_primitives::merkle::merklize(&parts);
+    let mut forward = PartialEncodedChunkForwardMsg {
+        chunk_hash: ChunkHash(CryptoHash::default()),
+        inner_header_hash: CryptoHash::default(),
+        merkle_root,
+        signature: Signature::default(),
+        prev_block_hash: CryptoHash::default(),
+        height_created: 1,
+        shard_id: 0,
+        parts: parts
+            .into_iter()
+            .zip(proofs)
+            .enumerate()
+            .map(|(i, (part, merkle_proof))| PartialEncodedChunkPart {
+                part_ord: i as u64,
+                part,
+                merkle_proof,
+            })
+            .collect(),
+    };
+    assert_eq!(Ok(()), forward.verify_parts());
+
+    // Part 2 carries the proof of part 3.
+    forward.parts[2].merkle_proof = forward.parts[3].merkle_proof.clone();
+    assert_eq!(
+        Err(PartsValidationError::InvalidMerkleProof { part_ord: 2 }),
+        forward.verify_parts()
+    );
+}
+
+#[test]
+fn partial_encoded_chunk_forward_validate() {
+    let inner_header_hash = CryptoHash::hash_bytes(b"inner header");
+    let merkle_root = CryptoHash::hash_bytes(b"merkle root");
+    let mut forward = PartialEncodedChunkForwardMsg {
+        chunk_hash: ChunkHash(combine_hash(&inner_header_hash, &merkle_root)),
+        inner_header_hash,
+        merkle_root,
+        signature: Signature::default(),
+        prev_block_hash: CryptoHash::default(),
+        height_created: 1,
+        shard_id: 3,
+        parts: vec![],
+    };
+    let shard_layout = ShardLayout::v0(4, 0);
+    assert_eq!(Ok(()), forward.validate(&shard_layout));
+
+    // Shard ids are numbered from 0.
+    forward.shard_id = 4;
+    assert_eq!(
+        Err(ForwardValidationError::InvalidShardId { shard_id: 4, num_shards: 4 }),
+        forward.validate(&shard_layout)
+    );
+    forward.shard_id = u64::MAX;
+    assert_eq!(
+        Err(ForwardValidationError::InvalidShardId { shard_id: u64::MAX, num_shards: 4 }),
+        forward.validate(&shard_layout)
+    );
+
+    forward.shard_id = 0;
+    forward.merkle_root = CryptoHash::default();
+    assert_eq!(Err(ForwardValidationError::InvalidHash), forward.validate(&shard_layout));
+}
+
+#[test]
+fn routing_table_update_merge() {
+    let mut rng = make_rng(5327843);
+    let [a, b, c] = [0, 1, 2].map(|_| PeerId::new(data::make_signer(&mut rng).public_key));
+    let edge = |p0: &PeerId, p1: &PeerId, nonce| {
+        let (p0, p1) = Edge::make_key(p0.clone(), p1.clone());
+        Edge::make_fake_edge(p0, p1, Nonce(nonce))
+    };
+    let aa = data::make_announce_account(&mut rng);
+    let aa_new = AnnounceAccount { epoch_id: data::make_epoch_id(&mut rng), ..aa.clone() };
+    let other_aa = data::make_announce_account(&mut rng);
+
+    let x = RoutingTableUpdate::new(
+        vec![edge(&a, &b, 7), edge(&b, &c, 1), edge(&a, &b, 3)],
+        vec![aa, other_aa.clone()],
+    );
+    let y = RoutingTableUpdate::new(
+        vec![edge(&b, &a, 5), edge(&c, &b, 4), edge(&a, &c, 2)],
+        vec![aa_new.clone()],
+    );
+    let got = x.clone().merge(y.clone());
+
+    // The newer edge wins, regardless of which update it comes from.
+    let mut want_edges = vec![edge(&a, &b, 7), edge(&b, &c, 4), edge(&a, &c, 2)];
+    want_edges.sort_by(|x, y| x.key().cmp(y.key()));
+    assert_eq!(want_edges, got.edges);
+    let mut want_accounts = vec![aa_new, other_aa];
+    want_accounts.sort_by(|x, y| x.account_id.cmp(&y.account_id));
+    assert_eq!(want_accounts, got.accounts);
+    // Merging is idempotent.
+    assert_eq!(got, got.clone().merge(got.clone()));
+    assert_eq!(got.edges, y.merge(x).edges);
+}
+
+#[test]
+fn routed_message_is_deliverable() {
+    let mut rng = make_rng(2384023);
+    let source = PeerId::new(data::make_signer(&mut rng).public_key);
+    let mut msg =
+        data::make_routed_message(&mut rng, RoutedMessageBody::Ping(Ping { nonce: 1, source }));
+    assert!(msg.is_deliverable());
+    msg.ttl = 1;
+    assert!(msg.is_deliverable());
+    assert!(!msg.decrease_ttl());
+    assert!(!msg.is_deliverable());
+}
+
+// Signing a message with zero TTL => panic (in debug mode).
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "zero ttl")]
+fn sign_zero_ttl() {
+    let mut rng = make_rng(2384023);
+    let signer = data::make_signer(&mut rng);
+    let target = PeerId::new(data::make_signer(&mut rng).public_key);
+    let body = RoutedMessageBody::Ping(Ping { nonce: 1, source: target.clone() });
+    RawRoutedMessage { target: AccountOrPeerIdOrHash::PeerId(target), body }
+        .sign(&signer.secret_key, 0, None)
+        .unwrap();
+}
+
+#[test]
+fn account_or_peer_id_or_hash_conversions() {
+    let mut rng = make_rng(9012384);
+    let account_id = data::make_account_id(&mut rng);
+    let peer_id = data::make_peer_id(&mut rng);
+    let hash = CryptoHash::hash_bytes(b"target");
+
+    let target = AccountOrPeerIdOrHash::AccountId(account_id.clone());
+    assert_eq!(Some(&account_id), target.as_account_id());
+    assert_eq!(None, target.as_peer_id());
+    assert_eq!(None, target.as_hash());
+    assert_eq!(
+        Err(RoutingTargetError::AccountId(account_id.clone())),
+        target.try_into_peer_id_or_hash()
+    );
+
+    let target = AccountOrPeerIdOrHash::PeerId(peer_id.clone());
+    assert_eq!(None, target.as_account_id());
+    assert_eq!(Some(&peer_id), target.as_peer_id());
+    assert_eq!(None, target.as_hash());
+    assert_eq!(Ok(PeerIdOrHash::PeerId(peer_id.clone())), target.try_into_peer_id_or_hash());
+
+    let target = AccountOrPeerIdOrHash::Hash(hash);
+    assert_eq!(None, target.as_account_id());
+    assert_eq!(None, target.as_peer_id());
+    assert_eq!(Some(&hash), target.as_hash());
+    assert_eq!(Ok(PeerIdOrHash::Hash(hash)), target.try_into_peer_id_or_hash());
+
+    // Signing a message addressed to an account fails instead of panicking.
+    let signer = data::make_signer(&mut rng);
+    let body = RoutedMessageBody::Ping(Ping { nonce: 1, source: peer_id });
+    let got =
+        RawRoutedMessage { target: AccountOrPeerIdOrHash::AccountId(account_id.clone()), body }
+            .sign(&signer.secret_key, 1, None);
+    assert_eq!(Err(RoutingTargetError::AccountId(account_id)), got.map(|_| ()));
+}
+
+#[test]
+fn peek_routing_header_matches_full_decode() -> anyhow::Result<()> {
+    let mut rng = make_rng(3894203);
+    let mut clock = time::FakeClock::default();
+    let chain = data::Chain::make(&mut clock, &mut rng, 4);
+    let chunk_hash = chain.blocks[3].chunks()[0].chunk_hash();
+    let signer = data::make_signer(&mut rng);
+    let ping_source = data::make_peer_id(&mut rng);
+    let msgs = [
+        PeerMessage::Handshake(data::make_handshake(&mut rng, &chain)),
+        PeerMessage::PeersRequest,
+        PeerMessage::Block(chain.blocks[2].clone()),
+        PeerMessage::Transaction(data::make_signed_transaction(&mut rng)),
+        PeerMessage::Routed(Box::new(data::make_routed_message(
+            &mut rng,
+            RoutedMessageBody::Ping(Ping { nonce: 3, source: ping_source }),
+        ))),
+        // Body much larger than the header.
+        PeerMessage::Routed(Box::new(data::make_routed_message(
+            &mut rng,
+            RoutedMessageBody::PartialEncodedChunkResponse(PartialEncodedChunkResponseMsg {
+                chunk_hash: chunk_hash.clone(),
+                parts: data::make_chunk_parts(chain.chunks[&chunk_hash].clone()),
+                receipts: vec![],
+            }),
+        ))),
+        PeerMessage::Routed(Box::new(
+            RawRoutedMessage {
+                target: AccountOrPeerIdOrHash::Hash(chain.blocks[1].hash().clone()),
+                body: RoutedMessageBody::Pong(Pong {
+                    nonce: 4,
+                    source: data::make_peer_id(&mut rng),
+                }),
+            }
+            .sign(&signer.secret_key, 7, None)
+            .unwrap(),
+        )),
+        PeerMessage::Disconnect,
+    ];
+    for enc in [Encoding::Proto, Encoding::Borsh] {
+        for m in &msgs {
+            let data = m.serialize(enc);
+            let got = peek_routing_header(enc, &data).with_context(|| m.to_string())?;
+            let full = PeerMessage::deserialize(enc, &data, &data::make_decode_config())?;
+            let want = RoutingHeader {
+                variant: (&full).into(),
+                routed: match &full {
+                    PeerMessage::Routed(r) => {
+                        Some(RoutedHeader { target: r.target.clone(), ttl: r.ttl })
+                    }
+                    _ => None,
+                },
+            };
+            assert_eq!(want, got, "encoding={enc:?}");
+        }
+    }
+    // A proto message without any of the oneof fields set.
+    assert!(peek_routing_header(Encoding::Proto, &[]).is_err());
+    Ok(())
+}
+
+fn make_peer_ring_peers(rng: &mut impl rand::Rng, n: usize) -> Vec<PeerAddr> {
+    (0..n)
+        .map(|_| {
+            let ip = data::make_ipv4(rng);
+            data::make_peer_addr(rng, ip)
+        })
+        .collect()
+}
+
+#[test]
+fn peer_ring_distribution() {
+    let mut rng = make_rng(5820351);
+    let peers = make_peer_ring_peers(&mut rng, 10);
+    let ring = PeerRing::new(peers.clone()).unwrap();
+    // The assignment doesn't depend on the order of peers.
+    let reversed = PeerRing::new(peers.iter().rev().cloned()).unwrap();
+
+    let accounts = 10000;
+    let mut counts: HashMap<PeerId, usize> = HashMap::new();
+    for _ in 0..accounts {
+        let account_id = data::make_account_id(&mut rng);
+        let peer = ring.route(&account_id);
+        assert_eq!(peer, reversed.route(&account_id));
+        *counts.entry(peer.peer_id.clone()).or_default() += 1;
+    }
+    let want = accounts / peers.len();
+    for p in &peers {
+        let got = counts.get(&p.peer_id).copied().unwrap_or(0);
+        assert!(
+            want * 6 / 10 <= got && got <= want * 14 / 10,
+            "peer got {got} accounts, want ~{want}"
+        );
+    }
+
+    assert!(PeerRing::new(vec![]).is_none());
+}
+
+#[test]
+fn peer_ring_reshuffling() {
+    let mut rng = make_rng(1923023);
+    let peers = make_peer_ring_peers(&mut rng, 11);
+    let accounts: Vec<_> = (0..5000).map(|_| data::make_account_id(&mut rng)).collect();
+    let small = PeerRing::new(peers[..10].iter().cloned()).unwrap();
+    let big = PeerRing::new(peers.iter().cloned()).unwrap();
+
+    // Adding peers[10] moves accounts only to peers[10] and removing it moves
+    // only its accounts, which is the same condition seen from the other side.
+    let mut moved = 0;
+    for account_id in &accounts {
+        let (before, after) = (small.route(account_id), big.route(account_id));
+        if before != after {
+            assert_eq!(&peers[10], after);
+            moved += 1;
+        }
+    }
+    // Roughly 1/11 of the accounts should move.
+    assert!(moved > 0);
+    assert!(moved <= 2 * accounts.len() / 11, "{moved} of {} accounts moved", accounts.len());
+
+    // Removing a peer from the middle moves only the accounts of that peer.
+    let removed = &peers[3];
+    let without = PeerRing::new(peers.iter().filter(|p| *p != removed).cloned()).unwrap();
+    for account_id in &accounts {
+        let (before, after) = (big.route(account_id), without.route(account_id));
+        if before != removed {
+            assert_eq!(before, after);
+        }
+        assert_ne!(removed, after);
+    }
+}
+
+#[test]
+fn edge_mutual_signatures() {
+    let mut rng = make_rng(8219534);
+    let [a, b, c] = [0, 1, 2].map(|_| data::make_signer(&mut rng));
+
+    let edge = data::make_edge(&a, &b);
+    assert!(edge.is_mutually_signed());
+    assert!(data::make_edge_tombstone(&a, &b).is_mutually_signed());
+
+    let (p0, p1) = edge.key().clone();
+    let nonce = edge.nonce();
+    let one_sided = Edge::new(
+        p0.clone(),
+        p1.clone(),
+        nonce,
+        edge.signature0().clone(),
+        near_crypto::Signature::empty(near_crypto::KeyType::ED25519),
+    );
+    assert!(!one_sided.is_mutually_signed());
+
+    // signature1 signed by a third party.
+    let hash = Edge::build_hash(&p0, &p1, nonce);
+    let invalid =
+        Edge::new(p0, p1, nonce, edge.signature0().clone(), c.secret_key.sign(hash.as_ref()));
+    assert!(!invalid.is_mutually_signed());
+}
+
+#[test]
+fn state_response_shard_id_within_layout() {
+    use near_primitives::shard_layout::ShardLayout;
+    use near_primitives::syncing::{
+        ShardStateSyncResponse, ShardStateSyncResponseV1, ShardStateSyncResponseV2,
+    };
+
+    let layout = ShardLayout::v0(4, 0);
+    let v1 = |shard_id: ShardId| {
+        StateResponseInfo::V1(StateResponseInfoV1 {
+            shard_id,
+            sync_hash: CryptoHash::default(),
+            state_response: ShardStateSyncResponseV1 { header: None, part: None },
+        })
+    };
+    let v2 = |shard_id: ShardId| {
+        StateResponseInfo::V2(StateResponseInfoV2 {
+            shard_id,
+            sync_hash: CryptoHash::default(),
+            state_response: ShardStateSyncResponse::V2(ShardStateSyncResponseV2 {
+                header: None,
+                part: None,
+            }),
+        })
+    };
+    let versions: [fn(ShardId) -> StateResponseInfo; 2] = [v1, v2];
+    for make in versions {
+        assert_eq!(Ok(()), make(0).validate_against_layout(&layout));
+        assert_eq!(Ok(()), make(3).validate_against_layout(&layout));
+        assert_eq!(
+            Err(StateResponseError::UnknownShard { shard_id: 4, num_shards: 4 }),
+            make(4).validate_against_layout(&layout)
+        );
+        assert!(make(ShardId::MAX).validate_against_layout(&layout).is_err());
+    }
+}
+
+/// Messages with fixed content, whose serialization is compared against the files in
+/// `res/golden`, to catch unintended changes of the wire format.
+fn golden_messages() -> Vec<(&'static str, PeerMessage)> {
+    let mut rng = make_rng(5120934871);
+    // The protocol versions and the timestamp are fixed, so that the golden files don't
+    // change with every protocol upgrade.
+    let chunks = genesis_chunks(vec![CryptoHash::default()], 2, 1000, 0, 57);
+    let block = Block::genesis(
+        57,
+        chunks.into_iter().map(|c| c.take_header()).collect(),
+        "2022-08-01T00:00:00Z".parse().unwrap(),
+        0,
+        1000,
+        1000,
+        CryptoHash::default(),
+    );
+    let handshake = Handshake {
+        protocol_version: 57,
+        oldest_supported_version: 54,
+        sender_peer_id: data::make_peer_id(&mut rng),
+        target_peer_id: data::make_peer_id(&mut rng),
+        sender_listen_port: Some(24567),
+        sender_chain_info: PeerChainInfoV2 {
+            genesis_id: GenesisId { chain_id: "testchain".to_string(), hash: *block.hash() },
+            tracked_shards: vec![0, 1],
+            archival: false,
+            height: block.header().height(),
+        },
+        partial_edge_info: data::make_partial_edge(&mut rng),
+    };
+    let source = data::make_peer_id(&mut rng);
+    let routed =
+        data::make_routed_message(&mut rng, RoutedMessageBody::Ping(Ping { nonce: 9, source }));
+    vec![
+        ("peers_request", PeerMessage::PeersRequest),
+        ("disconnect", PeerMessage::Disconnect),
+        ("block_request", PeerMessage::BlockRequest(CryptoHash([7; 32]))),
+        (
+            "block_headers_request",
+            PeerMessage::BlockHeadersRequest(vec![CryptoHash([1; 32]), CryptoHash([2; 32])]),
+        ),
+        ("epoch_sync_request", PeerMessage::EpochSyncRequest(EpochId(CryptoHash([3; 32])))),
+        (
+            "request_update_nonce",
+            PeerMessage::RequestUpdateNonce(PartialEdgeInfo {
+                nonce: Nonce(5),
+                signature: near_crypto::Signature::empty(near_crypto::KeyType::ED25519),
+            }),
+        ),
+        ("handshake", PeerMessage::Handshake(handshake)),
+        ("routed", PeerMessage::Routed(Box::new(routed))),
+        ("block", PeerMessage::Block(block)),
+    ]
+}
+
+/// Serializing into a writer produces the same bytes as serializing into a Vec.
+#[test]
+fn serialize_to_writer() {
+    for enc in [Encoding::Proto, Encoding::Borsh] {
+        for (_, msg) in &golden_messages() {
+            let mut writer = std::io::BufWriter::new(vec![]);
+            let n = msg.serialize_to(enc, &mut writer).unwrap();
+            let got = writer.into_inner().unwrap();
+            assert_eq!(msg.serialize(enc), got, "{msg}");
+            assert_eq!(got.len(), n);
+        }
+    }
+}
+
+/// If the change of the format is intended, regenerate the golden files with
+/// `UPDATE_GOLDEN=1 cargo test -p near-network serialization_golden_files`.
+#[test]
+fn serialization_golden_files() {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res/golden");
+    let update = std::env::var("UPDATE_GOLDEN").is_ok();
+    let mut mismatches = vec![];
+    for (name, msg) in golden_messages() {
+        for enc in [Encoding::Borsh, Encoding::Proto] {
+            let path = dir.join(format!("{name}.{}.bin", format!("{enc:?}").to_lowercase()));
+            let got = msg.serialize(enc);
+            if update {
+                std::fs::write(&path, &got).unwrap();
+                continue;
+            }
+            let want = std::fs::read(&path).with_context(|| format!("{}", path.display())).unwrap();
+            assert_eq!(
+                msg,
+                PeerMessage::deserialize(enc, &want, &data::make_decode_config()).unwrap(),
+                "{}",
+                path.display()
+            );
+            if got != want {
+                mismatches.push(path.display().to_string());
+            }
+        }
+    }
+    assert!(
+        mismatches.is_empty(),
+        "serialization differs from the golden files {mismatches:?}; \
+         if the change of the format is intended, regenerate them with UPDATE_GOLDEN=1"
+    );
+}
+
+#[test]
+fn routing_table_update_accounts_limit() {
+    let mut rng = make_rng(7834521);
+    let aa = data::make_announce_account(&mut rng);
+    let edge = data::make_edge(&data::make_signer(&mut rng), &data::make_signer(&mut rng));
+    let round_trip = |rtu: &RoutingTableUpdate| {
+        let msg = PeerMessage::SyncRoutingTable(rtu.clone());
+        assert_eq!(
+            msg,
+            PeerMessage::deserialize(
+                Encoding::Proto,
+                &msg.serialize(Encoding::Proto),
+                &data::make_decode_config()
+            )
+            .unwrap()
+        );
+    };
+
+    // An update at the limit is accepted and sent as is.
+    let rtu =
+        RoutingTableUpdate::new(vec![edge.clone()], vec![aa.clone(); MAX_ACCOUNTS_PER_UPDATE]);
+    round_trip(&rtu);
+    assert_eq!(vec![rtu.clone()], rtu.into_chunks());
+
+    // An oversized update is rejected.
+    let got = 2 * MAX_ACCOUNTS_PER_UPDATE + 1;
+    let rtu = RoutingTableUpdate::new(vec![edge.clone()], vec![aa.clone(); got]);
+    assert_matches!(
+        proto_conv::routing_table_update_from_proto(&(&rtu).into(), &data::make_decode_config()),
+        Err(proto_conv::ParseRoutingTableUpdateError::TooManyAccounts { got: g, max })
+            if g == got && max == MAX_ACCOUNTS_PER_UPDATE
+    );
+
+    // Instead, it is sent in chunks, which are accepted.
+    let chunks = rtu.clone().into_chunks();
+    assert_eq!(3, chunks.len());
+    assert_eq!(vec![edge.clone()], chunks[0].edges);
+    assert!(chunks[1..].iter().all(|c| c.edges.is_empty()));
+    assert_eq!(rtu.accounts, chunks.iter().flat_map(|c| c.accounts.clone()).collect::<Vec<_>>());
+    for c in &chunks {
+        round_trip(c);
+    }
+
+    // The limit is configurable: with a lower limit, an update accepted by default
+    // gets rejected.
+    let rtu = RoutingTableUpdate::new(vec![edge], vec![aa; 10]);
+    let msg = PeerMessage::SyncRoutingTable(rtu);
+    let cfg = DecodeConfig { max_accounts_per_update: 9, ..data::make_decode_config() };
+    assert_matches!(
+        PeerMessage::deserialize(Encoding::Proto, &msg.serialize(Encoding::Proto), &cfg),
+        Err(ParsePeerMessageError::ProtoConv(proto_conv::ParsePeerMessageError::SyncRoutingTable(
+            proto_conv::ParseRoutingTableUpdateError::TooManyAccounts { got: 10, max: 9 }
+        )))
+    );
+}
+
+/// Toy codec, which scrambles the proto encoding, to check that codecs other
+/// than the builtin ones plug into PeerMessage serialization.
+struct XorCodec(u8);
+
+impl PeerMessageCodec for XorCodec {
+    fn encode(&self, msg: &PeerMessage, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let mut buf = vec![];
+        Encoding::Proto.codec().encode(msg, &mut buf)?;
+        writer.write_all(&buf.iter().map(|b| b ^ self.0).collect::<Vec<_>>())
+    }
+
+    fn decode(
+        &self,
+        data: &[u8],
+        cfg: &DecodeConfig,
+    ) -> Result<PeerMessage, ParsePeerMessageError> {
+        Encoding::Proto.codec().decode(&data.iter().map(|b| b ^ self.0).collect::<Vec<_>>(), cfg)
+    }
+}
+
+#[test]
+fn custom_codec() {
+    let mut rng = make_rng(89028037453);
+    let mut clock = time::FakeClock::default();
+    let chain = data::Chain::make(&mut clock, &mut rng, 2);
+    let msg = PeerMessage::Handshake(data::make_handshake(&mut rng, &chain));
+
+    let codec = XorCodec(0x5a);
+    let data = msg.serialize_with(&codec);
+    assert_ne!(msg.serialize(Encoding::Proto), data);
+    assert_eq!(
+        msg,
+        PeerMessage::deserialize_with(&codec, &data, &data::make_decode_config()).unwrap()
+    );
+    // A message encoded with the custom codec is not valid proto.
+    assert!(PeerMessage::deserialize(Encoding::Proto, &data, &data::make_decode_config()).is_err());
+
+    // The builtin codecs are the ones used by serialize/deserialize.
+    for enc in [Encoding::Proto, Encoding::Borsh] {
+        let data = msg.serialize_with(enc.codec());
+        assert_eq!(msg.serialize(enc), data);
+        assert_eq!(msg, PeerMessage::deserialize(enc, &data, &data::make_decode_config()).unwrap());
+    }
+}
+
+#[test]
+fn decode_failure_report() {
+    let mut rng = make_rng(19283746501);
+    let mut clock = time::FakeClock::default();
+    let chain = data::Chain::make(&mut clock, &mut rng, 2);
+    let msg = PeerMessage::Handshake(data::make_handshake(&mut rng, &chain));
+
+    // Truncated proto: the report points at the handshake field, which
+    // starts at the beginning of the message.
+    let data = msg.serialize(Encoding::Proto);
+    let truncated = &data[..data.len() - 10];
+    let err = PeerMessage::deserialize(Encoding::Proto, truncated, &data::make_decode_config())
+        .unwrap_err();
+    let report = debug_decode_failure(Encoding::Proto, truncated, err);
+    assert_eq!(truncated.len(), report.len);
+    assert_eq!(Some("Handshake"), report.variant);
+    assert_eq!(Some(0), report.offset);
+    assert_eq!(Some(10), report.missing_bytes);
+
+    // Truncated borsh: the variant is known from the first byte and decoding
+    // fails somewhere within the payload.
+    let data = msg.serialize(Encoding::Borsh);
+    let truncated = &data[..data.len() - 10];
+    let err = PeerMessage::deserialize(Encoding::Borsh, truncated, &data::make_decode_config())
+        .unwrap_err();
+    let report = debug_decode_failure(Encoding::Borsh, truncated, err);
+    assert_eq!(Some("Handshake"), report.variant);
+    assert!(report.offset.unwrap() <= truncated.len());
+
+    // A correctly framed message, which fails the conversion, is reported
+    // with the original error.
+    let challenge = data::make_challenge(&mut rng);
+    let challenge_len = challenge.try_to_vec().unwrap().len();
+    let data = PeerMessage::Challenge(challenge).serialize(Encoding::Proto);
+    let cfg = DecodeConfig { max_challenge_bytes: challenge_len - 1, ..data::make_decode_config() };
+    let decode = || PeerMessage::deserialize(Encoding::Proto, &data, &cfg).unwrap_err();
+    let want = format!("{:#}", anyhow::Error::new(decode()));
+    let report = debug_decode_failure(Encoding::Proto, &data, decode());
+    assert_eq!(Some("Challenge"), report.variant);
+    assert_eq!(None, report.offset);
+    assert_eq!(want, report.error);
+}
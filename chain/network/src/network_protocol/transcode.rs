@@ -0,0 +1,133 @@
+/// Conversion of serialized PeerMessages between encodings.
+use crate::network_protocol::proto::peer_message::Message_type as ProtoMT;
+use crate::network_protocol::{
+    borsh_, proto, DecodeConfig, Encoding, ParsePeerMessageError, PeerMessage,
+};
+use protobuf::{Message as _, MessageField as MF};
+
+#[derive(thiserror::Error, Debug)]
+pub enum TranscodeError {
+    #[error("decode: {0}")]
+    Decode(#[source] ParsePeerMessageError),
+    #[error("{0} cannot be represented in {1:?} encoding")]
+    Unsupported(&'static str, Encoding),
+}
+
+/// Messages which carry an opaque borsh blob in both encodings.
+enum Opaque<'a> {
+    Block(&'a [u8]),
+    Challenge(&'a [u8]),
+}
+
+impl<'a> Opaque<'a> {
+    fn from_borsh(data: &'a [u8]) -> Option<Self> {
+        let (tag, blob) = data.split_first()?;
+        match *tag {
+            borsh_::PeerMessage::BLOCK_TAG => Some(Self::Block(blob)),
+            borsh_::PeerMessage::CHALLENGE_TAG => Some(Self::Challenge(blob)),
+            _ => None,
+        }
+    }
+
+    fn from_proto(msg: &'a proto::PeerMessage) -> Option<Self> {
+        match msg.message_type.as_ref()? {
+            ProtoMT::BlockResponse(br) => Some(Self::Block(&br.block.as_ref()?.borsh)),
+            ProtoMT::Challenge(c) => Some(Self::Challenge(&c.borsh)),
+            _ => None,
+        }
+    }
+
+    fn to_borsh(&self) -> Vec<u8> {
+        let (tag, blob) = match self {
+            Self::Block(blob) => (borsh_::PeerMessage::BLOCK_TAG, blob),
+            Self::Challenge(blob) => (borsh_::PeerMessage::CHALLENGE_TAG, blob),
+        };
+        let mut data = Vec::with_capacity(1 + blob.len());
+        data.push(tag);
+        data.extend_from_slice(blob);
+        data
+    }
+
+    fn to_proto(&self) -> Vec<u8> {
+        let message_type = match self {
+            Self::Block(blob) => ProtoMT::BlockResponse(proto::BlockResponse {
+                block: MF::some(proto::Block { borsh: blob.to_vec(), ..Default::default() }),
+                ..Default::default()
+            }),
+            Self::Challenge(blob) => {
+                ProtoMT::Challenge(proto::Challenge { borsh: blob.to_vec(), ..Default::default() })
+            }
+        };
+        proto::PeerMessage { message_type: Some(message_type), ..Default::default() }
+            .write_to_bytes()
+            .unwrap()
+    }
+}
+
+/// Converts a serialized PeerMessage from encoding `from` to encoding `to`.
+///
+/// Block and Challenge messages are borsh blobs in both encodings, so their payload
+/// is moved over verbatim, without decoding it. It is validated only once the
+/// receiver deserializes the output. Other messages are fully deserialized and
+/// serialized again.
+pub fn transcode(
+    from: Encoding,
+    to: Encoding,
+    data: &[u8],
+    cfg: &DecodeConfig,
+) -> Result<Vec<u8>, TranscodeError> {
+    if from == to {
+        return Ok(data.to_vec());
+    }
+    let msg = match from {
+        Encoding::Borsh => {
+            if let Some(opaque) = Opaque::from_borsh(data) {
+                return Ok(opaque.to_proto());
+            }
+            PeerMessage::deserialize(from, data, cfg).map_err(TranscodeError::Decode)?
+        }
+        Encoding::Proto => {
+            let msg = proto::PeerMessage::parse_from_bytes(data)
+                .map_err(|err| TranscodeError::Decode(ParsePeerMessageError::ProtoDecode(err)))?;
+            if let Some(opaque) = Opaque::from_proto(&msg) {
+                return Ok(opaque.to_borsh());
+            }
+            PeerMessage::from_proto(&msg, cfg)
+                .map_err(|err| TranscodeError::Decode(ParsePeerMessageError::ProtoConv(err)))?
+        }
+    };
+    serialize_representable(&msg, to)
+}
+
+fn serialize_representable(msg: &PeerMessage, to: Encoding) -> Result<Vec<u8>, TranscodeError> {
+    // Borsh encoding has no representation of the proto-only messages,
+    // so serializing them would silently drop the data.
+    if let (
+        Encoding::Borsh,
+        PeerMessage::SyncAccountsData(_)
+        | PeerMessage::KeepAliveConfig { .. }
+        | PeerMessage::RoutingTableSubsetRequest { .. }
+        | PeerMessage::SupportedEncodings(_),
+    ) = (to, msg)
+    {
+        return Err(TranscodeError::Unsupported(msg.msg_variant(), to));
+    }
+    Ok(msg.serialize(to))
+}
+
+impl PeerMessage {
+    /// Converts a serialized PeerMessage from encoding `from` to encoding `to`, like
+    /// [`transcode`], except that the message is always fully deserialized, including
+    /// the Block and Challenge payloads. Malformed input is therefore rejected here,
+    /// rather than by the receiver, which is what a node bridging peers which speak
+    /// different encodings wants, so that it doesn't get banned for relaying garbage.
+    pub fn reencode(
+        data: &[u8],
+        from: Encoding,
+        to: Encoding,
+        cfg: &DecodeConfig,
+    ) -> Result<Vec<u8>, TranscodeError> {
+        let msg = PeerMessage::deserialize(from, data, cfg).map_err(TranscodeError::Decode)?;
+        serialize_representable(&msg, to)
+    }
+}
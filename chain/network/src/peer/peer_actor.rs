@@ -1,9 +1,14 @@
 use crate::accounts_data;
 use crate::concurrency::atomic_cell::AtomicCell;
 use crate::concurrency::demux;
+use crate::concurrency::rate::TokenBucket;
 use crate::network_protocol::{
-    Edge, EdgeState, Encoding, ParsePeerMessageError, PartialEdgeInfo, PeerChainInfoV2, PeerInfo,
-    RoutedMessage, RoutedMessageBody, SyncAccountsData,
+    debug_decode_failure, shared_encodings, DecodeConfig, SUPPORTED_ENCODINGS,
+};
+use crate::network_protocol::{
+    AccountOrPeerIdOrHash, Edge, EdgeState, Encoding, ParsePeerMessageError, PartialEdgeInfo,
+    PeerChainInfoV2, PeerInfo, Ping, RawRoutedMessage, RoutedMessage, RoutedMessageBody,
+    RoutingTableUpdate, SyncAccountsData,
 };
 use crate::peer::stream;
 use crate::peer::tracker::Tracker;
@@ -14,7 +19,9 @@ use crate::private_actix::{
     PeerToManagerMsg, PeerToManagerMsgResp, PeersRequest, PeersResponse, RegisterPeer,
     RegisterPeerError, RegisterPeerResponse, SendMessage, Unregister,
 };
+use crate::routing::auditor::AuditVerdict;
 use crate::routing::edge::verify_nonce;
+use crate::routing::in_flight;
 use crate::stats::metrics;
 use crate::tcp;
 use crate::time;
@@ -27,17 +34,14 @@ use near_o11y::log_assert;
 
 use actix::{
     Actor, ActorContext, ActorFutureExt, AsyncContext, Context, ContextFutureSpawner, Handler,
-    Running, WrapFuture,
+    Running, SpawnHandle, WrapFuture,
 };
 use lru::LruCache;
 use near_crypto::Signature;
 use near_performance_metrics_macros::perf;
-use near_primitives::logging;
 use near_primitives::network::PeerId;
 use near_primitives::utils::DisplayOption;
-use near_primitives::version::{
-    ProtocolVersion, PEER_MIN_ALLOWED_PROTOCOL_VERSION, PROTOCOL_VERSION,
-};
+use near_primitives::version::{ProtocolVersion, PROTOCOL_VERSION};
 
 use parking_lot::Mutex;
 use std::fmt::Debug;
@@ -60,6 +64,9 @@ const MAX_TRANSACTIONS_PER_BLOCK_MESSAGE: usize = 1000;
 const ROUTED_MESSAGE_CACHE_SIZE: usize = 1000;
 /// Duplicated messages will be dropped if routed through the same peer multiple times.
 const DROP_DUPLICATED_MESSAGES_PERIOD: time::Duration = time::Duration::milliseconds(50);
+/// Lower bound on the agreed keepalive interval, so that a peer cannot make us
+/// ping it in a busy loop by proposing a tiny interval.
+const MIN_KEEP_ALIVE_INTERVAL: time::Duration = time::Duration::seconds(1);
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnectionClosedEvent {
@@ -89,6 +96,8 @@ pub(crate) enum ClosingReason {
     Ban(ReasonForBan),
     #[error("handshake failed")]
     HandshakeFailed,
+    #[error("handshake not received within the deadline")]
+    HandshakeReadTimeout,
     #[error("rejected by PeerManager: {0:?}")]
     RejectedByPeerManager(RegisterPeerError),
     #[error("stream error")]
@@ -99,8 +108,32 @@ pub(crate) enum ClosingReason {
     DisconnectMessage,
 }
 
+/// Keepalive interval agreed upon by both sides of a connection: the shorter
+/// of the two proposals, but at least `MIN_KEEP_ALIVE_INTERVAL`. A proposal of 0
+/// means that the peer has no preference.
+pub(crate) fn agreed_keep_alive_interval(ours: time::Duration, theirs_ms: u32) -> time::Duration {
+    let theirs = time::Duration::milliseconds(theirs_ms.into());
+    let agreed = if theirs_ms == 0 {
+        ours
+    } else if ours <= time::Duration::ZERO {
+        theirs
+    } else {
+        std::cmp::min(ours, theirs)
+    };
+    std::cmp::max(agreed, MIN_KEEP_ALIVE_INTERVAL)
+}
+
+/// Whether the Handshake is addressed to `my_id` by a peer other than the target.
+/// A Handshake with the sender equal to the target is nonsensical: it would make
+/// us connect to ourselves, so it is rejected even if the target is us.
+pub(crate) fn is_valid_handshake_target(my_id: &PeerId, handshake: &Handshake) -> bool {
+    &handshake.target_peer_id == my_id && handshake.sender_peer_id != handshake.target_peer_id
+}
+
 pub(crate) struct PeerActor {
     clock: time::Clock,
+    /// Parameters of decoding the messages received from the peer.
+    decode_config: DecodeConfig,
 
     /// Shared state of the network module.
     network_state: Arc<NetworkState>,
@@ -131,9 +164,14 @@ pub(crate) struct PeerActor {
     /// Whether the PeerActor should skip protobuf support detection and use
     /// a given encoding right away.
     force_encoding: Option<Encoding>,
+    /// Encoding agreed upon in the previous connection with the peer. Outbound
+    /// handshake is sent only in this encoding, instead of negotiating it.
+    preferred_encoding: Option<Encoding>,
 
     /// Peer status.
     peer_status: PeerStatus,
+    /// Whether a Handshake message has been received and parsed.
+    handshake_received: bool,
     closing_reason: Option<ClosingReason>,
 
     /// Peer id and info. Present when Ready,
@@ -143,6 +181,28 @@ pub(crate) struct PeerActor {
     peer_info: DisplayOption<PeerInfo>,
     /// Shared state of the connection. Present when ready.
     connection: Option<Arc<connection::Connection>>,
+    /// Timer sending the idle keepalive pings. Present once the keepalive
+    /// interval has been negotiated with the peer.
+    keep_alive: Option<SpawnHandle>,
+    /// Bounds the number of StateRequestPart messages from this peer which are
+    /// being processed by the view client at the same time.
+    state_part_permits: Arc<tokio::sync::Semaphore>,
+    /// Rate limit of the SyncRoutingTable messages from this peer.
+    sync_routing_table_rl: TokenBucket,
+    /// Rate limit of the RoutingTableSubsetRequest messages from this peer.
+    routing_table_subset_request_rl: TokenBucket,
+    /// Full accounts data sync requested from this peer, which hasn't been answered yet.
+    full_accounts_data_sync: Option<FullAccountsDataSyncRequest>,
+    /// Rate limit of repeating the full accounts data sync requests after incomplete responses.
+    full_accounts_data_sync_retry_rl: TokenBucket,
+}
+
+/// Full accounts data sync requested from the peer.
+#[derive(Clone, Copy, Debug)]
+struct FullAccountsDataSyncRequest {
+    /// Whether it is a repeated request, sent because the previous response was incomplete.
+    /// A repeated request is not repeated again.
+    retry: bool,
 }
 
 impl Debug for PeerActor {
@@ -205,6 +265,25 @@ impl PeerActor {
             },
         };
 
+        let preferred_encoding = match &stream.type_ {
+            crate::tcp::StreamType::Outbound { peer_id } if force_encoding.is_none() => {
+                network_state.preferred_encoding(clock.now(), peer_id)
+            }
+            _ => None,
+        };
+        let state_part_permits = Arc::new(tokio::sync::Semaphore::new(
+            network_state.config.max_concurrent_state_part_requests,
+        ));
+        let sync_routing_table_rl =
+            TokenBucket::new(network_state.config.sync_routing_table_rate_limit, clock.now());
+        let routing_table_subset_request_rl = TokenBucket::new(
+            network_state.config.routing_table_subset_request_rate_limit,
+            clock.now(),
+        );
+        let full_accounts_data_sync_retry_rl = TokenBucket::new(
+            network_state.config.full_accounts_data_sync_retry_rate_limit,
+            clock.now(),
+        );
         let my_node_info = PeerInfo {
             id: network_state.config.node_id(),
             addr: network_state.config.node_addr.clone(),
@@ -219,6 +298,7 @@ impl PeerActor {
             let framed = stream::FramedStream::spawn(ctx, stream, stats.clone());
             Self {
                 closing_reason: None,
+                decode_config: network_state.config.decode_config(clock.clone()),
                 clock,
                 my_node_info,
                 stream_id,
@@ -228,12 +308,14 @@ impl PeerActor {
                     tcp::StreamType::Outbound { .. } => PeerType::Outbound,
                 },
                 peer_status: PeerStatus::Connecting(connecting_status),
+                handshake_received: false,
                 framed,
                 tracker: Default::default(),
                 stats,
                 routed_message_cache: LruCache::new(ROUTED_MESSAGE_CACHE_SIZE),
                 protocol_buffers_supported: false,
                 force_encoding,
+                preferred_encoding,
                 peer_info: match &stream_type {
                     tcp::StreamType::Inbound => None,
                     tcp::StreamType::Outbound { peer_id } => Some(PeerInfo {
@@ -245,6 +327,12 @@ impl PeerActor {
                 .into(),
                 network_state,
                 connection: None,
+                keep_alive: None,
+                state_part_permits,
+                sync_routing_table_rl,
+                routing_table_subset_request_rl,
+                full_accounts_data_sync: None,
+                full_accounts_data_sync_retry_rl,
             }
         }))
     }
@@ -260,6 +348,9 @@ impl PeerActor {
         if self.protocol_buffers_supported {
             return Some(Encoding::Proto);
         }
+        if self.preferred_encoding.is_some() {
+            return self.preferred_encoding;
+        }
         match &self.peer_status {
             PeerStatus::Connecting { .. } => None,
             _ => Some(Encoding::Borsh),
@@ -269,19 +360,33 @@ impl PeerActor {
     fn parse_message(&mut self, msg: &[u8]) -> Result<PeerMessage, ParsePeerMessageError> {
         let _span = tracing::trace_span!(target: "network", "parse_message").entered();
         if let Some(e) = self.encoding() {
-            return PeerMessage::deserialize(e, msg);
+            return PeerMessage::deserialize(e, msg, &self.decode_config);
         }
-        if let Ok(msg) = PeerMessage::deserialize(Encoding::Proto, msg) {
+        if let Ok(msg) = PeerMessage::deserialize(Encoding::Proto, msg, &self.decode_config) {
             self.protocol_buffers_supported = true;
             return Ok(msg);
         }
-        return PeerMessage::deserialize(Encoding::Borsh, msg);
+        return PeerMessage::deserialize(Encoding::Borsh, msg, &self.decode_config);
     }
 
     fn send_message_or_log(&self, msg: &PeerMessage) {
         self.send_message(msg);
     }
 
+    /// Sends all the accounts data we have, requesting the peer to send back all of its data.
+    /// `retry` tells whether the request repeats a previous one, answered incompletely.
+    fn request_full_accounts_data_sync(&mut self, retry: bool) {
+        self.full_accounts_data_sync = Some(FullAccountsDataSyncRequest { retry });
+        let accounts_data: Vec<_> =
+            self.network_state.accounts_data.load().data.values().cloned().collect();
+        self.send_message_or_log(&PeerMessage::SyncAccountsData(SyncAccountsData {
+            incremental: false,
+            requesting_full_sync: true,
+            total_count: accounts_data.len(),
+            accounts_data,
+        }));
+    }
+
     fn send_message(&self, msg: &PeerMessage) {
         if let PeerMessage::PeersRequest = msg {
             if let Some(conn) = &self.connection {
@@ -326,7 +431,7 @@ impl PeerActor {
         let chain_info = self.network_state.chain_info.load();
         let handshake = Handshake {
             protocol_version: spec.protocol_version,
-            oldest_supported_version: PEER_MIN_ALLOWED_PROTOCOL_VERSION,
+            oldest_supported_version: self.network_state.config.min_accepted_protocol_version,
             sender_peer_id: self.network_state.config.node_id(),
             target_peer_id: spec.peer_id,
             sender_listen_port: self.network_state.config.node_addr.map(|a| a.port()),
@@ -350,6 +455,38 @@ impl PeerActor {
         ctx.stop();
     }
 
+    /// (Re)starts sending empty routed pings to the peer every `interval`.
+    /// A zero interval disables the keepalive.
+    fn start_keep_alive(&mut self, ctx: &mut Context<PeerActor>, interval: time::Duration) {
+        if let Some(handle) = self.keep_alive.take() {
+            ctx.cancel_future(handle);
+        }
+        if interval <= time::Duration::ZERO {
+            return;
+        }
+        debug!(target: "network", "Keepalive interval with {}: {}", self.peer_info_for_logs(), interval);
+        self.keep_alive = Some(ctx.run_interval(interval.try_into().unwrap(), |act, _ctx| {
+            let peer_id = match act.other_peer_id() {
+                Some(peer_id) => peer_id.clone(),
+                None => return,
+            };
+            let msg = act.network_state.sign_message(
+                &act.clock,
+                RawRoutedMessage {
+                    target: AccountOrPeerIdOrHash::PeerId(peer_id),
+                    body: RoutedMessageBody::Ping(Ping {
+                        nonce: 0,
+                        source: act.my_node_id().clone(),
+                    }),
+                },
+            );
+            match msg {
+                Ok(msg) => act.send_message_or_log(&PeerMessage::Routed(msg)),
+                Err(err) => error!(target: "network", "Failed to sign keepalive ping: {}", err),
+            }
+        }));
+    }
+
     /// `PeerId` of the current node.
     fn my_node_id(&self) -> &PeerId {
         &self.my_node_info.id
@@ -360,6 +497,21 @@ impl PeerActor {
         self.peer_info.as_ref().as_ref().map(|peer_info| &peer_info.id)
     }
 
+    /// `peer_info` in the form in which it should be logged.
+    fn peer_info_for_logs(&self) -> DisplayOption<PeerInfo> {
+        let config = &self.network_state.config;
+        self.peer_info
+            .as_ref()
+            .as_ref()
+            .map(|peer_info| config.peer_info_for_logs(peer_info))
+            .into()
+    }
+
+    /// `peer_addr` in the form in which it should be logged.
+    fn peer_addr_for_logs(&self) -> SocketAddr {
+        self.network_state.config.addr_for_logs(self.peer_addr)
+    }
+
     fn receive_message(&mut self, ctx: &mut Context<PeerActor>, msg: PeerMessage) {
         if msg.is_view_client_message() {
             metrics::PEER_VIEW_CLIENT_MESSAGE_RECEIVED_BY_TYPE_TOTAL
@@ -378,9 +530,13 @@ impl PeerActor {
 
     fn receive_view_client_message(&self, ctx: &mut Context<PeerActor>, msg: PeerMessage) {
         let mut msg_hash = None;
+        let mut request_id = None;
+        // Held until the view client responds to the StateRequestPart.
+        let mut state_part_permit = None;
         let view_client_message = match msg {
             PeerMessage::Routed(message) => {
                 msg_hash = Some(message.hash());
+                request_id = message.request_id;
                 match &message.msg.body {
                     RoutedMessageBody::TxStatusRequest(account_id, tx_hash) => {
                         NetworkViewClientMessages::TxStatus {
@@ -405,12 +561,36 @@ impl PeerActor {
                         }
                     }
                     RoutedMessageBody::StateRequestPart(shard_id, sync_hash, part_id) => {
+                        // Computing a state part is expensive, so instead of queuing the
+                        // excess requests we tell the peer to retry later.
+                        match self.state_part_permits.clone().try_acquire_owned() {
+                            Ok(permit) => state_part_permit = Some(permit),
+                            Err(_) => {
+                                debug!(target: "network", shard_id, part_id, "Refusing StateRequestPart from {}: too many in progress", self.peer_info_for_logs());
+                                metrics::STATE_PART_REQUESTS_REFUSED.inc();
+                                self.network_state.send_state_request_part_busy(
+                                    &self.clock,
+                                    *shard_id,
+                                    *sync_hash,
+                                    *part_id,
+                                    message.hash(),
+                                    message.request_id,
+                                );
+                                return;
+                            }
+                        }
                         NetworkViewClientMessages::StateRequestPart {
                             shard_id: *shard_id,
                             sync_hash: sync_hash.clone(),
                             part_id: *part_id,
                         }
                     }
+                    RoutedMessageBody::TxInclusionProofRequest(tx_hash) => {
+                        NetworkViewClientMessages::TxInclusionProofRequest { tx_hash: *tx_hash }
+                    }
+                    RoutedMessageBody::TxInclusionProofResponse(proof) => {
+                        NetworkViewClientMessages::TxInclusionProofResponse(Box::new(proof.clone()))
+                    }
                     body => {
                         error!(target: "network", "Peer receive_view_client_message received unexpected type: {:?}", body);
                         return;
@@ -438,14 +618,20 @@ impl PeerActor {
             .send(view_client_message)
             .into_actor(self)
             .then(move |res, act, _ctx| {
+                let _state_part_permit = state_part_permit;
                 // Ban peer if client thinks received data is bad.
                 match res {
                     Ok(NetworkViewClientResponses::TxStatus(tx_result)) => {
                         let body = Box::new(RoutedMessageBody::TxStatusResponse(*tx_result));
-                        let _ = act
-                            .network_state
-                            .peer_manager_addr
-                            .do_send(PeerToManagerMsg::RouteBack(body, msg_hash.unwrap()));
+                        let _ = act.network_state.peer_manager_addr.do_send(
+                            PeerToManagerMsg::RouteBack(body, msg_hash.unwrap(), request_id),
+                        );
+                    }
+                    Ok(NetworkViewClientResponses::TxInclusionProof(proof)) => {
+                        let body = Box::new(RoutedMessageBody::TxInclusionProofResponse(*proof));
+                        let _ = act.network_state.peer_manager_addr.do_send(
+                            PeerToManagerMsg::RouteBack(body, msg_hash.unwrap(), request_id),
+                        );
                     }
                     Ok(NetworkViewClientResponses::StateResponse(state_response)) => {
                         let body = match *state_response {
@@ -457,7 +643,11 @@ impl PeerActor {
                             }
                         };
                         let _ = act.network_state.peer_manager_addr.do_send(
-                            PeerToManagerMsg::RouteBack(Box::new(body), msg_hash.unwrap()),
+                            PeerToManagerMsg::RouteBack(
+                                Box::new(body),
+                                msg_hash.unwrap(),
+                                request_id,
+                            ),
                         );
                     }
                     Ok(NetworkViewClientResponses::Block(block)) => {
@@ -479,7 +669,7 @@ impl PeerActor {
                         error!(
                             target: "network",
                             "Received error sending message to view client: {} for {}",
-                            err, act.peer_info
+                            err, act.peer_info_for_logs()
                         );
                         return actix::fut::ready(());
                     }
@@ -561,10 +751,20 @@ impl PeerActor {
                         NetworkClientMessages::PartialEncodedChunk(chunk.clone())
                     }
                     RoutedMessageBody::PartialEncodedChunkForward(forward) => {
+                        if let Err(err) = forward.verify_parts() {
+                            debug!(target: "network", chunk_hash = ?forward.chunk_hash, "Dropping PartialEncodedChunkForward from {}: {}", self.peer_info_for_logs(), err);
+                            return;
+                        }
                         NetworkClientMessages::PartialEncodedChunkForward(forward.clone())
                     }
+                    RoutedMessageBody::EncryptedBlob(blob) => NetworkClientMessages::EncryptedBlob(
+                        blob.clone(),
+                        routed_message.author.clone(),
+                    ),
                     RoutedMessageBody::Ping(_)
                     | RoutedMessageBody::Pong(_)
+                    | RoutedMessageBody::Heartbeat(_)
+                    | RoutedMessageBody::HeartbeatAck(_)
                     | RoutedMessageBody::TxStatusRequest(_, _)
                     | RoutedMessageBody::TxStatusResponse(_)
                     | RoutedMessageBody::_UnusedQueryRequest
@@ -573,7 +773,12 @@ impl PeerActor {
                     | RoutedMessageBody::ReceiptOutcomeRequest(_)
                     | RoutedMessageBody::_UnusedReceiptOutcomeResponse
                     | RoutedMessageBody::StateRequestHeader(_, _)
-                    | RoutedMessageBody::StateRequestPart(_, _, _) => {
+                    | RoutedMessageBody::StateRequestPart(_, _, _)
+                    | RoutedMessageBody::StateRequestPartBusy(_, _, _)
+                    | RoutedMessageBody::TxInclusionProofRequest(_)
+                    | RoutedMessageBody::TxInclusionProofResponse(_)
+                    | RoutedMessageBody::TelemetryRequest
+                    | RoutedMessageBody::TelemetryResponse(_) => {
                         error!(target: "network", "Peer receive_client_message received unexpected type: {:?}", routed_message);
                         return;
                     }
@@ -599,7 +804,10 @@ impl PeerActor {
             | PeerMessage::BlockHeadersRequest(_)
             | PeerMessage::EpochSyncRequest(_)
             | PeerMessage::EpochSyncFinalizationRequest(_)
-            | PeerMessage::SyncAccountsData(_) => {
+            | PeerMessage::SyncAccountsData(_)
+            | PeerMessage::KeepAliveConfig { .. }
+            | PeerMessage::RoutingTableSubsetRequest { .. }
+            | PeerMessage::SupportedEncodings(_) => {
                 error!(target: "network", "Peer receive_client_message received unexpected type: {:?}", msg);
                 return;
             }
@@ -612,7 +820,7 @@ impl PeerActor {
                 // Ban peer if client thinks received data is bad.
                 match res {
                     Ok(NetworkClientResponses::InvalidTx(err)) => {
-                        warn!(target: "network", "Received invalid tx from peer {}: {}", act.peer_info, err);
+                        warn!(target: "network", "Received invalid tx from peer {}: {}", act.peer_info_for_logs(), err);
                         // TODO: count as malicious behavior?
                     }
                     Ok(NetworkClientResponses::Ban { ban_reason }) => {
@@ -622,7 +830,7 @@ impl PeerActor {
                         error!(
                             target: "network",
                             "Received error sending message to client: {} for {}",
-                            err, act.peer_info
+                            err, act.peer_info_for_logs()
                         );
                         return actix::fut::ready(());
                     }
@@ -694,6 +902,11 @@ impl PeerActor {
                     self.stop(ctx, ClosingReason::HandshakeFailed);
                     return;
                 }
+                if !is_valid_handshake_target(&self.my_node_info.id, &handshake) {
+                    warn!(target: "network", "Invalid handshake target {}. Disconnecting peer {}", handshake.target_peer_id, handshake.sender_peer_id);
+                    self.stop(ctx, ClosingReason::HandshakeFailed);
+                    return;
+                }
                 if handshake.partial_edge_info.nonce != spec.partial_edge_info.nonce {
                     warn!(target: "network", "Nonce mismatch. Disconnecting peer {}", handshake.sender_peer_id);
                     self.stop(ctx, ClosingReason::HandshakeFailed);
@@ -701,7 +914,8 @@ impl PeerActor {
                 }
             }
             ConnectingStatus::Inbound { .. } => {
-                if PEER_MIN_ALLOWED_PROTOCOL_VERSION > handshake.protocol_version
+                let min_version = self.network_state.config.min_accepted_protocol_version;
+                if min_version > handshake.protocol_version
                     || handshake.protocol_version > PROTOCOL_VERSION
                 {
                     debug!(
@@ -712,7 +926,7 @@ impl PeerActor {
                         self.my_node_info.clone(),
                         HandshakeFailureReason::ProtocolVersionMismatch {
                             version: PROTOCOL_VERSION,
-                            oldest_supported_version: PEER_MIN_ALLOWED_PROTOCOL_VERSION,
+                            oldest_supported_version: min_version,
                         },
                     ));
                     return;
@@ -726,7 +940,7 @@ impl PeerActor {
                     ));
                     return;
                 }
-                if handshake.target_peer_id != self.my_node_info.id {
+                if !is_valid_handshake_target(&self.my_node_info.id, &handshake) {
                     debug!(target: "network", "Received handshake from {:?} to {:?} but I am {:?}", handshake.sender_peer_id, handshake.target_peer_id, self.my_node_info.id);
                     self.send_message_or_log(&PeerMessage::HandshakeFailure(
                         self.my_node_info.clone(),
@@ -746,7 +960,7 @@ impl PeerActor {
                     self.network_state.routing_table_view.get_local_edge(&handshake.sender_peer_id)
                 {
                     if last_edge.nonce() >= handshake.partial_edge_info.nonce {
-                        debug!(target: "network", "{:?}: Received too low nonce from peer {:?} sending evidence.", self.my_node_id(), self.peer_addr);
+                        debug!(target: "network", "{:?}: Received too low nonce from peer {:?} sending evidence.", self.my_node_id(), self.peer_addr_for_logs());
                         self.send_message_or_log(&PeerMessage::LastEdge(last_edge));
                         return;
                     }
@@ -803,6 +1017,11 @@ impl PeerActor {
             peer_info: peer_info.clone(),
             initial_chain_info: handshake.sender_chain_info.clone(),
             chain_height: AtomicU64::new(handshake.sender_chain_info.height),
+            protocol_version: handshake.protocol_version,
+            // A handshake has been parsed already, so unless it was in proto,
+            // the peer uses borsh (even though the status is still Connecting).
+            encoding: self.encoding().unwrap_or(Encoding::Borsh),
+            shared_encodings: AtomicCell::new(vec![]),
             edge,
             peer_type: self.peer_type,
             stats: self.stats.clone(),
@@ -810,6 +1029,11 @@ impl PeerActor {
                 type_: self.peer_type,
                 encoding: self.encoding(),
             }),
+            _peer_protocol_version_metric: metrics::GaugePoint::new(
+                metrics::PEER_PROTOCOL_VERSIONS.with_label_values(&[
+                    &metrics::protocol_version_label(handshake.protocol_version),
+                ]),
+            ),
             last_time_peer_requested: AtomicCell::new(self.clock.now()),
             last_time_received_message: AtomicCell::new(self.clock.now()),
             connection_established_time: self.clock.now(),
@@ -881,11 +1105,18 @@ impl PeerActor {
                         } else {
                             // Outbound peer triggers the inital full accounts data sync.
                             // TODO(gprusak): implement triggering the periodic full sync.
-                            act.send_message_or_log(&PeerMessage::SyncAccountsData(SyncAccountsData{
-                                accounts_data: act.network_state.accounts_data.load().data.values().cloned().collect(),
-                                incremental: false,
-                                requesting_full_sync: true,
-                            }));
+                            act.request_full_accounts_data_sync(false);
+                        }
+                        // Borsh encoding cannot represent KeepAliveConfig and SupportedEncodings,
+                        // so they are only exchanged over proto connections.
+                        if act.encoding() == Some(Encoding::Proto) {
+                            let interval = act.network_state.config.keep_alive_interval;
+                            act.send_message_or_log(&PeerMessage::KeepAliveConfig {
+                                interval_ms: interval.whole_milliseconds().try_into().unwrap_or(u32::MAX),
+                            });
+                            act.send_message_or_log(&PeerMessage::SupportedEncodings(
+                                SUPPORTED_ENCODINGS.to_vec(),
+                            ));
                         }
                         act.network_state.config.event_sink.push(Event::HandshakeCompleted(HandshakeCompletedEvent{
                             stream_id: act.stream_id,
@@ -913,7 +1144,7 @@ impl Actor for PeerActor {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         metrics::PEER_CONNECTIONS_TOTAL.inc();
-        debug!(target: "network", "{:?}: Peer {:?} {:?} started", self.my_node_info.id, self.peer_addr, self.peer_type);
+        debug!(target: "network", "{:?}: Peer {:?} {:?} started", self.my_node_info.id, self.peer_addr_for_logs(), self.peer_type);
         // Set Handshake timeout for stopping actor if peer is not ready after given period of time.
 
         near_performance_metrics::actix::run_later(
@@ -921,12 +1152,34 @@ impl Actor for PeerActor {
             self.network_state.config.handshake_timeout.try_into().unwrap(),
             move |act, ctx| match &act.peer_status {
                 PeerStatus::Connecting { .. } => {
-                    info!(target: "network", "Handshake timeout expired for {}", act.peer_info);
+                    info!(target: "network", "Handshake timeout expired for {}", act.peer_info_for_logs());
+                    // The peer might not understand the encoding that we proposed.
+                    // Forget it, so that the next connection negotiates the encoding.
+                    if let (Some(_), Some(peer_info)) =
+                        (act.preferred_encoding, act.peer_info.as_ref())
+                    {
+                        act.network_state
+                            .peer_manager_addr
+                            .do_send(PeerToManagerMsg::EncodingRejected(peer_info.id.clone()));
+                    }
                     act.stop(ctx, ClosingReason::HandshakeFailed);
                 }
                 _ => {}
             },
         );
+        // Separately bound the time to receive the peer's Handshake, so that a peer
+        // trickling the bytes of a frame can't hold a connection slot for long.
+        near_performance_metrics::actix::run_later(
+            ctx,
+            self.network_state.config.handshake_read_timeout.try_into().unwrap(),
+            move |act, ctx| {
+                if !act.handshake_received && act.closing_reason.is_none() {
+                    info!(target: "network", "Handshake not received in time from {}", act.peer_addr_for_logs());
+                    metrics::PEER_HANDSHAKE_READ_TIMEOUT_TOTAL.inc();
+                    act.stop(ctx, ClosingReason::HandshakeReadTimeout);
+                }
+            },
+        );
 
         // If outbound peer, initiate handshake.
         if let PeerStatus::Connecting(ConnectingStatus::Outbound { handshake_spec, .. }) =
@@ -942,7 +1195,7 @@ impl Actor for PeerActor {
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
         metrics::PEER_CONNECTIONS_TOTAL.dec();
-        debug!(target: "network", "{:?}: [status = {:?}] Peer {} disconnected.", self.my_node_info.id, self.peer_status, self.peer_info);
+        debug!(target: "network", "{:?}: [status = {:?}] Peer {} disconnected.", self.my_node_info.id, self.peer_status, self.peer_info_for_logs());
         if let Some(peer_info) = self.peer_info.as_ref() {
             if let Some(ClosingReason::Ban(ban_reason)) = self.closing_reason {
                 let _ = self.network_state.peer_manager_addr.do_send(PeerToManagerMsg::Ban(Ban {
@@ -1009,7 +1262,7 @@ impl actix::Handler<stream::Error> for PeerActor {
             },
         };
         log_assert!(expected, "unexpected closing reason: {err}");
-        tracing::info!(target: "network", ?err, "Closing connection to {}", self.peer_info);
+        tracing::info!(target: "network", ?err, "Closing connection to {}", self.peer_info_for_logs());
         self.stop(ctx, ClosingReason::StreamError);
     }
 }
@@ -1031,7 +1284,10 @@ impl actix::Handler<stream::Frame> for PeerActor {
         let mut peer_msg = match self.parse_message(&msg) {
             Ok(msg) => msg,
             Err(err) => {
-                debug!(target: "network", "Received invalid data {:?} from {}: {}", logging::pretty_vec(&msg), self.peer_info, err);
+                // The report doesn't contain the payload itself, which might be large.
+                // If the encoding is not known yet, the error comes from the last attempt, i.e. borsh.
+                let enc = self.encoding().unwrap_or(Encoding::Borsh);
+                debug!(target: "network", "Received invalid data from {}: {}", self.peer_info_for_logs(), debug_decode_failure(enc, &msg, err));
                 return;
             }
         };
@@ -1053,6 +1309,14 @@ impl actix::Handler<stream::Frame> for PeerActor {
             }
             self.routed_message_cache.put(key, now);
         }
+        if let PeerMessage::Routed(routed) = &peer_msg {
+            let auditor = &self.network_state.config.routed_message_auditor;
+            if auditor.audit(&routed.author, routed.body_variant(), msg.len()) == AuditVerdict::Drop
+            {
+                debug!(target: "network", "Auditor dropped {} message from {}", routed.body_variant(), routed.author);
+                return;
+            }
+        }
         if let PeerMessage::Routed(routed) = &peer_msg {
             if let RoutedMessage { body: RoutedMessageBody::ForwardTx(_), .. } = routed.as_ref().msg
             {
@@ -1097,7 +1361,7 @@ impl actix::Handler<stream::Frame> for PeerActor {
             ) => {
                 match reason {
                     HandshakeFailureReason::GenesisMismatch(genesis) => {
-                        warn!(target: "network", "Attempting to connect to a node ({}) with a different genesis block. Our genesis: {:?}, their genesis: {:?}", peer_info, self.network_state.genesis_id, genesis);
+                        warn!(target: "network", "Attempting to connect to a node ({}) with a different genesis block. Our genesis: {:?}, their genesis: {:?}", self.network_state.config.peer_info_for_logs(&peer_info), self.network_state.genesis_id, genesis);
                         self.stop(ctx, ClosingReason::HandshakeFailed);
                     }
                     HandshakeFailureReason::ProtocolVersionMismatch {
@@ -1106,10 +1370,10 @@ impl actix::Handler<stream::Frame> for PeerActor {
                     } => {
                         // Retry the handshake with the common protocol version.
                         let common_version = std::cmp::min(version, PROTOCOL_VERSION);
-                        if common_version < oldest_supported_version
-                            || common_version < PEER_MIN_ALLOWED_PROTOCOL_VERSION
+                        let min_version = self.network_state.config.min_accepted_protocol_version;
+                        if common_version < oldest_supported_version || common_version < min_version
                         {
-                            warn!(target: "network", "Unable to connect to a node ({}) due to a network protocol version mismatch. Our version: {:?}, their: {:?}", peer_info, (PROTOCOL_VERSION, PEER_MIN_ALLOWED_PROTOCOL_VERSION), (version, oldest_supported_version));
+                            warn!(target: "network", "Unable to connect to a node ({}) due to a network protocol version mismatch. Our version: {:?}, their: {:?}", self.network_state.config.peer_info_for_logs(&peer_info), (PROTOCOL_VERSION, min_version), (version, oldest_supported_version));
                             self.stop(ctx, ClosingReason::HandshakeFailed);
                             return;
                         }
@@ -1121,7 +1385,7 @@ impl actix::Handler<stream::Frame> for PeerActor {
                         }));
                     }
                     HandshakeFailureReason::InvalidTarget => {
-                        debug!(target: "network", "Peer found was not what expected. Updating peer info with {:?}", peer_info);
+                        debug!(target: "network", "Peer found was not what expected. Updating peer info with {:?}", self.network_state.config.peer_info_for_logs(&peer_info));
                         self.network_state
                             .peer_manager_addr
                             .do_send(PeerToManagerMsg::UpdatePeerInfo(peer_info));
@@ -1152,7 +1416,7 @@ impl actix::Handler<stream::Frame> for PeerActor {
                     edge.verify();
                 // Disconnect if neighbor sent an invalid edge.
                 if !ok {
-                    info!(target: "network", "{:?}: Peer {:?} sent invalid edge. Disconnect.", self.my_node_id(), self.peer_addr);
+                    info!(target: "network", "{:?}: Peer {:?} sent invalid edge. Disconnect.", self.my_node_id(), self.peer_addr_for_logs());
                     self.stop(ctx, ClosingReason::HandshakeFailed);
                     return;
                 }
@@ -1166,30 +1430,49 @@ impl actix::Handler<stream::Frame> for PeerActor {
                 }));
             }
             (PeerStatus::Connecting { .. }, PeerMessage::Handshake(msg)) => {
+                self.handshake_received = true;
                 self.process_handshake(ctx, msg)
             }
             (PeerStatus::Ready, PeerMessage::Disconnect) => {
                 debug!(target: "network", "Disconnect signal. Me: {:?} Peer: {:?}", self.my_node_info.id, self.other_peer_id());
                 self.stop(ctx, ClosingReason::DisconnectMessage);
             }
+            (PeerStatus::Ready, PeerMessage::KeepAliveConfig { interval_ms }) => {
+                let interval = agreed_keep_alive_interval(
+                    self.network_state.config.keep_alive_interval,
+                    interval_ms,
+                );
+                self.start_keep_alive(ctx, interval);
+            }
+            (PeerStatus::Ready, PeerMessage::SupportedEncodings(encodings)) => {
+                let shared = shared_encodings(SUPPORTED_ENCODINGS, &encodings);
+                debug!(target: "network", "Encodings shared with {}: {:?}", self.peer_info_for_logs(), shared);
+                if let Some(conn) = &self.connection {
+                    conn.shared_encodings.store(shared);
+                }
+            }
             (PeerStatus::Ready, PeerMessage::Handshake(_)) => {
                 // Received handshake after already have seen handshake from this peer.
-                debug!(target: "network", "Duplicate handshake from {}", self.peer_info);
+                debug!(target: "network", "Duplicate handshake from {}", self.peer_info_for_logs());
             }
             (PeerStatus::Ready, PeerMessage::PeersRequest) => {
                 self.network_state.peer_manager_addr.send(PeerToManagerMsg::PeersRequest(PeersRequest {}))
                 .into_actor(self).then(|res, act, _ctx| {
                     if let Ok(peers) = res.map(|f|f.unwrap_peers_request_result()) {
                         if !peers.peers.is_empty() {
-                            debug!(target: "network", "Peers request from {}: sending {} peers.", act.peer_info, peers.peers.len());
-                            act.send_message_or_log(&PeerMessage::PeersResponse(peers.peers));
+                            debug!(target: "network", "Peers request from {}: sending {} peers.", act.peer_info_for_logs(), peers.peers.len());
+                            let enc = act.encoding().unwrap_or(Encoding::Proto);
+                            let max_bytes = act.network_state.config.max_peers_response_bytes;
+                            for msg in PeerMessage::peers_response_chunks(enc, peers.peers, max_bytes) {
+                                act.send_message_or_log(&msg);
+                            }
                         }
                     }
                     actix::fut::ready(())
                 }).spawn(ctx);
             }
             (PeerStatus::Ready, PeerMessage::PeersResponse(peers)) => {
-                debug!(target: "network", "Received peers from {}: {} peers.", self.peer_info, peers.len());
+                debug!(target: "network", "Received peers from {}: {} peers.", self.peer_info_for_logs(), peers.len());
                 self.network_state
                     .peer_manager_addr
                     .do_send(PeerToManagerMsg::PeersResponse(PeersResponse { peers }));
@@ -1233,23 +1516,93 @@ impl actix::Handler<stream::Frame> for PeerActor {
                     actix::fut::ready(())
                 })
                 .spawn(ctx),
+            (PeerStatus::Ready, PeerMessage::LastEdge(edge)) => {
+                // The peer presents evidence that the edge between us has a newer version than
+                // the one the connection was established with. Pass it on to the routing table
+                // only if it is a correctly signed, newer version of our edge.
+                let current = &self.connection.as_ref().unwrap().edge;
+                if !edge.supersedes(current) || !edge.verify() {
+                    debug!(target: "network", "{:?}: Ignoring stale or unrelated LastEdge from {}: nonce {} (current {})", self.my_node_id(), self.peer_info_for_logs(), edge.nonce(), current.nonce());
+                    return;
+                }
+                self.network_state.peer_manager_addr.do_send(PeerToManagerMsg::SyncRoutingTable {
+                    peer_id: self.other_peer_id().unwrap().clone(),
+                    routing_table_update: RoutingTableUpdate::from_edges(vec![edge]),
+                });
+            }
+            (PeerStatus::Ready, PeerMessage::RoutingTableSubsetRequest { account_prefix }) => {
+                // An empty prefix would request the whole routing table.
+                let dropped = if account_prefix.is_empty() {
+                    Some("empty_prefix")
+                } else if !self.routing_table_subset_request_rl.try_acquire(self.clock.now()) {
+                    Some("rate_limited")
+                } else {
+                    None
+                };
+                if let Some(reason) = dropped {
+                    debug!(target: "network", reason, "Dropping RoutingTableSubsetRequest from {}", self.peer_info_for_logs());
+                    metrics::ROUTING_TABLE_SUBSET_REQUESTS_DROPPED
+                        .with_label_values(&[reason])
+                        .inc();
+                    self.network_state.config.event_sink.push(Event::MessageProcessed(peer_msg));
+                    return;
+                }
+                self.network_state
+                    .peer_manager_addr
+                    .send(PeerToManagerMsg::RoutingTableSubsetRequest(account_prefix))
+                    .into_actor(self)
+                    .then(|res, act, _ctx| {
+                        if let Ok(PeerToManagerMsgResp::RoutingTableUpdate(update)) = res {
+                            for update in update.into_chunks() {
+                                act.send_message_or_log(&PeerMessage::SyncRoutingTable(update));
+                            }
+                        }
+                        act.network_state.config.event_sink.push(Event::MessageProcessed(peer_msg));
+                        actix::fut::ready(())
+                    })
+                    .spawn(ctx)
+            }
             (PeerStatus::Ready, PeerMessage::SyncRoutingTable(routing_table_update)) => {
+                if !self.sync_routing_table_rl.try_acquire(self.clock.now()) {
+                    debug!(target: "network", "Dropping SyncRoutingTable from {}: rate limit exceeded", self.peer_info_for_logs());
+                    metrics::SYNC_ROUTING_TABLE_RATE_LIMITED.inc();
+                    return;
+                }
                 self.network_state.peer_manager_addr.do_send(PeerToManagerMsg::SyncRoutingTable {
                     peer_id: self.other_peer_id().unwrap().clone(),
                     routing_table_update,
                 });
             }
-            (PeerStatus::Ready, PeerMessage::SyncAccountsData(msg)) => {
+            (PeerStatus::Ready, PeerMessage::SyncAccountsData(mut msg)) => {
                 let peer_id = self.other_peer_id().unwrap().clone();
                 let pms = self.network_state.clone();
+                if msg.incremental && pms.config.validate_accounts_data_epochs {
+                    let dropped = pms.accounts_data.retain_active_epochs(&mut msg.accounts_data);
+                    metrics::ACCOUNTS_DATA_INACTIVE_EPOCH.inc_by(dropped as u64);
+                }
                 // In case a full sync is requested, immediately send what we got.
                 // It is a microoptimization: we do not send back the data we just received.
                 if msg.requesting_full_sync {
+                    let accounts_data: Vec<_> =
+                        pms.accounts_data.load().data.values().cloned().collect();
                     self.send_message_or_log(&PeerMessage::SyncAccountsData(SyncAccountsData {
                         requesting_full_sync: false,
                         incremental: false,
-                        accounts_data: pms.accounts_data.load().data.values().cloned().collect(),
+                        total_count: accounts_data.len(),
+                        accounts_data,
                     }));
+                } else if !msg.incremental {
+                    // A full sync answers our outstanding request, if any. If some of its
+                    // entries were lost, ask for the full set again, but only once per request.
+                    let request = self.full_accounts_data_sync.take();
+                    if !msg.is_complete() {
+                        debug!(target: "network", got = msg.accounts_data.len(), want = msg.total_count, "Incomplete full accounts data sync from {}", self.peer_info_for_logs());
+                        if let Some(FullAccountsDataSyncRequest { retry: false }) = request {
+                            if self.full_accounts_data_sync_retry_rl.try_acquire(self.clock.now()) {
+                                self.request_full_accounts_data_sync(true);
+                            }
+                        }
+                    }
                 }
                 async move {
                     // Early exit, if there is no data in the message.
@@ -1262,6 +1615,8 @@ impl actix::Handler<stream::Frame> for PeerActor {
                     // This will prevent a malicious peer from forcing us to re-verify valid
                     // datasets. See accounts_data::Cache documentation for details.
                     if new_data.len() > 0 {
+                        metrics::ACCOUNTS_DATA_KNOWN_VALIDATORS
+                            .set(pms.accounts_data.known_validator_accounts().len() as i64);
                         let handles: Vec<_> = pms
                             .tier2
                             .load()
@@ -1292,27 +1647,61 @@ impl actix::Handler<stream::Frame> for PeerActor {
                 tracing::trace!(
                     target: "network",
                     "Received routed message from {} to {:?}.",
-                    self.peer_info,
+                    self.peer_info_for_logs(),
                     msg.target);
 
+                // Drop messages from banned authors before the expensive signature check.
+                if self.network_state.is_banned(msg.author()) {
+                    debug!(target: "network", author = ?msg.author(), "Dropping routed message from a banned author from {}", self.peer_info_for_logs());
+                    metrics::ROUTED_MESSAGE_DROPPED.with_label_values(&[msg.body_variant()]).inc();
+                    self.network_state.config.event_sink.push(Event::RoutedMessageDropped);
+                    return;
+                }
                 // Receive invalid routed message from peer.
                 if !msg.verify() {
                     self.stop(ctx, ClosingReason::Ban(ReasonForBan::InvalidSignature));
                     return;
                 }
+                // Drop messages already received recently, possibly from another peer.
+                // This happens only after the signature has been verified, so that a forged
+                // copy with the same hash cannot shadow the genuine message.
+                // Requests are exempt, as they are retried by design with the same content.
+                if !msg.expect_response()
+                    && self
+                        .network_state
+                        .routed_message_dedup
+                        .is_duplicate(self.clock.now(), msg.hash())
+                {
+                    debug!(target: "network", "Dropping {} message from {} already received over another path", msg.body_variant(), msg.author);
+                    metrics::ROUTED_MESSAGE_DEDUPLICATED
+                        .with_label_values(&[msg.body_variant()])
+                        .inc();
+                    self.network_state.config.event_sink.push(Event::RoutedMessageDropped);
+                    return;
+                }
+                // A message with zero TTL should have never been sent.
+                if !msg.is_deliverable() {
+                    debug!(target: "network", ?msg, "Dropping routed message with zero TTL from {}", self.peer_info_for_logs());
+                    metrics::ROUTED_MESSAGE_DROPPED.with_label_values(&[msg.body_variant()]).inc();
+                    self.network_state.config.event_sink.push(Event::RoutedMessageDropped);
+                    return;
+                }
                 let from = self.other_peer_id().unwrap().clone();
                 if msg.expect_response() {
                     tracing::trace!(target: "network", route_back = ?msg.clone(), "Received peer message that requires response");
                     self.network_state.routing_table_view.add_route_back(
                         &self.clock,
                         msg.hash(),
-                        from.clone(),
+                        from,
                     );
                 }
                 if self.network_state.message_for_me(&msg.target) {
                     metrics::record_routed_msg_latency(&self.clock, &msg);
-                    // Handle Ping and Pong message if they are for us without sending to client.
-                    // i.e. Return false in case of Ping and Pong
+                    if let Some(id) = in_flight::state_part_response(&msg.body) {
+                        self.network_state.state_part_requests.finish(&id, msg.request_id);
+                    }
+                    // Handle Ping, Pong and Heartbeat messages if they are for us without sending to client.
+                    // i.e. Return false in case of Ping, Pong and Heartbeat
                     match &msg.body {
                         RoutedMessageBody::Ping(ping) => {
                             self.network_state.send_pong(&self.clock, ping.nonce, msg.hash());
@@ -1331,20 +1720,53 @@ impl actix::Handler<stream::Frame> for PeerActor {
                                 .event_sink
                                 .push(Event::MessageProcessed(PeerMessage::Routed(msg)));
                         }
+                        RoutedMessageBody::Heartbeat(heartbeat) => {
+                            self.network_state.send_heartbeat_ack(
+                                &self.clock,
+                                heartbeat.nonce,
+                                msg.hash(),
+                            );
+                            self.network_state
+                                .config
+                                .event_sink
+                                .push(Event::MessageProcessed(PeerMessage::Routed(msg)));
+                        }
+                        RoutedMessageBody::HeartbeatAck(_) => {
+                            self.network_state
+                                .config
+                                .event_sink
+                                .push(Event::MessageProcessed(PeerMessage::Routed(msg)));
+                        }
+                        RoutedMessageBody::TelemetryRequest => {
+                            self.network_state.send_telemetry_response(&self.clock, msg.hash());
+                            self.network_state
+                                .config
+                                .event_sink
+                                .push(Event::MessageProcessed(PeerMessage::Routed(msg)));
+                        }
+                        RoutedMessageBody::TelemetryResponse(_) => {
+                            self.network_state
+                                .config
+                                .event_sink
+                                .push(Event::MessageProcessed(PeerMessage::Routed(msg)));
+                        }
+                        RoutedMessageBody::StateRequestPartBusy(shard_id, _, part_id) => {
+                            // State sync requests the part again after a timeout, possibly
+                            // from another peer.
+                            debug!(target: "network", shard_id, part_id, author = ?msg.author(), "StateRequestPart refused as busy");
+                            self.network_state
+                                .config
+                                .event_sink
+                                .push(Event::MessageProcessed(PeerMessage::Routed(msg)));
+                        }
                         _ => {
                             self.receive_message(ctx, PeerMessage::Routed(msg.clone()));
                         }
                     }
                 } else {
-                    if msg.decrease_ttl() {
-                        self.network_state.send_message_to_peer(&self.clock, msg);
-                    } else {
-                        self.network_state.config.event_sink.push(Event::RoutedMessageDropped);
-                        warn!(target: "network", ?msg, ?from, "Message dropped because TTL reached 0.");
-                        metrics::ROUTED_MESSAGE_DROPPED
-                            .with_label_values(&[msg.body_variant()])
-                            .inc();
-                    }
+                    // Messages which run out of TTL are dropped by the routing.
+                    msg.decrease_ttl();
+                    self.network_state.send_message_to_peer(&self.clock, msg);
                 }
             }
             (PeerStatus::Ready, msg) => {
@@ -3,7 +3,7 @@ use crate::concurrency::demux;
 use crate::config::NetworkConfig;
 use crate::network_protocol::testonly as data;
 use crate::network_protocol::{
-    Edge, PartialEdgeInfo, PeerInfo, PeerMessage, RawRoutedMessage, RoutedMessageBody,
+    Edge, Nonce, PartialEdgeInfo, PeerInfo, PeerMessage, RawRoutedMessage, RoutedMessageBody,
     RoutedMessageV2, RoutingTableUpdate,
 };
 use crate::peer::peer_actor::{ClosingReason, PeerActor};
@@ -17,7 +17,7 @@ use crate::tcp;
 use crate::testonly::actix::ActixSystem;
 use crate::testonly::fake_client;
 use crate::time;
-use crate::types::AccountOrPeerIdOrHash;
+use crate::types::{AccountOrPeerIdOrHash, ROUTED_MESSAGE_TTL};
 use actix::{Actor, Context, Handler};
 use near_crypto::{InMemorySigner, Signature};
 use near_primitives::network::PeerId;
@@ -37,7 +37,7 @@ pub struct PeerConfig {
     ///   so to enforce the nonce below, we add an artificial edge to RoutingTableView.
     ///   Once we switch to generating nonce from timestamp, this field should be deprecated
     ///   in favor of passing a fake clock.
-    pub nonce: Option<u64>,
+    pub nonce: Option<Nonce>,
 }
 
 impl PeerConfig {
@@ -45,7 +45,7 @@ impl PeerConfig {
         self.network.node_id()
     }
 
-    pub fn partial_edge_info(&self, other: &PeerId, nonce: u64) -> PartialEdgeInfo {
+    pub fn partial_edge_info(&self, other: &PeerId, nonce: Nonce) -> PartialEdgeInfo {
         PartialEdgeInfo::new(&self.id(), other, nonce, &self.network.node_key)
     }
 
@@ -85,6 +85,9 @@ impl Handler<PeerToManagerMsg> for FakePeerManagerActor {
             }
             PeerToManagerMsg::RequestUpdateNonce(..) => PeerToManagerMsgResp::Empty,
             PeerToManagerMsg::ResponseUpdateNonce(..) => PeerToManagerMsgResp::Empty,
+            PeerToManagerMsg::RoutingTableSubsetRequest(..) => {
+                PeerToManagerMsgResp::RoutingTableUpdate(RoutingTableUpdate::default())
+            }
             PeerToManagerMsg::PeersRequest(_) => {
                 // PeerActor would panic if we returned a different response.
                 // This also triggers sending a message to the peer.
@@ -94,6 +97,7 @@ impl Handler<PeerToManagerMsg> for FakePeerManagerActor {
             }
             PeerToManagerMsg::PeersResponse(..) => PeerToManagerMsgResp::Empty,
             PeerToManagerMsg::Unregister(_) => PeerToManagerMsgResp::Empty,
+            PeerToManagerMsg::EncodingRejected(_) => PeerToManagerMsgResp::Empty,
             _ => panic!("unsupported message"),
         }
     }
@@ -144,11 +148,12 @@ impl PeerHandle {
         ttl: u8,
         utc: Option<time::Utc>,
     ) -> RoutedMessageV2 {
-        RawRoutedMessage { target: AccountOrPeerIdOrHash::PeerId(peer_id), body }.sign(
-            &self.cfg.network.node_key,
-            ttl,
-            utc,
-        )
+        let mut msg = RawRoutedMessage { target: AccountOrPeerIdOrHash::PeerId(peer_id), body }
+            .sign(&self.cfg.network.node_key, ROUTED_MESSAGE_TTL, utc)
+            .unwrap();
+        // TTL is not signed, so it can be set to anything, including 0.
+        msg.ttl = ttl;
+        msg
     }
 
     pub async fn start_endpoint(
@@ -171,7 +176,7 @@ impl PeerHandle {
                 routing_table_view.add_local_edges(&[Edge::new(
                     cfg.id(),
                     peer_id.clone(),
-                    nonce - 1,
+                    Nonce(nonce.0 - 1),
                     Signature::default(),
                     Signature::default(),
                 )]);
@@ -179,6 +184,7 @@ impl PeerHandle {
             let mut network_cfg = cfg.network.clone();
             network_cfg.event_sink = send.sink().compose(Event::Network);
             let network_state = Arc::new(NetworkState::new(
+                &clock,
                 Arc::new(network_cfg.verify().unwrap()),
                 cfg.chain.genesis_id.clone(),
                 fc.clone().recipient(),
@@ -1,8 +1,11 @@
+use crate::concurrency::demux;
 use crate::network_protocol::testonly as data;
-use crate::network_protocol::Encoding;
+use crate::network_protocol::{Encoding, Nonce, RoutingTableUpdate};
 use crate::network_protocol::{Handshake, HandshakeFailureReason, PeerMessage, RoutedMessageBody};
+use crate::peer::peer_actor::agreed_keep_alive_interval;
 use crate::peer::testonly::{Event, PeerConfig, PeerHandle};
 use crate::peer_manager::peer_manager_actor::Event as PME;
+use crate::stats::metrics;
 use crate::tcp;
 use crate::testonly::fake_client::Event as CE;
 use crate::testonly::make_rng;
@@ -240,7 +243,7 @@ async fn test_handshake(outbound_encoding: Option<Encoding>, inbound_encoding: O
         target_peer_id: inbound.cfg.id(),
         sender_listen_port: Some(outbound_port),
         sender_chain_info: outbound_cfg.chain.get_peer_chain_info(),
-        partial_edge_info: outbound_cfg.partial_edge_info(&inbound.cfg.id(), 1),
+        partial_edge_info: outbound_cfg.partial_edge_info(&inbound.cfg.id(), Nonce::FIRST),
     };
     // We will also introduce chain_id mismatch, but ProtocolVersionMismatch is expected to take priority.
     handshake.sender_chain_info.genesis_id.chain_id = "unknown_chain".to_string();
@@ -272,8 +275,23 @@ async fn test_handshake(outbound_encoding: Option<Encoding>, inbound_encoding: O
         PeerMessage::HandshakeFailure(_, HandshakeFailureReason::GenesisMismatch(_))
     );
 
-    // Send a correct Handshake, expect a matching Handshake response.
+    // Send Handshake addressed to another peer, expect InvalidTarget.
     handshake.sender_chain_info = chain.get_peer_chain_info();
+    handshake.target_peer_id = data::make_peer_info(&mut rng).id;
+    outbound.write(&PeerMessage::Handshake(handshake.clone())).await;
+    let resp = outbound.read().await;
+    assert_matches!(resp, PeerMessage::HandshakeFailure(_, HandshakeFailureReason::InvalidTarget));
+
+    // Send Handshake from the target to itself, expect InvalidTarget.
+    let mut self_handshake = handshake.clone();
+    self_handshake.sender_peer_id = inbound.cfg.id();
+    self_handshake.target_peer_id = inbound.cfg.id();
+    outbound.write(&PeerMessage::Handshake(self_handshake)).await;
+    let resp = outbound.read().await;
+    assert_matches!(resp, PeerMessage::HandshakeFailure(_, HandshakeFailureReason::InvalidTarget));
+
+    // Send a correct Handshake, expect a matching Handshake response.
+    handshake.target_peer_id = inbound.cfg.id();
     outbound.write(&PeerMessage::Handshake(handshake.clone())).await;
     let resp = outbound.read().await;
     assert_matches!(resp, PeerMessage::Handshake(_));
@@ -297,3 +315,151 @@ async fn handshake() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+#[tokio::test]
+// Verifies that peers below min_accepted_protocol_version are refused,
+// even if they support our protocol version.
+async fn min_accepted_protocol_version() {
+    init_test_logger();
+    let mut rng = make_rng(89028037453);
+    let mut clock = time::FakeClock::default();
+
+    let chain = Arc::new(data::Chain::make(&mut clock, &mut rng, 12));
+    let mut network = chain.make_config(&mut rng);
+    network.min_accepted_protocol_version = PROTOCOL_VERSION;
+    let inbound_cfg = PeerConfig {
+        network,
+        chain: chain.clone(),
+        peers: vec![],
+        force_encoding: Some(Encoding::Proto),
+        nonce: None,
+    };
+    let outbound_cfg = PeerConfig {
+        network: chain.make_config(&mut rng),
+        chain: chain.clone(),
+        peers: vec![],
+        force_encoding: Some(Encoding::Proto),
+        nonce: None,
+    };
+    let (outbound_stream, inbound_stream) = tcp::Stream::loopback(inbound_cfg.id()).await;
+    let inbound = PeerHandle::start_endpoint(clock.clock(), inbound_cfg, inbound_stream).await;
+    let outbound_port = outbound_stream.local_addr.port();
+    let mut outbound = Stream::new(Some(Encoding::Proto), outbound_stream);
+
+    // Just below the floor, expect ProtocolVersionMismatch advertising the floor.
+    let mut handshake = Handshake {
+        protocol_version: PROTOCOL_VERSION - 1,
+        oldest_supported_version: PEER_MIN_ALLOWED_PROTOCOL_VERSION,
+        sender_peer_id: outbound_cfg.id(),
+        target_peer_id: inbound.cfg.id(),
+        sender_listen_port: Some(outbound_port),
+        sender_chain_info: outbound_cfg.chain.get_peer_chain_info(),
+        partial_edge_info: outbound_cfg.partial_edge_info(&inbound.cfg.id(), Nonce::FIRST),
+    };
+    outbound.write(&PeerMessage::Handshake(handshake.clone())).await;
+    assert_matches!(
+        outbound.read().await,
+        PeerMessage::HandshakeFailure(
+            _,
+            HandshakeFailureReason::ProtocolVersionMismatch { oldest_supported_version, .. }
+        ) if oldest_supported_version == PROTOCOL_VERSION
+    );
+
+    // At the floor, expect a Handshake response.
+    handshake.protocol_version = PROTOCOL_VERSION;
+    outbound.write(&PeerMessage::Handshake(handshake.clone())).await;
+    assert_matches!(outbound.read().await, PeerMessage::Handshake(_));
+}
+
+// SyncRoutingTable messages within the rate limit are passed to PeerManager,
+// the excess ones are dropped and counted.
+#[tokio::test]
+async fn sync_routing_table_rate_limit() {
+    init_test_logger();
+    let mut rng = make_rng(4920183746);
+    let mut clock = time::FakeClock::default();
+
+    let chain = Arc::new(data::Chain::make(&mut clock, &mut rng, 12));
+    let mut inbound_network = chain.make_config(&mut rng);
+    inbound_network.sync_routing_table_rate_limit = demux::RateLimit { qps: 1., burst: 2 };
+    let inbound_cfg = PeerConfig {
+        chain: chain.clone(),
+        network: inbound_network,
+        peers: vec![],
+        force_encoding: Some(Encoding::Proto),
+        nonce: None,
+    };
+    let outbound_cfg = PeerConfig {
+        chain: chain.clone(),
+        network: chain.make_config(&mut rng),
+        peers: vec![],
+        force_encoding: Some(Encoding::Proto),
+        nonce: None,
+    };
+    let (outbound_stream, inbound_stream) = tcp::Stream::loopback(inbound_cfg.id()).await;
+    let mut inbound = PeerHandle::start_endpoint(clock.clock(), inbound_cfg, inbound_stream).await;
+    let mut outbound =
+        PeerHandle::start_endpoint(clock.clock(), outbound_cfg, outbound_stream).await;
+    outbound.complete_handshake().await;
+    inbound.complete_handshake().await;
+
+    let updates: Vec<_> = (0..4)
+        .map(|_| {
+            let edge = data::make_edge(&data::make_signer(&mut rng), &data::make_signer(&mut rng));
+            RoutingTableUpdate::from_edges(vec![edge])
+        })
+        .collect();
+    let mut events = inbound.events.from_now();
+    let mut routing_table_events = inbound.events.from_now();
+    let dropped = metrics::SYNC_ROUTING_TABLE_RATE_LIMITED.get();
+
+    // The burst is processed, the next update is dropped.
+    for update in &updates[..3] {
+        outbound.send(PeerMessage::SyncRoutingTable(update.clone())).await;
+    }
+    // Messages are processed in order, so once the next message is processed,
+    // the updates above have been processed as well.
+    let flush = PeerMessage::RequestUpdateNonce(data::make_partial_edge(&mut rng));
+    outbound.send(flush.clone()).await;
+    events
+        .recv_until(|ev| match ev {
+            Event::Network(PME::MessageProcessed(msg)) if msg == flush => Some(()),
+            _ => None,
+        })
+        .await;
+    assert_eq!(dropped + 1, metrics::SYNC_ROUTING_TABLE_RATE_LIMITED.get());
+
+    // Once a token is refilled, the next update is processed.
+    clock.advance(time::Duration::seconds(1));
+    outbound.send(PeerMessage::SyncRoutingTable(updates[3].clone())).await;
+    let mut got = vec![];
+    while got.last() != Some(&updates[3]) {
+        got.push(
+            routing_table_events
+                .recv_until(|ev| match ev {
+                    Event::RoutingTable(update) => Some(update),
+                    _ => None,
+                })
+                .await,
+        );
+    }
+    assert_eq!(vec![updates[0].clone(), updates[1].clone(), updates[3].clone()], got);
+    assert_eq!(dropped + 1, metrics::SYNC_ROUTING_TABLE_RATE_LIMITED.get());
+}
+
+#[test]
+fn keep_alive_interval() {
+    let s = time::Duration::seconds;
+    // The shorter proposal wins, regardless of which side made it.
+    assert_eq!(s(10), agreed_keep_alive_interval(s(10), 20_000));
+    assert_eq!(s(10), agreed_keep_alive_interval(s(20), 10_000));
+    assert_eq!(s(15), agreed_keep_alive_interval(s(15), 15_000));
+    // A proposal of 0 means no preference.
+    assert_eq!(s(10), agreed_keep_alive_interval(s(10), 0));
+    assert_eq!(s(20), agreed_keep_alive_interval(s(0), 20_000));
+    // The agreed interval is never shorter than 1s.
+    assert_eq!(s(1), agreed_keep_alive_interval(s(0), 0));
+    assert_eq!(s(1), agreed_keep_alive_interval(s(10), 1));
+    assert_eq!(s(1), agreed_keep_alive_interval(time::Duration::milliseconds(10), 20_000));
+    assert_eq!(s(1), agreed_keep_alive_interval(s(1), 1_000));
+}
@@ -2,7 +2,7 @@ use crate::concurrency::arc_mutex::ArcMutex;
 use crate::concurrency::atomic_cell::AtomicCell;
 use crate::concurrency::demux;
 use crate::network_protocol::{
-    Edge, PartialEdgeInfo, PeerChainInfoV2, PeerInfo, PeerMessage, SignedAccountData,
+    Edge, Encoding, PartialEdgeInfo, PeerChainInfoV2, PeerInfo, PeerMessage, SignedAccountData,
     SyncAccountsData,
 };
 use crate::peer::peer_actor::PeerActor;
@@ -12,6 +12,7 @@ use crate::time;
 use crate::types::FullPeerInfo;
 use crate::types::{PeerManagerRequest, PeerManagerRequestWithContext, PeerType, ReasonForBan};
 use near_primitives::network::PeerId;
+use near_primitives::version::ProtocolVersion;
 use std::collections::{hash_map::Entry, HashMap};
 use std::fmt;
 use std::future::Future;
@@ -50,6 +51,13 @@ pub(crate) struct Connection {
     pub edge: Edge,
     pub initial_chain_info: PeerChainInfoV2,
     pub chain_height: AtomicU64,
+    /// Protocol version from the peer's handshake.
+    pub protocol_version: ProtocolVersion,
+    /// Encoding agreed upon in the handshake.
+    pub encoding: Encoding,
+    /// Encodings which both sides can decode, as declared in the peer's
+    /// SupportedEncodings message. Empty until that message arrives.
+    pub shared_encodings: AtomicCell<Vec<Encoding>>,
 
     /// Who started connection. Inbound (other) or Outbound (us).
     pub peer_type: PeerType,
@@ -64,6 +72,8 @@ pub(crate) struct Connection {
     pub stats: Arc<Stats>,
     /// prometheus gauge point guard.
     pub _peer_connections_metric: metrics::GaugePoint,
+    /// prometheus gauge point guard of the peer's protocol version.
+    pub _peer_protocol_version_metric: metrics::GaugePoint,
 
     /// A helper data structure for limiting reading, reporting stats.
     pub send_accounts_data_demux: demux::Demux<Vec<Arc<SignedAccountData>>, ()>,
@@ -75,6 +85,7 @@ impl fmt::Debug for Connection {
             .field("peer_info", &self.peer_info)
             .field("edge", &self.edge)
             .field("peer_type", &self.peer_type)
+            .field("shared_encodings", &self.shared_encodings.load())
             .field("connection_established_time", &self.connection_established_time)
             .finish()
     }
@@ -132,9 +143,9 @@ impl Connection {
                         let res = ds.iter().map(|_| ()).collect();
                         let mut sum = HashMap::<_, Arc<SignedAccountData>>::new();
                         for d in ds.into_iter().flatten() {
-                            match sum.entry((d.epoch_id.clone(), d.account_id.clone())) {
+                            match sum.entry((d.epoch_id().clone(), d.account_id().clone())) {
                                 Entry::Occupied(mut x) => {
-                                    if x.get().timestamp < d.timestamp {
+                                    if x.get().timestamp() < d.timestamp() {
                                         x.insert(d);
                                     }
                                 }
@@ -147,6 +158,7 @@ impl Connection {
                             incremental: true,
                             requesting_full_sync: false,
                             accounts_data: sum.into_values().collect(),
+                            total_count: 0,
                         }));
                         this.send_message(msg);
                         res
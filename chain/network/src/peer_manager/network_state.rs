@@ -2,12 +2,17 @@ use crate::accounts_data;
 use crate::concurrency::demux;
 use crate::config;
 use crate::network_protocol::{
-    AccountOrPeerIdOrHash, PartialEdgeInfo, PeerIdOrHash, PeerMessage, Ping, Pong,
-    RawRoutedMessage, RoutedMessageBody, RoutedMessageV2,
+    AccountOrPeerIdOrHash, Encoding, HeartbeatAck, Nonce, PartialEdgeInfo, PeerIdOrHash,
+    PeerMessage, Ping, Pong, RawRoutedMessage, RoutedMessageBody, RoutedMessageV2,
+    RoutingTargetError, TelemetryData, UptimeBucket,
 };
 use crate::peer_manager::connection;
+use crate::peer_manager::peer_manager_actor::{Event, PREFERRED_BORSH_ENCODING_TTL};
 use crate::private_actix::PeerToManagerMsg;
-use crate::routing::routing_table_view::RoutingTableView;
+use crate::routing::dedup::RoutedMessageDedup;
+use crate::routing::in_flight::InFlightStatePartRequests;
+use crate::routing::routing_table_view::{FindRouteError, RoutingTableView};
+use crate::routing::RouteOutcome;
 use crate::stats::metrics;
 use crate::time;
 use crate::types::{ChainInfo, NetworkClientMessages, NetworkViewClientMessages};
@@ -16,18 +21,42 @@ use arc_swap::ArcSwap;
 use near_primitives::block::GenesisId;
 use near_primitives::hash::CryptoHash;
 use near_primitives::network::PeerId;
+use near_primitives::types::ShardId;
+use near_primitives::version::{ProtocolVersion, PROTOCOL_VERSION};
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
 /// How often to request peers from active peers.
 const REQUEST_PEERS_INTERVAL: time::Duration = time::Duration::milliseconds(60_000);
 /// Limit number of pending Peer actors to avoid OOM.
 pub(crate) const LIMIT_PENDING_PEERS: usize = 60;
 
+/// Encoding agreed upon in the last completed handshake with a peer.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PreferredEncoding {
+    pub encoding: Encoding,
+    /// Borsh is preferred only until then, see `PREFERRED_BORSH_ENCODING_TTL`.
+    /// None for proto, which is preferred until the peer rejects it.
+    pub expires_at: Option<time::Instant>,
+}
+
+impl PreferredEncoding {
+    pub fn new(now: time::Instant, encoding: Encoding) -> Self {
+        let expires_at = match encoding {
+            Encoding::Proto => None,
+            Encoding::Borsh => Some(now + PREFERRED_BORSH_ENCODING_TTL),
+        };
+        Self { encoding, expires_at }
+    }
+}
+
 pub(crate) struct NetworkState {
     /// PeerManager config.
     pub config: Arc<config::VerifiedConfig>,
+    /// When the network layer has been started. Reported (coarsely) in the telemetry.
+    pub started: time::Instant,
     /// GenesisId of the chain.
     pub genesis_id: GenesisId,
     pub send_accounts_data_rl: demux::RateLimit,
@@ -57,10 +86,21 @@ pub(crate) struct NetworkState {
     /// Shared counter across all PeerActors, which counts number of `RoutedMessageBody::ForwardTx`
     /// messages sincce last block.
     pub txns_since_last_block: AtomicUsize,
+    /// Peers which are currently banned. Kept in sync with the PeerStore by PeerManagerActor,
+    /// so that PeerActors can drop routed messages authored by these peers without verifying them.
+    pub banned_peers: parking_lot::RwLock<HashSet<PeerId>>,
+    /// Encodings agreed upon in the last completed handshakes with the peers. Kept in sync with
+    /// the PeerStore by PeerManagerActor, so that outbound PeerActors can propose them right away.
+    pub preferred_encodings: parking_lot::RwLock<HashMap<PeerId, PreferredEncoding>>,
+    /// Recently received routed messages, to drop the ones arriving over multiple paths.
+    pub routed_message_dedup: RoutedMessageDedup,
+    /// StateRequestPart messages sent by this node which haven't been answered yet.
+    pub state_part_requests: InFlightStatePartRequests,
 }
 
 impl NetworkState {
     pub fn new(
+        clock: &time::Clock,
         config: Arc<config::VerifiedConfig>,
         genesis_id: GenesisId,
         client_addr: Recipient<NetworkClientMessages>,
@@ -70,6 +110,7 @@ impl NetworkState {
         send_accounts_data_rl: demux::RateLimit,
     ) -> Self {
         Self {
+            started: clock.now(),
             genesis_id,
             client_addr,
             view_client_addr,
@@ -80,11 +121,34 @@ impl NetworkState {
             accounts_data: Arc::new(accounts_data::Cache::new()),
             routing_table_view,
             send_accounts_data_rl,
+            routed_message_dedup: RoutedMessageDedup::new(
+                config.routed_message_dedup_window,
+                config::ROUTED_MESSAGE_DEDUP_CAPACITY,
+            ),
+            state_part_requests: InFlightStatePartRequests::new(
+                config.state_part_request_timeout,
+                config::IN_FLIGHT_STATE_PART_REQUESTS_CAPACITY,
+            ),
             config,
             txns_since_last_block: AtomicUsize::new(0),
+            banned_peers: Default::default(),
+            preferred_encodings: Default::default(),
         }
     }
 
+    /// Encoding to propose right away to the peer, if any.
+    pub fn preferred_encoding(&self, now: time::Instant, peer_id: &PeerId) -> Option<Encoding> {
+        let pref = *self.preferred_encodings.read().get(peer_id)?;
+        match pref.expires_at {
+            Some(expires_at) if expires_at <= now => None,
+            _ => Some(pref.encoding),
+        }
+    }
+
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.banned_peers.read().contains(peer_id)
+    }
+
     /// Query connected peers for more peers.
     pub fn ask_for_more_peers(&self, clock: &time::Clock) {
         let now = clock.now();
@@ -96,11 +160,21 @@ impl NetworkState {
         }
     }
 
-    pub fn propose_edge(&self, peer1: &PeerId, with_nonce: Option<u64>) -> PartialEdgeInfo {
+    /// Distribution of the protocol versions (as declared in the handshakes)
+    /// across the connected peers.
+    pub fn peer_protocol_versions(&self) -> HashMap<ProtocolVersion, usize> {
+        let mut versions = HashMap::new();
+        for conn in self.tier2.load().ready.values() {
+            *versions.entry(conn.protocol_version).or_default() += 1;
+        }
+        versions
+    }
+
+    pub fn propose_edge(&self, peer1: &PeerId, with_nonce: Option<Nonce>) -> PartialEdgeInfo {
         // When we create a new edge we increase the latest nonce by 2 in case we miss a removal
         // proposal from our partner.
         let nonce = with_nonce.unwrap_or_else(|| {
-            self.routing_table_view.get_local_edge(peer1).map_or(1, |edge| edge.next())
+            self.routing_table_view.get_local_edge(peer1).map_or(Nonce::FIRST, |edge| edge.next())
         });
         PartialEdgeInfo::new(&self.config.node_id(), peer1, nonce, &self.config.node_key)
     }
@@ -119,26 +193,101 @@ impl NetworkState {
     pub fn send_ping(&self, clock: &time::Clock, nonce: u64, target: PeerId) {
         let body = RoutedMessageBody::Ping(Ping { nonce, source: self.config.node_id() });
         let msg = RawRoutedMessage { target: AccountOrPeerIdOrHash::PeerId(target), body };
-        self.send_message_to_peer(clock, self.sign_message(clock, msg));
+        self.sign_and_send_message(clock, msg);
     }
 
     pub fn send_pong(&self, clock: &time::Clock, nonce: u64, target: CryptoHash) {
         let body = RoutedMessageBody::Pong(Pong { nonce, source: self.config.node_id() });
         let msg = RawRoutedMessage { target: AccountOrPeerIdOrHash::Hash(target), body };
-        self.send_message_to_peer(clock, self.sign_message(clock, msg));
+        self.sign_and_send_message(clock, msg);
     }
 
-    pub fn sign_message(&self, clock: &time::Clock, msg: RawRoutedMessage) -> Box<RoutedMessageV2> {
-        Box::new(msg.sign(
+    pub fn send_heartbeat_ack(&self, clock: &time::Clock, nonce: u64, target: CryptoHash) {
+        let body = RoutedMessageBody::HeartbeatAck(HeartbeatAck { nonce });
+        let msg = RawRoutedMessage { target: AccountOrPeerIdOrHash::Hash(target), body };
+        self.sign_and_send_message(clock, msg);
+    }
+
+    /// Telemetry reported to the peers which request it. Empty, unless the node
+    /// is configured to share it.
+    pub fn telemetry_data(&self, clock: &time::Clock) -> TelemetryData {
+        if !self.config.share_telemetry {
+            return TelemetryData::default();
+        }
+        TelemetryData {
+            protocol_version: Some(PROTOCOL_VERSION),
+            uptime: Some(UptimeBucket::from_uptime(clock.now() - self.started)),
+            tracked_shards: Some(self.chain_info.load().tracked_shards.clone()),
+        }
+    }
+
+    pub fn send_telemetry_response(&self, clock: &time::Clock, target: CryptoHash) {
+        let body = RoutedMessageBody::TelemetryResponse(self.telemetry_data(clock));
+        let msg = RawRoutedMessage { target: AccountOrPeerIdOrHash::Hash(target), body };
+        self.sign_and_send_message(clock, msg);
+    }
+
+    pub fn send_state_request_part_busy(
+        &self,
+        clock: &time::Clock,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        part_id: u64,
+        target: CryptoHash,
+        request_id: Option<u64>,
+    ) {
+        let body = RoutedMessageBody::StateRequestPartBusy(shard_id, sync_hash, part_id);
+        let msg = RawRoutedMessage { target: AccountOrPeerIdOrHash::Hash(target), body };
+        self.sign_and_send_message_with_request_id(clock, msg, request_id);
+    }
+
+    pub fn sign_message(
+        &self,
+        clock: &time::Clock,
+        msg: RawRoutedMessage,
+    ) -> Result<Box<RoutedMessageV2>, RoutingTargetError> {
+        Ok(Box::new(msg.sign(
             &self.config.node_key,
             self.config.routed_message_ttl,
             Some(clock.now_utc()),
-        ))
+        )?))
+    }
+
+    /// Signs the message and routes it to the target peer.
+    pub fn sign_and_send_message(
+        &self,
+        clock: &time::Clock,
+        msg: RawRoutedMessage,
+    ) -> RouteOutcome {
+        self.sign_and_send_message_with_request_id(clock, msg, None)
+    }
+
+    /// Same as `sign_and_send_message`, but also attaches `request_id` to the message,
+    /// see `RoutedMessageV2::request_id`.
+    pub fn sign_and_send_message_with_request_id(
+        &self,
+        clock: &time::Clock,
+        msg: RawRoutedMessage,
+        request_id: Option<u64>,
+    ) -> RouteOutcome {
+        match self.sign_message(clock, msg) {
+            Ok(mut msg) => {
+                msg.request_id = request_id;
+                self.send_message_to_peer(clock, msg)
+            }
+            Err(err) => {
+                debug!(target: "network", "Dropping routed message: {}", err);
+                RouteOutcome::TargetUnknown
+            }
+        }
     }
 
     /// Route signed message to target peer.
-    /// Return whether the message is sent or not.
-    pub fn send_message_to_peer(&self, clock: &time::Clock, msg: Box<RoutedMessageV2>) -> bool {
+    pub fn send_message_to_peer(
+        &self,
+        clock: &time::Clock,
+        msg: Box<RoutedMessageV2>,
+    ) -> RouteOutcome {
         let my_peer_id = self.config.node_id();
 
         // Check if the message is for myself and don't try to send it in that case.
@@ -146,9 +295,22 @@ impl NetworkState {
             if target == &my_peer_id {
                 debug!(target: "network", account_id = ?self.config.validator.as_ref().map(|v|v.account_id()), ?my_peer_id, ?msg, "Drop signed message to myself");
                 metrics::CONNECTED_TO_MYSELF.inc();
-                return false;
+                return RouteOutcome::NoRoute;
             }
         }
+        if !msg.is_deliverable() {
+            warn!(target: "network", ?msg, "Message dropped because TTL reached 0.");
+            metrics::ROUTED_MESSAGE_DROPPED.with_label_values(&[msg.body_variant()]).inc();
+            self.config.event_sink.push(Event::RoutedMessageDropped);
+            return RouteOutcome::TtlExpired;
+        }
+        // The author might have been banned after the message has been received.
+        if self.is_banned(msg.author()) {
+            debug!(target: "network", author = ?msg.author(), "Drop signed message from a banned author");
+            metrics::ROUTED_MESSAGE_DROPPED.with_label_values(&[msg.body_variant()]).inc();
+            self.config.event_sink.push(Event::RoutedMessageDropped);
+            return RouteOutcome::AuthorBanned;
+        }
 
         match self.routing_table_view.find_route(&clock, &msg.target) {
             Ok(peer_id) => {
@@ -157,7 +319,11 @@ impl NetworkState {
                     trace!(target: "network", ?msg, "initiate route back");
                     self.routing_table_view.add_route_back(&clock, msg.hash(), my_peer_id);
                 }
-                self.tier2.send_message(peer_id, Arc::new(PeerMessage::Routed(msg)))
+                if self.tier2.send_message(peer_id, Arc::new(PeerMessage::Routed(msg))) {
+                    RouteOutcome::Delivered
+                } else {
+                    RouteOutcome::NoRoute
+                }
             }
             Err(find_route_error) => {
                 // TODO(MarX, #1369): Message is dropped here. Define policy for this case.
@@ -171,7 +337,12 @@ impl NetworkState {
                       msg = ?msg.msg.body,
                     "Drop signed message"
                 );
-                false
+                match find_route_error {
+                    FindRouteError::PeerUnreachable => RouteOutcome::NoRoute,
+                    FindRouteError::AccountNotFound | FindRouteError::RouteBackNotFound => {
+                        RouteOutcome::TargetUnknown
+                    }
+                }
             }
         }
     }
@@ -1,12 +1,12 @@
 use crate::config;
 use crate::network_protocol::{
-    AccountData, AccountOrPeerIdOrHash, Edge, EdgeState, PartialEdgeInfo, PeerInfo, PeerMessage,
-    Ping, Pong, RawRoutedMessage, RoutedMessageBody, RoutingTableUpdate, StateResponseInfo,
-    SyncAccountsData,
+    AccountData, AccountOrPeerIdOrHash, Edge, EdgeState, Encoding, Heartbeat, Nonce,
+    PartialEdgeInfo, PeerInfo, PeerMessage, Ping, Pong, RawRoutedMessage, RoutedMessageBody,
+    RoutingTableUpdate, StateResponseInfo, SyncAccountsData,
 };
 use crate::peer::peer_actor::PeerActor;
 use crate::peer_manager::connection;
-use crate::peer_manager::network_state::NetworkState;
+use crate::peer_manager::network_state::{NetworkState, PreferredEncoding};
 use crate::peer_manager::peer_store::PeerStore;
 use crate::private_actix::{
     PeerRequestResult, PeersRequest, RegisterPeer, RegisterPeerError, RegisterPeerResponse,
@@ -16,6 +16,7 @@ use crate::private_actix::{PeerToManagerMsg, PeerToManagerMsgResp, PeersResponse
 use crate::routing;
 use crate::routing::edge_validator_actor::EdgeValidatorHelper;
 use crate::routing::routing_table_view::RoutingTableView;
+use crate::routing::RouteOutcome;
 use crate::stats::metrics;
 use crate::store;
 use crate::tcp;
@@ -84,6 +85,10 @@ const PRUNE_EDGES_AFTER: time::Duration = time::Duration::minutes(30);
 /// We send these messages multiple times to reduce the chance that they are lost
 const IMPORTANT_MESSAGE_RESENT_COUNT: usize = 3;
 
+/// How long borsh is proposed right away to a peer, with which borsh has been agreed upon
+/// in the last handshake. After that proto is negotiated again, in case the peer got upgraded.
+pub(crate) const PREFERRED_BORSH_ENCODING_TTL: time::Duration = time::Duration::hours(1);
+
 /// If a peer is more than these blocks behind (comparing to our current head) - don't route any messages through it.
 /// We are updating the list of unreliable peers every MONITOR_PEER_MAX_DURATION (60 seconds) - so the current
 /// horizon value is roughly matching this threshold (if the node is 60 blocks behind, it will take it a while to recover).
@@ -144,7 +149,7 @@ pub struct PeerManagerActor {
     /// Flag that track whether we started attempts to establish outbound connections.
     started_connect_attempts: bool,
     /// Connected peers we have sent new edge update, but we haven't received response so far.
-    local_peer_pending_update_nonce_request: HashMap<PeerId, u64>,
+    local_peer_pending_update_nonce_request: HashMap<PeerId, Nonce>,
     /// RoutingTableActor, responsible for computing routing table, routing table exchange, etc.
     routing_table_addr: Addr<routing::Actor>,
     /// Whitelisted nodes, which are allowed to connect even if the connection limit has been
@@ -191,6 +196,22 @@ impl Actor for PeerManagerActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        // Peers banned before the restart.
+        *self.state.banned_peers.write() = self
+            .peer_store
+            .iter()
+            .filter(|(_, peer_state)| peer_state.status.is_banned())
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect();
+        // Encodings agreed upon with the peers before the restart.
+        let now = self.clock.now();
+        *self.state.preferred_encodings.write() = self
+            .peer_store
+            .encodings()
+            .iter()
+            .map(|(peer_id, encoding)| (peer_id.clone(), PreferredEncoding::new(now, *encoding)))
+            .collect();
+
         // Start server if address provided.
         if let Some(server_addr) = self.config.node_addr {
             debug!(target: "network", at = ?server_addr, "starting public server");
@@ -211,7 +232,7 @@ impl Actor for PeerManagerActor {
                         // It is expected to be reasonably cheap: eventually, for TIER2 network
                         // we would like to exchange set of connected peers even without establishing
                         // a proper connection.
-                        debug!(target: "network", from = ?stream.peer_addr, "got new connection");
+                        debug!(target: "network", from = ?state.config.addr_for_logs(stream.peer_addr), "got new connection");
                         if let Err(err) =
                             PeerActor::spawn(clock.clone(), stream, None, state.clone())
                         {
@@ -304,7 +325,7 @@ impl PeerManagerActor {
         let config = Arc::new(config);
         let rl = config.accounts_data_broadcast_rate_limit;
         Ok(Self::start_in_arbiter(&actix::Arbiter::new().handle(), move |ctx| Self {
-            clock,
+            clock: clock.clone(),
             my_peer_id: my_peer_id.clone(),
             config: config.clone(),
             max_num_peers: config.max_num_peers,
@@ -316,6 +337,7 @@ impl PeerManagerActor {
             routing_table_addr,
             whitelist_nodes,
             state: Arc::new(NetworkState::new(
+                &clock,
                 config.clone(),
                 genesis_id,
                 client_addr,
@@ -368,9 +390,9 @@ impl PeerManagerActor {
         let new_accounts = self.state.routing_table_view.add_accounts(accounts);
         debug!(target: "network", account_id = ?self.config.validator.as_ref().map(|v|v.account_id()), ?new_accounts, "Received new accounts");
         if new_accounts.len() > 0 {
-            self.state.tier2.broadcast_message(Arc::new(PeerMessage::SyncRoutingTable(
-                RoutingTableUpdate::from_accounts(new_accounts),
-            )));
+            for update in RoutingTableUpdate::from_accounts(new_accounts).into_chunks() {
+                self.state.tier2.broadcast_message(Arc::new(PeerMessage::SyncRoutingTable(update)));
+            }
         }
     }
 
@@ -543,12 +565,26 @@ impl PeerManagerActor {
     ) -> Result<(), connection::PoolError> {
         let peer_info = &connection.peer_info;
         let _span = tracing::trace_span!(target: "network", "register_peer").entered();
-        debug!(target: "network", ?peer_info, "Consolidated connection");
+        let addr = peer_info.addr.map(|addr| self.config.addr_for_logs(addr));
+        debug!(target: "network", id = ?peer_info.id, ?addr, "Consolidated connection");
         self.state.tier2.insert_ready(connection.clone())?;
         // Best effort write to DB.
         if let Err(err) = self.peer_store.peer_connected(&self.clock, peer_info) {
             error!(target: "network", ?err, "Failed to save peer data");
         }
+        self.state.preferred_encodings.write().insert(
+            peer_info.id.clone(),
+            PreferredEncoding::new(self.clock.now(), connection.encoding),
+        );
+        // Borsh is remembered only in memory and only temporarily, so that the peer
+        // gets a chance to switch to proto after an upgrade.
+        let res = match connection.encoding {
+            Encoding::Proto => self.peer_store.set_encoding(&peer_info.id, connection.encoding),
+            Encoding::Borsh => self.peer_store.forget_encoding(&peer_info.id),
+        };
+        if let Err(err) = res {
+            error!(target: "network", ?err, "Failed to save peer encoding");
+        }
         self.add_verified_edges_to_routing_table(vec![connection.edge.clone()]);
         self.sync_after_handshake(connection.clone(), ctx);
         Ok(())
@@ -571,9 +607,9 @@ impl PeerManagerActor {
                     metrics::EDGE_TOMBSTONE_SENDING_SKIPPED.inc();
                 }
                 let known_accounts = act.state.routing_table_view.get_announce_accounts();
-                peer.send_message(Arc::new(PeerMessage::SyncRoutingTable(
-                    RoutingTableUpdate::new(known_edges, known_accounts),
-                )));
+                for update in RoutingTableUpdate::new(known_edges, known_accounts).into_chunks() {
+                    peer.send_message(Arc::new(PeerMessage::SyncRoutingTable(update)));
+                }
 
                 // Ask for peers list on connection.
                 peer.send_message(Arc::new(PeerMessage::PeersRequest));
@@ -624,6 +660,7 @@ impl PeerManagerActor {
     fn ban_peer(&mut self, peer_id: &PeerId, ban_reason: ReasonForBan) {
         warn!(target: "network", ?peer_id, ?ban_reason, "Banning peer");
         self.remove_connected_peer(peer_id, None);
+        self.state.banned_peers.write().insert(peer_id.clone());
         if let Err(err) = self.peer_store.peer_ban(&self.clock, peer_id, ban_reason) {
             error!(target: "network", ?err, "Failed to save peer data");
         };
@@ -911,6 +948,7 @@ impl PeerManagerActor {
         }
 
         for peer_id in to_unban {
+            self.state.banned_peers.write().remove(&peer_id);
             if let Err(err) = self.peer_store.peer_unban(&peer_id) {
                 error!(target: "network", ?err, "Failed to unban a peer");
             }
@@ -940,7 +978,8 @@ impl PeerManagerActor {
                             PeerActor::spawn(clock,stream,None,state.clone()).context("PeerActor::spawn()")?;
                             anyhow::Ok(())
                         }.await {
-                            tracing::info!(target:"network", ?err, "failed to connect to {peer_info}");
+                            let addr = peer_info.addr.map(|addr| state.config.addr_for_logs(addr));
+                            tracing::info!(target:"network", ?err, peer_id = %peer_info.id, ?addr, "failed to connect");
                         }
                     }
                 }));
@@ -984,36 +1023,51 @@ impl PeerManagerActor {
             ValidateEdgeList {
                 source_peer_id: peer_id,
                 edges,
+                drop_asymmetric_edges: self.config.drop_asymmetric_edges,
                 edges_info_shared: self.routing_table_exchange_helper.edges_info_shared.clone(),
                 sender: self.routing_table_exchange_helper.edges_to_add_sender.clone(),
             },
         ));
     }
 
-    /// Return whether the message is sent or not.
     fn send_message_to_account_or_peer_or_hash(
         &mut self,
         target: &AccountOrPeerIdOrHash,
         msg: RoutedMessageBody,
-    ) -> bool {
+        request_id: Option<u64>,
+    ) -> RouteOutcome {
         match target {
             AccountOrPeerIdOrHash::AccountId(account_id) => {
-                self.send_message_to_account(account_id, msg)
+                self.send_message_to_account_with_request_id(account_id, msg, request_id)
             }
             peer_or_hash @ AccountOrPeerIdOrHash::PeerId(_)
-            | peer_or_hash @ AccountOrPeerIdOrHash::Hash(_) => self.state.send_message_to_peer(
-                &self.clock,
-                self.state.sign_message(
+            | peer_or_hash @ AccountOrPeerIdOrHash::Hash(_) => {
+                self.state.sign_and_send_message_with_request_id(
                     &self.clock,
                     RawRoutedMessage { target: peer_or_hash.clone(), body: msg },
-                ),
-            ),
+                    request_id,
+                )
+            }
         }
     }
 
     /// Send message to specific account.
-    /// Return whether the message is sent or not.
-    fn send_message_to_account(&mut self, account_id: &AccountId, msg: RoutedMessageBody) -> bool {
+    fn send_message_to_account(
+        &mut self,
+        account_id: &AccountId,
+        msg: RoutedMessageBody,
+    ) -> RouteOutcome {
+        self.send_message_to_account_with_request_id(account_id, msg, None)
+    }
+
+    /// Same as `send_message_to_account`, but also attaches `request_id` to the message,
+    /// see `RoutedMessageV2::request_id`.
+    fn send_message_to_account_with_request_id(
+        &mut self,
+        account_id: &AccountId,
+        msg: RoutedMessageBody,
+        request_id: Option<u64>,
+    ) -> RouteOutcome {
         let target = match self.state.routing_table_view.account_owner(account_id) {
             Ok(peer_id) => peer_id,
             Err(find_route_error) => {
@@ -1026,18 +1080,30 @@ impl PeerManagerActor {
                        ?msg,"Drop message",
                 );
                 trace!(target: "network", known_peers = ?self.state.routing_table_view.get_accounts_keys(), "Known peers");
-                return false;
+                return RouteOutcome::TargetUnknown;
             }
         };
 
         let msg = RawRoutedMessage { target: AccountOrPeerIdOrHash::PeerId(target), body: msg };
-        let msg = self.state.sign_message(&self.clock, msg);
+        let msg = match self.state.sign_message(&self.clock, msg) {
+            Ok(mut msg) => {
+                msg.request_id = request_id;
+                msg
+            }
+            Err(err) => {
+                debug!(target: "network", to = ?account_id, "Drop message: {}", err);
+                return RouteOutcome::TargetUnknown;
+            }
+        };
         if msg.body.is_important() {
-            let mut success = false;
+            let mut outcome = RouteOutcome::NoRoute;
             for _ in 0..IMPORTANT_MESSAGE_RESENT_COUNT {
-                success |= self.state.send_message_to_peer(&self.clock, msg.clone());
+                let got = self.state.send_message_to_peer(&self.clock, msg.clone());
+                if !outcome.is_delivered() {
+                    outcome = got;
+                }
             }
-            success
+            outcome
         } else {
             self.state.send_message_to_peer(&self.clock, msg)
         }
@@ -1150,22 +1216,44 @@ impl PeerManagerActor {
                 }
             }
             NetworkRequests::StateRequestHeader { shard_id, sync_hash, target } => {
-                if self.send_message_to_account_or_peer_or_hash(
-                    &target,
-                    RoutedMessageBody::StateRequestHeader(shard_id, sync_hash),
-                ) {
+                if self
+                    .send_message_to_account_or_peer_or_hash(
+                        &target,
+                        RoutedMessageBody::StateRequestHeader(shard_id, sync_hash),
+                        None,
+                    )
+                    .is_delivered()
+                {
                     NetworkResponses::NoResponse
                 } else {
                     NetworkResponses::RouteNotFound
                 }
             }
             NetworkRequests::StateRequestPart { shard_id, sync_hash, part_id, target } => {
-                if self.send_message_to_account_or_peer_or_hash(
-                    &target,
-                    RoutedMessageBody::StateRequestPart(shard_id, sync_hash, part_id),
-                ) {
+                let id = (shard_id, sync_hash, part_id);
+                let request_id = match self
+                    .state
+                    .state_part_requests
+                    .try_start(self.clock.now(), id)
+                {
+                    Some(request_id) => request_id,
+                    None => {
+                        debug!(target: "network", shard_id, part_id, "Not sending StateRequestPart, the part has already been requested");
+                        metrics::STATE_PART_REQUESTS_SUPPRESSED.inc();
+                        return NetworkResponses::NoResponse;
+                    }
+                };
+                if self
+                    .send_message_to_account_or_peer_or_hash(
+                        &target,
+                        RoutedMessageBody::StateRequestPart(shard_id, sync_hash, part_id),
+                        Some(request_id),
+                    )
+                    .is_delivered()
+                {
                     NetworkResponses::NoResponse
                 } else {
+                    self.state.state_part_requests.finish(&id, Some(request_id));
                     NetworkResponses::RouteNotFound
                 }
             }
@@ -1176,13 +1264,14 @@ impl PeerManagerActor {
                         RoutedMessageBody::VersionedStateResponse(response)
                     }
                 };
-                if self.state.send_message_to_peer(
-                    &self.clock,
-                    self.state.sign_message(
+                if self
+                    .state
+                    .sign_and_send_message(
                         &self.clock,
                         RawRoutedMessage { target: AccountOrPeerIdOrHash::Hash(route_back), body },
-                    ),
-                ) {
+                    )
+                    .is_delivered()
+                {
                     NetworkResponses::NoResponse
                 } else {
                     NetworkResponses::RouteNotFound
@@ -1229,10 +1318,13 @@ impl PeerManagerActor {
                 for prefer_peer in &[target.prefer_peer, !target.prefer_peer] {
                     if !prefer_peer {
                         if let Some(account_id) = target.account_id.as_ref() {
-                            if self.send_message_to_account(
-                                account_id,
-                                RoutedMessageBody::PartialEncodedChunkRequest(request.clone()),
-                            ) {
+                            if self
+                                .send_message_to_account(
+                                    account_id,
+                                    RoutedMessageBody::PartialEncodedChunkRequest(request.clone()),
+                                )
+                                .is_delivered()
+                            {
                                 success = true;
                                 break;
                             }
@@ -1250,9 +1342,9 @@ impl PeerManagerActor {
 
                         if let Some(matching_peer) = matching_peers.iter().choose(&mut thread_rng())
                         {
-                            if self.state.send_message_to_peer(
-                                &self.clock,
-                                self.state.sign_message(
+                            if self
+                                .state
+                                .sign_and_send_message(
                                     &self.clock,
                                     RawRoutedMessage {
                                         target: AccountOrPeerIdOrHash::PeerId(
@@ -1262,8 +1354,9 @@ impl PeerManagerActor {
                                             request.clone(),
                                         ),
                                     },
-                                ),
-                            ) {
+                                )
+                                .is_delivered()
+                            {
                                 success = true;
                                 break;
                             }
@@ -1281,53 +1374,81 @@ impl PeerManagerActor {
                 }
             }
             NetworkRequests::PartialEncodedChunkResponse { route_back, response } => {
-                if self.state.send_message_to_peer(
-                    &self.clock,
-                    self.state.sign_message(
+                if self
+                    .state
+                    .sign_and_send_message(
                         &self.clock,
                         RawRoutedMessage {
                             target: AccountOrPeerIdOrHash::Hash(route_back),
                             body: RoutedMessageBody::PartialEncodedChunkResponse(response),
                         },
-                    ),
-                ) {
+                    )
+                    .is_delivered()
+                {
                     NetworkResponses::NoResponse
                 } else {
                     NetworkResponses::RouteNotFound
                 }
             }
             NetworkRequests::PartialEncodedChunkMessage { account_id, partial_encoded_chunk } => {
-                if self.send_message_to_account(
-                    &account_id,
-                    RoutedMessageBody::VersionedPartialEncodedChunk(partial_encoded_chunk.into()),
-                ) {
+                if self
+                    .send_message_to_account(
+                        &account_id,
+                        RoutedMessageBody::VersionedPartialEncodedChunk(
+                            partial_encoded_chunk.into(),
+                        ),
+                    )
+                    .is_delivered()
+                {
                     NetworkResponses::NoResponse
                 } else {
                     NetworkResponses::RouteNotFound
                 }
             }
             NetworkRequests::PartialEncodedChunkForward { account_id, forward } => {
-                if self.send_message_to_account(
-                    &account_id,
-                    RoutedMessageBody::PartialEncodedChunkForward(forward),
-                ) {
+                if self
+                    .send_message_to_account(
+                        &account_id,
+                        RoutedMessageBody::PartialEncodedChunkForward(forward),
+                    )
+                    .is_delivered()
+                {
                     NetworkResponses::NoResponse
                 } else {
                     NetworkResponses::RouteNotFound
                 }
             }
             NetworkRequests::ForwardTx(account_id, tx) => {
-                if self.send_message_to_account(&account_id, RoutedMessageBody::ForwardTx(tx)) {
+                if self
+                    .send_message_to_account(&account_id, RoutedMessageBody::ForwardTx(tx))
+                    .is_delivered()
+                {
                     NetworkResponses::NoResponse
                 } else {
                     NetworkResponses::RouteNotFound
                 }
             }
             NetworkRequests::TxStatus(account_id, signer_account_id, tx_hash) => {
-                if self.send_message_to_account(
-                    &account_id,
-                    RoutedMessageBody::TxStatusRequest(signer_account_id, tx_hash),
-                ) {
+                if self
+                    .send_message_to_account(
+                        &account_id,
+                        RoutedMessageBody::TxStatusRequest(signer_account_id, tx_hash),
+                    )
+                    .is_delivered()
+                {
+                    NetworkResponses::NoResponse
+                } else {
+                    NetworkResponses::RouteNotFound
+                }
+            }
+            NetworkRequests::TxInclusionProof(account_id, tx_hash) => {
+                if self
+                    .send_message_to_account(
+                        &account_id,
+                        RoutedMessageBody::TxInclusionProofRequest(tx_hash),
+                    )
+                    .is_delivered()
+                {
                     NetworkResponses::NoResponse
                 } else {
                     NetworkResponses::RouteNotFound
@@ -1359,7 +1480,8 @@ impl PeerManagerActor {
         let peer_info = &msg.connection.peer_info;
         // Check if this is a blacklisted peer.
         if peer_info.addr.as_ref().map_or(true, |addr| self.peer_store.is_blacklisted(addr)) {
-            debug!(target: "network", peer_info = ?peer_info, "Dropping connection from blacklisted peer or unknown address");
+            let addr = peer_info.addr.map(|addr| self.config.addr_for_logs(addr));
+            debug!(target: "network", id = ?peer_info.id, ?addr, "Dropping connection from blacklisted peer or unknown address");
             return RegisterPeerResponse::Reject(RegisterPeerError::Blacklisted);
         }
 
@@ -1440,6 +1562,7 @@ impl PeerManagerActor {
                 if let Err(err) =
                     PeerActor::spawn(self.clock.clone(), stream, None, self.state.clone())
                 {
+                    let peer_addr = self.config.addr_for_logs(peer_addr);
                     tracing::info!(target:"network", ?err, ?peer_addr, "spawn_outbound()");
                 }
                 PeerManagerMessageResponse::OutboundTcpConnect
@@ -1458,6 +1581,23 @@ impl PeerManagerActor {
                 self.state.send_ping(&self.clock, nonce, target);
                 PeerManagerMessageResponse::PingTo
             }
+            PeerManagerMessageRequest::HeartbeatTo { nonce, account_id } => {
+                PeerManagerMessageResponse::HeartbeatTo(self.send_message_to_account(
+                    &account_id,
+                    RoutedMessageBody::Heartbeat(Heartbeat { nonce }),
+                ))
+            }
+            // TEST-ONLY
+            PeerManagerMessageRequest::RouteMessage(msg) => {
+                PeerManagerMessageResponse::RouteMessage(
+                    self.state.send_message_to_peer(&self.clock, msg),
+                )
+            }
+            PeerManagerMessageRequest::PeerProtocolVersions => {
+                PeerManagerMessageResponse::PeerProtocolVersions(
+                    self.state.peer_protocol_versions(),
+                )
+            }
         }
     }
 
@@ -1477,17 +1617,12 @@ impl PeerManagerActor {
                 self.handle_msg_peers_response(msg);
                 PeerToManagerMsgResp::Empty
             }
-            PeerToManagerMsg::RouteBack(body, target) => {
+            PeerToManagerMsg::RouteBack(body, target, request_id) => {
                 trace!(target: "network", ?target, "Sending message to route back");
-                self.state.send_message_to_peer(
+                self.state.sign_and_send_message_with_request_id(
                     &self.clock,
-                    self.state.sign_message(
-                        &self.clock,
-                        RawRoutedMessage {
-                            target: AccountOrPeerIdOrHash::Hash(target),
-                            body: *body,
-                        },
-                    ),
+                    RawRoutedMessage { target: AccountOrPeerIdOrHash::Hash(target), body: *body },
+                    request_id,
                 );
                 PeerToManagerMsgResp::Empty
             }
@@ -1505,6 +1640,13 @@ impl PeerManagerActor {
                 self.handle_msg_ban(msg);
                 PeerToManagerMsgResp::Empty
             }
+            PeerToManagerMsg::EncodingRejected(peer_id) => {
+                self.state.preferred_encodings.write().remove(&peer_id);
+                if let Err(err) = self.peer_store.forget_encoding(&peer_id) {
+                    error!(target: "network", ?err, "Failed to save peer encoding");
+                }
+                PeerToManagerMsgResp::Empty
+            }
             PeerToManagerMsg::RequestUpdateNonce(peer_id, edge_info) => {
                 if Edge::partial_verify(&self.my_peer_id, &peer_id, &edge_info) {
                     if let Some(cur_edge) = self.state.routing_table_view.get_local_edge(&peer_id) {
@@ -1603,6 +1745,21 @@ impl PeerManagerActor {
                 self.validate_edges_and_add_to_routing_table(peer_id, edges);
                 PeerToManagerMsgResp::Empty
             }
+            PeerToManagerMsg::RoutingTableSubsetRequest(account_prefix) => {
+                let accounts = self
+                    .state
+                    .routing_table_view
+                    .get_announce_accounts_with_prefix(&account_prefix);
+                // Only the edges adjacent to the peers these accounts are announced at.
+                let peers: HashSet<&PeerId> = accounts.iter().map(|a| &a.peer_id).collect();
+                let graph = self.network_graph.read();
+                let mut edges: Vec<_> =
+                    peers.into_iter().flat_map(|peer_id| graph.adjacent_edges(peer_id)).collect();
+                // An edge between two of the peers is found twice.
+                edges.sort_by(|a, b| a.key().cmp(b.key()));
+                edges.dedup_by(|a, b| a.key() == b.key());
+                PeerToManagerMsgResp::RoutingTableUpdate(RoutingTableUpdate::new(edges, accounts))
+            }
         }
     }
 }
@@ -1686,12 +1843,10 @@ impl Handler<SetChainInfo> for PeerManagerActor {
                     }
                     // This unwrap is safe, because we did signed a sample payload during
                     // config validation. See config::Config::new().
-                    Some(Arc::new(AccountData {
-                        epoch_id: epoch_id.clone(),
-                        account_id: my_account_id.clone(),
-                        timestamp: now,
-                        peers: my_peers.clone(),
-                    }.sign(vc.signer.as_ref()).unwrap()))
+                    Some(Arc::new(AccountData::builder(my_account_id.clone(), epoch_id.clone(), now)
+                        .peers(my_peers.clone())
+                        .build()
+                        .sign(vc.signer.as_ref()).unwrap()))
                 }).collect();
                 // Insert node's own AccountData should never fail.
                 // We ignore the new data, because we trigger a full sync anyway.
@@ -1699,15 +1854,19 @@ impl Handler<SetChainInfo> for PeerManagerActor {
                     panic!("inserting node's own AccountData to self.state.accounts_data: {err}");
                 }
             }
+            metrics::ACCOUNTS_DATA_KNOWN_VALIDATORS
+                .set(state.accounts_data.known_validator_accounts().len() as i64);
             // The set of tier1 accounts has changed.
             // We might miss some data, so we start a full sync with the connected peers.
             // TODO(gprusak): add a daemon which does a periodic full sync in case some messages
             // are lost (at a frequency which makes the additional network load negligible).
+            let accounts_data: Vec<_> = state.accounts_data.load().data.values().cloned().collect();
             state.tier2.broadcast_message(Arc::new(PeerMessage::SyncAccountsData(
                 SyncAccountsData {
                     incremental: false,
                     requesting_full_sync: true,
-                    accounts_data: state.accounts_data.load().data.values().cloned().collect(),
+                    total_count: accounts_data.len(),
+                    accounts_data,
                 },
             )));
             state.config.event_sink.push(Event::SetChainInfo);
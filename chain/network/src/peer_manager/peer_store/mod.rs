@@ -1,6 +1,6 @@
 use crate::blacklist::Blacklist;
 use crate::config;
-use crate::network_protocol::PeerInfo;
+use crate::network_protocol::{Encoding, PeerInfo};
 use crate::store;
 use crate::time;
 use crate::types::{KnownPeerState, KnownPeerStatus, ReasonForBan};
@@ -55,6 +55,9 @@ pub struct PeerStore {
     blacklist: Blacklist,
     boot_nodes: HashSet<PeerId>,
     connect_only_to_boot_nodes: bool,
+    /// Encoding agreed upon in the last completed handshake with the peer.
+    /// Outbound connections propose it first, instead of negotiating the encoding.
+    encodings: HashMap<PeerId, Encoding>,
 }
 
 impl PeerStore {
@@ -151,6 +154,7 @@ impl PeerStore {
             }
         }
 
+        let encodings = store.list_peer_encodings()?.into_iter().collect();
         let mut peer_store = PeerStore {
             store,
             peer_states: peerid_2_state,
@@ -158,6 +162,7 @@ impl PeerStore {
             blacklist,
             boot_nodes: boot_nodes_set,
             connect_only_to_boot_nodes,
+            encodings,
         };
         peer_store.delete_peers(&peers_to_delete)?;
         Ok(peer_store)
@@ -225,9 +230,36 @@ impl PeerStore {
         Ok(())
     }
 
+    /// Encodings to propose first when connecting to the peers, see `set_encoding`.
+    pub(crate) fn encodings(&self) -> &HashMap<PeerId, Encoding> {
+        &self.encodings
+    }
+
+    /// Remembers the encoding agreed upon in a completed handshake with the peer.
+    pub(crate) fn set_encoding(
+        &mut self,
+        peer_id: &PeerId,
+        encoding: Encoding,
+    ) -> anyhow::Result<()> {
+        if self.encodings.insert(peer_id.clone(), encoding) == Some(encoding) {
+            return Ok(());
+        }
+        Ok(self.store.set_peer_encoding(peer_id, &encoding)?)
+    }
+
+    /// Forgets the encoding of the peer, so that the next handshake
+    /// negotiates the encoding from scratch.
+    pub(crate) fn forget_encoding(&mut self, peer_id: &PeerId) -> anyhow::Result<()> {
+        if self.encodings.remove(peer_id).is_none() {
+            return Ok(());
+        }
+        Ok(self.store.delete_peer_encoding(peer_id)?)
+    }
+
     /// Deletes peers from the internal cache and the persistent store.
     fn delete_peers(&mut self, peer_ids: &[PeerId]) -> anyhow::Result<()> {
         for peer_id in peer_ids {
+            self.encodings.remove(peer_id);
             if let Some(peer_state) = self.peer_states.remove(peer_id) {
                 if let Some(addr) = peer_state.peer_info.addr {
                     self.addr_peers.remove(&addr);
@@ -432,3 +432,37 @@ fn test_delete_peers() {
     }
     assert_peers_in_store(&opener, &[]);
 }
+
+#[test]
+fn encoding_store() {
+    let clock = time::FakeClock::default();
+    let (_tmp_dir, opener) = NodeStorage::test_opener();
+    let peer_a = gen_peer_info(0);
+    let peer_b = gen_peer_info(1);
+    let boot_nodes = vec![peer_a.clone(), peer_b.clone()];
+    {
+        let store = store::Store::from(opener.open().unwrap());
+        let mut peer_store =
+            PeerStore::new(&clock.clock(), store, &boot_nodes, Default::default(), false).unwrap();
+        assert!(peer_store.encodings().is_empty());
+        peer_store.set_encoding(&peer_a.id, Encoding::Proto).unwrap();
+        peer_store.set_encoding(&peer_b.id, Encoding::Proto).unwrap();
+        peer_store.set_encoding(&peer_b.id, Encoding::Borsh).unwrap();
+    }
+    {
+        let store = store::Store::from(opener.open().unwrap());
+        let mut peer_store =
+            PeerStore::new(&clock.clock(), store, &boot_nodes, Default::default(), false).unwrap();
+        let want: HashMap<_, _> =
+            [(peer_a.id.clone(), Encoding::Proto), (peer_b.id.clone(), Encoding::Borsh)].into();
+        assert_eq!(&want, peer_store.encodings());
+        peer_store.forget_encoding(&peer_a.id).unwrap();
+    }
+    {
+        let store = store::Store::from(opener.open().unwrap());
+        let peer_store =
+            PeerStore::new(&clock.clock(), store, &boot_nodes, Default::default(), false).unwrap();
+        let want: HashMap<_, _> = [(peer_b.id.clone(), Encoding::Borsh)].into();
+        assert_eq!(&want, peer_store.encodings());
+    }
+}
@@ -2,7 +2,8 @@ use crate::broadcast;
 use crate::config;
 use crate::network_protocol::testonly as data;
 use crate::network_protocol::{
-    Encoding, PeerAddr, PeerInfo, PeerMessage, SignedAccountData, SyncAccountsData,
+    Encoding, Handshake, Nonce, PartialEdgeInfo, PeerAddr, PeerInfo, PeerMessage,
+    SignedAccountData, SyncAccountsData,
 };
 use crate::peer;
 use crate::peer::peer_actor::ClosingReason;
@@ -10,11 +11,14 @@ use crate::peer_manager::peer_manager_actor::Event as PME;
 use crate::tcp;
 use crate::testonly::actix::ActixSystem;
 use crate::testonly::fake_client;
+use crate::testonly::stream::Stream;
 use crate::time;
 use crate::types::{ChainInfo, GetNetworkInfo, PeerManagerMessageRequest, SetChainInfo};
 use crate::PeerManagerActor;
+use near_crypto::InMemorySigner;
 use near_primitives::network::PeerId;
 use near_primitives::types::{AccountId, EpochId};
+use near_primitives::version::{ProtocolVersion, PEER_MIN_ALLOWED_PROTOCOL_VERSION};
 use std::collections::HashSet;
 use std::sync::Arc;
 
@@ -47,9 +51,9 @@ pub struct NormalAccountData {
 impl From<&Arc<SignedAccountData>> for NormalAccountData {
     fn from(d: &Arc<SignedAccountData>) -> Self {
         Self {
-            epoch_id: d.epoch_id.clone(),
-            account_id: d.account_id.clone(),
-            peers: d.peers.clone(),
+            epoch_id: d.epoch_id().clone(),
+            account_id: d.account_id().clone(),
+            peers: d.peers().to_vec(),
         }
     }
 }
@@ -84,6 +88,45 @@ impl RawConnection {
         peer
     }
 
+    /// Performs the handshake over a raw Stream, answering the Handshake sent by the
+    /// PeerManager. Returns the stream once the PeerManager has completed the handshake,
+    /// so that the test can exchange messages with it directly.
+    pub async fn raw_handshake(mut self) -> Stream {
+        let stream_id = self.stream.id();
+        let port = self.stream.local_addr.port();
+        let mut stream = Stream::new(Some(Encoding::Proto), self.stream);
+        let handshake = match stream.read().await {
+            PeerMessage::Handshake(handshake) => handshake,
+            msg => panic!("expected Handshake, got {msg}"),
+        };
+        stream
+            .write(&PeerMessage::Handshake(Handshake {
+                protocol_version: handshake.protocol_version,
+                oldest_supported_version: PEER_MIN_ALLOWED_PROTOCOL_VERSION,
+                sender_peer_id: self.cfg.id(),
+                target_peer_id: handshake.sender_peer_id.clone(),
+                sender_listen_port: Some(port),
+                sender_chain_info: self.cfg.chain.get_peer_chain_info(),
+                partial_edge_info: self.cfg.partial_edge_info(
+                    &handshake.sender_peer_id,
+                    handshake.partial_edge_info.nonce,
+                ),
+            }))
+            .await;
+        self.events
+            .recv_until(|ev| match ev {
+                Event::PeerManager(PME::HandshakeCompleted(ev)) if ev.stream_id == stream_id => {
+                    Some(())
+                }
+                Event::PeerManager(PME::ConnectionClosed(ev)) if ev.stream_id == stream_id => {
+                    panic!("handshake aborted: {}", ev.reason)
+                }
+                _ => None,
+            })
+            .await;
+        stream
+    }
+
     // Try to perform a handshake. PeerManager is expected to reject the handshake.
     pub async fn manager_fail_handshake(mut self, clock: &time::Clock) -> ClosingReason {
         let stream_id = self.stream.id();
@@ -104,6 +147,33 @@ impl RawConnection {
         drop(peer);
         reason
     }
+
+    // Announce a large frame and then send its bytes one by one, every `interval`,
+    // never completing the Handshake. Returns the reason for which PeerManager
+    // closed the connection.
+    pub async fn trickle_handshake(mut self, interval: time::Duration) -> ClosingReason {
+        use tokio::io::AsyncWriteExt as _;
+        let stream_id = self.stream.id();
+        let mut stream = self.stream.stream;
+        let trickle = async move {
+            let _ = stream.write_u32_le(1024).await;
+            loop {
+                // The connection is closed by PeerManager eventually, ignore write errors.
+                let _ = stream.write_all(&[0]).await;
+                tokio::time::sleep(interval.try_into().unwrap()).await;
+            }
+        };
+        let closed = self.events.recv_until(|ev| match ev {
+            Event::PeerManager(PME::ConnectionClosed(ev)) if ev.stream_id == stream_id => {
+                Some(ev.reason)
+            }
+            _ => None,
+        });
+        tokio::select! {
+            reason = closed => reason,
+            _ = trickle => unreachable!(),
+        }
+    }
 }
 
 impl ActorHandler {
@@ -133,6 +203,73 @@ impl ActorHandler {
             .await;
     }
 
+    /// Connects to the PeerManager over a raw Stream and performs the handshake as the peer
+    /// owning `signer`, declaring `protocol_version`. Returns the stream once the PeerManager
+    /// has completed the handshake, so that the test can exchange messages with it directly.
+    pub async fn raw_handshake(
+        &self,
+        chain: &data::Chain,
+        signer: &InMemorySigner,
+        protocol_version: ProtocolVersion,
+    ) -> Stream {
+        let peer_id = PeerId::new(signer.public_key.clone());
+        let stream = tcp::Stream::connect(&self.peer_info()).await.unwrap();
+        let stream_id = stream.id();
+        let port = stream.local_addr.port();
+        let mut events = self.events.from_now();
+        let mut stream = Stream::new(Some(Encoding::Proto), stream);
+        stream
+            .write(&PeerMessage::Handshake(Handshake {
+                protocol_version,
+                oldest_supported_version: PEER_MIN_ALLOWED_PROTOCOL_VERSION,
+                sender_peer_id: peer_id.clone(),
+                target_peer_id: self.cfg.node_id(),
+                sender_listen_port: Some(port),
+                sender_chain_info: chain.get_peer_chain_info(),
+                partial_edge_info: PartialEdgeInfo::new(
+                    &peer_id,
+                    &self.cfg.node_id(),
+                    Nonce::FIRST,
+                    &signer.secret_key,
+                ),
+            }))
+            .await;
+        events
+            .recv_until(|ev| match ev {
+                Event::PeerManager(PME::HandshakeCompleted(ev)) if ev.stream_id == stream_id => {
+                    Some(())
+                }
+                Event::PeerManager(PME::ConnectionClosed(ev)) if ev.stream_id == stream_id => {
+                    panic!("handshake aborted: {}", ev.reason)
+                }
+                _ => None,
+            })
+            .await;
+        stream
+    }
+
+    /// Connects a new peer to the PeerManager, which sends the messages in `encoding`,
+    /// and waits until the peer completes the handshake.
+    pub async fn connect_peer(
+        &self,
+        clock: &time::Clock,
+        chain: Arc<data::Chain>,
+        network_cfg: config::NetworkConfig,
+        encoding: Encoding,
+    ) -> peer::testonly::PeerHandle {
+        let cfg = peer::testonly::PeerConfig {
+            network: network_cfg,
+            chain,
+            peers: vec![],
+            force_encoding: Some(encoding),
+            nonce: None,
+        };
+        let stream = tcp::Stream::connect(&self.peer_info()).await.unwrap();
+        let mut peer = peer::testonly::PeerHandle::start_endpoint(clock.clone(), cfg, stream).await;
+        peer.complete_handshake().await;
+        peer
+    }
+
     pub async fn start_inbound(
         &self,
         chain: Arc<data::Chain>,
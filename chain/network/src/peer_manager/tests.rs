@@ -1,29 +1,44 @@
+use crate::broadcast;
 use crate::concurrency::demux;
 use crate::config;
 use crate::network_protocol::testonly as data;
-use crate::network_protocol::{Encoding, Handshake, PartialEdgeInfo, PeerAddr, SyncAccountsData};
-use crate::network_protocol::{Ping, RoutedMessageBody, EDGE_MIN_TIMESTAMP_NONCE};
+use crate::network_protocol::{
+    Encoding, Handshake, HandshakeFailureReason, Nonce, PartialEdgeInfo, PeerAddr,
+};
+use crate::network_protocol::{
+    Ping, Pong, RawRoutedMessage, RoutedMessageBody, RoutedMessageV2, SyncAccountsData,
+    TelemetryData, UptimeBucket, EDGE_MIN_TIMESTAMP_NONCE,
+};
 use crate::peer;
 use crate::peer::peer_actor::ClosingReason;
 use crate::peer_manager;
 use crate::peer_manager::connection;
 use crate::peer_manager::network_state::LIMIT_PENDING_PEERS;
-use crate::peer_manager::peer_manager_actor::Event as PME;
+use crate::peer_manager::peer_manager_actor::{Event as PME, PREFERRED_BORSH_ENCODING_TTL};
 use crate::peer_manager::testonly::{Event, NormalAccountData};
-use crate::private_actix::RegisterPeerError;
+use crate::private_actix::PeerToManagerMsg;
+use crate::routing::auditor::{AuditVerdict, RoutedMessageAuditor};
+use crate::routing::RouteOutcome;
+use crate::stats::metrics;
 use crate::tcp;
+use crate::testonly::fake_client;
 use crate::testonly::stream::Stream;
 use crate::testonly::{assert_is_superset, make_rng, AsSet as _};
 use crate::time;
-use crate::types::{PeerMessage, RoutingTableUpdate};
+use crate::types::{
+    AccountOrPeerIdOrHash, Ban, NetworkRequests, NetworkResponses, PeerManagerMessageRequest,
+    PeerManagerMessageResponse, PeerMessage, ReasonForBan, RoutingTableUpdate,
+};
 use itertools::Itertools;
 use near_o11y::testonly::init_test_logger;
-use near_primitives::version::PROTOCOL_VERSION;
+use near_primitives::hash::CryptoHash;
+use near_primitives::network::PeerId;
+use near_primitives::version::{PEER_MIN_ALLOWED_PROTOCOL_VERSION, PROTOCOL_VERSION};
 use pretty_assertions::assert_eq;
 use rand::seq::SliceRandom as _;
 use rand::Rng as _;
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
 // After the initial exchange, all subsequent SyncRoutingTable messages are
 // expected to contain only the diff of the known data.
@@ -165,16 +180,6 @@ async fn no_edge_broadcast_after_restart() {
     }
 }
 
-// Nonces must be odd (as even ones are reserved for tombstones).
-fn to_active_nonce(timestamp: time::Utc) -> u64 {
-    let value = timestamp.unix_timestamp() as u64;
-    if value % 2 == 0 {
-        value + 1
-    } else {
-        value
-    }
-}
-
 // Test connecting to peer manager with timestamp-like nonces.
 #[tokio::test]
 async fn test_nonces() {
@@ -195,20 +200,20 @@ async fn test_nonces() {
 
     let test_cases = [
         // Try to connect with peer with a valid nonce (current timestamp).
-        (Some(to_active_nonce(clock.now_utc())), true, "current timestamp"),
+        (Some(Nonce::from_utc(clock.now_utc())), true, "current timestamp"),
         // Now try the peer with invalid timestamp (in the past)
-        (Some(to_active_nonce(clock.now_utc() - time::Duration::days(1))), false, "past timestamp"),
+        (Some(Nonce::from_utc(clock.now_utc() - time::Duration::days(1))), false, "past timestamp"),
         // Now try the peer with invalid timestamp (in the future)
         (
-            Some(to_active_nonce(clock.now_utc() + time::Duration::days(1))),
+            Some(Nonce::from_utc(clock.now_utc() + time::Duration::days(1))),
             false,
             "future timestamp",
         ),
-        (Some(u64::MAX), false, "u64 max"),
-        (Some(i64::MAX as u64), false, "i64 max"),
-        (Some((i64::MAX - 1) as u64), false, "i64 max - 1"),
-        (Some(253402300799), false, "Max time"),
-        (Some(253402300799 + 2), false, "Over max time"),
+        (Some(Nonce(u64::MAX)), false, "u64 max"),
+        (Some(Nonce(i64::MAX as u64)), false, "i64 max"),
+        (Some(Nonce((i64::MAX - 1) as u64)), false, "i64 max - 1"),
+        (Some(Nonce(253402300799)), false, "Max time"),
+        (Some(Nonce(253402300799 + 2)), false, "Over max time"),
         //(Some(0), false, "Nonce 0"),
         (None, true, "Nonce 1"),
     ];
@@ -248,16 +253,9 @@ async fn ttl() {
         chain.clone(),
     )
     .await;
-    let cfg = peer::testonly::PeerConfig {
-        network: chain.make_config(rng),
-        chain,
-        peers: vec![],
-        force_encoding: Some(Encoding::Proto),
-        nonce: None,
-    };
-    let stream = tcp::Stream::connect(&pm.peer_info()).await.unwrap();
-    let mut peer = peer::testonly::PeerHandle::start_endpoint(clock.clock(), cfg, stream).await;
-    peer.complete_handshake().await;
+    let mut peer = pm
+        .connect_peer(&clock.clock(), chain.clone(), chain.make_config(rng), Encoding::Proto)
+        .await;
     // await for peer manager to compute the routing table.
     // TODO(gprusak): probably extract it to a separate function when migrating other tests from
     // integration-tests to near_network.
@@ -302,6 +300,313 @@ async fn ttl() {
     }
 }
 
+#[derive(Default)]
+struct RecordingAuditor(Mutex<Vec<(PeerId, &'static str, usize)>>);
+
+impl RoutedMessageAuditor for RecordingAuditor {
+    fn audit(&self, author: &PeerId, body_variant: &'static str, size: usize) -> AuditVerdict {
+        self.0.lock().unwrap().push((author.clone(), body_variant, size));
+        AuditVerdict::Accept
+    }
+}
+
+// Test that the routed message auditor observes the forwarded messages.
+#[tokio::test]
+async fn routed_message_auditor() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+    let auditor = Arc::new(RecordingAuditor::default());
+    let mut pm_cfg = chain.make_config(rng);
+    pm_cfg.routed_message_auditor = auditor.clone();
+    let mut pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        pm_cfg,
+        chain.clone(),
+    )
+    .await;
+    let mut peer = pm
+        .connect_peer(&clock.clock(), chain.clone(), chain.make_config(rng), Encoding::Proto)
+        .await;
+    // await for peer manager to compute the routing table.
+    pm.events
+        .recv_until(|ev| match ev {
+            Event::PeerManager(PME::RoutingTableUpdate(rt)) => {
+                if rt.get(&peer.cfg.id()).map_or(false, |v| v.len() > 0) {
+                    Some(())
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .await;
+
+    // Send a message which PeerManager will forward back to the peer.
+    let msg = RoutedMessageBody::Ping(Ping { nonce: rng.gen(), source: peer.cfg.id() });
+    let msg = PeerMessage::Routed(Box::new(peer.routed_message(
+        msg,
+        peer.cfg.id(),
+        2,
+        Some(clock.now_utc()),
+    )));
+    let size = msg.serialize(Encoding::Proto).len();
+    peer.send(msg).await;
+    peer.events
+        .recv_until(|ev| match ev {
+            peer::testonly::Event::Network(PME::MessageProcessed(PeerMessage::Routed(_))) => {
+                Some(())
+            }
+            _ => None,
+        })
+        .await;
+    assert_eq!(vec![(peer.cfg.id(), "Ping", size)], *auditor.0.lock().unwrap());
+}
+
+// Test that routed messages with zero TTL are dropped on arrival,
+// even if they are addressed to the receiving node.
+#[tokio::test]
+async fn zero_ttl_dropped() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+    let mut pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        chain.make_config(rng),
+        chain.clone(),
+    )
+    .await;
+    let peer = pm
+        .connect_peer(&clock.clock(), chain.clone(), chain.make_config(rng), Encoding::Proto)
+        .await;
+
+    let dropped = metrics::ROUTED_MESSAGE_DROPPED.with_label_values(&["Ping"]);
+    let dropped_before = dropped.get();
+    let msg = RoutedMessageBody::Ping(Ping { nonce: rng.gen(), source: peer.cfg.id() });
+    let msg = Box::new(peer.routed_message(msg, pm.cfg.node_id(), 0, Some(clock.now_utc())));
+    assert!(!msg.is_deliverable());
+    peer.send(PeerMessage::Routed(msg)).await;
+    pm.events
+        .recv_until(|ev| match ev {
+            Event::PeerManager(PME::RoutedMessageDropped) => Some(()),
+            Event::PeerManager(PME::Ping(ping)) => panic!("zero TTL ping processed: {ping:?}"),
+            _ => None,
+        })
+        .await;
+    assert!(dropped.get() > dropped_before);
+}
+
+// Test that an identical routed message received again within the dedup window is dropped,
+// and that it is processed again once the window has passed. Requests are never dropped.
+#[tokio::test]
+async fn duplicate_routed_message_dropped() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+    let mut pm_cfg = chain.make_config(rng);
+    pm_cfg.routed_message_dedup_window = time::Duration::seconds(1);
+    let mut pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        pm_cfg,
+        chain.clone(),
+    )
+    .await;
+    let peer = pm
+        .connect_peer(&clock.clock(), chain.clone(), chain.make_config(rng), Encoding::Proto)
+        .await;
+
+    let deduplicated = metrics::ROUTED_MESSAGE_DEDUPLICATED.with_label_values(&["Pong"]);
+    let deduplicated_before = deduplicated.get();
+    let pong = Pong { nonce: rng.gen(), source: peer.cfg.id() };
+    let msg = Box::new(peer.routed_message(
+        RoutedMessageBody::Pong(pong.clone()),
+        pm.cfg.node_id(),
+        3,
+        Some(clock.now_utc()),
+    ));
+    // The second delivery is past the period in which PeerActor drops duplicates
+    // from the same peer, but within the dedup window.
+    for (delay, want_processed) in [
+        (time::Duration::ZERO, true),
+        (time::Duration::milliseconds(500), false),
+        (time::Duration::seconds(1), true),
+    ] {
+        clock.advance(delay);
+        peer.send(PeerMessage::Routed(msg.clone())).await;
+        let processed = pm
+            .events
+            .recv_until(|ev| match ev {
+                Event::PeerManager(PME::Pong(got)) if got == pong => Some(true),
+                Event::PeerManager(PME::RoutedMessageDropped) => Some(false),
+                _ => None,
+            })
+            .await;
+        assert_eq!(want_processed, processed, "after {delay}");
+    }
+    assert!(deduplicated.get() > deduplicated_before);
+
+    // A retried request is processed even within the dedup window.
+    let ping = Ping { nonce: rng.gen(), source: peer.cfg.id() };
+    let msg = Box::new(peer.routed_message(
+        RoutedMessageBody::Ping(ping.clone()),
+        pm.cfg.node_id(),
+        3,
+        Some(clock.now_utc()),
+    ));
+    for delay in [time::Duration::ZERO, time::Duration::milliseconds(500)] {
+        clock.advance(delay);
+        peer.send(PeerMessage::Routed(msg.clone())).await;
+        pm.events
+            .recv_until(|ev| match ev {
+                Event::PeerManager(PME::Ping(got)) if got == ping => Some(()),
+                Event::PeerManager(PME::RoutedMessageDropped) => panic!("request dropped"),
+                _ => None,
+            })
+            .await;
+    }
+}
+
+// Test that routed messages authored by a banned peer are dropped before their signature is verified.
+#[tokio::test]
+async fn banned_author_dropped() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+    let mut pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        chain.make_config(rng),
+        chain.clone(),
+    )
+    .await;
+    let peer = pm
+        .connect_peer(&clock.clock(), chain.clone(), chain.make_config(rng), Encoding::Proto)
+        .await;
+
+    let banned = data::make_peer_id(rng);
+    pm.actix
+        .addr
+        .send(PeerToManagerMsg::Ban(Ban {
+            peer_id: banned.clone(),
+            ban_reason: ReasonForBan::Abusive,
+        }))
+        .await
+        .unwrap();
+
+    // The message is signed by the peer, so the signature is invalid for the banned author.
+    // Verifying it would make PeerManager ban the peer and close the connection.
+    let msg = RoutedMessageBody::Ping(Ping { nonce: rng.gen(), source: banned.clone() });
+    let mut msg = Box::new(peer.routed_message(msg, pm.cfg.node_id(), 2, Some(clock.now_utc())));
+    msg.author = banned;
+    assert!(!msg.verify());
+    peer.send(PeerMessage::Routed(msg)).await;
+    pm.events
+        .recv_until(|ev| match ev {
+            Event::PeerManager(PME::RoutedMessageDropped) => Some(()),
+            Event::PeerManager(PME::ConnectionClosed(ev)) => {
+                panic!("connection closed: {}", ev.reason)
+            }
+            Event::PeerManager(PME::Ping(ping)) => panic!("banned author ping processed: {ping:?}"),
+            _ => None,
+        })
+        .await;
+}
+
+async fn route_message(
+    pm: &peer_manager::testonly::ActorHandler,
+    msg: RoutedMessageV2,
+) -> RouteOutcome {
+    match pm.actix.addr.send(PeerManagerMessageRequest::RouteMessage(Box::new(msg))).await {
+        Ok(PeerManagerMessageResponse::RouteMessage(outcome)) => outcome,
+        resp => panic!("unexpected response: {resp:?}"),
+    }
+}
+
+// Test that routing tells apart the reasons why a message couldn't be sent.
+#[tokio::test]
+async fn route_outcomes() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+    let mut pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        chain.make_config(rng),
+        chain.clone(),
+    )
+    .await;
+    let peer = pm
+        .connect_peer(&clock.clock(), chain.clone(), chain.make_config(rng), Encoding::Proto)
+        .await;
+    // Wait for the peer manager to compute the route to the peer.
+    pm.events
+        .recv_until(|ev| match ev {
+            Event::PeerManager(PME::RoutingTableUpdate(rt)) => {
+                if rt.get(&peer.cfg.id()).map_or(false, |v| v.len() > 0) {
+                    Some(())
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .await;
+
+    let ping = RoutedMessageBody::Ping(Ping { nonce: rng.gen(), source: peer.cfg.id() });
+    let now = Some(clock.now_utc());
+
+    let msg = peer.routed_message(ping.clone(), peer.cfg.id(), 2, now);
+    assert_eq!(RouteOutcome::Delivered, route_message(&pm, msg).await);
+
+    let msg = peer.routed_message(ping.clone(), data::make_peer_id(rng), 2, now);
+    assert_eq!(RouteOutcome::NoRoute, route_message(&pm, msg).await);
+
+    let msg = peer.routed_message(ping.clone(), peer.cfg.id(), 0, now);
+    assert_eq!(RouteOutcome::TtlExpired, route_message(&pm, msg).await);
+
+    let msg = RawRoutedMessage {
+        target: AccountOrPeerIdOrHash::Hash(CryptoHash::hash_bytes(b"no route back")),
+        body: ping.clone(),
+    }
+    .sign(&peer.cfg.network.node_key, 2, now)
+    .unwrap();
+    assert_eq!(RouteOutcome::TargetUnknown, route_message(&pm, msg).await);
+    let account_id = "unknown.near".parse().unwrap();
+    match pm.actix.addr.send(PeerManagerMessageRequest::HeartbeatTo { nonce: 1, account_id }).await
+    {
+        Ok(PeerManagerMessageResponse::HeartbeatTo(outcome)) => {
+            assert_eq!(RouteOutcome::TargetUnknown, outcome)
+        }
+        resp => panic!("unexpected response: {resp:?}"),
+    }
+
+    let banned = data::make_peer_id(rng);
+    pm.actix
+        .addr
+        .send(PeerToManagerMsg::Ban(Ban {
+            peer_id: banned.clone(),
+            ban_reason: ReasonForBan::Abusive,
+        }))
+        .await
+        .unwrap();
+    let mut msg = peer.routed_message(ping, peer.cfg.id(), 2, now);
+    msg.author = banned;
+    assert_eq!(RouteOutcome::AuthorBanned, route_message(&pm, msg).await);
+}
+
 #[tokio::test]
 async fn accounts_data_broadcast() {
     init_test_logger();
@@ -340,6 +645,7 @@ async fn accounts_data_broadcast() {
         accounts_data: vec![data[0].clone(), data[1].clone()],
         incremental: true,
         requesting_full_sync: false,
+        total_count: 0,
     };
     let want = msg.accounts_data.clone();
     peer1.send(PeerMessage::SyncAccountsData(msg)).await;
@@ -350,12 +656,14 @@ async fn accounts_data_broadcast() {
         pm.start_inbound(chain.clone(), chain.make_config(rng)).await.handshake(clock).await;
     let got2 = peer2.events.recv_until(take_sync).await;
     assert_eq!(got2.accounts_data.as_set(), want.as_set());
+    assert!(got2.is_complete());
 
     // Send a mix of new and old data. Only new data should be broadcasted.
     let msg = SyncAccountsData {
         accounts_data: vec![data[1].clone(), data[2].clone()],
         incremental: true,
         requesting_full_sync: false,
+        total_count: 0,
     };
     let want = vec![data[2].clone()];
     peer1.send(PeerMessage::SyncAccountsData(msg)).await;
@@ -369,10 +677,74 @@ async fn accounts_data_broadcast() {
             accounts_data: vec![],
             incremental: true,
             requesting_full_sync: true,
+            total_count: 0,
         }))
         .await;
     let got1 = peer1.events.recv_until(take_sync).await;
     assert_eq!(got1.accounts_data.as_set(), want.as_set());
+    assert_eq!(got1.total_count, want.len());
+}
+
+async fn next_sync_accounts_data(stream: &mut Stream) -> SyncAccountsData {
+    loop {
+        if let PeerMessage::SyncAccountsData(msg) = stream.read().await {
+            return msg;
+        }
+    }
+}
+
+// Test that a full accounts data sync requested by PeerManager is requested again
+// after an incomplete response, but only once, and not after unsolicited full syncs.
+#[tokio::test]
+async fn accounts_data_full_sync_retry() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+    let clock = clock.clock();
+
+    let pm = peer_manager::testonly::start(
+        clock.clone(),
+        near_store::db::TestDB::new(),
+        chain.make_config(rng),
+        chain.clone(),
+    )
+    .await;
+    let data = chain.make_tier1_data(rng, &clock);
+    let incomplete = PeerMessage::SyncAccountsData(SyncAccountsData {
+        accounts_data: vec![data[0].clone()],
+        incremental: false,
+        requesting_full_sync: false,
+        total_count: 2,
+    });
+    // PeerManager answers a full sync request right away, so its response comes after
+    // any full sync request PeerManager has sent before.
+    let request = PeerMessage::SyncAccountsData(SyncAccountsData {
+        accounts_data: vec![],
+        incremental: true,
+        requesting_full_sync: true,
+        total_count: 0,
+    });
+
+    // PeerManager requests a full sync on an outbound connection.
+    let conn = pm.start_outbound(chain.clone(), chain.make_config(rng)).await;
+    let mut stream = conn.raw_handshake().await;
+    assert!(next_sync_accounts_data(&mut stream).await.requesting_full_sync);
+
+    // The incomplete response is followed by a repeated request.
+    stream.write(&incomplete).await;
+    assert!(next_sync_accounts_data(&mut stream).await.requesting_full_sync);
+
+    // The incomplete response to the repeated request is not followed by another one.
+    stream.write(&incomplete).await;
+    stream.write(&request).await;
+    assert!(!next_sync_accounts_data(&mut stream).await.requesting_full_sync);
+
+    // Neither is an unsolicited incomplete full sync.
+    stream.write(&incomplete).await;
+    stream.write(&request).await;
+    assert!(!next_sync_accounts_data(&mut stream).await.requesting_full_sync);
 }
 
 fn peer_addrs(vc: &config::ValidatorConfig) -> Vec<PeerAddr> {
@@ -561,6 +933,7 @@ async fn connection_spam_security_test() {
     let mut cfg = chain.make_config(rng);
     // Make sure that connections will never get dropped.
     cfg.handshake_timeout = time::Duration::hours(1);
+    cfg.handshake_read_timeout = time::Duration::hours(1);
     let pm = peer_manager::testonly::start(
         clock.clock(),
         near_store::db::TestDB::new(),
@@ -588,6 +961,40 @@ async fn connection_spam_security_test() {
     }
 }
 
+#[tokio::test]
+async fn slow_handshake() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+
+    let mut cfg = chain.make_config(rng);
+    // The general handshake timeout should not be the one closing the connection.
+    cfg.handshake_timeout = time::Duration::hours(1);
+    cfg.handshake_read_timeout = time::Duration::milliseconds(500);
+    let pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        cfg,
+        chain.clone(),
+    )
+    .await;
+
+    let timeouts = metrics::PEER_HANDSHAKE_READ_TIMEOUT_TOTAL.get();
+    let start = std::time::Instant::now();
+    let conn = pm.start_inbound(chain.clone(), chain.make_config(rng)).await;
+    assert_eq!(
+        ClosingReason::HandshakeReadTimeout,
+        conn.trickle_handshake(time::Duration::milliseconds(50)).await
+    );
+    assert!(start.elapsed() >= std::time::Duration::from_millis(500));
+    assert!(metrics::PEER_HANDSHAKE_READ_TIMEOUT_TOTAL.get() > timeouts);
+
+    // A peer completing the handshake in time is not affected.
+    pm.start_inbound(chain.clone(), chain.make_config(rng)).await.handshake(&clock.clock()).await;
+}
+
 #[tokio::test]
 async fn loop_connection() {
     init_test_logger();
@@ -615,9 +1022,7 @@ async fn loop_connection() {
 
     // An inbound connection pretending to be a loop should be rejected.
     let stream = tcp::Stream::connect(&pm.peer_info()).await.unwrap();
-    let stream_id = stream.id();
     let port = stream.local_addr.port();
-    let mut events = pm.events.from_now();
     let mut stream = Stream::new(Some(Encoding::Proto), stream);
     stream
         .write(&PeerMessage::Handshake(Handshake {
@@ -630,26 +1035,309 @@ async fn loop_connection() {
             partial_edge_info: PartialEdgeInfo::new(
                 &pm.cfg.node_id(),
                 &pm.cfg.node_id(),
-                1,
+                Nonce::FIRST,
                 &pm.cfg.node_key,
             ),
         }))
         .await;
-    let reason = events
+    // The handshake sent by the node to itself has an invalid target.
+    match stream.read().await {
+        PeerMessage::HandshakeFailure(_, HandshakeFailureReason::InvalidTarget) => {}
+        msg => panic!("expected HandshakeFailure, got {msg}"),
+    }
+}
+
+// Test that the distribution of protocol versions of connected peers is tracked.
+#[tokio::test]
+async fn peer_protocol_versions() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+
+    let pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        chain.make_config(rng),
+        chain.clone(),
+    )
+    .await;
+
+    let versions = [PROTOCOL_VERSION, PEER_MIN_ALLOWED_PROTOCOL_VERSION, PROTOCOL_VERSION];
+    // Keep the streams, so that the connections are not closed.
+    let mut streams = vec![];
+    for version in versions {
+        let signer = data::make_signer(rng);
+        let stream = pm.raw_handshake(&chain, &signer, version).await;
+        streams.push(stream);
+    }
+
+    let got = match pm.actix.addr.send(PeerManagerMessageRequest::PeerProtocolVersions).await {
+        Ok(PeerManagerMessageResponse::PeerProtocolVersions(got)) => got,
+        resp => panic!("unexpected response: {resp:?}"),
+    };
+    let mut want = HashMap::new();
+    for version in versions {
+        *want.entry(version).or_default() += 1;
+    }
+    assert_eq!(want, got);
+}
+
+// Test that StateRequestPart messages exceeding the per-peer concurrency limit
+// are answered with StateRequestPartBusy instead of being queued.
+#[tokio::test]
+async fn state_part_requests_limit() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+
+    let limit = 2;
+    let mut cfg = chain.make_config(rng);
+    cfg.max_concurrent_state_part_requests = limit;
+    let pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        cfg,
+        chain.clone(),
+    )
+    .await;
+
+    let signer = data::make_signer(rng);
+    let mut events = pm.events.from_now();
+    let mut stream = pm.raw_handshake(&chain, &signer, PROTOCOL_VERSION).await;
+
+    // The fake client never responds to StateRequestPart, so the first `limit`
+    // requests stay in progress.
+    let sync_hash = CryptoHash::hash_bytes(b"sync_hash");
+    let parts = 5;
+    for part_id in 0..parts {
+        let msg = RawRoutedMessage {
+            target: AccountOrPeerIdOrHash::PeerId(pm.cfg.node_id()),
+            body: RoutedMessageBody::StateRequestPart(0, sync_hash, part_id),
+        }
+        .sign(&signer.secret_key, 10, Some(clock.now_utc()))
+        .unwrap();
+        stream.write(&PeerMessage::Routed(Box::new(msg))).await;
+    }
+    let mut busy = vec![];
+    while busy.len() < parts as usize - limit {
+        if let PeerMessage::Routed(msg) = stream.read().await {
+            if let RoutedMessageBody::StateRequestPartBusy(0, hash, part_id) = &msg.body {
+                assert_eq!(&sync_hash, hash);
+                busy.push(*part_id);
+            }
+        }
+    }
+    assert_eq!((limit as u64..parts).collect::<Vec<_>>(), busy);
+    for part_id in 0..limit as u64 {
+        events
+            .recv_until(|ev| match ev {
+                Event::Client(fake_client::Event::StateRequestPart(0, hash, id))
+                    if hash == sync_hash && id == part_id =>
+                {
+                    Some(())
+                }
+                _ => None,
+            })
+            .await;
+    }
+}
+
+async fn request_state_part(
+    pm: &peer_manager::testonly::ActorHandler,
+    target: &PeerId,
+    sync_hash: CryptoHash,
+    part_id: u64,
+) {
+    let req = NetworkRequests::StateRequestPart {
+        shard_id: 0,
+        sync_hash,
+        part_id,
+        target: AccountOrPeerIdOrHash::PeerId(target.clone()),
+    };
+    match pm.actix.addr.send(PeerManagerMessageRequest::NetworkRequests(req)).await {
+        Ok(PeerManagerMessageResponse::NetworkResponses(NetworkResponses::NoResponse)) => {}
+        resp => panic!("unexpected response: {resp:?}"),
+    }
+}
+
+async fn next_state_part_request(
+    events: &mut broadcast::Receiver<peer::testonly::Event>,
+    sync_hash: CryptoHash,
+) -> u64 {
+    events
         .recv_until(|ev| match ev {
-            Event::PeerManager(PME::ConnectionClosed(ev)) if ev.stream_id == stream_id => {
-                Some(ev.reason)
+            peer::testonly::Event::Client(fake_client::Event::StateRequestPart(0, hash, id))
+                if hash == sync_hash =>
+            {
+                Some(id)
             }
-            Event::PeerManager(PME::HandshakeCompleted(ev)) if ev.stream_id == stream_id => {
-                panic!("PeerManager accepted the handshake")
+            _ => None,
+        })
+        .await
+}
+
+// Test that a StateRequestPart for a part which has already been requested, and not
+// answered yet, is not sent again until the request times out.
+#[tokio::test]
+async fn duplicate_state_part_request_suppressed() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+    let mut pm_cfg = chain.make_config(rng);
+    pm_cfg.state_part_request_timeout = time::Duration::seconds(5);
+    let mut pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        pm_cfg,
+        chain.clone(),
+    )
+    .await;
+    let peer = pm
+        .connect_peer(&clock.clock(), chain.clone(), chain.make_config(rng), Encoding::Proto)
+        .await;
+    // Wait for the peer manager to compute the route to the peer.
+    pm.events
+        .recv_until(|ev| match ev {
+            Event::PeerManager(PME::RoutingTableUpdate(rt)) => {
+                if rt.get(&peer.cfg.id()).map_or(false, |v| v.len() > 0) {
+                    Some(())
+                } else {
+                    None
+                }
             }
             _ => None,
         })
         .await;
-    assert_eq!(
-        ClosingReason::RejectedByPeerManager(RegisterPeerError::PoolError(
-            connection::PoolError::LoopConnection
-        )),
-        reason
-    );
+
+    // The fake client of the peer never responds to StateRequestPart.
+    let sync_hash = CryptoHash::hash_bytes(b"sync_hash");
+    let mut events = peer.events.from_now();
+    let suppressed = metrics::STATE_PART_REQUESTS_SUPPRESSED.get();
+    request_state_part(&pm, &peer.cfg.id(), sync_hash, 0).await;
+    assert_eq!(0, next_state_part_request(&mut events, sync_hash).await);
+    // The duplicate is not sent, so the next request to arrive is for part 1.
+    request_state_part(&pm, &peer.cfg.id(), sync_hash, 0).await;
+    request_state_part(&pm, &peer.cfg.id(), sync_hash, 1).await;
+    assert_eq!(1, next_state_part_request(&mut events, sync_hash).await);
+    assert!(metrics::STATE_PART_REQUESTS_SUPPRESSED.get() > suppressed);
+    // After the timeout part 0 can be requested again.
+    clock.advance(time::Duration::seconds(5));
+    request_state_part(&pm, &peer.cfg.id(), sync_hash, 0).await;
+    assert_eq!(0, next_state_part_request(&mut events, sync_hash).await);
+}
+
+// Test that PeerManager remembers the encoding agreed upon with a peer
+// and proposes it right away when connecting to the peer again.
+// Borsh is remembered only for PREFERRED_BORSH_ENCODING_TTL, after which proto is proposed again.
+#[tokio::test]
+async fn preferred_encoding_on_reconnect() {
+    init_test_logger();
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+    let mut pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        chain.make_config(rng),
+        chain.clone(),
+    )
+    .await;
+
+    // Connect a peer which speaks only borsh. Negotiation would propose proto first.
+    let peer = pm
+        .connect_peer(&clock.clock(), chain.clone(), chain.make_config(rng), Encoding::Borsh)
+        .await;
+    let peer_id = peer.cfg.id();
+    pm.events
+        .recv_until(|ev| match ev {
+            Event::PeerManager(PME::HandshakeCompleted(_)) => Some(()),
+            _ => None,
+        })
+        .await;
+    drop(peer);
+    pm.events
+        .recv_until(|ev| match ev {
+            Event::PeerManager(PME::ConnectionClosed(_)) => Some(()),
+            _ => None,
+        })
+        .await;
+
+    // Reconnect. The Handshake is expected to be sent only in borsh.
+    let (outbound_stream, inbound_stream) = tcp::Stream::loopback(peer_id.clone()).await;
+    pm.actix.addr.do_send(PeerManagerMessageRequest::OutboundTcpConnect(outbound_stream));
+    let mut stream = Stream::new(None, inbound_stream);
+    let (msg, enc) = stream.read_with_encoding().await;
+    assert!(matches!(msg, PeerMessage::Handshake(_)), "got {msg:?}");
+    assert_eq!(Encoding::Borsh, enc);
+    drop(stream);
+    pm.events
+        .recv_until(|ev| match ev {
+            Event::PeerManager(PME::ConnectionClosed(_)) => Some(()),
+            _ => None,
+        })
+        .await;
+
+    // Once the preference expires, the encoding is negotiated again, proto first.
+    clock.advance(PREFERRED_BORSH_ENCODING_TTL);
+    let (outbound_stream, inbound_stream) = tcp::Stream::loopback(peer_id).await;
+    pm.actix.addr.do_send(PeerManagerMessageRequest::OutboundTcpConnect(outbound_stream));
+    let mut stream = Stream::new(None, inbound_stream);
+    let (msg, enc) = stream.read_with_encoding().await;
+    assert!(matches!(msg, PeerMessage::Handshake(_)), "got {msg:?}");
+    assert_eq!(Encoding::Proto, enc);
+}
+
+/// Sends a TelemetryRequest to a PeerManager configured with `share_telemetry`
+/// and returns the telemetry from the response.
+async fn request_telemetry(share_telemetry: bool) -> TelemetryData {
+    let mut rng = make_rng(921853233);
+    let rng = &mut rng;
+    let mut clock = time::FakeClock::default();
+    let chain = Arc::new(data::Chain::make(&mut clock, rng, 10));
+    let mut cfg = chain.make_config(rng);
+    cfg.share_telemetry = share_telemetry;
+    let pm = peer_manager::testonly::start(
+        clock.clock(),
+        near_store::db::TestDB::new(),
+        cfg,
+        chain.clone(),
+    )
+    .await;
+
+    let signer = data::make_signer(rng);
+    let mut stream = pm.raw_handshake(&chain, &signer, PROTOCOL_VERSION).await;
+
+    let msg = RawRoutedMessage {
+        target: AccountOrPeerIdOrHash::PeerId(pm.cfg.node_id()),
+        body: RoutedMessageBody::TelemetryRequest,
+    }
+    .sign(&signer.secret_key, 10, Some(clock.now_utc()))
+    .unwrap();
+    stream.write(&PeerMessage::Routed(Box::new(msg))).await;
+    loop {
+        if let PeerMessage::Routed(msg) = stream.read().await {
+            if let RoutedMessageBody::TelemetryResponse(telemetry) = &msg.body {
+                return telemetry.clone();
+            }
+        }
+    }
+}
+
+// Test that TelemetryRequest is answered with the node's telemetry only if the node
+// is configured to share it, and with empty telemetry otherwise.
+#[tokio::test]
+async fn telemetry_request() {
+    init_test_logger();
+    assert!(request_telemetry(false).await.is_empty());
+    let telemetry = request_telemetry(true).await;
+    assert_eq!(Some(PROTOCOL_VERSION), telemetry.protocol_version);
+    assert_eq!(Some(UptimeBucket::LessThanHour), telemetry.uptime);
+    assert!(telemetry.tracked_shards.is_some());
 }
@@ -1,7 +1,7 @@
 /// This file is contains all types used for communication between `Actors` within this crate.
 /// They are not meant to be used outside.
 use crate::network_protocol::{
-    Edge, PartialEdgeInfo, PeerInfo, PeerMessage, RoutedMessageBody, RoutingTableUpdate,
+    Edge, Nonce, PartialEdgeInfo, PeerInfo, PeerMessage, RoutedMessageBody, RoutingTableUpdate,
 };
 use crate::peer_manager::connection;
 use crate::types::{Ban, PeerType, ReasonForBan};
@@ -34,10 +34,18 @@ pub(crate) enum PeerToManagerMsg {
         peer_id: PeerId,
         routing_table_update: RoutingTableUpdate,
     },
+    /// Request from an active peer for the routing table entries of the accounts
+    /// with the given prefix.
+    RoutingTableSubsetRequest(String),
 
     // PeerRequest
-    RouteBack(Box<RoutedMessageBody>, CryptoHash),
+    /// Response to the routed request with the given hash, together with the
+    /// request id of the request, which is echoed in the response.
+    RouteBack(Box<RoutedMessageBody>, CryptoHash, Option<u64>),
     UpdatePeerInfo(PeerInfo),
+    /// The peer didn't complete the handshake proposed in the encoding
+    /// remembered from the previous connection.
+    EncodingRejected(PeerId),
 }
 
 /// List of all replies to messages to `PeerManager`. See `PeerManagerMessageRequest` for more details.
@@ -50,6 +58,8 @@ pub(crate) enum PeerToManagerMsgResp {
     // ResponseUpdateNonce
     EdgeUpdate(Box<Edge>),
     BanPeer(ReasonForBan),
+    // RoutingTableSubsetRequest
+    RoutingTableUpdate(RoutingTableUpdate),
 
     // PeerResponse
     Empty,
@@ -123,10 +133,14 @@ pub(crate) struct ValidateEdgeList {
     pub source_peer_id: PeerId,
     /// List of Edges, which will be sent to `EdgeValidatorActor`.
     pub edges: Vec<Edge>,
+    /// If set, the edges which are not signed by both of their peers are dropped and the
+    /// validation continues with the remaining edges. Otherwise the validation stops at the
+    /// first invalid edge. `source_peer_id` gets banned either way.
+    pub drop_asymmetric_edges: bool,
     /// A set of edges, which have been verified. This is a cache with all verified edges.
     /// `EdgeValidatorActor`, and is a source of memory leak.
     /// TODO(#5254): Simplify this process.
-    pub edges_info_shared: Arc<Mutex<HashMap<(PeerId, PeerId), u64>>>,
+    pub edges_info_shared: Arc<Mutex<HashMap<(PeerId, PeerId), Nonce>>>,
     /// A concurrent queue. After edge become validated it will be sent from `EdgeValidatorActor` back to
     /// `PeerManagetActor`, and then send to `RoutingTableActor`. And then `RoutingTableActor`
     /// will add them.
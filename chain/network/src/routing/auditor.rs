@@ -0,0 +1,30 @@
+//! Hook for auditing the routed messages received by this node.
+//! It allows to build a (sampled) audit trail of unusual messages, like very large ones,
+//! or ones of unexpected type for a given author, and to drop them based on a policy.
+use near_primitives::network::PeerId;
+
+/// Decision of a `RoutedMessageAuditor` about a routed message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditVerdict {
+    /// Process the message as usual.
+    Accept,
+    /// Drop the message without processing it.
+    Drop,
+}
+
+/// Invoked by PeerActor for every routed message received from a peer,
+/// before the message is handled or forwarded.
+/// Implementations are called on the hot path, so they should be cheap.
+pub trait RoutedMessageAuditor: Send + Sync {
+    /// `size` is the size in bytes of the encoded PeerMessage carrying the routed message.
+    fn audit(&self, author: &PeerId, body_variant: &'static str, size: usize) -> AuditVerdict;
+}
+
+/// Auditor accepting every message.
+pub struct NoopAuditor;
+
+impl RoutedMessageAuditor for NoopAuditor {
+    fn audit(&self, _author: &PeerId, _body_variant: &'static str, _size: usize) -> AuditVerdict {
+        AuditVerdict::Accept
+    }
+}
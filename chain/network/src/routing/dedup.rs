@@ -0,0 +1,36 @@
+use crate::time;
+use lru::LruCache;
+use near_primitives::hash::CryptoHash;
+
+/// Set of hashes of the recently received routed messages, shared by all the
+/// connections, so that a message arriving over multiple paths is processed
+/// only once per `window`. The set is bounded: once it holds `capacity` hashes,
+/// the least recently received one is forgotten.
+pub(crate) struct RoutedMessageDedup {
+    window: time::Duration,
+    seen: parking_lot::Mutex<LruCache<CryptoHash, time::Instant>>,
+}
+
+impl RoutedMessageDedup {
+    pub fn new(window: time::Duration, capacity: usize) -> Self {
+        Self { window, seen: parking_lot::Mutex::new(LruCache::new(capacity)) }
+    }
+
+    /// Records that a routed message with the given `hash` has been received at `now`.
+    /// Returns true iff the same message has already been received less than `window`
+    /// ago, in which case it should be dropped. A non-positive window disables
+    /// deduplication.
+    pub fn is_duplicate(&self, now: time::Instant, hash: CryptoHash) -> bool {
+        if self.window <= time::Duration::ZERO {
+            return false;
+        }
+        let mut seen = self.seen.lock();
+        if let Some(&received) = seen.get(&hash) {
+            if now < received + self.window {
+                return true;
+            }
+        }
+        seen.put(hash, now);
+        false
+    }
+}
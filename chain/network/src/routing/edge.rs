@@ -1,4 +1,4 @@
-use crate::network_protocol::{Edge, InvalidNonceError};
+use crate::network_protocol::{InvalidNonceError, Nonce};
 use crate::stats::metrics;
 use crate::time;
 
@@ -16,11 +16,11 @@ pub(crate) enum VerifyNonceError {
     ZeroNonce,
 }
 
-pub(crate) fn verify_nonce(clock: &time::Clock, nonce: u64) -> Result<(), VerifyNonceError> {
-    if nonce == 0 {
+pub(crate) fn verify_nonce(clock: &time::Clock, nonce: Nonce) -> Result<(), VerifyNonceError> {
+    if nonce == Nonce(0) {
         return Err(VerifyNonceError::ZeroNonce);
     }
-    match Edge::nonce_to_utc(nonce) {
+    match nonce.to_utc() {
         Err(err) => Err(VerifyNonceError::InvalidNonce(err)),
         Ok(Some(nonce)) => {
             let now = clock.now_utc();
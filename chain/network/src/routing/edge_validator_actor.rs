@@ -1,5 +1,6 @@
-use crate::network_protocol::Edge;
+use crate::network_protocol::{Edge, Nonce};
 use crate::private_actix::{StopMsg, ValidateEdgeList};
+use crate::stats::metrics;
 use actix::{Actor, ActorContext, Handler, SyncContext};
 use conqueue::{QueueReceiver, QueueSender};
 use near_performance_metrics_macros::perf;
@@ -32,33 +33,49 @@ impl Handler<ValidateEdgeList> for EdgeValidatorActor {
 
     #[perf]
     fn handle(&mut self, msg: ValidateEdgeList, _ctx: &mut Self::Context) -> Self::Result {
-        for edge in msg.edges {
-            let key = edge.key();
-            if msg.edges_info_shared.lock().unwrap().get(key).cloned().unwrap_or(0u64)
-                >= edge.nonce()
-            {
-                continue;
-            }
+        validate_edge_list(msg)
+    }
+}
 
-            if !edge.verify() {
-                return false;
-            }
-            {
-                let mut guard = msg.edges_info_shared.lock().unwrap();
-                let entry = guard.entry(key.clone());
+/// Checks signatures of the edges in `msg` and pushes the valid ones to `msg.sender`.
+/// Returns false iff an invalid edge has been found.
+pub(crate) fn validate_edge_list(msg: ValidateEdgeList) -> bool {
+    let mut valid = true;
+    for edge in msg.edges {
+        let key = edge.key();
+        if msg.edges_info_shared.lock().unwrap().get(key).cloned().unwrap_or(Nonce(0))
+            >= edge.nonce()
+        {
+            continue;
+        }
 
-                let cur_nonce = entry.or_insert_with(|| edge.nonce());
-                *cur_nonce = max(*cur_nonce, edge.nonce());
+        if !edge.verify() {
+            // Edges which are not signed by both of their peers are counted separately,
+            // we want to know how often peers send them.
+            if !edge.is_mutually_signed() {
+                metrics::EDGE_ASYMMETRIC_RECEIVED.inc();
+                if msg.drop_asymmetric_edges {
+                    valid = false;
+                    continue;
+                }
             }
-            msg.sender.push(edge);
+            return false;
+        }
+        {
+            let mut guard = msg.edges_info_shared.lock().unwrap();
+            let entry = guard.entry(key.clone());
+
+            let cur_nonce = entry.or_insert_with(|| edge.nonce());
+            *cur_nonce = max(*cur_nonce, edge.nonce());
         }
-        true
+        msg.sender.push(edge);
     }
+    valid
 }
 
 pub struct EdgeValidatorHelper {
     /// Shared version of `edges_info` used by multiple threads.
-    pub edges_info_shared: Arc<Mutex<HashMap<(PeerId, PeerId), u64>>>,
+    pub edges_info_shared: Arc<Mutex<HashMap<(PeerId, PeerId), Nonce>>>,
     /// Queue of edges verified, but not added yes.
     pub edges_to_add_receiver: QueueReceiver<Edge>,
     pub edges_to_add_sender: QueueSender<Edge>,
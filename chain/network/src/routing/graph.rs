@@ -84,6 +84,13 @@ impl Graph {
         false
     }
 
+    /// Peers connected to `peer` by an active edge.
+    pub fn neighbors<'a>(&'a self, peer: &PeerId) -> impl Iterator<Item = &'a PeerId> + 'a {
+        self.p2id.get(peer).into_iter().flat_map(move |id| {
+            self.adjacency[*id as usize].iter().map(move |other| &self.id2p[*other as usize])
+        })
+    }
+
     fn remove_if_unused(&mut self, id: u32) {
         let entry = &self.adjacency[id as usize];
 
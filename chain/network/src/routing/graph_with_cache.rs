@@ -47,6 +47,16 @@ impl GraphWithCache {
         &self.edges
     }
 
+    /// Active edges adjacent to `peer_id`. Takes time proportional to the number of them,
+    /// rather than to the size of the graph.
+    pub fn adjacent_edges(&self, peer_id: &PeerId) -> Vec<Edge> {
+        self.graph
+            .neighbors(peer_id)
+            .filter_map(|other| self.edges.get(&Edge::make_key(peer_id.clone(), other.clone())))
+            .cloned()
+            .collect()
+    }
+
     pub fn has(&self, edge: &Edge) -> bool {
         let prev = self.edges.get(&edge.key());
         prev.map_or(false, |x| x.nonce() >= edge.nonce())
@@ -0,0 +1,92 @@
+use crate::network_protocol::{RoutedMessageBody, StateResponseInfo};
+use crate::time;
+use lru::LruCache;
+use near_primitives::hash::CryptoHash;
+use near_primitives::types::ShardId;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Identifies a StateRequestPart, as well as the responses to it: (shard_id, sync_hash, part_id).
+pub(crate) type StatePartId = (ShardId, CryptoHash, u64);
+
+/// Returns the id of the state part which `body` answers, if it is a response to a
+/// StateRequestPart (including a refusal).
+pub(crate) fn state_part_response(body: &RoutedMessageBody) -> Option<StatePartId> {
+    match body {
+        RoutedMessageBody::StateResponse(info) => {
+            Some((info.shard_id, info.sync_hash, info.state_response.part_id()?))
+        }
+        RoutedMessageBody::VersionedStateResponse(info) => {
+            let part_id = match info {
+                StateResponseInfo::V1(info) => info.state_response.part_id(),
+                StateResponseInfo::V2(info) => info.state_response.part_id(),
+            }?;
+            Some((info.shard_id(), info.sync_hash(), part_id))
+        }
+        RoutedMessageBody::StateRequestPartBusy(shard_id, sync_hash, part_id) => {
+            Some((*shard_id, *sync_hash, *part_id))
+        }
+        _ => None,
+    }
+}
+
+/// StateRequestPart messages sent by this node which haven't been answered yet.
+/// StateRequestPart is one of the routed messages which expect a response
+/// (see `RoutedMessage::expect_response`), and sending it again before the
+/// response arrives only wastes bandwidth of both sides. A request is considered
+/// in flight until a response arrives or `timeout` passes, whichever is first.
+/// The set is bounded: once it holds `capacity` requests, the least recently
+/// sent one is forgotten.
+///
+/// Every request gets a unique request id (see `RoutedMessageV2::request_id`),
+/// so that a late response to a request which has been repeated after the
+/// timeout doesn't finish the repeated one.
+pub(crate) struct InFlightStatePartRequests {
+    timeout: time::Duration,
+    sent: parking_lot::Mutex<LruCache<StatePartId, (time::Instant, u64)>>,
+    /// Request id for the next request. 0 is not used, since it means
+    /// "no request id" in the proto encoding.
+    next_request_id: AtomicU64,
+}
+
+impl InFlightStatePartRequests {
+    pub fn new(timeout: time::Duration, capacity: usize) -> Self {
+        Self {
+            timeout,
+            sent: parking_lot::Mutex::new(LruCache::new(capacity)),
+            next_request_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Records that the part `id` is being requested at `now`.
+    /// Returns the request id to attach to the request, or None iff the same part
+    /// has been requested less than `timeout` ago and hasn't been answered since,
+    /// in which case the request should not be sent.
+    pub fn try_start(&self, now: time::Instant, id: StatePartId) -> Option<u64> {
+        let mut sent = self.sent.lock();
+        if let Some(&(started, _)) = sent.get(&id) {
+            if now < started + self.timeout {
+                return None;
+            }
+        }
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        sent.put(id, (now, request_id));
+        Some(request_id)
+    }
+
+    /// Marks the request for the part `id` as no longer in flight, either because
+    /// it has been answered or because it couldn't be sent.
+    /// If `request_id` is set, the request is finished only if it is the one with
+    /// this id. Responses from peers which don't echo request ids are matched by
+    /// `id` alone. Returns whether a request has been finished.
+    pub fn finish(&self, id: &StatePartId, request_id: Option<u64>) -> bool {
+        let mut sent = self.sent.lock();
+        match (sent.peek(id), request_id) {
+            (None, _) => false,
+            (Some(&(_, want)), Some(got)) if want != got => false,
+            (Some(_), _) => {
+                sent.pop(id);
+                true
+            }
+        }
+    }
+}
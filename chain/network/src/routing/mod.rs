@@ -1,8 +1,11 @@
+pub(crate) mod dedup;
 pub(crate) mod edge_validator_actor;
+pub(crate) mod in_flight;
 pub(crate) mod route_back_cache;
 pub mod routing_table_view;
 
 pub mod actor;
+pub mod auditor;
 pub(crate) mod edge;
 mod graph;
 mod graph_with_cache;
@@ -12,5 +15,28 @@ pub(crate) use graph_with_cache::NextHopTable;
 pub use graph::Graph;
 pub use graph_with_cache::GraphWithCache;
 
+/// What happened to a routed message handed over to the routing layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteOutcome {
+    /// The message has been sent to the next hop towards the target.
+    Delivered,
+    /// There is no path to the target peer: it is unreachable in the routing table,
+    /// the next hop has disconnected, or the target is this node.
+    NoRoute,
+    /// The message has no hops left.
+    TtlExpired,
+    /// The author of the message is banned, so it is not relayed any further.
+    AuthorBanned,
+    /// The target doesn't resolve to a peer: nobody announced the target account,
+    /// or the hash doesn't match any route back.
+    TargetUnknown,
+}
+
+impl RouteOutcome {
+    pub fn is_delivered(&self) -> bool {
+        *self == RouteOutcome::Delivered
+    }
+}
+
 #[cfg(test)]
 mod tests;
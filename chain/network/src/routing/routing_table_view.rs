@@ -1,4 +1,4 @@
-use crate::network_protocol::Edge;
+use crate::network_protocol::{Edge, Nonce};
 use crate::routing;
 use crate::routing::route_back_cache::RouteBackCache;
 use crate::store;
@@ -61,8 +61,8 @@ impl Inner {
 
     /// Checks whenever edge is newer than the one we already have.
     /// Works only for local edges.
-    fn is_local_edge_newer(&self, other_peer: &PeerId, nonce: u64) -> bool {
-        self.local_edges.get(other_peer).map_or(0, |x| x.nonce()) < nonce
+    fn is_local_edge_newer(&self, other_peer: &PeerId, nonce: Nonce) -> bool {
+        self.local_edges.get(other_peer).map_or(Nonce(0), |x| x.nonce()) < nonce
     }
 
     /// Get AnnounceAccount for the given AccountId.
@@ -119,7 +119,7 @@ impl RoutingTableView {
 
     /// Checks whenever edge is newer than the one we already have.
     /// Works only for local edges.
-    pub(crate) fn is_local_edge_newer(&self, other_peer: &PeerId, nonce: u64) -> bool {
+    pub(crate) fn is_local_edge_newer(&self, other_peer: &PeerId, nonce: Nonce) -> bool {
         self.0.lock().is_local_edge_newer(other_peer, nonce)
     }
 
@@ -204,6 +204,20 @@ impl RoutingTableView {
         self.0.lock().account_peers.iter().map(|(_, v)| v.clone()).collect()
     }
 
+    /// Get announce accounts on cache, whose id starts with `account_prefix`.
+    pub(crate) fn get_announce_accounts_with_prefix(
+        &self,
+        account_prefix: &str,
+    ) -> Vec<AnnounceAccount> {
+        self.0
+            .lock()
+            .account_peers
+            .iter()
+            .filter(|(account_id, _)| account_id.as_str().starts_with(account_prefix))
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
     /// Get AnnounceAccount for the given AccountId.
     pub(crate) fn get_announces<'a>(
         &'a self,
@@ -226,7 +240,7 @@ impl RoutingTableView {
                 Some(other) => other,
                 None => continue,
             };
-            let old_nonce = inner.local_edges.get(other).map_or(0, |e| e.nonce());
+            let old_nonce = inner.local_edges.get(other).map_or(Nonce(0), |e| e.nonce());
             if old_nonce >= edge.nonce() {
                 continue;
             }
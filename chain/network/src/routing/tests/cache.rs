@@ -106,3 +106,27 @@ fn load_from_disk() {
     // Cache of second routing table should contain account loaded from disk
     assert_eq!(routing_table1.get_announce_accounts().len(), 1);
 }
+
+#[test]
+fn announce_accounts_with_prefix() {
+    let store = crate::store::Store::from(near_store::db::TestDB::new());
+    let routing_table = RoutingTableView::new(store, random_peer_id());
+    let announce = |account_id: &str| AnnounceAccount {
+        account_id: account_id.parse().unwrap(),
+        peer_id: random_peer_id(),
+        epoch_id: random_epoch_id(),
+        signature: Signature::default(),
+    };
+    let [alice, alex, bob] = ["alice.near", "alex.near", "bob.near"].map(announce);
+    routing_table.add_accounts(vec![alice.clone(), alex.clone(), bob.clone()]);
+
+    let mut got = routing_table.get_announce_accounts_with_prefix("al");
+    got.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+    assert_eq!(vec![alex, alice.clone()], got);
+    assert_eq!(vec![alice], routing_table.get_announce_accounts_with_prefix("alice"));
+    assert_eq!(vec![bob], routing_table.get_announce_accounts_with_prefix("bob"));
+    assert_eq!(
+        Vec::<AnnounceAccount>::new(),
+        routing_table.get_announce_accounts_with_prefix("carol")
+    );
+}
@@ -1,6 +1,6 @@
 use crate::network_protocol::testonly as data;
-use crate::network_protocol::Edge;
 use crate::network_protocol::EDGE_MIN_TIMESTAMP_NONCE;
+use crate::network_protocol::{Edge, Nonce};
 use crate::routing;
 use crate::store;
 use crate::store::testonly::Component;
@@ -13,7 +13,7 @@ use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 fn edge(p0: &PeerId, p1: &PeerId, nonce: u64) -> Edge {
-    Edge::new(p0.clone(), p1.clone(), nonce, Signature::default(), Signature::default())
+    Edge::new(p0.clone(), p1.clone(), Nonce(nonce), Signature::default(), Signature::default())
 }
 
 struct RoutingTableTest {
@@ -210,13 +210,6 @@ fn components_nonces_are_tracked_in_storage() {
     );
 }
 
-fn to_active_nonce(value: u64) -> u64 {
-    if value % 2 == 1 {
-        return value;
-    }
-    return value + 1;
-}
-
 #[test]
 fn expired_edges() {
     let mut test = RoutingTableTest::new();
@@ -224,7 +217,7 @@ fn expired_edges() {
     let mut actor = test.new_actor();
     let p1 = test.make_peer();
     let p2 = test.make_peer();
-    let current_odd_nonce = to_active_nonce(test.clock.now_utc().unix_timestamp() as u64);
+    let current_odd_nonce = Nonce::from_utc(test.clock.now_utc()).0;
 
     let e1 = edge(&test.me(), &p1, current_odd_nonce);
 
@@ -262,8 +255,7 @@ fn expired_edges() {
     test.check(&[], &[]);
 
     // let's create a removal edge
-    let e1v2 =
-        edge(&test.me(), &p1, to_active_nonce(test.clock.now_utc().unix_timestamp() as u64) + 1);
+    let e1v2 = edge(&test.me(), &p1, Nonce::from_utc(test.clock.now_utc()).0 + 1);
     actor.add_verified_edges(vec![e1v2.clone()]);
     test.check(&[e1v2.clone()], &[]);
 
@@ -285,3 +277,27 @@ fn expired_edges() {
     );
     test.check(&[], &[]);
 }
+
+#[test]
+fn adjacent_edges() {
+    let mut test = RoutingTableTest::new();
+    let [a, b, c, d] = [(); 4].map(|_| test.make_peer());
+    let edge = |p0: &PeerId, p1: &PeerId, nonce| {
+        let (p0, p1) = Edge::make_key(p0.clone(), p1.clone());
+        edge(&p0, &p1, nonce)
+    };
+    let sorted = |mut edges: Vec<Edge>| {
+        edges.sort_by(|x, y| x.key().cmp(y.key()));
+        edges
+    };
+    let mut graph = test.graph.write();
+    for e in [edge(&a, &b, 1), edge(&b, &c, 1), edge(&c, &d, 1), edge(&a, &c, 1)] {
+        graph.update_edge(e);
+    }
+    assert_eq!(sorted(vec![edge(&a, &b, 1), edge(&a, &c, 1)]), sorted(graph.adjacent_edges(&a)));
+    assert_eq!(vec![edge(&c, &d, 1)], graph.adjacent_edges(&d));
+    // Removed edges are not adjacent anymore.
+    graph.update_edge(edge(&c, &d, 2));
+    assert_eq!(Vec::<Edge>::new(), graph.adjacent_edges(&d));
+    assert_eq!(sorted(vec![edge(&b, &c, 1), edge(&a, &c, 1)]), sorted(graph.adjacent_edges(&c)));
+}
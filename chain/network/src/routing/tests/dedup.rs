@@ -0,0 +1,40 @@
+use crate::routing::dedup::RoutedMessageDedup;
+use crate::time;
+use near_primitives::hash::hash;
+
+#[test]
+fn duplicates_within_window() {
+    let clock = time::FakeClock::default();
+    let dedup = RoutedMessageDedup::new(time::Duration::seconds(1), 10);
+    let [a, b] = [hash(b"a"), hash(b"b")];
+    assert!(!dedup.is_duplicate(clock.now(), a));
+    assert!(dedup.is_duplicate(clock.now(), a));
+    assert!(!dedup.is_duplicate(clock.now(), b));
+    clock.advance(time::Duration::milliseconds(999));
+    assert!(dedup.is_duplicate(clock.now(), a));
+    // The window is counted from the first reception, not the latest one.
+    clock.advance(time::Duration::milliseconds(1));
+    assert!(!dedup.is_duplicate(clock.now(), a));
+    assert!(dedup.is_duplicate(clock.now(), a));
+}
+
+#[test]
+fn bounded() {
+    let clock = time::FakeClock::default();
+    let dedup = RoutedMessageDedup::new(time::Duration::seconds(1), 2);
+    let hashes = [hash(b"a"), hash(b"b"), hash(b"c")];
+    for h in hashes {
+        assert!(!dedup.is_duplicate(clock.now(), h));
+    }
+    // The least recently received hash has been forgotten.
+    assert!(!dedup.is_duplicate(clock.now(), hashes[0]));
+    assert!(dedup.is_duplicate(clock.now(), hashes[2]));
+}
+
+#[test]
+fn disabled() {
+    let clock = time::FakeClock::default();
+    let dedup = RoutedMessageDedup::new(time::Duration::ZERO, 10);
+    assert!(!dedup.is_duplicate(clock.now(), hash(b"a")));
+    assert!(!dedup.is_duplicate(clock.now(), hash(b"a")));
+}
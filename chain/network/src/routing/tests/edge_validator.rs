@@ -0,0 +1,42 @@
+use crate::network_protocol::testonly as data;
+use crate::network_protocol::Edge;
+use crate::private_actix::ValidateEdgeList;
+use crate::routing::edge_validator_actor::{validate_edge_list, EdgeValidatorHelper};
+use crate::testonly::make_rng;
+use near_primitives::network::PeerId;
+
+#[test]
+fn asymmetric_edges() {
+    let mut rng = make_rng(7301942);
+    let [a, b, c, d] = [0, 1, 2, 3].map(|_| data::make_signer(&mut rng));
+    let valid = [data::make_edge(&a, &b), data::make_edge(&c, &d)];
+    let (p0, p1) = valid[0].key().clone();
+    let one_sided = Edge::new(
+        p0.clone(),
+        p1.clone(),
+        valid[0].nonce().next_active(),
+        valid[0].signature0().clone(),
+        near_crypto::Signature::empty(near_crypto::KeyType::ED25519),
+    );
+    let edges = vec![valid[0].clone(), one_sided, valid[1].clone()];
+
+    for drop_asymmetric_edges in [false, true] {
+        let mut helper = EdgeValidatorHelper::default();
+        let ok = validate_edge_list(ValidateEdgeList {
+            source_peer_id: PeerId::new(a.public_key.clone()),
+            edges: edges.clone(),
+            drop_asymmetric_edges,
+            edges_info_shared: helper.edges_info_shared.clone(),
+            sender: helper.edges_to_add_sender.clone(),
+        });
+        // The sender gets banned in both modes.
+        assert!(!ok);
+        let mut got = vec![];
+        while let Some(edge) = helper.edges_to_add_receiver.pop() {
+            got.push(edge);
+        }
+        // Only in the drop mode the edges after the asymmetric one are validated.
+        let want = if drop_asymmetric_edges { valid.to_vec() } else { valid[..1].to_vec() };
+        assert_eq!(want, got);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::network_protocol::{RoutedMessageBody, StateResponseInfo, StateResponseInfoV2};
+use crate::routing::in_flight::{state_part_response, InFlightStatePartRequests};
+use crate::time;
+use near_primitives::hash::hash;
+use near_primitives::syncing::{ShardStateSyncResponse, ShardStateSyncResponseV2};
+
+#[test]
+fn duplicate_suppressed_until_timeout() {
+    let clock = time::FakeClock::default();
+    let requests = InFlightStatePartRequests::new(time::Duration::seconds(2), 10);
+    let sync_hash = hash(b"sync_hash");
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 1)).is_some());
+    // Duplicate request => suppressed.
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 1)).is_none());
+    // Other parts, shards and sync hashes are independent.
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 2)).is_some());
+    assert!(requests.try_start(clock.now(), (1, sync_hash, 1)).is_some());
+    assert!(requests.try_start(clock.now(), (0, hash(b"other"), 1)).is_some());
+    clock.advance(time::Duration::milliseconds(1999));
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 1)).is_none());
+    // After the timeout the part can be requested again, which starts a new timeout.
+    clock.advance(time::Duration::milliseconds(1));
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 1)).is_some());
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 1)).is_none());
+}
+
+#[test]
+fn response_reenables_request() {
+    let clock = time::FakeClock::default();
+    let requests = InFlightStatePartRequests::new(time::Duration::seconds(2), 10);
+    let sync_hash = hash(b"sync_hash");
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 1)).is_some());
+    let response =
+        RoutedMessageBody::VersionedStateResponse(StateResponseInfo::V2(StateResponseInfoV2 {
+            shard_id: 0,
+            sync_hash,
+            state_response: ShardStateSyncResponse::V2(ShardStateSyncResponseV2 {
+                header: None,
+                part: Some((1, vec![1, 2, 3])),
+            }),
+        }));
+    let id = state_part_response(&response).unwrap();
+    assert_eq!((0, sync_hash, 1), id);
+    requests.finish(&id, None);
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 1)).is_some());
+
+    // A refusal is a response as well.
+    let busy = RoutedMessageBody::StateRequestPartBusy(0, sync_hash, 1);
+    requests.finish(&state_part_response(&busy).unwrap(), None);
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 1)).is_some());
+
+    // Responses without a part don't answer any StateRequestPart.
+    let header_response =
+        RoutedMessageBody::VersionedStateResponse(StateResponseInfo::V2(StateResponseInfoV2 {
+            shard_id: 0,
+            sync_hash,
+            state_response: ShardStateSyncResponse::V2(ShardStateSyncResponseV2 {
+                header: None,
+                part: None,
+            }),
+        }));
+    assert_eq!(None, state_part_response(&header_response));
+}
+
+#[test]
+fn bounded() {
+    let clock = time::FakeClock::default();
+    let requests = InFlightStatePartRequests::new(time::Duration::seconds(2), 2);
+    let sync_hash = hash(b"sync_hash");
+    for part_id in 0..3 {
+        assert!(requests.try_start(clock.now(), (0, sync_hash, part_id)).is_some());
+    }
+    // The least recently sent request has been forgotten.
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 0)).is_some());
+    assert!(requests.try_start(clock.now(), (0, sync_hash, 2)).is_none());
+}
+
+#[test]
+fn response_matched_by_request_id() {
+    let clock = time::FakeClock::default();
+    let requests = InFlightStatePartRequests::new(time::Duration::seconds(2), 10);
+    let id = (0, hash(b"sync_hash"), 1);
+    let first = requests.try_start(clock.now(), id).unwrap();
+    // The part is requested again after the timeout, which gets a new request id.
+    clock.advance(time::Duration::seconds(2));
+    let second = requests.try_start(clock.now(), id).unwrap();
+    assert_ne!(first, second);
+
+    // A late response to the first request doesn't finish the second one.
+    assert!(!requests.finish(&id, Some(first)));
+    assert!(requests.try_start(clock.now(), id).is_none());
+    // The response to the second one does.
+    assert!(requests.finish(&id, Some(second)));
+    assert!(!requests.finish(&id, Some(second)));
+    assert!(requests.try_start(clock.now(), id).is_some());
+
+    // A response without a request id (e.g. from a peer which doesn't echo them)
+    // is matched by the part alone.
+    assert!(requests.finish(&id, None));
+    assert!(requests.try_start(clock.now(), id).is_some());
+    // Responses for parts which are not in flight finish nothing.
+    assert!(!requests.finish(&(1, hash(b"sync_hash"), 1), None));
+}
@@ -1,3 +1,6 @@
 mod cache;
 mod cache_edges;
+mod dedup;
+mod edge_validator;
+mod in_flight;
 mod routing_table_view;
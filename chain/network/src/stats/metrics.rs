@@ -9,6 +9,9 @@ use near_o11y::metrics::{
     try_create_int_gauge, try_create_int_gauge_vec, Histogram, HistogramVec, IntCounter,
     IntCounterVec, IntGauge, IntGaugeVec, MetricVec, MetricVecBuilder,
 };
+use near_primitives::version::{
+    ProtocolVersion, PEER_MIN_ALLOWED_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
 use once_cell::sync::Lazy;
 
 /// Labels represents a schema of an IntGaugeVec metric.
@@ -43,9 +46,15 @@ impl<L: Labels> Gauge<L> {
     /// Returns a guard of the point - when the guard is dropped
     /// the point is removed from the gauge.
     pub fn new_point(&'static self, labels: &L) -> GaugePoint {
-        let point = self.inner.with_label_values(labels.values().as_ref());
-        point.inc();
-        GaugePoint(point)
+        GaugePoint::new(self.inner.with_label_values(labels.values().as_ref()))
+    }
+}
+
+impl GaugePoint {
+    /// Increments the gauge and returns a guard decrementing it back on drop.
+    pub fn new(gauge: IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
     }
 }
 
@@ -112,11 +121,38 @@ pub static PEER_CONNECTIONS: Lazy<Gauge<Connection>> =
 pub(crate) static PEER_CONNECTIONS_TOTAL: Lazy<IntGauge> = Lazy::new(|| {
     try_create_int_gauge("near_peer_connections_total", "Number of connected peers").unwrap()
 });
+pub(crate) static PEER_PROTOCOL_VERSIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    try_create_int_gauge_vec(
+        "near_peer_protocol_versions",
+        "Number of connected peers by the protocol version of their handshake",
+        &["protocol_version"],
+    )
+    .unwrap()
+});
+
+/// Label of `PEER_PROTOCOL_VERSIONS` for the given version.
+/// Versions outside of the range supported by this node share the "other" label,
+/// so that peers cannot blow up the number of time series.
+pub(crate) fn protocol_version_label(version: ProtocolVersion) -> String {
+    if (PEER_MIN_ALLOWED_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        version.to_string()
+    } else {
+        "other".to_string()
+    }
+}
 pub(crate) static PEER_DATA_RECEIVED_BYTES: Lazy<IntCounter> = Lazy::new(|| {
     try_create_int_counter("near_peer_data_received_bytes", "Total data received from peers")
         .unwrap()
 });
 
+pub(crate) static PEER_HANDSHAKE_READ_TIMEOUT_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    try_create_int_counter(
+        "near_peer_handshake_read_timeout_total",
+        "Number of connections closed because the peer didn't send a Handshake in time",
+    )
+    .unwrap()
+});
+
 pub(crate) static PEER_MSG_SIZE_BYTES: Lazy<HistogramVec> = Lazy::new(|| {
     try_create_histogram_vec(
         "near_peer_msg_size_bytes",
@@ -269,6 +305,13 @@ pub(crate) static EDGE_TOMBSTONE_RECEIVING_SKIPPED: Lazy<IntCounter> = Lazy::new
     )
     .unwrap()
 });
+pub(crate) static EDGE_ASYMMETRIC_RECEIVED: Lazy<IntCounter> = Lazy::new(|| {
+    try_create_int_counter(
+        "near_edge_asymmetric_received",
+        "Number of received edges which are not signed by both of their peers",
+    )
+    .unwrap()
+});
 
 pub(crate) static PEER_UNRELIABLE: Lazy<IntGauge> = Lazy::new(|| {
     try_create_int_gauge(
@@ -304,6 +347,35 @@ pub(crate) static ROUTING_TABLE_MESSAGES_TIME: Lazy<HistogramVec> = Lazy::new(||
     )
     .unwrap()
 });
+pub(crate) static STATE_PART_REQUESTS_REFUSED: Lazy<IntCounter> = Lazy::new(|| {
+    try_create_int_counter(
+        "near_state_part_requests_refused",
+        "Number of StateRequestPart messages refused, because too many were in progress for the peer",
+    )
+    .unwrap()
+});
+pub(crate) static SYNC_ROUTING_TABLE_RATE_LIMITED: Lazy<IntCounter> = Lazy::new(|| {
+    try_create_int_counter(
+        "near_sync_routing_table_rate_limited_total",
+        "Number of SyncRoutingTable messages dropped, because the peer exceeded the rate limit",
+    )
+    .unwrap()
+});
+pub(crate) static ROUTING_TABLE_SUBSET_REQUESTS_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+    try_create_int_counter_vec(
+        "near_routing_table_subset_requests_dropped_total",
+        "Number of RoutingTableSubsetRequest messages dropped without serving them",
+        &["reason"],
+    )
+    .unwrap()
+});
+pub(crate) static STATE_PART_REQUESTS_SUPPRESSED: Lazy<IntCounter> = Lazy::new(|| {
+    try_create_int_counter(
+        "near_state_part_requests_suppressed_total",
+        "Number of StateRequestPart messages not sent, because the same part was already requested and not answered yet",
+    )
+    .unwrap()
+});
 pub(crate) static ROUTED_MESSAGE_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
     try_create_int_counter_vec(
         "near_routed_message_dropped",
@@ -313,6 +385,31 @@ pub(crate) static ROUTED_MESSAGE_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub(crate) static ROUTED_MESSAGE_DEDUPLICATED: Lazy<IntCounterVec> = Lazy::new(|| {
+    try_create_int_counter_vec(
+        "near_routed_message_deduplicated_total",
+        "Number of routed messages dropped as duplicates of recently received ones, by type",
+        &["type"],
+    )
+    .unwrap()
+});
+
+pub(crate) static ACCOUNTS_DATA_INACTIVE_EPOCH: Lazy<IntCounter> = Lazy::new(|| {
+    try_create_int_counter(
+        "near_accounts_data_inactive_epoch_total",
+        "Number of entries of incremental SyncAccountsData messages dropped because they belong to an epoch we don't consider active",
+    )
+    .unwrap()
+});
+
+pub(crate) static ACCOUNTS_DATA_KNOWN_VALIDATORS: Lazy<IntGauge> = Lazy::new(|| {
+    try_create_int_gauge(
+        "near_accounts_data_known_validators",
+        "Number of TIER1 accounts which we have verified AccountData for",
+    )
+    .unwrap()
+});
+
 pub(crate) static PEER_REACHABLE: Lazy<IntGauge> = Lazy::new(|| {
     try_create_int_gauge(
         "near_peer_reachable",
@@ -393,3 +490,20 @@ impl MessageDropped {
         DROPPED_MESSAGE_COUNT.with_label_values(&[msg_type, reason]).inc();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protocol_version_label_is_bounded() {
+        assert_eq!(PROTOCOL_VERSION.to_string(), protocol_version_label(PROTOCOL_VERSION));
+        assert_eq!(
+            PEER_MIN_ALLOWED_PROTOCOL_VERSION.to_string(),
+            protocol_version_label(PEER_MIN_ALLOWED_PROTOCOL_VERSION)
+        );
+        assert_eq!("other", protocol_version_label(PROTOCOL_VERSION + 1));
+        assert_eq!("other", protocol_version_label(ProtocolVersion::MAX));
+        assert_eq!("other", protocol_version_label(PEER_MIN_ALLOWED_PROTOCOL_VERSION - 1));
+    }
+}
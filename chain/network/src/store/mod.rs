@@ -1,7 +1,7 @@
 /// Store module defines atomic DB operations on top of schema module.
 /// All transactions should be implemented within this module,
 /// in particular schema::StoreUpdate is not exported.
-use crate::network_protocol::Edge;
+use crate::network_protocol::{Edge, Encoding};
 use crate::types::KnownPeerState;
 use near_primitives::network::{AnnounceAccount, PeerId};
 use near_primitives::types::AccountId;
@@ -131,11 +131,12 @@ impl Store {
         self.0.commit(update).map_err(Error)
     }
 
-    /// Deletes rows with keys in <peers> from Peers column.
+    /// Deletes rows with keys in <peers> from Peers and PeerEncodings columns.
     pub fn delete_peer_states(&mut self, peers: &[PeerId]) -> Result<(), Error> {
         let mut update = self.0.new_update();
         for p in peers {
             update.delete::<schema::Peers>(p);
+            update.delete::<schema::PeerEncodings>(p);
         }
         self.0.commit(update).map_err(Error)
     }
@@ -144,6 +145,29 @@ impl Store {
     pub fn list_peer_states(&self) -> Result<Vec<(PeerId, KnownPeerState)>, Error> {
         self.0.iter::<schema::Peers>().collect::<Result<_, _>>().map_err(Error)
     }
+
+    /// Inserts (peer_id,encoding) to PeerEncodings column.
+    pub fn set_peer_encoding(
+        &mut self,
+        peer_id: &PeerId,
+        encoding: &Encoding,
+    ) -> Result<(), Error> {
+        let mut update = self.0.new_update();
+        update.set::<schema::PeerEncodings>(peer_id, encoding);
+        self.0.commit(update).map_err(Error)
+    }
+
+    /// Deletes row with key peer_id from PeerEncodings column.
+    pub fn delete_peer_encoding(&mut self, peer_id: &PeerId) -> Result<(), Error> {
+        let mut update = self.0.new_update();
+        update.delete::<schema::PeerEncodings>(peer_id);
+        self.0.commit(update).map_err(Error)
+    }
+
+    /// Reads the whole PeerEncodings column.
+    pub fn list_peer_encodings(&self) -> Result<Vec<(PeerId, Encoding)>, Error> {
+        self.0.iter::<schema::PeerEncodings>().collect::<Result<_, _>>().map_err(Error)
+    }
 }
 
 // TODO(mina86): Get rid of it.
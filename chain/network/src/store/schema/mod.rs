@@ -1,3 +1,4 @@
+use crate::network_protocol::Encoding;
 use crate::time;
 use crate::types as primitives;
 /// Schema module defines a type-safe access to the DB.
@@ -100,6 +101,30 @@ impl BorshRepr for KnownPeerStateRepr {
     }
 }
 
+/// A Borsh representation of the network_protocol::Encoding.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum EncodingRepr {
+    Borsh,
+    Proto,
+}
+
+impl BorshRepr for EncodingRepr {
+    type T = Encoding;
+    fn to_repr(e: &Encoding) -> Self {
+        match e {
+            Encoding::Borsh => Self::Borsh,
+            Encoding::Proto => Self::Proto,
+        }
+    }
+
+    fn from_repr(e: Self) -> Result<Encoding, Error> {
+        Ok(match e {
+            EncodingRepr::Borsh => Encoding::Borsh,
+            EncodingRepr::Proto => Encoding::Proto,
+        })
+    }
+}
+
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct EdgeRepr {
     key: (PeerId, PeerId),
@@ -115,15 +140,21 @@ impl BorshRepr for EdgeRepr {
     fn to_repr(e: &Self::T) -> Self {
         Self {
             key: e.key().clone(),
-            nonce: e.nonce(),
+            nonce: e.nonce().0,
             signature0: e.signature0().clone(),
             signature1: e.signature1().clone(),
             removal_info: e.removal_info().cloned(),
         }
     }
     fn from_repr(e: Self) -> Result<Self::T, Error> {
-        Ok(primitives::Edge::new(e.key.0, e.key.1, e.nonce, e.signature0, e.signature1)
-            .with_removal_info(e.removal_info))
+        Ok(primitives::Edge::new(
+            e.key.0,
+            e.key.1,
+            primitives::Nonce(e.nonce),
+            e.signature0,
+            e.signature1,
+        )
+        .with_removal_info(e.removal_info))
     }
 }
 
@@ -144,6 +175,13 @@ impl Column for Peers {
     type Value = KnownPeerStateRepr;
 }
 
+pub struct PeerEncodings;
+impl Column for PeerEncodings {
+    const COL: DBCol = DBCol::PeerEncodings;
+    type Key = Borsh<PeerId>;
+    type Value = EncodingRepr;
+}
+
 pub struct PeerComponent;
 impl Column for PeerComponent {
     const COL: DBCol = DBCol::PeerComponent;
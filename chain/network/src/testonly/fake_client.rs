@@ -9,7 +9,7 @@ use near_primitives::network::AnnounceAccount;
 use near_primitives::sharding::{ChunkHash, PartialEncodedChunkPart};
 use near_primitives::syncing::EpochSyncResponse;
 use near_primitives::transaction::SignedTransaction;
-use near_primitives::types::EpochId;
+use near_primitives::types::{EpochId, ShardId};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Event {
@@ -25,6 +25,7 @@ pub enum Event {
     EpochSyncResponse(EpochSyncResponse),
     EpochSyncFinalizationRequest(EpochId),
     AnnounceAccount(Vec<(AnnounceAccount, Option<EpochId>)>),
+    StateRequestPart(ShardId, CryptoHash, u64),
 }
 
 pub struct Actor {
@@ -40,9 +41,9 @@ pub fn start(event_sink: Sink<Event>) -> actix::Addr<Actor> {
 }
 
 impl actix::Handler<NetworkViewClientMessages> for Actor {
-    type Result = NetworkViewClientResponses;
+    type Result = actix::ResponseFuture<NetworkViewClientResponses>;
     fn handle(&mut self, msg: NetworkViewClientMessages, _ctx: &mut Self::Context) -> Self::Result {
-        match msg {
+        let resp = match msg {
             NetworkViewClientMessages::BlockRequest(block_hash) => {
                 self.event_sink.push(Event::BlockRequest(block_hash));
                 NetworkViewClientResponses::NoResponse
@@ -63,11 +64,17 @@ impl actix::Handler<NetworkViewClientMessages> for Actor {
                 self.event_sink.push(Event::AnnounceAccount(aas.clone()));
                 NetworkViewClientResponses::AnnounceAccount(aas.into_iter().map(|a| a.0).collect())
             }
+            NetworkViewClientMessages::StateRequestPart { shard_id, sync_hash, part_id } => {
+                self.event_sink.push(Event::StateRequestPart(shard_id, sync_hash, part_id));
+                // Never respond, so that the request stays in progress.
+                return Box::pin(std::future::pending::<NetworkViewClientResponses>());
+            }
             msg => {
                 let msg_type: &'static str = msg.into();
                 panic!("unsupported message {msg_type}")
             }
-        }
+        };
+        Box::pin(async move { resp })
     }
 }
 
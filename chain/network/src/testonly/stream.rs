@@ -3,6 +3,7 @@
 use bytes::BytesMut;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use crate::network_protocol::testonly as data;
 use crate::network_protocol::{Encoding, PeerMessage};
 use crate::tcp;
 
@@ -28,13 +29,20 @@ impl Stream {
     }
 
     pub async fn read(&mut self) -> PeerMessage {
+        self.read_with_encoding().await.0
+    }
+
+    /// Like `read`, but also returns the encoding in which the message was received.
+    pub async fn read_with_encoding(&mut self) -> (PeerMessage, Encoding) {
         'read: loop {
             let n = self.stream.stream.read_u32_le().await.unwrap() as usize;
             let mut buf = BytesMut::new();
             buf.resize(n, 0);
             self.stream.stream.read_exact(&mut buf[..]).await.unwrap();
             for enc in [Encoding::Proto, Encoding::Borsh] {
-                if let Ok(msg) = PeerMessage::deserialize(enc, &buf[..]) {
+                if let Ok(msg) =
+                    PeerMessage::deserialize(enc, &buf[..], &data::make_decode_config())
+                {
                     // If deserialize() succeeded but we expected different encoding, ignore the
                     // message.
                     if self.encoding().unwrap_or(enc) != enc {
@@ -44,7 +52,7 @@ impl Stream {
                     if enc == Encoding::Proto {
                         self.protocol_buffers_supported = true;
                     }
-                    return msg;
+                    return (msg, enc);
                 }
             }
             panic!("unknown encoding");
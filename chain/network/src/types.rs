@@ -1,9 +1,13 @@
+use crate::network_protocol::RoutedMessageV2;
 /// Type that belong to the network protocol.
 pub use crate::network_protocol::{
-    AccountOrPeerIdOrHash, Encoding, Handshake, HandshakeFailureReason, PeerMessage,
-    RoutingTableUpdate, SignedAccountData,
+    peek_routing_header, transcode, verify_accounts_data, verify_block_chunks, AccountData,
+    AccountDataBuilder, AccountOrPeerIdOrHash, DecodeConfig, Encoding, Handshake,
+    HandshakeFailureReason, PeerMessage, PeerRing, RoutedHeader, RoutingHeader, RoutingTableUpdate,
+    SignedAccountData, TranscodeError, VerifyAccountDataError,
 };
 use crate::routing::routing_table_view::RoutingTableInfo;
+use crate::routing::RouteOutcome;
 use crate::time;
 use futures::future::BoxFuture;
 use futures::FutureExt;
@@ -18,6 +22,7 @@ use near_primitives::syncing::{EpochSyncFinalizationResponse, EpochSyncResponse}
 use near_primitives::transaction::SignedTransaction;
 use near_primitives::types::BlockHeight;
 use near_primitives::types::{AccountId, EpochId, ShardId};
+use near_primitives::version::ProtocolVersion;
 use near_primitives::views::FinalExecutionOutcomeView;
 use near_primitives::views::{KnownProducerView, NetworkInfoView, PeerInfoView};
 use once_cell::sync::OnceCell;
@@ -28,9 +33,11 @@ use std::sync::Arc;
 
 /// Exported types, which are part of network protocol.
 pub use crate::network_protocol::{
-    Edge, PartialEdgeInfo, PartialEncodedChunkForwardMsg, PartialEncodedChunkRequestMsg,
-    PartialEncodedChunkResponseMsg, PeerChainInfo, PeerChainInfoV2, PeerIdOrHash, PeerInfo, Ping,
-    Pong, StateResponseInfo, StateResponseInfoV1, StateResponseInfoV2,
+    Edge, EncryptedBlob, ForwardValidationError, Heartbeat, HeartbeatAck, Nonce, PartialEdgeInfo,
+    PartialEncodedChunkForwardMsg, PartialEncodedChunkRequestMsg, PartialEncodedChunkResponseMsg,
+    PeerChainInfo, PeerChainInfoV2, PeerIdOrHash, PeerInfo, Ping, Pong, RoutingTargetError,
+    StateResponseError, StateResponseInfo, StateResponseInfoV1, StateResponseInfoV2, TelemetryData,
+    TxInclusionProof, UptimeBucket,
 };
 
 /// Number of hops a message is allowed to travel before being dropped.
@@ -120,11 +127,36 @@ impl KnownPeerStatus {
 }
 
 impl AccountOrPeerIdOrHash {
-    pub(crate) fn peer_id_or_hash(&self) -> Option<PeerIdOrHash> {
+    pub fn as_account_id(&self) -> Option<&AccountId> {
         match self {
-            AccountOrPeerIdOrHash::AccountId(_) => None,
-            AccountOrPeerIdOrHash::PeerId(peer_id) => Some(PeerIdOrHash::PeerId(peer_id.clone())),
-            AccountOrPeerIdOrHash::Hash(hash) => Some(PeerIdOrHash::Hash(*hash)),
+            AccountOrPeerIdOrHash::AccountId(account_id) => Some(account_id),
+            _ => None,
+        }
+    }
+
+    pub fn as_peer_id(&self) -> Option<&PeerId> {
+        match self {
+            AccountOrPeerIdOrHash::PeerId(peer_id) => Some(peer_id),
+            _ => None,
+        }
+    }
+
+    pub fn as_hash(&self) -> Option<&CryptoHash> {
+        match self {
+            AccountOrPeerIdOrHash::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// Converts the target to a PeerIdOrHash, which is what a RoutedMessage is addressed to.
+    /// AccountIds have to be resolved to a PeerId via the routing table first.
+    pub fn try_into_peer_id_or_hash(self) -> Result<PeerIdOrHash, RoutingTargetError> {
+        match self {
+            AccountOrPeerIdOrHash::AccountId(account_id) => {
+                Err(RoutingTargetError::AccountId(account_id))
+            }
+            AccountOrPeerIdOrHash::PeerId(peer_id) => Ok(PeerIdOrHash::PeerId(peer_id)),
+            AccountOrPeerIdOrHash::Hash(hash) => Ok(PeerIdOrHash::Hash(hash)),
         }
     }
 }
@@ -177,6 +209,16 @@ pub enum PeerManagerMessageRequest {
         nonce: u64,
         target: PeerId,
     },
+    /// Send a Heartbeat with `nonce` to the node owning `account_id`.
+    /// Whether it is reachable can be observed by the HeartbeatAck arriving back.
+    HeartbeatTo {
+        nonce: u64,
+        account_id: AccountId,
+    },
+    /// TEST-ONLY Route an already signed message, as if it was relayed by this node.
+    RouteMessage(Box<RoutedMessageV2>),
+    /// Fetch the number of connected peers per protocol version.
+    PeerProtocolVersions,
 }
 
 /// Messages from PeerManager to Peer
@@ -222,6 +264,9 @@ pub enum PeerManagerMessageResponse {
     SetAdvOptions,
     FetchRoutingTable(RoutingTableInfo),
     PingTo,
+    HeartbeatTo(RouteOutcome),
+    RouteMessage(RouteOutcome),
+    PeerProtocolVersions(HashMap<ProtocolVersion, usize>),
 }
 
 impl PeerManagerMessageResponse {
@@ -322,6 +367,8 @@ pub enum NetworkRequests {
     ForwardTx(AccountId, SignedTransaction),
     /// Query transaction status
     TxStatus(AccountId, AccountId, CryptoHash),
+    /// Query the inclusion proof of a transaction
+    TxInclusionProof(AccountId, CryptoHash),
     /// A challenge to invalidate a block.
     Challenge(Challenge),
 }
@@ -485,6 +532,9 @@ pub enum NetworkClientMessages {
     /// A challenge to invalidate the block.
     Challenge(Challenge),
 
+    /// Data routed to this validator by the author of the routed message.
+    EncryptedBlob(EncryptedBlob, PeerId),
+
     NetworkInfo(NetworkInfo),
 }
 
@@ -712,6 +762,10 @@ pub enum NetworkViewClientMessages {
     /// They are paired with last epoch id known to this announcement, in order to accept only
     /// newer announcements.
     AnnounceAccount(Vec<(AnnounceAccount, Option<EpochId>)>),
+    /// Request for a proof that the transaction has been executed.
+    TxInclusionProofRequest { tx_hash: CryptoHash },
+    /// Transaction inclusion proof response
+    TxInclusionProofResponse(Box<TxInclusionProof>),
 }
 
 #[derive(Debug, actix::MessageResponse)]
@@ -730,6 +784,8 @@ pub enum NetworkViewClientResponses {
     EpochSyncResponse(Box<EpochSyncResponse>),
     /// A response to a request for headers and proofs during Epoch Sync
     EpochSyncFinalizationResponse(Box<EpochSyncFinalizationResponse>),
+    /// Transaction inclusion proof
+    TxInclusionProof(Box<TxInclusionProof>),
     /// Ban peer for malicious behavior.
     Ban { ban_reason: ReasonForBan },
     /// Response not needed
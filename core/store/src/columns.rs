@@ -179,7 +179,8 @@ pub enum DBCol {
     #[strum(serialize = "ChunkPerHeightShard")]
     _ChunkPerHeightShard,
     /// Changes to state (Trie) that we have recorded.
-    /// - *Rows*: BlockHash || TrieKey (TrieKey is written via custom to_vec)
+    /// - *Rows*: BlockHash || TrieKey || ShardUId (TrieKey is written via custom to_vec,
+    ///   ShardUId is missing in the rows written by older versions)
     /// - *Column type*: TrieKey, new value and reason for change (RawStateChangesWithTrieKey)
     StateChanges,
     /// Mapping from Block to its refcount. (Refcounts are used in handling chain forks)
@@ -237,6 +238,22 @@ pub enum DBCol {
     /// - *Rows*: BlockShardId (BlockHash || ShardId) - 40 bytes
     /// - *Column type*: StateChangesForSplitStates
     StateChangesForSplitStates,
+    /// Encoding agreed upon with the peer in the last completed handshake.
+    /// - *Rows*: peer_id (PublicKey)
+    /// - *Column type*: network's `Encoding`
+    PeerEncodings,
+    /// Refcount deltas applied to `State` entries, kept for auditing garbage
+    /// collection. Only written when `TrieConfig::audit_refcounts` is set.
+    /// - *Rows*: ShardUId || node hash || block hash || kind (0 for
+    ///   insertions, 1 for deletions)
+    /// - *Column type*: i64
+    StateRefcountHistory,
+    /// JSON dumps of `TrieChanges`, written next to the `TrieChanges` rows
+    /// when persisting them with `SerializationFormat::Json`. Debug only,
+    /// never garbage collected.
+    /// - *Rows*: BlockShardId (BlockHash || ShardUId) - 40 bytes
+    /// - *Column type*: TrieChanges as JSON
+    TrieChangesDebug,
     /// Flat state contents. Used to get `ValueRef` by trie key faster than doing a trie lookup.
     /// - *Rows*: trie key (Vec<u8>)
     /// - *Column type*: ValueRef
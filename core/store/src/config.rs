@@ -53,6 +53,44 @@ pub struct StoreConfig {
     /// This config option is temporary and will be removed once flat storage is implemented.
     pub sweat_prefetch_senders: Vec<String>,
 
+    /// Trie values larger than this many bytes are stored compressed in
+    /// DBCol::State.  `null` (the default) disables compression.
+    /// The setting is recorded in the database when the node first opens it and
+    /// can't be changed afterwards: the refcounted State entries are merged by
+    /// value, so the same value has to be encoded the same way on every write.
+    pub trie_value_compression_threshold: Option<usize>,
+
+    /// Record every refcount change of trie nodes together with the block
+    /// which caused it, so that garbage collection can be audited.  Disabled
+    /// by default since the history grows with every applied block.
+    pub audit_trie_refcounts: bool,
+
+    /// Don't save changes of accounts, access keys, contract code and contract
+    /// data in DBCol::StateChanges.  They are only read by the
+    /// `EXPERIMENTAL_changes*` RPC methods and the indexer, so a node serving
+    /// neither can skip writing them.  The state itself is saved as usual.
+    /// Config validation rejects it on RPC, archival and indexer nodes.
+    pub skip_state_changes: bool,
+
+    /// If set, the trie caches of all shards share a limit of this many bytes
+    /// of cached values, instead of each shard having a fixed limit.  A shard
+    /// under heavy load can then use the capacity the idle shards don't need.
+    pub trie_cache_shared_budget_bytes: Option<u64>,
+
+    /// Part of `trie_cache_shared_budget_bytes` reserved for each shard, so
+    /// that a busy shard cannot evict everything the other shards cache.
+    pub trie_cache_min_shard_bytes: u64,
+
+    /// Whether the trie caches record when the nodes were inserted, to report
+    /// the age of the oldest cached node.  Helps to tell whether the caches are
+    /// too small for the workload, at the cost of a timestamp per cached node.
+    pub trie_cache_track_entry_age: bool,
+
+    /// Number of subtrees read ahead by the prefetcher when a whole shard is
+    /// exported.  `null` (the default) uses a built-in value, 0 disables the
+    /// read-ahead.
+    pub trie_export_read_ahead_window: Option<usize>,
+
     /// Path where to create RocksDB checkpoints during database migrations or
     /// `false` to disable that feature.
     ///
@@ -191,6 +229,13 @@ impl Default for StoreConfig {
                 "oracle.sweat".to_owned(),
                 "sweat_the_oracle.testnet".to_owned(),
             ],
+            trie_value_compression_threshold: None,
+            audit_trie_refcounts: false,
+            skip_state_changes: false,
+            trie_cache_shared_budget_bytes: None,
+            trie_cache_min_shard_bytes: 100_000_000,
+            trie_cache_track_entry_age: false,
+            trie_export_read_ahead_window: None,
 
             migration_snapshot: Default::default(),
         }
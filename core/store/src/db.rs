@@ -27,6 +27,10 @@ pub const GENESIS_STATE_ROOTS_KEY: &[u8; 19] = b"GENESIS_STATE_ROOTS";
 /// Boolean stored in DBCol::BlockMisc indicating whether the database is for an
 /// archival node.  The default value (if missing) is false.
 pub const IS_ARCHIVE_KEY: &[u8; 10] = b"IS_ARCHIVE";
+/// `Option<u64>` stored in DBCol::BlockMisc with the trie value compression
+/// threshold the database has been written with, see
+/// `StoreConfig::trie_value_compression_threshold`.
+pub const TRIE_VALUE_COMPRESSION_THRESHOLD_KEY: &[u8; 32] = b"TRIE_VALUE_COMPRESSION_THRESHOLD";
 
 #[derive(Default)]
 pub struct DBTransaction {
@@ -55,6 +55,8 @@ impl From<FlatStorageError> for StorageError {
 #[cfg(feature = "protocol_feature_flat_state")]
 mod imp {
     use crate::flat_state::{store_helper, FlatStorageState, POISONED_LOCK_ERR};
+    use crate::metrics;
+    use near_o11y::metrics::Histogram;
     use near_primitives::hash::CryptoHash;
     use near_primitives::state::ValueRef;
     use near_primitives::types::ShardId;
@@ -87,6 +89,8 @@ mod imp {
         /// blocks' state are stored in flat storage.
         #[allow(unused)]
         flat_storage_state: FlatStorageState,
+        /// Latency of resolving keys through the deltas, labeled with the shard of this flat state.
+        deltas_latency: Histogram,
     }
 
     #[derive(Clone)]
@@ -106,6 +110,8 @@ mod imp {
         pub fn get_ref(&self, key: &[u8]) -> Result<Option<ValueRef>, crate::StorageError> {
             // Take deltas ordered from `self.block_hash` to flat state head.
             // In other words, order of deltas is the opposite of the order of blocks in chain.
+            // The timer is observed on drop, so early returns are recorded as well.
+            let timer = self.deltas_latency.start_timer();
             let deltas = self.flat_storage_state.get_deltas_between_blocks(&self.block_hash)?;
             for delta in deltas {
                 // If we found a key in delta, we can return a value because it is the most recent key update.
@@ -116,6 +122,7 @@ mod imp {
                     None => {}
                 };
             }
+            timer.observe_duration();
 
             Ok(store_helper::get_ref(&self.store, key)?)
         }
@@ -233,6 +240,8 @@ mod imp {
                     block_hash,
                     cache,
                     flat_storage_state,
+                    deltas_latency: metrics::FLAT_STATE_DELTAS_APPLY_LATENCY
+                        .with_label_values(&[&shard_id.to_string()]),
                 })
             }
         }
@@ -327,6 +336,17 @@ pub struct KeyForFlatStateDelta {
     pub block_hash: CryptoHash,
 }
 
+/// Mismatch between flat state and trie, reported by
+/// `ShardTries::verify_flat_state_consistency`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inconsistency {
+    pub key: Vec<u8>,
+    /// Value reference stored in flat state, on top of the flat head.
+    pub flat_state: Option<ValueRef>,
+    /// Value reference found by traversing the trie.
+    pub trie: Option<ValueRef>,
+}
+
 /// Delta of the state for some shard and block, stores mapping from keys to value refs or None, if key was removed in
 /// this block.
 #[derive(BorshSerialize, BorshDeserialize, Default, Debug)]
@@ -720,9 +740,10 @@ mod tests {
     use crate::flat_state::{
         store_helper, BlockInfo, ChainAccessForFlatStorage, FlatStateFactory, FlatStorageState,
     };
-    use crate::test_utils::create_test_store;
-    use crate::FlatStateDelta;
-    use crate::StorageError;
+    use crate::metrics;
+    use crate::test_utils::{create_test_store, test_populate_trie};
+    use crate::trie::TrieConfig;
+    use crate::{FlatStateDelta, ShardTries, ShardUId, StorageError, Trie};
     use borsh::BorshSerialize;
     use near_primitives::borsh::maybestd::collections::HashSet;
     use near_primitives::hash::{hash, CryptoHash};
@@ -893,6 +914,105 @@ mod tests {
         assert_eq!(delta.get(&[5]), Some(Some(ValueRef::new(&[9]))));
     }
 
+    /// Check that reads through the flat state deltas are timed per shard.
+    #[test]
+    fn flat_state_deltas_latency() {
+        // Use a shard which no other test uses, as metrics are global.
+        let shard_id = 7;
+        let chain = MockChain::linear_chain(5);
+        let store = create_test_store();
+        let mut store_update = store.store_update();
+        store_helper::set_flat_head(&mut store_update, shard_id, &chain.get_block_hash(0));
+        for i in 1..5 {
+            store_helper::set_delta(
+                &mut store_update,
+                shard_id,
+                chain.get_block_hash(i),
+                &FlatStateDelta::from([(vec![i as u8], Some(ValueRef::new(&[i as u8])))]),
+            )
+            .unwrap();
+        }
+        store_update.commit().unwrap();
+
+        let flat_state_factory = FlatStateFactory::new(store.clone());
+        flat_state_factory.add_flat_storage_state_for_shard(
+            shard_id,
+            FlatStorageState::new(store, shard_id, 4, &chain),
+        );
+        let flat_state = flat_state_factory
+            .new_flat_state_for_shard(shard_id, Some(chain.get_block_hash(4)), false)
+            .unwrap();
+
+        let histogram =
+            metrics::FLAT_STATE_DELTAS_APPLY_LATENCY.with_label_values(&[&shard_id.to_string()]);
+        let count_before = histogram.get_sample_count();
+        // Found in the last delta, found in the first delta and not found at all.
+        assert_eq!(flat_state.get_ref(&[4]).unwrap(), Some(ValueRef::new(&[4])));
+        assert_eq!(flat_state.get_ref(&[1]).unwrap(), Some(ValueRef::new(&[1])));
+        assert_eq!(flat_state.get_ref(&[9]).unwrap(), None);
+        assert_eq!(count_before + 3, histogram.get_sample_count());
+    }
+
+    /// Check that `ShardTries::get_at_block` resolves keys through the deltas
+    /// of the requested block rather than the latest state.
+    #[test]
+    fn get_at_block_reads_deltas() {
+        let shard_uid = ShardUId::single_shard();
+        let chain = MockChain::linear_chain(3);
+        let store = create_test_store();
+        let flat_state_factory = FlatStateFactory::new(store.clone());
+        let tries = ShardTries::new(
+            store.clone(),
+            TrieConfig::default(),
+            &[shard_uid],
+            flat_state_factory.clone(),
+        );
+        // Store both values in the trie, so that the value refs can be resolved.
+        let key = b"key".to_vec();
+        let root0 = test_populate_trie(
+            &tries,
+            &Trie::EMPTY_ROOT,
+            shard_uid,
+            vec![(key.clone(), Some(b"old".to_vec()))],
+        );
+        let root1 = test_populate_trie(
+            &tries,
+            &root0,
+            shard_uid,
+            vec![(key.clone(), Some(b"new".to_vec()))],
+        );
+
+        // Flat head is at block 0, block 1 updates the key and block 2 deletes it.
+        let mut store_update = store.store_update();
+        store_helper::set_flat_head(&mut store_update, 0, &chain.get_block_hash(0));
+        store_helper::set_ref(&mut store_update, key.clone(), Some(ValueRef::new(b"old"))).unwrap();
+        store_helper::set_delta(
+            &mut store_update,
+            0,
+            chain.get_block_hash(1),
+            &FlatStateDelta::from([(key.clone(), Some(ValueRef::new(b"new")))]),
+        )
+        .unwrap();
+        store_helper::set_delta(
+            &mut store_update,
+            0,
+            chain.get_block_hash(2),
+            &FlatStateDelta::from([(key.clone(), None)]),
+        )
+        .unwrap();
+        store_update.commit().unwrap();
+        flat_state_factory
+            .add_flat_storage_state_for_shard(0, FlatStorageState::new(store, 0, 2, &chain));
+
+        let get = |root, height| {
+            tries.get_at_block(shard_uid, root, &chain.get_block_hash(height), &key).unwrap()
+        };
+        assert_eq!(Some(b"old".to_vec()), get(root0, 0));
+        assert_eq!(Some(b"new".to_vec()), get(root1, 1));
+        // The trie at `root1` still has the key, the delta of block 2 wins.
+        assert_eq!(None, get(root1, 2));
+    }
+
     // This test tests some basic use cases for FlatState and FlatStorageState.
     // We created a linear chain with no forks, start with flat head at the genesis block, then
     // moves the flat head forward, which checking that flat_state.get_ref() still returns the correct
@@ -32,8 +32,9 @@ pub use crate::trie::iterator::TrieIterator;
 pub use crate::trie::update::{TrieUpdate, TrieUpdateIterator, TrieUpdateValuePtr};
 pub use crate::trie::{
     estimator, split_state, ApplyStatePartResult, KeyForStateChanges, NibbleSlice, PartialStorage,
-    PrefetchApi, RawTrieNode, RawTrieNodeWithSize, ShardTries, Trie, TrieAccess, TrieCache,
-    TrieCachingStorage, TrieChanges, TrieConfig, TrieStorage, WrappedTrieChanges,
+    PrefetchApi, PrefetchPriority, RawTrieNode, RawTrieNodeWithSize, SerializationFormat,
+    ShardTries, StagedStateRootBlock, Trie, TrieAccess, TrieCache, TrieCachingStorage, TrieChanges,
+    TrieConfig, TrieCursor, TrieResumeToken, TrieStorage, WrappedTrieChanges,
 };
 pub use flat_state::FlatStateDelta;
 
@@ -285,6 +286,11 @@ impl Store {
 pub struct StoreUpdate {
     transaction: DBTransaction,
     storage: StoreUpdateStorage,
+    /// Shard write locks taken by `ShardTries::apply_all`, released once the
+    /// update is committed or dropped. Empty unless `ShardTries::serialize_writes`
+    /// has been called.
+    #[cfg(any(test, feature = "test_features"))]
+    shard_write_guards: Vec<trie::ShardWriteGuard>,
 }
 
 enum StoreUpdateStorage {
@@ -299,11 +305,21 @@ impl StoreUpdate {
     };
 
     pub(crate) fn new(db: Arc<dyn Database>) -> Self {
-        StoreUpdate { transaction: DBTransaction::new(), storage: StoreUpdateStorage::DB(db) }
+        StoreUpdate {
+            transaction: DBTransaction::new(),
+            storage: StoreUpdateStorage::DB(db),
+            #[cfg(any(test, feature = "test_features"))]
+            shard_write_guards: vec![],
+        }
     }
 
     pub fn new_with_tries(tries: ShardTries) -> Self {
-        StoreUpdate { transaction: DBTransaction::new(), storage: StoreUpdateStorage::Tries(tries) }
+        StoreUpdate {
+            transaction: DBTransaction::new(),
+            storage: StoreUpdateStorage::Tries(tries),
+            #[cfg(any(test, feature = "test_features"))]
+            shard_write_guards: vec![],
+        }
     }
 
     /// Inserts a new value into the database.
@@ -453,6 +469,8 @@ impl StoreUpdate {
                 assert!(same_db(self_db, &other_db));
             }
         }
+        #[cfg(any(test, feature = "test_features"))]
+        self.shard_write_guards.extend(other.shard_write_guards);
         self.transaction.merge(other.transaction)
     }
 
@@ -1,6 +1,7 @@
 use near_o11y::metrics::{
-    try_create_histogram_vec, try_create_int_counter_vec, try_create_int_gauge_vec, HistogramVec,
-    IntCounterVec, IntGaugeVec,
+    try_create_histogram_vec, try_create_int_counter, try_create_int_counter_vec,
+    try_create_int_gauge, try_create_int_gauge_vec, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec,
 };
 use once_cell::sync::Lazy;
 
@@ -78,6 +79,14 @@ pub static SHARD_CACHE_CURRENT_TOTAL_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static SHARD_CACHE_TOTAL_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    try_create_int_gauge(
+        "near_shard_cache_total_bytes",
+        "Total size of all the shard caches, including the view caches",
+    )
+    .unwrap()
+});
+
 pub static SHARD_CACHE_POP_HITS: Lazy<IntCounterVec> = Lazy::new(|| {
     try_create_int_counter_vec(
         "near_shard_cache_pop_hits",
@@ -118,6 +127,14 @@ pub static SHARD_CACHE_DELETIONS_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
     )
     .unwrap()
 });
+pub static SHARD_CACHE_WATCH_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+    try_create_int_counter_vec(
+        "near_shard_cache_watch_dropped",
+        "Shard cache eviction events dropped because the watcher's channel was full",
+        &["shard_id", "is_view"],
+    )
+    .unwrap()
+});
 pub static APPLIED_TRIE_DELETIONS: Lazy<IntCounterVec> = Lazy::new(|| {
     try_create_int_counter_vec(
         "near_applied_trie_deletions",
@@ -142,6 +159,56 @@ pub static REVERTED_TRIE_INSERTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
     )
     .unwrap()
 });
+pub static SHARD_CACHE_LOCK_WAIT: Lazy<HistogramVec> = Lazy::new(|| {
+    try_create_histogram_vec(
+        "near_shard_cache_lock_wait_seconds",
+        "Time spent waiting for the lock on the shard caches map, recorded only for noticeable waits",
+        &["op", "is_view"],
+        Some(vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0]),
+    )
+    .unwrap()
+});
+#[cfg(feature = "protocol_feature_flat_state")]
+pub static FLAT_STATE_DELTAS_APPLY_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    try_create_histogram_vec(
+        "near_flat_state_deltas_apply_latency_seconds",
+        "Time spent looking up a key in the flat state deltas between the flat head and the block of the trie",
+        &["shard_id"],
+        Some(vec![0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05]),
+    )
+    .unwrap()
+});
+pub static TRIE_LOOKUP_DEPTH: Lazy<HistogramVec> = Lazy::new(|| {
+    try_create_histogram_vec(
+        "near_trie_lookup_depth",
+        "Number of trie nodes traversed to look up a key, excluding lookups served by flat state",
+        &["shard_id"],
+        Some(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 12.0, 16.0, 24.0, 32.0, 48.0, 64.0]),
+    )
+    .unwrap()
+});
+pub static SHARD_CACHE_UPDATE_UNKNOWN_SHARD: Lazy<IntCounterVec> = Lazy::new(|| {
+    try_create_int_counter_vec(
+        "near_shard_cache_update_unknown_shard",
+        "Trie cache updates skipped because the shard uid in the key has no cache",
+        &["shard_id", "version"],
+    )
+    .unwrap()
+});
+pub static SHARD_CACHE_UPDATE_MALFORMED_KEY: Lazy<IntCounter> = Lazy::new(|| {
+    try_create_int_counter(
+        "near_shard_cache_update_malformed_key",
+        "Trie cache updates skipped because the State column key is not a shard uid followed by a hash",
+    )
+    .unwrap()
+});
+pub static STATE_CHANGES_MALFORMED_ENTRIES: Lazy<IntCounter> = Lazy::new(|| {
+    try_create_int_counter(
+        "near_state_changes_malformed_entries",
+        "Number of malformed StateChanges entries skipped while iterating",
+    )
+    .unwrap()
+});
 pub static PREFETCH_SENT: Lazy<IntCounterVec> = Lazy::new(|| {
     try_create_int_counter_vec("near_prefetch_sent", "Prefetch requests sent to DB", &["shard_id"])
         .unwrap()
@@ -26,6 +26,10 @@ const DEFAULT_SHARD_CACHE_DELETIONS_QUEUE_CAPACITY: usize =
 /// Note that most of Trie inner nodes are smaller than this - e.g. branches use around 32 * 16 = 512 bytes.
 const TRIE_LIMIT_CACHED_VALUE_SIZE: usize = 1000;
 
+/// Default number of subtrees read ahead by `ShardTries::export_shard`.
+/// It keeps all prefetching IO threads busy while the export walks the current subtree.
+const DEFAULT_EXPORT_READ_AHEAD_WINDOW: usize = 16;
+
 /// Stores necessary configuration for the creation of tries.
 #[derive(Default)]
 pub struct TrieConfig {
@@ -37,6 +41,26 @@ pub struct TrieConfig {
     pub sweat_prefetch_receivers: Vec<AccountId>,
     /// List of allowed predecessor accounts for SWEAT prefetching.
     pub sweat_prefetch_senders: Vec<AccountId>,
+
+    /// Whether `ShardTries` may be created for shard uids of different shard versions.
+    /// By default all shard uids are expected to come from a single shard layout.
+    pub allow_mixed_shard_versions: bool,
+
+    /// Values longer than this many bytes are compressed before being written to
+    /// the `State` column. If None, values are written as is.
+    pub compress_values_above: Option<usize>,
+
+    /// Whether refcount deltas applied on behalf of a block are recorded in
+    /// `DBCol::StateRefcountHistory`. See `ShardTries::refcount_history`.
+    pub audit_refcounts: bool,
+
+    /// Whether `WrappedTrieChanges::state_changes_into` drops the state changes
+    /// instead of saving them in `DBCol::StateChanges`.
+    pub skip_state_changes: bool,
+
+    /// Number of subtrees which `ShardTries::export_shard` reads ahead of the
+    /// subtree being exported. If None, a default is used; 0 disables read-ahead.
+    pub export_read_ahead_window: Option<usize>,
 }
 
 pub struct ShardCacheConfig {
@@ -52,6 +76,28 @@ pub struct ShardCacheConfig {
     pub override_max_entries: HashMap<ShardUId, u64>,
     /// Overrides `default_max_total_bytes` per shard.
     pub override_max_total_bytes: HashMap<ShardUId, u64>,
+    /// If set, the caches of all shards share a single limit on the sum of
+    /// cached value sizes, which replaces the per shard limits above.
+    pub shared_budget: Option<SharedCacheBudgetConfig>,
+    /// Whether the caches record insertion times of the nodes, which is needed
+    /// for `TrieCache::oldest_entry_age`.
+    pub track_entry_age: bool,
+}
+
+/// Limit on the sum of cached value sizes, shared by the caches of all shards.
+///
+/// A shard cache may grow into the part of the budget which is not used by the
+/// other shards, so that a shard under heavy load isn't limited by a fixed
+/// share while the others are idle. To keep the other shards from starving,
+/// `min_shard_bytes` is reserved for each of them, even if they use less.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SharedCacheBudgetConfig {
+    /// Limit on the sum of cached value sizes of all shards.
+    pub total_bytes: u64,
+    /// Size which every shard cache may reach, whatever the other shards use.
+    /// For the guarantee to hold within `total_bytes`, it should be at most
+    /// `total_bytes` divided by the number of shards.
+    pub min_shard_bytes: u64,
 }
 
 impl TrieConfig {
@@ -61,6 +107,17 @@ impl TrieConfig {
             .override_max_entries
             .extend(config.trie_cache_capacities.iter().cloned());
         this.enable_receipt_prefetching = config.enable_receipt_prefetching;
+        this.compress_values_above = config.trie_value_compression_threshold;
+        this.audit_refcounts = config.audit_trie_refcounts;
+        this.skip_state_changes = config.skip_state_changes;
+        if let Some(total_bytes) = config.trie_cache_shared_budget_bytes {
+            this.shard_cache_config.shared_budget = Some(SharedCacheBudgetConfig {
+                total_bytes,
+                min_shard_bytes: config.trie_cache_min_shard_bytes,
+            });
+        }
+        this.shard_cache_config.track_entry_age = config.trie_cache_track_entry_age;
+        this.export_read_ahead_window = config.trie_export_read_ahead_window;
         for account in &config.sweat_prefetch_receivers {
             match AccountId::from_str(account) {
                 Ok(account_id) => this.sweat_prefetch_receivers.push(account_id),
@@ -88,11 +145,27 @@ impl TrieConfig {
             .total_size_limit(shard_uid)
     }
 
+    /// Budget shared by the caches of all shards, if configured.
+    pub fn shard_cache_shared_budget(&self, is_view: bool) -> Option<SharedCacheBudgetConfig> {
+        if is_view { &self.view_shard_cache_config } else { &self.shard_cache_config }.shared_budget
+    }
+
+    /// Whether the shard caches record insertion times of the cached nodes.
+    pub fn shard_cache_tracks_entry_age(&self, is_view: bool) -> bool {
+        if is_view { &self.view_shard_cache_config } else { &self.shard_cache_config }
+            .track_entry_age
+    }
+
     /// Size limit in bytes per single value for caching in shard caches.
     pub fn max_cached_value_size() -> usize {
         TRIE_LIMIT_CACHED_VALUE_SIZE
     }
 
+    /// Number of subtrees read ahead when exporting a trie, see `export_read_ahead_window`.
+    pub fn export_read_ahead_window(&self) -> usize {
+        self.export_read_ahead_window.unwrap_or(DEFAULT_EXPORT_READ_AHEAD_WINDOW)
+    }
+
     /// Capacity for deletion queue in which nodes are after unforced eviction.
     ///
     /// The shard cache uses LRU eviction policy for forced evictions. But when a
@@ -125,6 +198,8 @@ impl Default for ShardCacheConfig {
             default_max_total_bytes: DEFAULT_SHARD_CACHE_TOTAL_SIZE_LIMIT,
             override_max_entries: HashMap::default(),
             override_max_total_bytes: HashMap::default(),
+            shared_budget: None,
+            track_entry_age: false,
         }
     }
 }
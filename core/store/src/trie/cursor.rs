@@ -0,0 +1,79 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use near_primitives::types::StateRoot;
+
+use crate::trie::iterator::TrieItem;
+use crate::trie::nibble_slice::NibbleSlice;
+use crate::{StorageError, Trie};
+
+/// Opaque position within a trie export, returned by [`TrieCursor::next_batch`].
+///
+/// The token only records the state root and the key to continue from, so it
+/// can be persisted (it implements borsh serialization) and used to resume the
+/// export after the store is reopened, as long as the state root is the same.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TrieResumeToken {
+    state_root: StateRoot,
+    next_key: Vec<u8>,
+}
+
+/// Iterates over all (key, value) pairs of a trie in key order, in bounded
+/// batches. Obtained with [`crate::ShardTries::trie_cursor`].
+///
+/// Unlike [`crate::trie::iterator::TrieIterator`] the cursor doesn't keep a
+/// borrow of the trie between batches; each batch seeks to the saved position
+/// again, which costs a walk from the root to the resumed key.
+pub struct TrieCursor {
+    trie: Trie,
+    /// Key to start the next batch from, `None` once the trie is exhausted.
+    next_key: Option<Vec<u8>>,
+}
+
+impl TrieCursor {
+    pub(crate) fn new(trie: Trie, token: Option<&TrieResumeToken>) -> Result<Self, StorageError> {
+        let next_key = match token {
+            None => Vec::new(),
+            Some(token) if token.state_root == trie.root => token.next_key.clone(),
+            Some(token) => {
+                return Err(StorageError::StorageInconsistentState(format!(
+                    "resume token is for state root {}, cursor is at {}",
+                    token.state_root, trie.root
+                )))
+            }
+        };
+        Ok(Self { trie, next_key: Some(next_key) })
+    }
+
+    /// Returns up to `limit` following (key, value) pairs, together with the
+    /// token to resume after them. The token is `None` once the whole trie
+    /// has been returned.
+    pub fn next_batch(
+        &mut self,
+        limit: usize,
+    ) -> Result<(Vec<TrieItem>, Option<TrieResumeToken>), StorageError> {
+        let start = match &self.next_key {
+            Some(key) => key,
+            None => return Ok((vec![], None)),
+        };
+        if limit == 0 {
+            let token = TrieResumeToken { state_root: self.trie.root, next_key: start.clone() };
+            return Ok((vec![], Some(token)));
+        }
+        let mut iter = self.trie.iter()?;
+        iter.seek_nibble_slice(NibbleSlice::new(start), false)?;
+        let batch = iter.take(limit).collect::<Result<Vec<_>, _>>()?;
+        self.next_key = match batch.last() {
+            Some((last_key, _)) if batch.len() == limit => {
+                // The smallest key which is greater than `last_key`.
+                let mut key = last_key.clone();
+                key.push(0);
+                Some(key)
+            }
+            _ => None,
+        };
+        let token = self
+            .next_key
+            .as_ref()
+            .map(|key| TrieResumeToken { state_root: self.trie.root, next_key: key.clone() });
+        Ok((batch, token))
+    }
+}
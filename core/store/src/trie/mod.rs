@@ -5,6 +5,7 @@ use std::io::{Cursor, Read};
 use borsh::{BorshDeserialize, BorshSerialize};
 use byteorder::{LittleEndian, ReadBytesExt};
 
+use near_o11y::metrics::Histogram;
 use near_primitives::challenge::PartialState;
 use near_primitives::contract::ContractCode;
 use near_primitives::hash::{hash, CryptoHash};
@@ -16,11 +17,16 @@ use near_primitives::types::{StateRoot, StateRootNode};
 
 use crate::flat_state::FlatState;
 pub use crate::trie::config::TrieConfig;
+pub use crate::trie::cursor::{TrieCursor, TrieResumeToken};
 use crate::trie::insert_delete::NodesStorage;
 use crate::trie::iterator::TrieIterator;
 pub use crate::trie::nibble_slice::NibbleSlice;
-pub use crate::trie::prefetching_trie_storage::PrefetchApi;
-pub use crate::trie::shard_tries::{KeyForStateChanges, ShardTries, WrappedTrieChanges};
+pub use crate::trie::prefetching_trie_storage::{PrefetchApi, PrefetchPriority};
+#[cfg(any(test, feature = "test_features"))]
+pub(crate) use crate::trie::shard_tries::ShardWriteGuard;
+pub use crate::trie::shard_tries::{
+    KeyForStateChanges, SerializationFormat, ShardTries, StagedStateRootBlock, WrappedTrieChanges,
+};
 pub use crate::trie::trie_storage::{TrieCache, TrieCachingStorage, TrieStorage};
 use crate::trie::trie_storage::{TrieMemoryPartialStorage, TrieRecordingStorage};
 use crate::StorageError;
@@ -28,6 +34,7 @@ pub use near_primitives::types::TrieNodesCount;
 use std::fmt::Write;
 
 mod config;
+mod cursor;
 mod insert_delete;
 pub mod iterator;
 mod nibble_slice;
@@ -464,6 +471,8 @@ pub struct Trie {
     pub storage: Box<dyn TrieStorage>,
     root: StateRoot,
     pub flat_state: Option<FlatState>,
+    /// If set, the number of nodes traversed by every lookup is recorded in it.
+    lookup_depth: Option<Histogram>,
 }
 
 /// Trait for reading data from a trie.
@@ -479,12 +488,17 @@ pub trait TrieAccess {
 
 /// Stores reference count change for some key-value pair in DB.
 #[derive(BorshSerialize, BorshDeserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "debug_trie_changes", derive(serde::Serialize))]
 pub struct TrieRefcountChange {
     /// Hash of trie_node_or_value and part of the DB key.
     /// Used for uniting with shard id to get actual DB key.
     trie_node_or_value_hash: CryptoHash,
     /// DB value. Can be either serialized RawTrieNodeWithSize or value corresponding to
     /// some TrieKey.
+    #[cfg_attr(
+        feature = "debug_trie_changes",
+        serde(with = "near_primitives::serialize::base64_format")
+    )]
     trie_node_or_value: Vec<u8>,
     /// Reference count difference which will be added to the total refcount if it corresponds to
     /// insertion and subtracted from it in the case of deletion.
@@ -515,6 +529,7 @@ pub struct TrieRefcountChange {
 ///
 /// StoreUpdate are the changes from current state refcount to refcount + delta.
 #[derive(BorshSerialize, BorshDeserialize, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "debug_trie_changes", derive(serde::Serialize))]
 pub struct TrieChanges {
     pub old_root: StateRoot,
     pub new_root: StateRoot,
@@ -544,7 +559,13 @@ impl Trie {
         root: StateRoot,
         flat_state: Option<FlatState>,
     ) -> Self {
-        Trie { storage, root, flat_state }
+        Trie { storage, root, flat_state, lookup_depth: None }
+    }
+
+    /// Makes lookups record the number of nodes they traverse in `histogram`.
+    pub(crate) fn with_lookup_depth_histogram(mut self, histogram: Histogram) -> Self {
+        self.lookup_depth = Some(histogram);
+        self
     }
 
     pub fn recording_reads(&self) -> Self {
@@ -555,7 +576,12 @@ impl Trie {
             shard_uid: storage.shard_uid,
             recorded: RefCell::new(Default::default()),
         };
-        Trie { storage: Box::new(storage), root: self.root.clone(), flat_state: None }
+        Trie {
+            storage: Box::new(storage),
+            root: self.root.clone(),
+            flat_state: None,
+            lookup_depth: self.lookup_depth.clone(),
+        }
     }
 
     pub fn recorded_storage(&self) -> Option<PartialStorage> {
@@ -761,6 +787,83 @@ impl Trie {
         Ok(())
     }
 
+    /// Visits the trie nodes in depth-first order, descending at most
+    /// `max_depth` levels below the root (the root itself is at depth 0).
+    /// `visit` is called with the nibbles of the path leading to the node
+    /// and the hash of the node. Values are not visited.
+    pub fn traverse_to_depth(
+        &self,
+        max_depth: u32,
+        mut visit: impl FnMut(&[u8], CryptoHash),
+    ) -> Result<(), StorageError> {
+        let mut prefix = vec![];
+        self.traverse_to_depth_internal(&self.root, max_depth, &mut prefix, &mut visit)
+    }
+
+    fn traverse_to_depth_internal(
+        &self,
+        hash: &CryptoHash,
+        max_depth: u32,
+        prefix: &mut Vec<u8>,
+        visit: &mut impl FnMut(&[u8], CryptoHash),
+    ) -> Result<(), StorageError> {
+        let node = match self.retrieve_raw_node(hash)? {
+            Some((_, node)) => node.node,
+            None => return Ok(()),
+        };
+        visit(prefix, *hash);
+        if max_depth == 0 {
+            return Ok(());
+        }
+        match node {
+            RawTrieNode::Leaf(..) => {}
+            RawTrieNode::Branch(children, _) => {
+                for (idx, child) in children.iter().enumerate() {
+                    if let Some(child) = child {
+                        prefix.push(idx as u8);
+                        self.traverse_to_depth_internal(child, max_depth - 1, prefix, visit)?;
+                        prefix.pop();
+                    }
+                }
+            }
+            RawTrieNode::Extension(key, child) => {
+                let (slice, _) = NibbleSlice::from_encoded(key.as_slice());
+                prefix.extend(slice.iter());
+                self.traverse_to_depth_internal(&child, max_depth - 1, prefix, visit)?;
+                prefix.truncate(prefix.len() - slice.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads all nodes and values of the trie through the storage, in depth-first
+    /// order, without decoding the keys. Used by the prefetcher to read ahead.
+    pub(crate) fn read_all_nodes(&self) -> Result<(), StorageError> {
+        self.read_all_nodes_internal(&self.root)
+    }
+
+    fn read_all_nodes_internal(&self, hash: &CryptoHash) -> Result<(), StorageError> {
+        let node = match self.retrieve_raw_node(hash)? {
+            Some((_, node)) => node.node,
+            None => return Ok(()),
+        };
+        match node {
+            RawTrieNode::Leaf(_, _, value_hash) => {
+                self.storage.retrieve_raw_bytes(&value_hash)?;
+            }
+            RawTrieNode::Branch(children, value) => {
+                if let Some((_, value_hash)) = value {
+                    self.storage.retrieve_raw_bytes(&value_hash)?;
+                }
+                for child in children.iter().flatten() {
+                    self.read_all_nodes_internal(child)?;
+                }
+            }
+            RawTrieNode::Extension(_, child) => self.read_all_nodes_internal(&child)?,
+        }
+        Ok(())
+    }
+
     fn retrieve_raw_node(
         &self,
         hash: &CryptoHash,
@@ -824,13 +927,28 @@ impl Trie {
         }
     }
 
-    fn lookup(&self, mut key: NibbleSlice<'_>) -> Result<Option<ValueRef>, StorageError> {
+    fn lookup(&self, key: NibbleSlice<'_>) -> Result<Option<ValueRef>, StorageError> {
+        let mut depth = 0;
+        let result = self.lookup_counting_depth(key, &mut depth);
+        if let Some(histogram) = &self.lookup_depth {
+            histogram.observe(depth as f64);
+        }
+        result
+    }
+
+    /// Looks up `key`, setting `depth` to the number of nodes traversed.
+    fn lookup_counting_depth(
+        &self,
+        mut key: NibbleSlice<'_>,
+        depth: &mut u32,
+    ) -> Result<Option<ValueRef>, StorageError> {
         let mut hash = self.root.clone();
         loop {
             let node = match self.retrieve_raw_node(&hash)? {
                 None => return Ok(None),
                 Some((_bytes, node)) => node.node,
             };
+            *depth += 1;
             match node {
                 RawTrieNode::Leaf(existing_key, value_length, value_hash) => {
                     if NibbleSlice::from_encoded(&existing_key).0 == key {
@@ -11,7 +11,8 @@ use near_primitives::hash::CryptoHash;
 use near_primitives::shard_layout::ShardUId;
 use near_primitives::trie_key::TrieKey;
 use near_primitives::types::{AccountId, ShardId, StateRoot, TrieNodesCount};
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
@@ -58,15 +59,16 @@ struct TriePrefetchingStorage {
 /// does not know about the trie structure. The only thing they share is this object.
 #[derive(Clone)]
 pub struct PrefetchApi {
-    /// Bounded, shared queue for all IO threads to take work from.
-    ///
-    /// Work items are defined as `TrieKey` because currently the only
-    /// work is to prefetch a trie key. If other IO work is added, consider
-    /// changing the queue to an enum.
-    /// The state root is also included because multiple chunks could be applied
-    /// at the same time.
-    work_queue_tx: crossbeam::channel::Sender<(StateRoot, TrieKey)>,
-    work_queue_rx: crossbeam::channel::Receiver<(StateRoot, TrieKey)>,
+    /// Bounded channel with one token per request in `queue`, which the IO
+    /// threads block on while there is no work. It bounds the number of queued
+    /// requests, while `queue` decides which request is served next.
+    work_queue_tx: crossbeam::channel::Sender<()>,
+    work_queue_rx: crossbeam::channel::Receiver<()>,
+    /// Shared queue for all IO threads to take work from, highest priority first.
+    /// Pushing and popping a request is logarithmic in the number of queued
+    /// requests, which is bounded by `MAX_QUEUED_WORK_ITEMS`, so the lock is
+    /// held only briefly.
+    queue: Arc<Mutex<PrefetchQueue>>,
     /// Prefetching IO threads will insert fetched data here. This is also used
     /// to mark what is already being fetched, to avoid fetching the same data
     /// multiple times.
@@ -81,6 +83,83 @@ pub struct PrefetchApi {
     pub shard_uid: ShardUId,
 }
 
+/// Priority of a prefetch request. Requests of higher priority are served first,
+/// requests of the same priority in the order they were submitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PrefetchPriority {
+    /// Data which might be needed, e.g. predicted by a heuristic.
+    Low,
+    /// Data which is known to be needed soon, e.g. the receiver account of a receipt.
+    High,
+}
+
+/// Work to be done by an IO thread, within the trie with the queued root.
+#[derive(Debug, PartialEq)]
+enum PrefetchRequest {
+    /// Look up the value of the trie key.
+    TrieKey(TrieKey),
+    /// Read all nodes and values of the trie, see `Trie::read_all_nodes`.
+    /// The root is usually the root of a subtree of some larger trie.
+    AllNodes,
+}
+
+/// Prefetch request waiting for an IO thread.
+///
+/// The state root is included because multiple chunks could be applied
+/// at the same time.
+struct QueuedPrefetch {
+    priority: PrefetchPriority,
+    /// Submission order, used to serve requests of the same priority first come first served.
+    seq: u64,
+    root: StateRoot,
+    request: PrefetchRequest,
+}
+
+impl QueuedPrefetch {
+    fn order(&self) -> (PrefetchPriority, Reverse<u64>) {
+        (self.priority, Reverse(self.seq))
+    }
+}
+
+impl PartialEq for QueuedPrefetch {
+    fn eq(&self, other: &Self) -> bool {
+        self.order() == other.order()
+    }
+}
+
+impl Eq for QueuedPrefetch {}
+
+impl PartialOrd for QueuedPrefetch {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedPrefetch {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.order().cmp(&other.order())
+    }
+}
+
+#[derive(Default)]
+struct PrefetchQueue {
+    heap: BinaryHeap<QueuedPrefetch>,
+    next_seq: u64,
+}
+
+impl PrefetchQueue {
+    fn push(&mut self, root: StateRoot, request: PrefetchRequest, priority: PrefetchPriority) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(QueuedPrefetch { priority, seq, root, request });
+    }
+
+    /// Removes the request which should be served next.
+    fn pop(&mut self) -> Option<(StateRoot, PrefetchRequest)> {
+        self.heap.pop().map(|q| (q.root, q.request))
+    }
+}
+
 /// Staging area for in-flight prefetch requests and a buffer for prefetched data.
 ///
 /// Before starting a pre-fetch, a slot is reserved for it. Once the data is
@@ -219,7 +298,8 @@ impl TrieStorage for TriePrefetchingStorage {
                 let key = TrieCachingStorage::get_key_from_shard_uid_and_hash(self.shard_uid, hash);
                 match self.store.get(DBCol::State, key.as_ref()) {
                     Ok(Some(value)) => {
-                        let value: Arc<[u8]> = value.into();
+                        let value: Arc<[u8]> =
+                            Arc::from(&*TrieCachingStorage::decode_stored_value(hash, &value));
                         self.prefetching.insert_fetched(hash.clone(), value.clone());
                         Ok(value)
                     }
@@ -386,20 +466,7 @@ impl PrefetchApi {
         shard_uid: ShardUId,
         trie_config: &TrieConfig,
     ) -> (Self, PrefetchingThreadsHandle) {
-        let (work_queue_tx, work_queue_rx) = crossbeam::channel::bounded(MAX_QUEUED_WORK_ITEMS);
-        let sweat_prefetch_receivers = trie_config.sweat_prefetch_receivers.clone();
-        let sweat_prefetch_senders = trie_config.sweat_prefetch_senders.clone();
-        let enable_receipt_prefetching = trie_config.enable_receipt_prefetching;
-
-        let this = Self {
-            work_queue_tx,
-            work_queue_rx,
-            prefetching: PrefetchStagingArea::new(shard_uid.shard_id()),
-            enable_receipt_prefetching,
-            sweat_prefetch_receivers,
-            sweat_prefetch_senders,
-            shard_uid,
-        };
+        let this = Self::new_without_io_threads(shard_uid, trie_config);
         let (shutdown_tx, shutdown_rx) = crossbeam::channel::bounded(1);
         let handles = (0..NUM_IO_THREADS)
             .map(|_| {
@@ -415,13 +482,92 @@ impl PrefetchApi {
         (this, handle)
     }
 
+    fn new_without_io_threads(shard_uid: ShardUId, trie_config: &TrieConfig) -> Self {
+        let (work_queue_tx, work_queue_rx) = crossbeam::channel::bounded(MAX_QUEUED_WORK_ITEMS);
+        let sweat_prefetch_receivers = trie_config.sweat_prefetch_receivers.clone();
+        let sweat_prefetch_senders = trie_config.sweat_prefetch_senders.clone();
+        let enable_receipt_prefetching = trie_config.enable_receipt_prefetching;
+
+        Self {
+            work_queue_tx,
+            work_queue_rx,
+            queue: Default::default(),
+            prefetching: PrefetchStagingArea::new(shard_uid.shard_id()),
+            enable_receipt_prefetching,
+            sweat_prefetch_receivers,
+            sweat_prefetch_senders,
+            shard_uid,
+        }
+    }
+
+    /// Queues a request of `PrefetchPriority::Low`, see `submit_with_priority`.
+    ///
     /// Returns the argument back if queue is full.
     pub fn prefetch_trie_key(
         &self,
         root: StateRoot,
         trie_key: TrieKey,
     ) -> Result<(), (StateRoot, TrieKey)> {
-        self.work_queue_tx.try_send((root, trie_key)).map_err(|e| e.into_inner())
+        self.submit_with_priority(root, trie_key, PrefetchPriority::Low)
+    }
+
+    /// Queues prefetching of `trie_key`. IO threads take the queued request of
+    /// the highest priority first.
+    ///
+    /// Returns the argument back if queue is full.
+    pub fn submit_with_priority(
+        &self,
+        root: StateRoot,
+        trie_key: TrieKey,
+        priority: PrefetchPriority,
+    ) -> Result<(), (StateRoot, TrieKey)> {
+        // Keep the lock while sending, so that an IO thread cannot take the
+        // token before the request is added to `queue`.
+        let mut queue = self.queue.lock().expect(POISONED_LOCK_ERR);
+        if self.work_queue_tx.try_send(()).is_err() {
+            return Err((root, trie_key));
+        }
+        queue.push(root, PrefetchRequest::TrieKey(trie_key), priority);
+        Ok(())
+    }
+
+    /// Queues reading of the whole subtree with the root `node_hash`, nodes and
+    /// values alike. Meant for reading ahead of a sequential walk over a trie.
+    /// The reads stop early if the staging area is full.
+    ///
+    /// Returns the argument back if queue is full.
+    pub fn prefetch_subtree(
+        &self,
+        node_hash: CryptoHash,
+        priority: PrefetchPriority,
+    ) -> Result<(), CryptoHash> {
+        let mut queue = self.queue.lock().expect(POISONED_LOCK_ERR);
+        if self.work_queue_tx.try_send(()).is_err() {
+            return Err(node_hash);
+        }
+        queue.push(node_hash, PrefetchRequest::AllNodes, priority);
+        Ok(())
+    }
+
+    /// Returns the trie keys which are queued for prefetching, but haven't been
+    /// picked up by an IO thread yet, in the order they will be picked up.
+    /// Queued subtrees are not included.
+    pub fn pending(&self) -> Vec<TrieKey> {
+        // Only copy the keys while holding the lock, the IO threads and the
+        // submitters shouldn't wait for the sorting.
+        let mut pending: Vec<_> = {
+            let queue = self.queue.lock().expect(POISONED_LOCK_ERR);
+            queue
+                .heap
+                .iter()
+                .filter_map(|q| match &q.request {
+                    PrefetchRequest::TrieKey(trie_key) => Some((q.order(), trie_key.clone())),
+                    PrefetchRequest::AllNodes => None,
+                })
+                .collect()
+        };
+        pending.sort_by(|(a, _), (b, _)| b.cmp(a));
+        pending.into_iter().map(|(_, trie_key)| trie_key).collect()
     }
 
     pub fn start_io_thread(
@@ -434,6 +580,7 @@ impl PrefetchApi {
         let prefetcher_storage =
             TriePrefetchingStorage::new(store, shard_uid, shard_cache, self.prefetching.clone());
         let work_queue = self.work_queue_rx.clone();
+        let queue = self.queue.clone();
         let metric_prefetch_sent =
             metrics::PREFETCH_SENT.with_label_values(&[&shard_uid.shard_id.to_string()]);
         let metric_prefetch_fail =
@@ -442,12 +589,17 @@ impl PrefetchApi {
             loop {
                 let selected = select! {
                     recv(shutdown_rx) -> _ => None,
-                    recv(work_queue) -> maybe_work_item => maybe_work_item.ok(),
+                    recv(work_queue) -> maybe_token => maybe_token.ok(),
                 };
+                if selected.is_none() {
+                    return;
+                }
+                // The queue may be empty if `clear_queue` ran after the token was taken.
+                let work_item = queue.lock().expect(POISONED_LOCK_ERR).pop();
 
-                match selected {
-                    None => return,
-                    Some((trie_root, trie_key)) => {
+                match work_item {
+                    None => continue,
+                    Some((trie_root, request)) => {
                         // Since the trie root can change,and since the root is
                         // not known at the time when the IO threads starts,
                         // we need to redefine the trie before each request.
@@ -456,9 +608,14 @@ impl PrefetchApi {
                         // hit is small.
                         let prefetcher_trie =
                             Trie::new(Box::new(prefetcher_storage.clone()), trie_root, None);
-                        let storage_key = trie_key.to_vec();
                         metric_prefetch_sent.inc();
-                        if let Ok(_maybe_value) = prefetcher_trie.get(&storage_key) {
+                        let result = match request {
+                            PrefetchRequest::TrieKey(trie_key) => {
+                                prefetcher_trie.get(&trie_key.to_vec()).map(|_| ())
+                            }
+                            PrefetchRequest::AllNodes => prefetcher_trie.read_all_nodes(),
+                        };
+                        if result.is_ok() {
                             near_o11y::io_trace!(count: "prefetch");
                         } else {
                             // This may happen in rare occasions and can be ignored safely.
@@ -477,7 +634,9 @@ impl PrefetchApi {
     /// Queued up work will not be finished. But trie keys that are already
     /// being fetched will finish.
     pub fn clear_queue(&self) {
-        while let Ok(_dropped) = self.work_queue_rx.try_recv() {}
+        let mut queue = self.queue.lock().expect(POISONED_LOCK_ERR);
+        while self.work_queue_rx.try_recv().is_ok() {}
+        queue.heap.clear();
     }
 
     /// Clear prefetched staging area from data that has not been picked up by the main thread.
@@ -506,6 +665,14 @@ pub(crate) struct PrefetchingThreadsHandle {
     handles: Vec<thread::JoinHandle<()>>,
 }
 
+impl PrefetchingThreadsHandle {
+    /// Whether any of the IO threads has exited. The threads only exit once the handle
+    /// is dropped, so while it is alive, an exited thread is one that panicked.
+    pub(crate) fn any_thread_dead(&self) -> bool {
+        self.handles.iter().any(|handle| handle.is_finished())
+    }
+}
+
 impl Drop for PrefetchingThreadsHandle {
     fn drop(&mut self) {
         // Dropping the single sender will hang up the channel and stop
@@ -558,3 +725,90 @@ mod tests {
         }
     }
 }
+
+#[cfg(test)]
+mod prefetch_api_tests {
+    use super::{PrefetchApi, PrefetchPriority, PrefetchRequest, PrefetchingThreadsHandle};
+    use crate::test_utils::create_test_store;
+    use crate::{Trie, TrieCache, TrieConfig};
+    use near_primitives::shard_layout::ShardUId;
+    use near_primitives::trie_key::TrieKey;
+    use std::time::{Duration, Instant};
+
+    impl PrefetchingThreadsHandle {
+        /// Replaces one of the IO threads with a thread that has panicked.
+        /// The replaced thread is detached and exits once the handle is dropped.
+        pub(crate) fn kill_io_thread(&mut self) {
+            let dead = std::thread::spawn(|| panic!("IO thread killed"));
+            while !dead.is_finished() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            self.handles[0] = dead;
+        }
+    }
+
+    fn account_keys(n: usize) -> Vec<TrieKey> {
+        (0..n)
+            .map(|i| TrieKey::Account { account_id: format!("test{i}.near").parse().unwrap() })
+            .collect()
+    }
+
+    #[test]
+    fn test_pending() {
+        let shard_uid = ShardUId::single_shard();
+        let trie_config = TrieConfig::default();
+        let api = PrefetchApi::new_without_io_threads(shard_uid, &trie_config);
+        assert!(api.pending().is_empty());
+
+        let keys = account_keys(3);
+        for key in &keys {
+            api.prefetch_trie_key(Trie::EMPTY_ROOT, key.clone()).unwrap();
+        }
+        assert_eq!(keys, api.pending());
+        api.clear_queue();
+        assert!(api.pending().is_empty());
+
+        for key in &keys {
+            api.prefetch_trie_key(Trie::EMPTY_ROOT, key.clone()).unwrap();
+        }
+        assert_eq!(keys, api.pending());
+        let (shutdown_tx, shutdown_rx) = crossbeam::channel::bounded(1);
+        let handle = api.start_io_thread(
+            create_test_store(),
+            TrieCache::new(&trie_config, shard_uid, false),
+            shard_uid,
+            shutdown_rx,
+        );
+        let _handle =
+            PrefetchingThreadsHandle { shutdown_channel: Some(shutdown_tx), handles: vec![handle] };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !api.pending().is_empty() {
+            assert!(Instant::now() < deadline, "prefetch requests not drained");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_priority() {
+        let shard_uid = ShardUId::single_shard();
+        let api = PrefetchApi::new_without_io_threads(shard_uid, &TrieConfig::default());
+        let keys = account_keys(4);
+        for key in &keys[..2] {
+            api.submit_with_priority(Trie::EMPTY_ROOT, key.clone(), PrefetchPriority::Low).unwrap();
+        }
+        for key in &keys[2..] {
+            api.submit_with_priority(Trie::EMPTY_ROOT, key.clone(), PrefetchPriority::High)
+                .unwrap();
+        }
+        let want = vec![keys[2].clone(), keys[3].clone(), keys[0].clone(), keys[1].clone()];
+        assert_eq!(want, api.pending());
+
+        // Take the requests the way an IO thread does.
+        for key in want {
+            api.work_queue_rx.try_recv().unwrap();
+            let (root, got) = api.queue.lock().unwrap().pop().unwrap();
+            assert_eq!((Trie::EMPTY_ROOT, PrefetchRequest::TrieKey(key)), (root, got));
+        }
+        assert!(api.pending().is_empty());
+    }
+}
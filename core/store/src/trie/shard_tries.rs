@@ -1,9 +1,15 @@
 use std::io;
 use std::rc::Rc;
-use std::sync::{Arc, RwLock};
+#[cfg(any(test, feature = "test_features"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(any(test, feature = "test_features"))]
+use std::sync::Condvar;
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
+use std::time::{Duration, Instant};
 
-use borsh::BorshSerialize;
-use near_primitives::borsh::maybestd::collections::HashMap;
+use borsh::{BorshDeserialize, BorshSerialize};
+use lru::LruCache;
+use near_primitives::borsh::maybestd::collections::{HashMap, HashSet};
 use near_primitives::hash::CryptoHash;
 use near_primitives::shard_layout::{self, ShardUId, ShardVersion};
 use near_primitives::trie_key::TrieKey;
@@ -13,12 +19,22 @@ use near_primitives::types::{
 
 use crate::flat_state::FlatStateFactory;
 use crate::trie::config::TrieConfig;
+use crate::trie::cursor::{TrieCursor, TrieResumeToken};
+use crate::trie::nibble_slice::NibbleSlice;
 use crate::trie::prefetching_trie_storage::PrefetchingThreadsHandle;
-use crate::trie::trie_storage::{TrieCache, TrieCachingStorage};
+use crate::trie::trie_storage::{SharedCacheBudget, TrieCache, TrieCachingStorage};
 use crate::trie::{TrieRefcountChange, POISONED_LOCK_ERR};
-use crate::{metrics, DBCol, DBOp, DBTransaction, PrefetchApi};
+use crate::{metrics, DBCol, DBOp, DBTransaction, PrefetchApi, PrefetchPriority, StorageError};
 use crate::{Store, StoreUpdate, Trie, TrieChanges, TrieUpdate};
 
+/// Waits for the caches lock shorter than this are not recorded, so that
+/// the uncontended path doesn't pay for updating the histogram.
+const CACHES_LOCK_WAIT_THRESHOLD: Duration = Duration::from_micros(100);
+
+/// Number of most recent (shard, state root) -> block mappings remembered
+/// by `ShardTries::block_for_state_root`.
+const STATE_ROOT_BLOCKS_CAPACITY: usize = 1000;
+
 struct ShardTriesInner {
     store: Store,
     trie_config: TrieConfig,
@@ -26,9 +42,70 @@ struct ShardTriesInner {
     caches: RwLock<HashMap<ShardUId, TrieCache>>,
     /// Cache for readers.
     view_caches: RwLock<HashMap<ShardUId, TrieCache>>,
+    /// Budgets shared by `caches` and `view_caches` respectively, if configured.
+    cache_budget: Option<Arc<SharedCacheBudget>>,
+    view_cache_budget: Option<Arc<SharedCacheBudget>>,
     flat_state_factory: FlatStateFactory,
     /// Prefetcher state, such as IO threads, per shard.
     prefetchers: RwLock<HashMap<ShardUId, (PrefetchApi, PrefetchingThreadsHandle)>>,
+    /// Blocks which produced the recently saved state roots.
+    state_root_blocks: Mutex<LruCache<(ShardUId, StateRoot), CryptoHash>>,
+    /// Whether `apply_all` serializes the writes per shard, see `serialize_writes`.
+    #[cfg(any(test, feature = "test_features"))]
+    serialize_writes: AtomicBool,
+    #[cfg(any(test, feature = "test_features"))]
+    shard_write_locks: Mutex<HashMap<ShardUId, Arc<ShardWriteLock>>>,
+}
+
+/// Lock held from `ShardTries::apply_all` until the returned update is committed
+/// or dropped. Unlike a mutex guard, the guard owns the lock, so that it can be
+/// moved along with the `StoreUpdate`.
+#[cfg(any(test, feature = "test_features"))]
+#[derive(Default)]
+struct ShardWriteLock {
+    locked: Mutex<bool>,
+    released: Condvar,
+}
+
+#[cfg(any(test, feature = "test_features"))]
+impl ShardWriteLock {
+    fn acquire(self: &Arc<Self>) -> ShardWriteGuard {
+        let mut locked = self.locked.lock().expect(POISONED_LOCK_ERR);
+        while *locked {
+            locked = self.released.wait(locked).expect(POISONED_LOCK_ERR);
+        }
+        *locked = true;
+        ShardWriteGuard(self.clone())
+    }
+}
+
+#[cfg(any(test, feature = "test_features"))]
+pub(crate) struct ShardWriteGuard(Arc<ShardWriteLock>);
+
+/// A state root -> block mapping of saved trie changes, see
+/// `WrappedTrieChanges::staged_state_root_block`. The caller records it in
+/// `ShardTries::block_for_state_root` only after the store update with the trie
+/// changes has been committed, so that a failed commit doesn't leave a mapping
+/// to a state root which was never saved.
+pub struct StagedStateRootBlock {
+    tries: ShardTries,
+    shard_uid: ShardUId,
+    state_root: StateRoot,
+    block_hash: CryptoHash,
+}
+
+impl StagedStateRootBlock {
+    pub fn record(self) {
+        self.tries.record_state_root_block(self.shard_uid, self.state_root, self.block_hash);
+    }
+}
+
+#[cfg(any(test, feature = "test_features"))]
+impl Drop for ShardWriteGuard {
+    fn drop(&mut self) {
+        *self.0.locked.lock().expect(POISONED_LOCK_ERR) = false;
+        self.0.released.notify_one();
+    }
 }
 
 #[derive(Clone)]
@@ -41,15 +118,30 @@ impl ShardTries {
         shard_uids: &[ShardUId],
         flat_state_factory: FlatStateFactory,
     ) -> Self {
-        let caches = Self::create_initial_caches(&trie_config, &shard_uids, false);
-        let view_caches = Self::create_initial_caches(&trie_config, &shard_uids, true);
+        Self::check_shard_uids(&trie_config, shard_uids);
+        let cache_budget = trie_config
+            .shard_cache_shared_budget(false)
+            .map(|c| Arc::new(SharedCacheBudget::new(c)));
+        let view_cache_budget = trie_config
+            .shard_cache_shared_budget(true)
+            .map(|c| Arc::new(SharedCacheBudget::new(c)));
+        let caches = Self::create_initial_caches(&trie_config, &shard_uids, false, &cache_budget);
+        let view_caches =
+            Self::create_initial_caches(&trie_config, &shard_uids, true, &view_cache_budget);
         ShardTries(Arc::new(ShardTriesInner {
             store: store.clone(),
             trie_config,
             caches: RwLock::new(caches),
             view_caches: RwLock::new(view_caches),
+            cache_budget,
+            view_cache_budget,
             flat_state_factory,
             prefetchers: Default::default(),
+            state_root_blocks: Mutex::new(LruCache::new(STATE_ROOT_BLOCKS_CAPACITY)),
+            #[cfg(any(test, feature = "test_features"))]
+            serialize_writes: AtomicBool::new(false),
+            #[cfg(any(test, feature = "test_features"))]
+            shard_write_locks: Default::default(),
         }))
     }
 
@@ -74,18 +166,49 @@ impl ShardTries {
         )
     }
 
-    /// Create caches for all shards according to the trie config.
+    /// Checks that `shard_uids` contains no duplicates, which would make the
+    /// caches of the later entries silently replace the earlier ones, and that
+    /// all uids share a single shard version unless the config allows mixing them.
+    fn check_shard_uids(trie_config: &TrieConfig, shard_uids: &[ShardUId]) {
+        let mut seen = HashSet::new();
+        for shard_uid in shard_uids {
+            let inserted = seen.insert(shard_uid);
+            debug_assert!(inserted, "duplicate shard uid {:?}", shard_uid);
+        }
+        if !trie_config.allow_mixed_shard_versions {
+            if let Some(first) = shard_uids.first() {
+                debug_assert!(
+                    shard_uids.iter().all(|shard_uid| shard_uid.version == first.version),
+                    "shard uids of different versions: {:?}",
+                    shard_uids
+                );
+            }
+        }
+    }
+
+    /// Create caches for all shards according to the trie config. If `budget` is
+    /// given, the caches share it instead of being limited in size individually.
     fn create_initial_caches(
         config: &TrieConfig,
         shard_uids: &[ShardUId],
         is_view: bool,
+        budget: &Option<Arc<SharedCacheBudget>>,
     ) -> HashMap<ShardUId, TrieCache> {
         shard_uids
             .iter()
-            .map(|&shard_uid| (shard_uid, TrieCache::new(config, shard_uid, is_view)))
+            .map(|&shard_uid| {
+                (shard_uid, TrieCache::with_budget(config, shard_uid, is_view, budget.clone()))
+            })
             .collect()
     }
 
+    /// Creates a cache for a shard which has none yet, sharing the budget of
+    /// the other caches if configured.
+    fn new_cache(&self, shard_uid: ShardUId, is_view: bool) -> TrieCache {
+        let budget = if is_view { &self.0.view_cache_budget } else { &self.0.cache_budget };
+        TrieCache::with_budget(&self.0.trie_config, shard_uid, is_view, budget.clone())
+    }
+
     pub(crate) fn is_same(&self, other: &Self) -> bool {
         Arc::ptr_eq(&self.0, &other.0)
     }
@@ -98,6 +221,25 @@ impl ShardTries {
         TrieUpdate::new(Rc::new(self.get_view_trie_for_shard(shard_uid, state_root)))
     }
 
+    /// Acquires the write lock on `caches` (or `view_caches`), recording the wait
+    /// in `near_shard_cache_lock_wait_seconds` if the lock was contended.
+    fn write_caches(
+        &self,
+        op: &str,
+        is_view: bool,
+    ) -> RwLockWriteGuard<'_, HashMap<ShardUId, TrieCache>> {
+        let caches = if is_view { &self.0.view_caches } else { &self.0.caches };
+        let start = Instant::now();
+        let guard = caches.write().expect(POISONED_LOCK_ERR);
+        let waited = start.elapsed();
+        if waited >= CACHES_LOCK_WAIT_THRESHOLD {
+            metrics::SHARD_CACHE_LOCK_WAIT
+                .with_label_values(&[op, if is_view { "1" } else { "0" }])
+                .observe(waited.as_secs_f64());
+        }
+        guard
+    }
+
     #[allow(unused_variables)]
     fn get_trie_for_shard_internal(
         &self,
@@ -106,13 +248,9 @@ impl ShardTries {
         is_view: bool,
         block_hash: Option<CryptoHash>,
     ) -> Trie {
-        let caches_to_use = if is_view { &self.0.view_caches } else { &self.0.caches };
         let cache = {
-            let mut caches = caches_to_use.write().expect(POISONED_LOCK_ERR);
-            caches
-                .entry(shard_uid)
-                .or_insert_with(|| TrieCache::new(&self.0.trie_config, shard_uid, is_view))
-                .clone()
+            let mut caches = self.write_caches("get_trie", is_view);
+            caches.entry(shard_uid).or_insert_with(|| self.new_cache(shard_uid, is_view)).clone()
         };
         // Do not enable prefetching on view caches.
         // 1) Performance of view calls is not crucial.
@@ -124,10 +262,15 @@ impl ShardTries {
                 || (!self.0.trie_config.sweat_prefetch_receivers.is_empty()
                     && !self.0.trie_config.sweat_prefetch_senders.is_empty()));
         let prefetch_api = prefetch_enabled.then(|| {
-            self.0
-                .prefetchers
-                .write()
-                .expect(POISONED_LOCK_ERR)
+            let mut prefetchers = self.0.prefetchers.write().expect(POISONED_LOCK_ERR);
+            // A dead IO thread never completes the prefetches it picked up, so instead of
+            // handing out a degraded prefetcher, replace it. Dropping the handle joins the
+            // remaining threads.
+            if prefetchers.get(&shard_uid).map_or(false, |(_, handle)| handle.any_thread_dead()) {
+                tracing::warn!(target: "store", ?shard_uid, "prefetcher IO thread died, restarting the prefetcher");
+                prefetchers.remove(&shard_uid);
+            }
+            prefetchers
                 .entry(shard_uid)
                 .or_insert_with(|| {
                     PrefetchApi::new(
@@ -153,8 +296,10 @@ impl ShardTries {
             block_hash,
             is_view,
         );
+        let lookup_depth =
+            metrics::TRIE_LOOKUP_DEPTH.with_label_values(&[&shard_uid.shard_id.to_string()]);
 
-        Trie::new(storage, state_root, flat_state)
+        Trie::new(storage, state_root, flat_state).with_lookup_depth_histogram(lookup_depth)
     }
 
     pub fn get_trie_for_shard(&self, shard_uid: ShardUId, state_root: StateRoot) -> Trie {
@@ -170,10 +315,258 @@ impl ShardTries {
         self.get_trie_for_shard_internal(shard_uid, state_root, false, Some(block_hash.clone()))
     }
 
+    /// Reads the value of `key` as of the block `block_hash`, whose post-state
+    /// root is `state_root`. If flat storage is enabled for the shard, the value
+    /// is resolved through flat state deltas of that block, falling back to
+    /// the trie otherwise.
+    pub fn get_at_block(
+        &self,
+        shard_uid: ShardUId,
+        state_root: StateRoot,
+        block_hash: &CryptoHash,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, StorageError> {
+        self.get_trie_with_block_hash_for_shard(shard_uid, state_root, block_hash).get(key)
+    }
+
     pub fn get_view_trie_for_shard(&self, shard_uid: ShardUId, state_root: StateRoot) -> Trie {
         self.get_trie_for_shard_internal(shard_uid, state_root, true, None)
     }
 
+    /// Returns a trie whose reads are served from the shard cache when possible, but
+    /// are never inserted into it. Meant for jobs which read each key once, where
+    /// caching would only evict entries needed by block processing.
+    pub fn get_trie_for_shard_bypass_cache(
+        &self,
+        shard_uid: ShardUId,
+        state_root: StateRoot,
+    ) -> Trie {
+        let cache = {
+            let mut caches = self.write_caches("get_trie", false);
+            caches.entry(shard_uid).or_insert_with(|| self.new_cache(shard_uid, false)).clone()
+        };
+        // The prefetcher inserts into the shard cache, so it is not used here.
+        let storage = Box::new(
+            TrieCachingStorage::new(self.0.store.clone(), cache, shard_uid, false, None)
+                .bypass_shard_cache(),
+        );
+        let flat_state =
+            self.0.flat_state_factory.new_flat_state_for_shard(shard_uid.shard_id(), None, false);
+        Trie::new(storage, state_root, flat_state)
+    }
+
+    /// Passes all (key, value) pairs of the shard's trie at `state_root` to `sink`,
+    /// in key order. Values are streamed one at a time, so memory usage doesn't
+    /// grow with the size of the state. Uses the view cache, so that exporting
+    /// doesn't evict nodes needed by block processing.
+    /// Reads ahead as many subtrees as configured with
+    /// `TrieConfig::export_read_ahead_window`, see `export_shard_with_read_ahead`.
+    pub fn export_shard(
+        &self,
+        shard_uid: ShardUId,
+        state_root: StateRoot,
+        sink: impl FnMut(Vec<u8>, Vec<u8>),
+    ) -> Result<(), StorageError> {
+        let window = self.0.trie_config.export_read_ahead_window();
+        self.export_shard_with_read_ahead(shard_uid, state_root, window, sink)
+    }
+
+    /// Same as `export_shard`, but with an explicit read-ahead `window`.
+    /// The trie is split into subtrees a few levels below the root, and while the
+    /// export walks one of them, the IO threads of a prefetcher dedicated to the
+    /// export read the following `window` subtrees. 0 disables the read-ahead.
+    pub fn export_shard_with_read_ahead(
+        &self,
+        shard_uid: ShardUId,
+        state_root: StateRoot,
+        window: usize,
+        mut sink: impl FnMut(Vec<u8>, Vec<u8>),
+    ) -> Result<(), StorageError> {
+        if window == 0 {
+            let trie = self.get_view_trie_for_shard(shard_uid, state_root);
+            for item in trie.iter()? {
+                let (key, value) = item?;
+                sink(key, value);
+            }
+            return Ok(());
+        }
+        let cache = {
+            let mut caches = self.write_caches("export_shard", true);
+            caches.entry(shard_uid).or_insert_with(|| self.new_cache(shard_uid, true)).clone()
+        };
+        // The prefetcher is not shared with the view tries, which read without one, so
+        // that the staging area is only drained by the export, see `get_trie_for_shard_internal`.
+        let (prefetch_api, _io_threads) =
+            PrefetchApi::new(self.0.store.clone(), cache.clone(), shard_uid, &self.0.trie_config);
+        let storage = Box::new(TrieCachingStorage::new(
+            self.0.store.clone(),
+            cache,
+            shard_uid,
+            true,
+            Some(prefetch_api.clone()),
+        ));
+        let trie = Trie::new(storage, state_root, None);
+        let mut read_ahead = ExportReadAhead::new(&trie, window)?;
+        let result = trie.iter().and_then(|iter| {
+            for item in iter {
+                let (key, value) = item?;
+                read_ahead.advance(&prefetch_api, &key);
+                sink(key, value);
+            }
+            Ok(())
+        });
+        // Don't make the IO threads finish the read-ahead which is no longer needed.
+        prefetch_api.clear_queue();
+        result
+    }
+
+    /// Returns a cursor over the shard's trie at `state_root` which returns its
+    /// (key, value) pairs in batches, see [`TrieCursor`]. If `token` is given,
+    /// the cursor continues where the batch which returned it ended; the token
+    /// must have been created for the same `state_root`. Like `export_shard`,
+    /// uses the view cache.
+    pub fn trie_cursor(
+        &self,
+        shard_uid: ShardUId,
+        state_root: StateRoot,
+        token: Option<&TrieResumeToken>,
+    ) -> Result<TrieCursor, StorageError> {
+        TrieCursor::new(self.get_view_trie_for_shard(shard_uid, state_root), token)
+    }
+
+    /// Visits the nodes of the shard's trie at `state_root` which are at most
+    /// `max_depth` levels below the root, see `Trie::traverse_to_depth`.
+    /// Allows to inspect the top of the trie without walking all of it.
+    pub fn traverse_to_depth(
+        &self,
+        shard_uid: ShardUId,
+        state_root: StateRoot,
+        max_depth: u32,
+        visit: impl FnMut(&[u8], CryptoHash),
+    ) -> Result<(), StorageError> {
+        self.get_view_trie_for_shard(shard_uid, state_root).traverse_to_depth(max_depth, visit)
+    }
+
+    /// Returns up to `limit` (key, value) pairs of the shard's trie at `state_root`
+    /// whose keys start with `prefix`, in key order. Only the subtree below the
+    /// prefix is walked, so the cost doesn't depend on the size of the whole state.
+    pub fn keys_with_prefix(
+        &self,
+        shard_uid: ShardUId,
+        state_root: StateRoot,
+        prefix: &[u8],
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        let trie = self.get_view_trie_for_shard(shard_uid, state_root);
+        let mut iter = trie.iter()?;
+        iter.seek_prefix(prefix)?;
+        iter.take(limit).collect()
+    }
+
+    /// Reads every key of `sample` both from flat state and by traversing the shard's
+    /// trie at `state_root`, which should be the state on top of the flat head, and
+    /// returns the keys for which the two disagree. Delayed receipts are not kept in
+    /// flat state, so their keys are skipped. Meant for auditing flat state in the
+    /// background, so the trie is read through the view cache.
+    #[cfg(feature = "protocol_feature_flat_state")]
+    pub fn verify_flat_state_consistency(
+        &self,
+        shard_uid: ShardUId,
+        state_root: StateRoot,
+        sample: &[Vec<u8>],
+    ) -> Result<Vec<crate::flat_state::Inconsistency>, StorageError> {
+        // View tries don't use flat state, so their reads always traverse the trie.
+        let trie = self.get_view_trie_for_shard(shard_uid, state_root);
+        let mut inconsistencies = vec![];
+        for key in sample {
+            if near_primitives::state_record::is_delayed_receipt_key(key) {
+                continue;
+            }
+            let flat_state = crate::flat_state::store_helper::get_ref(&self.0.store, key)?;
+            let trie = trie.get_ref(key)?;
+            if flat_state != trie {
+                inconsistencies.push(crate::flat_state::Inconsistency {
+                    key: key.clone(),
+                    flat_state,
+                    trie,
+                });
+            }
+        }
+        Ok(inconsistencies)
+    }
+
+    /// Returns the block whose trie changes produced `state_root` for the shard.
+    /// Only the recently saved trie changes are remembered, once their
+    /// `StagedStateRootBlock` has been recorded; if several blocks produced
+    /// the same root, the last recorded one is returned.
+    pub fn block_for_state_root(
+        &self,
+        shard_uid: ShardUId,
+        state_root: &StateRoot,
+    ) -> Option<CryptoHash> {
+        let mut blocks = self.0.state_root_blocks.lock().expect(POISONED_LOCK_ERR);
+        blocks.get(&(shard_uid, *state_root)).cloned()
+    }
+
+    fn record_state_root_block(
+        &self,
+        shard_uid: ShardUId,
+        state_root: StateRoot,
+        block_hash: CryptoHash,
+    ) {
+        let mut blocks = self.0.state_root_blocks.lock().expect(POISONED_LOCK_ERR);
+        blocks.put((shard_uid, state_root), block_hash);
+    }
+
+    /// Returns the trie keys queued for prefetching in the shard, which the IO threads
+    /// haven't picked up yet. See `PrefetchApi::pending`.
+    pub fn pending_prefetches(&self, shard_uid: ShardUId) -> Vec<TrieKey> {
+        let prefetchers = self.0.prefetchers.read().expect(POISONED_LOCK_ERR);
+        prefetchers.get(&shard_uid).map_or_else(Vec::new, |(api, _)| api.pending())
+    }
+
+    /// Returns the shards which have a prefetcher, in ascending order. Prefetchers
+    /// are created together with the first non-view trie of a shard, if prefetching
+    /// is enabled, so a shard is missing until it has been used for block processing.
+    pub fn active_prefetcher_shards(&self) -> Vec<ShardUId> {
+        let prefetchers = self.0.prefetchers.read().expect(POISONED_LOCK_ERR);
+        let mut shards: Vec<ShardUId> = prefetchers.keys().cloned().collect();
+        shards.sort();
+        shards
+    }
+
+    /// Queues prefetching of `keys` of the trie with root `state_root` in the shard.
+    /// Hints are dropped if the shard has no prefetcher, which is created together
+    /// with the first non-view trie of the shard, or once the prefetch queue is full.
+    /// Returns the number of queued hints.
+    pub fn submit_prefetch_hints(
+        &self,
+        shard_uid: ShardUId,
+        state_root: StateRoot,
+        keys: impl IntoIterator<Item = TrieKey>,
+        priority: PrefetchPriority,
+    ) -> usize {
+        let prefetchers = self.0.prefetchers.read().expect(POISONED_LOCK_ERR);
+        let api = match prefetchers.get(&shard_uid) {
+            Some((api, _)) => api,
+            None => return 0,
+        };
+        keys.into_iter()
+            .map_while(|key| api.submit_with_priority(state_root, key, priority).ok())
+            .count()
+    }
+
+    /// Subscribes to evictions of the given keys from the main cache of the shard,
+    /// creating the cache if it doesn't exist yet. See `TrieCache::watch`.
+    pub fn watch_cache_evictions(
+        &self,
+        shard_uid: ShardUId,
+        keys: HashSet<CryptoHash>,
+    ) -> crossbeam::channel::Receiver<CryptoHash> {
+        let mut caches = self.write_caches("watch_cache_evictions", false);
+        caches.entry(shard_uid).or_insert_with(|| self.new_cache(shard_uid, false)).watch(keys)
+    }
+
     pub fn get_store(&self) -> Store {
         self.0.store.clone()
     }
@@ -182,15 +575,46 @@ impl ShardTries {
         &self.0.store.storage
     }
 
+    /// Returns the reference count of the trie node or value `node_hash` of shard
+    /// `shard_uid`, as stored in the `State` column, or 0 if it is not stored.
+    pub fn get_refcount(
+        &self,
+        shard_uid: ShardUId,
+        node_hash: &CryptoHash,
+    ) -> Result<u32, StorageError> {
+        let key = TrieCachingStorage::get_key_from_shard_uid_and_hash(shard_uid, node_hash);
+        let value = self
+            .get_db()
+            .get_raw_bytes(DBCol::State, &key)
+            .map_err(|_| StorageError::StorageInternalError)?;
+        let rc = value.map_or(0, |value| crate::db::refcount::decode_value_with_rc(&value).1);
+        // Entries whose refcount dropped to zero or below stay in the database
+        // until compaction removes them.
+        u32::try_from(rc.max(0)).map_err(|_| {
+            StorageError::StorageInconsistentState(format!(
+                "refcount {rc} of {node_hash} doesn't fit into u32"
+            ))
+        })
+    }
+
     pub(crate) fn update_cache(&self, transaction: &DBTransaction) -> std::io::Result<()> {
-        let mut caches = self.0.caches.write().expect(POISONED_LOCK_ERR);
+        let caches = self.write_caches("update_cache", false);
         let mut shards = HashMap::new();
         for op in &transaction.ops {
             match op {
                 DBOp::UpdateRefcount { col, key, value } => {
                     if *col == DBCol::State {
+                        // A single malformed key shouldn't prevent updating the cache
+                        // with the other changes of the transaction.
                         let (shard_uid, hash) =
-                            TrieCachingStorage::get_shard_uid_and_hash_from_key(key)?;
+                            match TrieCachingStorage::get_shard_uid_and_hash_from_key(key) {
+                                Ok(shard_uid_and_hash) => shard_uid_and_hash,
+                                Err(err) => {
+                                    metrics::SHARD_CACHE_UPDATE_MALFORMED_KEY.inc();
+                                    tracing::warn!(target: "store", key_len = key.len(), %err, "skipping cache update for malformed key");
+                                    continue;
+                                }
+                            };
                         shards
                             .entry(shard_uid)
                             .or_insert(vec![])
@@ -211,19 +635,185 @@ impl ShardTries {
             }
         }
         for (shard_uid, ops) in shards {
-            let cache = caches
-                .entry(shard_uid)
-                .or_insert_with(|| TrieCache::new(&self.0.trie_config, shard_uid, false))
-                .clone();
-            cache.update_cache(ops);
+            // A shard uid without a cache is either not managed by these tries or comes
+            // from another shard version. In both cases there are no cached nodes to
+            // update, and creating a cache here could mix nodes of unrelated shards.
+            match caches.get(&shard_uid) {
+                Some(cache) => cache.update_cache(ops),
+                None => {
+                    metrics::SHARD_CACHE_UPDATE_UNKNOWN_SHARD
+                        .with_label_values(&[
+                            &shard_uid.shard_id.to_string(),
+                            &shard_uid.version.to_string(),
+                        ])
+                        .inc();
+                    tracing::debug!(target: "store", ?shard_uid, "skipping cache update for unknown shard");
+                }
+            }
         }
         Ok(())
     }
 
+    /// Evicts the trie nodes and values deleted by `trie_changes`, which are not reachable
+    /// from `trie_changes.new_root`, from the main and view caches of the shard. Unlike
+    /// `update_cache`, which only puts them into the deletions queue, they are evicted
+    /// right away. Nodes which are inserted back by `trie_changes` stay cached.
+    /// Returns the number of evicted entries.
+    pub fn invalidate_above_root(&self, shard_uid: ShardUId, trie_changes: &TrieChanges) -> usize {
+        let inserted: HashSet<&CryptoHash> =
+            trie_changes.insertions.iter().map(|change| &change.trie_node_or_value_hash).collect();
+        let deleted: Vec<&CryptoHash> = trie_changes
+            .deletions
+            .iter()
+            .map(|change| &change.trie_node_or_value_hash)
+            .filter(|hash| !inserted.contains(hash))
+            .collect();
+        let mut evicted = 0;
+        for caches in [&self.0.caches, &self.0.view_caches] {
+            let caches = caches.read().expect(POISONED_LOCK_ERR);
+            if let Some(cache) = caches.get(&shard_uid) {
+                evicted += cache.remove_all(deleted.iter().copied());
+            }
+        }
+        evicted
+    }
+
+    /// Returns the total size of the values held in the main and view caches
+    /// of all shards, in bytes, and exports it as `near_shard_cache_total_bytes`.
+    /// Doesn't affect the LRU order of the cached entries, but locks all the caches,
+    /// so it is meant to be called periodically, e.g. by the client's log summary.
+    pub fn total_cache_bytes(&self) -> usize {
+        let mut total = 0;
+        for caches in [&self.0.caches, &self.0.view_caches] {
+            let caches = caches.read().expect(POISONED_LOCK_ERR);
+            total +=
+                caches.values().map(|cache| cache.current_total_size() as usize).sum::<usize>();
+        }
+        metrics::SHARD_CACHE_TOTAL_BYTES.set(total as i64);
+        total
+    }
+
+    /// Returns, for every shard with a non-empty main (or view) cache, how long the
+    /// oldest cached node has been in the cache as of `now`. Empty unless the
+    /// caches track the insertion times, see `ShardCacheConfig::track_entry_age`.
+    pub fn oldest_cache_entry_ages(
+        &self,
+        is_view: bool,
+        now: Instant,
+    ) -> HashMap<ShardUId, Duration> {
+        let caches = if is_view { &self.0.view_caches } else { &self.0.caches };
+        let caches = caches.read().expect(POISONED_LOCK_ERR);
+        caches
+            .iter()
+            .filter_map(|(shard_uid, cache)| Some((*shard_uid, cache.oldest_entry_age(now)?)))
+            .collect()
+    }
+
+    /// TEST-ONLY: Returns the first shard and node hash, ordered by shard and then
+    /// by hash, which is cached by only one of `self` and `other`, or by both but
+    /// with different values. Only the main (non-view) caches are compared, and a
+    /// shard without a cache is treated like one with an empty cache.
+    #[cfg(any(test, feature = "test_features"))]
+    pub fn find_cache_divergence(&self, other: &ShardTries) -> Option<(ShardUId, CryptoHash)> {
+        use std::collections::{BTreeMap, BTreeSet};
+        let cached_nodes = |tries: &ShardTries| -> BTreeMap<ShardUId, BTreeMap<CryptoHash, _>> {
+            let caches = tries.0.caches.read().expect(POISONED_LOCK_ERR);
+            caches
+                .iter()
+                .map(|(shard_uid, cache)| (*shard_uid, cache.entries().into_iter().collect()))
+                .collect()
+        };
+        let (ours, theirs) = (cached_nodes(self), cached_nodes(other));
+        let empty = BTreeMap::new();
+        let shard_uids: BTreeSet<&ShardUId> = ours.keys().chain(theirs.keys()).collect();
+        for shard_uid in shard_uids {
+            let ours = ours.get(shard_uid).unwrap_or(&empty);
+            let theirs = theirs.get(shard_uid).unwrap_or(&empty);
+            let hashes: BTreeSet<&CryptoHash> = ours.keys().chain(theirs.keys()).collect();
+            if let Some(hash) = hashes.into_iter().find(|hash| ours.get(hash) != theirs.get(hash)) {
+                return Some((*shard_uid, *hash));
+            }
+        }
+        None
+    }
+
+    /// TEST-ONLY: Panics, reporting the first divergent node hash, unless the main
+    /// caches of `self` and `other` hold the same entries for every shard.
+    /// See `find_cache_divergence`.
+    #[cfg(any(test, feature = "test_features"))]
+    pub fn assert_caches_equivalent(&self, other: &ShardTries) {
+        if let Some((shard_uid, hash)) = self.find_cache_divergence(other) {
+            panic!("shard caches of {shard_uid:?} diverge at node {hash}");
+        }
+    }
+
+    /// Returns the refcount deltas recorded for the trie node or value `node_hash`
+    /// of shard `shard_uid`, as `(block hash, delta)` pairs ordered by block hash.
+    /// A block which both inserted and deleted the node appears twice.
+    ///
+    /// History is only recorded while `TrieConfig::audit_refcounts` is set, and
+    /// only for changes applied on behalf of a block, so changes written with
+    /// `apply_all` (e.g. genesis state) are not included.
+    pub fn refcount_history(
+        &self,
+        shard_uid: ShardUId,
+        node_hash: &CryptoHash,
+    ) -> io::Result<Vec<(CryptoHash, i64)>> {
+        let prefix = TrieCachingStorage::get_key_from_shard_uid_and_hash(shard_uid, node_hash);
+        self.0
+            .store
+            .iter_prefix_ser::<i64>(DBCol::StateRefcountHistory, &prefix)
+            .map(|item| {
+                let (key, delta) = item?;
+                let start = prefix.len();
+                let block_hash = key
+                    .get(start..start + std::mem::size_of::<CryptoHash>())
+                    .and_then(|bytes| CryptoHash::try_from(bytes).ok())
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("invalid StateRefcountHistory key: {key:?}"),
+                        )
+                    })?;
+                Ok((block_hash, delta))
+            })
+            .collect()
+    }
+
+    /// Records refcount deltas of `changes`, applied on behalf of `block_hash`,
+    /// in `DBCol::StateRefcountHistory` if auditing is enabled.
+    fn record_refcount_history(
+        &self,
+        changes: &[TrieRefcountChange],
+        shard_uid: ShardUId,
+        block_hash: Option<&CryptoHash>,
+        is_deletion: bool,
+        store_update: &mut StoreUpdate,
+    ) {
+        let block_hash = match block_hash {
+            Some(block_hash) if self.0.trie_config.audit_refcounts => block_hash,
+            _ => return,
+        };
+        for TrieRefcountChange { trie_node_or_value_hash, rc, .. } in changes.iter() {
+            let mut key = TrieCachingStorage::get_key_from_shard_uid_and_hash(
+                shard_uid,
+                trie_node_or_value_hash,
+            )
+            .to_vec();
+            key.extend_from_slice(block_hash.as_ref());
+            key.push(is_deletion as u8);
+            let delta = if is_deletion { -i64::from(rc.get()) } else { i64::from(rc.get()) };
+            store_update
+                .set_ser(DBCol::StateRefcountHistory, &key, &delta)
+                .expect("Borsh serializer is not expected to ever fail");
+        }
+    }
+
     fn apply_deletions_inner(
         &self,
         deletions: &[TrieRefcountChange],
         shard_uid: ShardUId,
+        block_hash: Option<&CryptoHash>,
         store_update: &mut StoreUpdate,
     ) {
         store_update.set_shard_tries(self);
@@ -234,12 +824,14 @@ impl ShardTries {
             );
             store_update.decrement_refcount_by(DBCol::State, key.as_ref(), *rc);
         }
+        self.record_refcount_history(deletions, shard_uid, block_hash, true, store_update);
     }
 
     fn apply_insertions_inner(
         &self,
         insertions: &[TrieRefcountChange],
         shard_uid: ShardUId,
+        block_hash: Option<&CryptoHash>,
         store_update: &mut StoreUpdate,
     ) {
         store_update.set_shard_tries(self);
@@ -250,8 +842,13 @@ impl ShardTries {
                 shard_uid,
                 trie_node_or_value_hash,
             );
-            store_update.increment_refcount_by(DBCol::State, key.as_ref(), trie_node_or_value, *rc);
+            let value = TrieCachingStorage::encode_stored_value(
+                trie_node_or_value,
+                self.0.trie_config.compress_values_above,
+            );
+            store_update.increment_refcount_by(DBCol::State, key.as_ref(), &value, *rc);
         }
+        self.record_refcount_history(insertions, shard_uid, block_hash, false, store_update);
     }
 
     fn apply_all_inner(
@@ -261,9 +858,9 @@ impl ShardTries {
         apply_deletions: bool,
     ) -> (StoreUpdate, StateRoot) {
         let mut store_update = StoreUpdate::new_with_tries(self.clone());
-        self.apply_insertions_inner(&trie_changes.insertions, shard_uid, &mut store_update);
+        self.apply_insertions_inner(&trie_changes.insertions, shard_uid, None, &mut store_update);
         if apply_deletions {
-            self.apply_deletions_inner(&trie_changes.deletions, shard_uid, &mut store_update);
+            self.apply_deletions_inner(&trie_changes.deletions, shard_uid, None, &mut store_update);
         }
         (store_update, trie_changes.new_root)
     }
@@ -272,6 +869,7 @@ impl ShardTries {
         &self,
         trie_changes: &TrieChanges,
         shard_uid: ShardUId,
+        block_hash: &CryptoHash,
         store_update: &mut StoreUpdate,
     ) {
         // `itoa` is much faster for printing shard_id to a string than trivial alternatives.
@@ -281,13 +879,19 @@ impl ShardTries {
         metrics::APPLIED_TRIE_INSERTIONS
             .with_label_values(&[&shard_id])
             .inc_by(trie_changes.insertions.len() as u64);
-        self.apply_insertions_inner(&trie_changes.insertions, shard_uid, store_update)
+        self.apply_insertions_inner(
+            &trie_changes.insertions,
+            shard_uid,
+            Some(block_hash),
+            store_update,
+        )
     }
 
     pub fn apply_deletions(
         &self,
         trie_changes: &TrieChanges,
         shard_uid: ShardUId,
+        block_hash: &CryptoHash,
         store_update: &mut StoreUpdate,
     ) {
         // `itoa` is much faster for printing shard_id to a string than trivial alternatives.
@@ -297,13 +901,19 @@ impl ShardTries {
         metrics::APPLIED_TRIE_DELETIONS
             .with_label_values(&[&shard_id])
             .inc_by(trie_changes.deletions.len() as u64);
-        self.apply_deletions_inner(&trie_changes.deletions, shard_uid, store_update)
+        self.apply_deletions_inner(
+            &trie_changes.deletions,
+            shard_uid,
+            Some(block_hash),
+            store_update,
+        )
     }
 
     pub fn revert_insertions(
         &self,
         trie_changes: &TrieChanges,
         shard_uid: ShardUId,
+        block_hash: &CryptoHash,
         store_update: &mut StoreUpdate,
     ) {
         // `itoa` is much faster for printing shard_id to a string than trivial alternatives.
@@ -313,7 +923,12 @@ impl ShardTries {
         metrics::REVERTED_TRIE_INSERTIONS
             .with_label_values(&[&shard_id])
             .inc_by(trie_changes.insertions.len() as u64);
-        self.apply_deletions_inner(&trie_changes.insertions, shard_uid, store_update)
+        self.apply_deletions_inner(
+            &trie_changes.insertions,
+            shard_uid,
+            Some(block_hash),
+            store_update,
+        )
     }
 
     pub fn apply_all(
@@ -321,16 +936,119 @@ impl ShardTries {
         trie_changes: &TrieChanges,
         shard_uid: ShardUId,
     ) -> (StoreUpdate, StateRoot) {
-        self.apply_all_inner(trie_changes, shard_uid, true)
+        #[cfg(any(test, feature = "test_features"))]
+        let guard = self.0.serialize_writes.load(Ordering::Relaxed).then(|| {
+            let lock = self
+                .0
+                .shard_write_locks
+                .lock()
+                .expect(POISONED_LOCK_ERR)
+                .entry(shard_uid)
+                .or_default()
+                .clone();
+            lock.acquire()
+        });
+        #[allow(unused_mut)]
+        let (mut store_update, root) = self.apply_all_inner(trie_changes, shard_uid, true);
+        #[cfg(any(test, feature = "test_features"))]
+        store_update.shard_write_guards.extend(guard);
+        (store_update, root)
+    }
+
+    /// TEST-ONLY: Makes `apply_all` wait until the updates returned by the previous calls
+    /// for the same shard are committed or dropped, so that concurrent writers can't
+    /// interleave their cache and database updates. A thread which calls `apply_all`
+    /// twice for the same shard before committing the first update deadlocks.
+    /// Off by default, since it would limit the throughput of block processing.
+    #[cfg(any(test, feature = "test_features"))]
+    pub fn serialize_writes(&self) {
+        self.0.serialize_writes.store(true, Ordering::Relaxed);
     }
 }
 
+/// Depth below the root of the subtrees which `ExportReadAhead` reads ahead.
+/// With 16 children per branch, it splits large tries into tens of thousands
+/// of subtrees, each of which is small enough to be read in a moment.
+const EXPORT_READ_AHEAD_DEPTH: u32 = 4;
+
+/// Tracks the position of a trie export among the subtrees `EXPORT_READ_AHEAD_DEPTH`
+/// levels below the root, and queues the subtrees following the current one
+/// for prefetching.
+struct ExportReadAhead {
+    /// Nibble paths and hashes of the subtree roots, in key order.
+    subtrees: Vec<(Vec<u8>, CryptoHash)>,
+    /// Index of the subtree which contains the last exported key, or the first
+    /// subtree after it.
+    current: usize,
+    /// Number of subtrees queued for prefetching so far, counted from the first one.
+    submitted: usize,
+    window: usize,
+}
+
+impl ExportReadAhead {
+    fn new(trie: &Trie, window: usize) -> Result<Self, StorageError> {
+        let mut nodes = vec![];
+        trie.traverse_to_depth(EXPORT_READ_AHEAD_DEPTH, |path, hash| {
+            nodes.push((path.to_vec(), hash))
+        })?;
+        // Nodes are visited depth first, so a node which was descended into is
+        // followed by its first child, whose path extends the path of the node.
+        let subtrees = (0..nodes.len())
+            .filter(|&i| match nodes.get(i + 1) {
+                Some((next, _)) => {
+                    !(next.len() > nodes[i].0.len() && next.starts_with(&nodes[i].0))
+                }
+                None => true,
+            })
+            .map(|i| nodes[i].clone())
+            .collect();
+        Ok(Self { subtrees, current: 0, submitted: 0, window })
+    }
+
+    /// Moves past the subtrees which precede `key` and queues the `window`
+    /// subtrees following the current one. Keys must be passed in order.
+    fn advance(&mut self, prefetch_api: &PrefetchApi, key: &[u8]) {
+        let key: Vec<u8> = NibbleSlice::new(key).iter().collect();
+        while let Some((path, _)) = self.subtrees.get(self.current) {
+            if key.starts_with(path) || path > &key {
+                break;
+            }
+            self.current += 1;
+        }
+        // The current subtree is being read by the export itself.
+        self.submitted = self.submitted.max(self.current + 1);
+        let end = std::cmp::min(self.current + 1 + self.window, self.subtrees.len());
+        while self.submitted < end {
+            let (_, hash) = self.subtrees[self.submitted];
+            if prefetch_api.prefetch_subtree(hash, PrefetchPriority::Low).is_err() {
+                // The queue is full, try again with the next key.
+                break;
+            }
+            self.submitted += 1;
+        }
+    }
+}
+
+/// Format in which `WrappedTrieChanges::trie_changes_into_with_format` persists
+/// trie changes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SerializationFormat {
+    /// Borsh in `DBCol::TrieChanges`, which is what garbage collection reads.
+    #[default]
+    Borsh,
+    /// Borsh as above, plus a human-readable dump in `DBCol::TrieChangesDebug`.
+    #[cfg(feature = "debug_trie_changes")]
+    Json,
+}
+
 pub struct WrappedTrieChanges {
     tries: ShardTries,
     shard_uid: ShardUId,
     trie_changes: TrieChanges,
     state_changes: Vec<RawStateChangesWithTrieKey>,
     block_hash: CryptoHash,
+    /// See `TrieConfig::skip_state_changes`.
+    skip_state_changes: bool,
 }
 
 impl WrappedTrieChanges {
@@ -341,7 +1059,15 @@ impl WrappedTrieChanges {
         state_changes: Vec<RawStateChangesWithTrieKey>,
         block_hash: CryptoHash,
     ) -> Self {
-        WrappedTrieChanges { tries, shard_uid, trie_changes, state_changes, block_hash }
+        let skip_state_changes = tries.0.trie_config.skip_state_changes;
+        WrappedTrieChanges {
+            tries,
+            shard_uid,
+            trie_changes,
+            state_changes,
+            block_hash,
+            skip_state_changes,
+        }
     }
 
     pub fn state_changes(&self) -> &[RawStateChangesWithTrieKey] {
@@ -350,18 +1076,33 @@ impl WrappedTrieChanges {
 
     /// Save insertions of trie nodes into Store.
     pub fn insertions_into(&self, store_update: &mut StoreUpdate) {
-        self.tries.apply_insertions(&self.trie_changes, self.shard_uid, store_update)
+        self.tries.apply_insertions(
+            &self.trie_changes,
+            self.shard_uid,
+            &self.block_hash,
+            store_update,
+        )
     }
 
     /// Save deletions of trie nodes into Store.
     pub fn deletions_into(&self, store_update: &mut StoreUpdate) {
-        self.tries.apply_deletions(&self.trie_changes, self.shard_uid, store_update)
+        self.tries.apply_deletions(
+            &self.trie_changes,
+            self.shard_uid,
+            &self.block_hash,
+            store_update,
+        )
     }
 
     /// Save state changes into Store.
+    /// Nothing is saved if `TrieConfig::skip_state_changes` is set.
     ///
     /// NOTE: the changes are drained from `self`.
     pub fn state_changes_into(&mut self, store_update: &mut StoreUpdate) {
+        if self.skip_state_changes {
+            self.state_changes.clear();
+            return;
+        }
         for change_with_trie_key in self.state_changes.drain(..) {
             assert!(
                 !change_with_trie_key.changes.iter().any(|RawStateChange { cause, .. }| matches!(
@@ -380,8 +1121,9 @@ impl WrappedTrieChanges {
             );
 
             // Filtering trie keys for user facing RPC reporting.
-            // NOTE: If the trie key is not one of the account specific, it may cause key conflict
-            // when the node tracks multiple shards. See #2563.
+            // NOTE: The storage key is suffixed with the shard, so the same trie key changed in
+            // multiple tracked shards doesn't conflict anymore (see #2563), but the RPC only
+            // knows how to report the account specific keys.
             match &change_with_trie_key.trie_key {
                 TrieKey::Account { .. }
                 | TrieKey::ContractCode { .. }
@@ -389,8 +1131,11 @@ impl WrappedTrieChanges {
                 | TrieKey::ContractData { .. } => {}
                 _ => continue,
             };
-            let storage_key =
-                KeyForStateChanges::from_trie_key(&self.block_hash, &change_with_trie_key.trie_key);
+            let storage_key = KeyForStateChanges::from_trie_key_for_shard(
+                &self.block_hash,
+                &change_with_trie_key.trie_key,
+                self.shard_uid,
+            );
             store_update.set(
                 DBCol::StateChanges,
                 storage_key.as_ref(),
@@ -399,19 +1144,71 @@ impl WrappedTrieChanges {
         }
     }
 
+    /// Saves trie node insertions, state changes and trie changes into Store,
+    /// in this order. Trie nodes referenced by the saved trie changes have to be
+    /// already persisted when the trie changes get read after a crash, so prefer
+    /// this method over calling the individual `*_into` methods.
+    /// Deletions are not applied, see `deletions_into`.
+    pub fn persist(mut self, store_update: &mut StoreUpdate) -> io::Result<()> {
+        self.insertions_into(store_update);
+        self.state_changes_into(store_update);
+        self.trie_changes_into(store_update)
+    }
+
+    /// Same as `persist`, but without saving the trie changes, for stores which
+    /// don't keep them.
+    pub fn persist_without_trie_changes(mut self, store_update: &mut StoreUpdate) {
+        self.insertions_into(store_update);
+        self.state_changes_into(store_update);
+    }
+
+    /// Mapping of the new state root to the block, to be recorded once the trie
+    /// changes have been committed, see `StagedStateRootBlock`.
+    pub fn staged_state_root_block(&self) -> StagedStateRootBlock {
+        StagedStateRootBlock {
+            tries: self.tries.clone(),
+            shard_uid: self.shard_uid,
+            state_root: self.trie_changes.new_root,
+            block_hash: self.block_hash,
+        }
+    }
+
     pub fn trie_changes_into(&mut self, store_update: &mut StoreUpdate) -> io::Result<()> {
-        store_update.set_ser(
-            DBCol::TrieChanges,
-            &shard_layout::get_block_shard_uid(&self.block_hash, &self.shard_uid),
-            &self.trie_changes,
-        )
+        self.trie_changes_into_with_format(store_update, SerializationFormat::default())
+    }
+
+    /// Same as `trie_changes_into`, but with the given serialization `format`.
+    pub fn trie_changes_into_with_format(
+        &mut self,
+        store_update: &mut StoreUpdate,
+        format: SerializationFormat,
+    ) -> io::Result<()> {
+        let key = shard_layout::get_block_shard_uid(&self.block_hash, &self.shard_uid);
+        match format {
+            SerializationFormat::Borsh => {}
+            #[cfg(feature = "debug_trie_changes")]
+            SerializationFormat::Json => {
+                let json = serde_json::to_vec_pretty(&self.trie_changes)?;
+                store_update.set(DBCol::TrieChangesDebug, &key, &json);
+            }
+        }
+        store_update.set_ser(DBCol::TrieChanges, &key, &self.trie_changes)
     }
 }
 
+/// Key of `DBCol::StateChanges`: `block_hash || trie_key || shard_uid`.
+///
+/// The shard suffix was added to tell apart the changes of the same trie key
+/// in different shards; keys written by older versions don't have it. Since
+/// the suffix comes last, lookups by `block_hash || trie_key` prefix find
+/// both kinds of keys. The trie key is stored in the value as well, which is
+/// what makes the suffix unambiguous to split off, see `Self::split_shard`.
 #[derive(derive_more::AsRef, derive_more::Into)]
 pub struct KeyForStateChanges(Vec<u8>);
 
 impl KeyForStateChanges {
+    const SHARD_SUFFIX_LEN: usize = std::mem::size_of::<ShardUId>();
+
     fn estimate_prefix_len() -> usize {
         std::mem::size_of::<CryptoHash>()
     }
@@ -439,6 +1236,31 @@ impl KeyForStateChanges {
         key
     }
 
+    /// Full key under which the changes of `trie_key` in the shard `shard_uid` are stored.
+    pub fn from_trie_key_for_shard(
+        block_hash: &CryptoHash,
+        trie_key: &TrieKey,
+        shard_uid: ShardUId,
+    ) -> Self {
+        let mut key = Self::new(block_hash, trie_key.len() + Self::SHARD_SUFFIX_LEN);
+        trie_key.append_into(&mut key.0);
+        key.0.extend(shard_uid.to_bytes());
+        key
+    }
+
+    /// Splits the shard off `key_suffix` (the storage key without the block hash)
+    /// of an entry holding the changes of `trie_key`.
+    /// Returns `Some(None)` for legacy keys which have no shard, and `None`
+    /// if the key doesn't match `trie_key`.
+    fn split_shard(key_suffix: &[u8], trie_key: &TrieKey) -> Option<Option<ShardUId>> {
+        let shard = key_suffix.strip_prefix(&trie_key.to_vec()[..])?;
+        match shard.len() {
+            0 => Some(None),
+            Self::SHARD_SUFFIX_LEN => Some(Some(ShardUId::try_from(shard).ok()?)),
+            _ => None,
+        }
+    }
+
     pub fn find_iter<'a>(
         &'a self,
         store: &'a Store,
@@ -476,4 +1298,1118 @@ impl KeyForStateChanges {
             }
         })
     }
+
+    /// Iterates over state changes under this prefix, pairing each with its trie key.
+    /// Entries which fail to deserialize, or whose storage key doesn't match the
+    /// trie key embedded in the value, are skipped and counted in
+    /// `near_state_changes_malformed_entries`. Database errors are returned as is.
+    pub fn iter_decoded<'a>(
+        &'a self,
+        store: &'a Store,
+    ) -> impl Iterator<Item = Result<(TrieKey, RawStateChangesWithTrieKey), std::io::Error>> + 'a
+    {
+        let prefix_len = Self::estimate_prefix_len();
+        debug_assert!(self.0.len() >= prefix_len);
+        store.iter_prefix(DBCol::StateChanges, &self.0).filter_map(move |item| {
+            let (key, value) = match item {
+                Ok(item) => item,
+                Err(err) => return Some(Err(err)),
+            };
+            let state_changes = match RawStateChangesWithTrieKey::try_from_slice(&value) {
+                Ok(state_changes) => state_changes,
+                Err(_) => {
+                    metrics::STATE_CHANGES_MALFORMED_ENTRIES.inc();
+                    return None;
+                }
+            };
+            if Self::split_shard(&key[prefix_len..], &state_changes.trie_key).is_none() {
+                metrics::STATE_CHANGES_MALFORMED_ENTRIES.inc();
+                return None;
+            }
+            Some(Ok((state_changes.trie_key.clone(), state_changes)))
+        })
+    }
+
+    /// Same as `find_iter`, but returns only the changes made in the shard `shard_uid`.
+    /// Entries written by older versions don't record the shard and are skipped,
+    /// use `find_iter` to get them.
+    pub fn find_iter_for_shard<'a>(
+        &'a self,
+        store: &'a Store,
+        shard_uid: ShardUId,
+    ) -> impl Iterator<Item = Result<RawStateChangesWithTrieKey, std::io::Error>> + 'a {
+        let prefix_len = Self::estimate_prefix_len();
+        debug_assert!(self.0.len() >= prefix_len);
+        store.iter_prefix(DBCol::StateChanges, &self.0).filter_map(move |item| {
+            let (key, value) = match item {
+                Ok(item) => item,
+                Err(err) => return Some(Err(err)),
+            };
+            let state_changes = match RawStateChangesWithTrieKey::try_from_slice(&value) {
+                Ok(state_changes) => state_changes,
+                Err(err) => return Some(Err(err)),
+            };
+            match Self::split_shard(&key[prefix_len..], &state_changes.trie_key) {
+                Some(Some(shard)) if shard == shard_uid => Some(Ok(state_changes)),
+                _ => None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{create_test_store, create_tries, test_populate_trie};
+    use near_primitives::hash::hash;
+
+    fn shard_tries_for(trie_config: TrieConfig, shard_uids: &[ShardUId]) -> ShardTries {
+        let store = create_test_store();
+        ShardTries::new(store.clone(), trie_config, shard_uids, FlatStateFactory::new(store))
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "duplicate shard uid")]
+    fn test_new_duplicate_shard_uids() {
+        let shard_uid = ShardUId { shard_id: 0, version: 1 };
+        shard_tries_for(TrieConfig::default(), &[shard_uid, ShardUId::single_shard(), shard_uid]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "shard uids of different versions")]
+    fn test_new_mixed_shard_versions() {
+        let shard_uids =
+            [ShardUId { shard_id: 0, version: 0 }, ShardUId { shard_id: 1, version: 1 }];
+        shard_tries_for(TrieConfig::default(), &shard_uids);
+    }
+
+    #[test]
+    fn test_new_mixed_shard_versions_allowed() {
+        let shard_uids =
+            [ShardUId { shard_id: 0, version: 0 }, ShardUId { shard_id: 0, version: 1 }];
+        let trie_config = TrieConfig { allow_mixed_shard_versions: true, ..TrieConfig::default() };
+        let tries = shard_tries_for(trie_config, &shard_uids);
+        assert_eq!(2, tries.0.caches.read().unwrap().len());
+    }
+
+    #[test]
+    fn test_caches_lock_contention() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let histogram = metrics::SHARD_CACHE_LOCK_WAIT.with_label_values(&["get_trie", "0"]);
+        let count_before = histogram.get_sample_count();
+
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let holder = {
+            let tries = tries.clone();
+            std::thread::spawn(move || {
+                let _caches = tries.0.caches.write().unwrap();
+                locked_tx.send(()).unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+            })
+        };
+        locked_rx.recv().unwrap();
+        tries.get_trie_for_shard(shard_uid, Trie::EMPTY_ROOT);
+        holder.join().unwrap();
+
+        assert!(histogram.get_sample_count() > count_before);
+        assert!(histogram.get_sample_sum() >= 0.01);
+    }
+
+    #[test]
+    fn test_update_cache_unknown_shard_uid() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let unknown_shard_uid = ShardUId { shard_id: 0, version: 1 };
+        let value = b"value";
+        let hash = hash(value);
+        let refcounted = crate::db::refcount::add_positive_refcount(
+            value,
+            std::num::NonZeroU32::new(1).unwrap(),
+        );
+
+        let mut transaction = DBTransaction::new();
+        transaction.update_refcount(
+            DBCol::State,
+            TrieCachingStorage::get_key_from_shard_uid_and_hash(unknown_shard_uid, &hash).to_vec(),
+            refcounted,
+        );
+        let unknown_counter =
+            metrics::SHARD_CACHE_UPDATE_UNKNOWN_SHARD.with_label_values(&["0", "1"]);
+        let unknown_before = unknown_counter.get();
+        tries.update_cache(&transaction).unwrap();
+
+        let caches = tries.0.caches.read().unwrap();
+        assert!(!caches.contains_key(&unknown_shard_uid));
+        assert_eq!(None, caches[&shard_uid].get(&hash));
+        assert_eq!(unknown_before + 1, unknown_counter.get());
+    }
+
+    #[test]
+    fn test_update_cache_malformed_key() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let refcounted = |value: &[u8]| {
+            crate::db::refcount::add_positive_refcount(value, std::num::NonZeroU32::new(1).unwrap())
+        };
+
+        let mut transaction = DBTransaction::new();
+        transaction.update_refcount(
+            DBCol::State,
+            TrieCachingStorage::get_key_from_shard_uid_and_hash(shard_uid, &hash(b"first"))
+                .to_vec(),
+            refcounted(b"first"),
+        );
+        transaction.update_refcount(DBCol::State, b"malformed".to_vec(), refcounted(b"bad"));
+        transaction.update_refcount(
+            DBCol::State,
+            TrieCachingStorage::get_key_from_shard_uid_and_hash(shard_uid, &hash(b"second"))
+                .to_vec(),
+            refcounted(b"second"),
+        );
+        let malformed_before = metrics::SHARD_CACHE_UPDATE_MALFORMED_KEY.get();
+        tries.update_cache(&transaction).unwrap();
+
+        let caches = tries.0.caches.read().unwrap();
+        assert_eq!(Some(b"first".as_slice().into()), caches[&shard_uid].get(&hash(b"first")));
+        assert_eq!(Some(b"second".as_slice().into()), caches[&shard_uid].get(&hash(b"second")));
+        assert_eq!(2, caches[&shard_uid].len());
+        assert!(metrics::SHARD_CACHE_UPDATE_MALFORMED_KEY.get() > malformed_before);
+    }
+
+    #[test]
+    fn test_total_cache_bytes() {
+        let tries = crate::test_utils::create_tries_complex(0, 3);
+        assert_eq!(0, tries.total_cache_bytes());
+
+        let mut expected = 0;
+        for caches in [&tries.0.caches, &tries.0.view_caches] {
+            for (i, cache) in caches.read().unwrap().values().enumerate() {
+                let values: Vec<_> = (0..=i)
+                    .map(|j| {
+                        let value = vec![j as u8; 10 * (i + 1)];
+                        expected += value.len();
+                        let refcounted = crate::db::refcount::add_positive_refcount(
+                            &value,
+                            std::num::NonZeroU32::new(1).unwrap(),
+                        );
+                        (hash(&value), refcounted)
+                    })
+                    .collect();
+                cache.update_cache(
+                    values.iter().map(|(hash, value)| (*hash, Some(value.as_slice()))).collect(),
+                );
+            }
+        }
+
+        let stats: u64 = [&tries.0.caches, &tries.0.view_caches]
+            .iter()
+            .flat_map(|caches| {
+                caches
+                    .read()
+                    .unwrap()
+                    .values()
+                    .map(|cache| cache.current_total_size())
+                    .collect::<Vec<_>>()
+            })
+            .sum();
+        assert_eq!(expected, tries.total_cache_bytes());
+        assert_eq!(stats as usize, tries.total_cache_bytes());
+    }
+
+    #[test]
+    fn test_get_refcount() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let changes = vec![(b"key".to_vec(), Some(b"value".to_vec()))];
+        let trie_changes =
+            tries.get_trie_for_shard(shard_uid, Trie::EMPTY_ROOT).update(changes).unwrap();
+        let root = trie_changes.new_root;
+        assert_eq!(0, tries.get_refcount(shard_uid, &root).unwrap());
+
+        for want in 1..=3 {
+            let mut store_update = StoreUpdate::new_with_tries(tries.clone());
+            tries.apply_insertions(&trie_changes, shard_uid, &hash(&[want]), &mut store_update);
+            store_update.commit().unwrap();
+            assert_eq!(u32::from(want), tries.get_refcount(shard_uid, &root).unwrap());
+        }
+        assert_eq!(0, tries.get_refcount(ShardUId { shard_id: 1, version: 1 }, &root).unwrap());
+
+        let mut store_update = StoreUpdate::new_with_tries(tries.clone());
+        tries.revert_insertions(&trie_changes, shard_uid, &hash(&[3]), &mut store_update);
+        store_update.commit().unwrap();
+        assert_eq!(2, tries.get_refcount(shard_uid, &root).unwrap());
+        // Auditing is disabled by default.
+        assert_eq!(
+            Vec::<(CryptoHash, i64)>::new(),
+            tries.refcount_history(shard_uid, &root).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_refcount_history() {
+        let shard_uid = ShardUId::single_shard();
+        let trie_config = TrieConfig { audit_refcounts: true, ..TrieConfig::default() };
+        let tries = shard_tries_for(trie_config, &[shard_uid]);
+        let block1 = hash(b"block1");
+        let block2 = hash(b"block2");
+        let fork = hash(b"fork");
+
+        let changes1 = vec![
+            (b"alice".to_vec(), Some(b"1".to_vec())),
+            (b"bob".to_vec(), Some(b"2".to_vec())),
+            (b"carol".to_vec(), Some(b"3".to_vec())),
+        ];
+        let trie_changes1 =
+            tries.get_trie_for_shard(shard_uid, Trie::EMPTY_ROOT).update(changes1).unwrap();
+        let mut store_update = StoreUpdate::new_with_tries(tries.clone());
+        tries.apply_insertions(&trie_changes1, shard_uid, &block1, &mut store_update);
+        store_update.commit().unwrap();
+
+        let changes2 = vec![(b"bob".to_vec(), Some(b"20".to_vec())), (b"carol".to_vec(), None)];
+        let trie_changes2 =
+            tries.get_trie_for_shard(shard_uid, trie_changes1.new_root).update(changes2).unwrap();
+        let mut store_update = StoreUpdate::new_with_tries(tries.clone());
+        tries.apply_insertions(&trie_changes2, shard_uid, &block2, &mut store_update);
+        store_update.commit().unwrap();
+
+        // A fork block reaching the same state as block1, which is later
+        // garbage collected.
+        let mut store_update = StoreUpdate::new_with_tries(tries.clone());
+        tries.apply_insertions(&trie_changes1, shard_uid, &fork, &mut store_update);
+        store_update.commit().unwrap();
+        let mut store_update = StoreUpdate::new_with_tries(tries.clone());
+        tries.revert_insertions(&trie_changes1, shard_uid, &fork, &mut store_update);
+        store_update.commit().unwrap();
+
+        // Garbage collection of canonical block2 removes the state it replaced.
+        let mut store_update = StoreUpdate::new_with_tries(tries.clone());
+        tries.apply_deletions(&trie_changes2, shard_uid, &block2, &mut store_update);
+        store_update.commit().unwrap();
+
+        let nodes: HashSet<CryptoHash> = [&trie_changes1, &trie_changes2]
+            .iter()
+            .flat_map(|changes| changes.insertions.iter().chain(changes.deletions.iter()))
+            .map(|change| change.trie_node_or_value_hash)
+            .collect();
+        for node in &nodes {
+            let history = tries.refcount_history(shard_uid, node).unwrap();
+            assert!(!history.is_empty());
+            let total: i64 = history.iter().map(|(_, delta)| delta).sum();
+            assert_eq!(total, i64::from(tries.get_refcount(shard_uid, node).unwrap()), "{node}");
+        }
+
+        let root1 = trie_changes1.new_root;
+        let mut history = tries.refcount_history(shard_uid, &root1).unwrap();
+        history.sort();
+        let mut want = vec![(block1, 1), (block2, -1), (fork, 1), (fork, -1)];
+        want.sort();
+        assert_eq!(want, history);
+        assert_eq!(0, tries.get_refcount(shard_uid, &root1).unwrap());
+    }
+
+    #[test]
+    fn test_assert_caches_equivalent() {
+        let shard_uid = ShardUId::single_shard();
+        let changes: Vec<_> = (0..10u8).map(|i| (vec![i; 3], Some(vec![i; 10]))).collect();
+        let tries = create_tries();
+        let other = create_tries();
+        let root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes.clone());
+        let other_root = test_populate_trie(&other, &Trie::EMPTY_ROOT, shard_uid, changes);
+        assert_eq!(root, other_root);
+        assert_eq!(None, tries.find_cache_divergence(&other));
+        tries.assert_caches_equivalent(&other);
+
+        let changes = vec![(b"divergent".to_vec(), Some(b"value".to_vec()))];
+        test_populate_trie(&other, &root, shard_uid, changes);
+        let (divergent_shard, hash) = tries.find_cache_divergence(&other).unwrap();
+        assert_eq!(shard_uid, divergent_shard);
+        assert!(tries.0.caches.read().unwrap()[&shard_uid].get(&hash).is_none());
+        assert!(other.0.caches.read().unwrap()[&shard_uid].get(&hash).is_some());
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            tries.assert_caches_equivalent(&other)
+        }))
+        .unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains(&hash.to_string()), "{msg}");
+    }
+
+    #[test]
+    fn test_lookup_depth_metric() {
+        // A shard no other test uses, since the histogram is global.
+        let tries = crate::test_utils::create_tries_complex(0, 7);
+        let shard_uid = ShardUId { version: 0, shard_id: 6 };
+        // The keys differ in the first nibble, so the root is a branch with leaf children.
+        let changes = vec![(vec![0x10], Some(b"a".to_vec())), (vec![0x20], Some(b"b".to_vec()))];
+        // Not test_populate_trie, which only supports shard 0.
+        let trie_changes =
+            tries.get_trie_for_shard(shard_uid, Trie::EMPTY_ROOT).update(changes).unwrap();
+        let (store_update, root) = tries.apply_all(&trie_changes, shard_uid);
+        store_update.commit().unwrap();
+
+        let histogram = metrics::TRIE_LOOKUP_DEPTH.with_label_values(&["6"]);
+        let (count, sum) = (histogram.get_sample_count(), histogram.get_sample_sum());
+        let trie = tries.get_trie_for_shard(shard_uid, root);
+        assert_eq!(Some(b"a".to_vec()), trie.get(&[0x10]).unwrap());
+        assert_eq!(count + 1, histogram.get_sample_count());
+        assert_eq!(sum + 2.0, histogram.get_sample_sum());
+        // A missing key whose nibble has no child in the root branch stops at the root.
+        assert_eq!(None, trie.get(&[0x30]).unwrap());
+        assert_eq!(count + 2, histogram.get_sample_count());
+        assert_eq!(sum + 3.0, histogram.get_sample_sum());
+    }
+
+    #[test]
+    fn test_serialize_writes() {
+        let tries = create_tries();
+        tries.serialize_writes();
+        let shard_uid = ShardUId::single_shard();
+        let value = b"value".to_vec();
+        let value_hash = hash(&value);
+        let change = TrieRefcountChange {
+            trie_node_or_value_hash: value_hash,
+            trie_node_or_value: value,
+            rc: std::num::NonZeroU32::new(1).unwrap(),
+        };
+        let insert = TrieChanges {
+            old_root: Trie::EMPTY_ROOT,
+            new_root: Trie::EMPTY_ROOT,
+            insertions: vec![change.clone()],
+            deletions: vec![],
+        };
+        let delete = TrieChanges { insertions: vec![], deletions: vec![change], ..insert.clone() };
+
+        // Every thread inserts the same value and deletes it again. The update of one thread
+        // has to be committed before another thread gets its own, and in the end the
+        // value is gone from the database.
+        let writing = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        for changes in [&insert, &delete] {
+                            let (store_update, _) = tries.apply_all(changes, shard_uid);
+                            assert!(!writing.swap(true, Ordering::SeqCst), "concurrent writes");
+                            std::thread::yield_now();
+                            writing.store(false, Ordering::SeqCst);
+                            store_update.commit().unwrap();
+                        }
+                    }
+                });
+            }
+        });
+
+        let key = TrieCachingStorage::get_key_from_shard_uid_and_hash(shard_uid, &value_hash);
+        assert_eq!(None, tries.get_store().get(DBCol::State, &key).unwrap());
+        // Writes are serialized per shard, so a pending update for another shard doesn't
+        // block this one.
+        let other_shard = ShardUId { version: shard_uid.version, shard_id: 1 };
+        let (_pending, _) = tries.apply_all(&insert, other_shard);
+        tries.apply_all(&insert, shard_uid).0.commit().unwrap();
+    }
+
+    #[test]
+    fn test_get_trie_for_shard_bypass_cache() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let changes: Vec<_> =
+            (0..10u8).map(|i| (vec![i; 3], Some(vec![i; 100 + i as usize]))).collect();
+        let root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes.clone());
+        let cache = tries.0.caches.read().unwrap()[&shard_uid].clone();
+        let read_all = |trie: Trie| {
+            for (key, value) in &changes {
+                assert_eq!(value, &trie.get(key).unwrap());
+            }
+        };
+
+        cache.clear();
+        read_all(tries.get_trie_for_shard_bypass_cache(shard_uid, root));
+        assert_eq!(0, cache.len());
+        assert_eq!(0, cache.current_total_size());
+
+        read_all(tries.get_trie_for_shard(shard_uid, root));
+        let (len, size) = (cache.len(), cache.current_total_size());
+        assert!(len > 0);
+        read_all(tries.get_trie_for_shard_bypass_cache(shard_uid, root));
+        assert_eq!((len, size), (cache.len(), cache.current_total_size()));
+    }
+
+    #[test]
+    fn test_value_compression() {
+        let shard_uid = ShardUId::single_shard();
+        let trie_config = TrieConfig { compress_values_above: Some(100), ..TrieConfig::default() };
+        let tries = shard_tries_for(trie_config, &[shard_uid]);
+        let small = b"small".to_vec();
+        // Starts with the compression tag, but is too short to be compressed.
+        let tagged = vec![0xff, 1, 2, 3];
+        // Compressed, but small enough to be put into the shard cache.
+        let medium = vec![1; 500];
+        let large = vec![2; 10_000];
+        let changes = vec![
+            (b"small".to_vec(), Some(small.clone())),
+            (b"tagged".to_vec(), Some(tagged.clone())),
+            (b"medium".to_vec(), Some(medium.clone())),
+            (b"large".to_vec(), Some(large.clone())),
+        ];
+        let root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes.clone());
+
+        let stored = |value: &[u8]| {
+            let key = TrieCachingStorage::get_key_from_shard_uid_and_hash(shard_uid, &hash(value));
+            tries.get_store().get(DBCol::State, &key).unwrap().unwrap().to_vec()
+        };
+        assert_eq!(small, stored(&small));
+        assert_eq!(tagged, stored(&tagged));
+        for value in [&medium, &large] {
+            let stored = stored(value);
+            assert_eq!(0xff, stored[0]);
+            assert!(stored.len() < value.len());
+        }
+        let cache = tries.0.caches.read().unwrap()[&shard_uid].clone();
+        assert_eq!(Some(medium.clone().into()), cache.get(&hash(&medium)));
+
+        cache.clear();
+        let trie = tries.get_trie_for_shard(shard_uid, root);
+        for (key, value) in &changes {
+            assert_eq!(value, &trie.get(key).unwrap());
+        }
+
+        // Values written without compression are read the same way.
+        let raw_tries = shard_tries_for(TrieConfig::default(), &[shard_uid]);
+        let root = test_populate_trie(&raw_tries, &Trie::EMPTY_ROOT, shard_uid, changes.clone());
+        let trie = raw_tries.get_trie_for_shard(shard_uid, root);
+        for (key, value) in &changes {
+            assert_eq!(value, &trie.get(key).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_invalidate_above_root() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let changes = vec![
+            (b"aaa".to_vec(), Some(b"old".to_vec())),
+            (b"bbb".to_vec(), Some(b"kept".to_vec())),
+            (b"ccc".to_vec(), Some(b"deleted".to_vec())),
+        ];
+        let old_root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes);
+        let trie_changes = tries
+            .get_trie_for_shard(shard_uid, old_root)
+            .update(vec![(b"aaa".to_vec(), Some(b"new".to_vec())), (b"ccc".to_vec(), None)])
+            .unwrap();
+        let (store_update, new_root) = tries.apply_all(&trie_changes, shard_uid);
+        store_update.commit().unwrap();
+
+        let cache = tries.0.caches.read().unwrap()[&shard_uid].clone();
+        for value in [&b"old"[..], b"kept", b"deleted"] {
+            assert!(cache.get(&hash(value)).is_some());
+        }
+        assert!(cache.get(&old_root).is_some());
+        let size_before = cache.current_total_size();
+
+        let evicted = tries.invalidate_above_root(shard_uid, &trie_changes);
+        assert!(evicted >= 3);
+        assert_eq!(None, cache.get(&old_root));
+        assert_eq!(None, cache.get(&hash(b"old")));
+        assert_eq!(None, cache.get(&hash(b"deleted")));
+        assert!(cache.get(&hash(b"kept")).is_some());
+        assert!(cache.get(&new_root).is_some());
+        assert!(cache.current_total_size() < size_before);
+
+        let trie = tries.get_trie_for_shard(shard_uid, new_root);
+        assert_eq!(Some(b"new".to_vec()), trie.get(b"aaa").unwrap());
+        assert_eq!(Some(b"kept".to_vec()), trie.get(b"bbb").unwrap());
+        assert_eq!(None, trie.get(b"ccc").unwrap());
+    }
+
+    #[test]
+    fn test_export_shard() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let changes: Vec<(Vec<u8>, Option<Vec<u8>>)> = (0..100u32)
+            .map(|i| (format!("key{i}").into_bytes(), Some(i.to_le_bytes().to_vec())))
+            .collect();
+        let root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes.clone());
+
+        let mut exported = vec![];
+        tries.export_shard(shard_uid, root, |key, value| exported.push((key, value))).unwrap();
+        let mut expected: Vec<_> =
+            changes.into_iter().map(|(key, value)| (key, value.unwrap())).collect();
+        expected.sort();
+        assert_eq!(expected, exported);
+    }
+
+    /// Exports a trie large enough to be split into many read-ahead subtrees,
+    /// with and without read-ahead. Both exports must return the same items,
+    /// and with read-ahead some of the nodes must be served by the prefetcher.
+    #[test]
+    fn test_export_shard_read_ahead() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let changes: Vec<(Vec<u8>, Option<Vec<u8>>)> = (0..10_000u32)
+            .map(|i| (hash(&i.to_le_bytes()).as_ref().to_vec(), Some(i.to_le_bytes().to_vec())))
+            .collect();
+        let root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes.clone());
+        let mut expected: Vec<_> =
+            changes.into_iter().map(|(key, value)| (key, value.unwrap())).collect();
+        expected.sort();
+        // Reads which found the node staged by the prefetcher, or being fetched by it.
+        let shard_id = shard_uid.shard_id.to_string();
+        let prefetched = || {
+            metrics::PREFETCH_HITS.with_label_values(&[&shard_id]).get()
+                + metrics::PREFETCH_PENDING.with_label_values(&[&shard_id]).get()
+        };
+
+        for window in [0, 1, 16, 1000] {
+            for cache in tries.0.view_caches.read().unwrap().values() {
+                cache.clear();
+            }
+            let prefetched_before = prefetched();
+            let mut exported = vec![];
+            tries
+                .export_shard_with_read_ahead(shard_uid, root, window, |key, value| {
+                    exported.push((key, value))
+                })
+                .unwrap();
+            assert_eq!(expected, exported);
+            if window > 0 {
+                assert!(prefetched() > prefetched_before, "window {window}: nothing prefetched");
+            }
+        }
+    }
+
+    #[test]
+    fn test_trie_cursor() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let changes: Vec<(Vec<u8>, Option<Vec<u8>>)> = (0..100u32)
+            .map(|i| (format!("key{i}").into_bytes(), Some(i.to_le_bytes().to_vec())))
+            .collect();
+        let root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes);
+
+        let mut expected = vec![];
+        tries.export_shard(shard_uid, root, |key, value| expected.push((key, value))).unwrap();
+
+        // Open a fresh cursor for every batch, as if the export was restarted,
+        // passing the token through borsh.
+        let mut exported = vec![];
+        let mut token: Option<Vec<u8>> = None;
+        let mut batches = 0;
+        loop {
+            let resume = token.take().map(|bytes| TrieResumeToken::try_from_slice(&bytes).unwrap());
+            let mut cursor = tries.trie_cursor(shard_uid, root, resume.as_ref()).unwrap();
+            let (batch, next) = cursor.next_batch(7).unwrap();
+            assert!(batch.len() <= 7);
+            exported.extend(batch);
+            batches += 1;
+            match next {
+                Some(next) => token = Some(next.try_to_vec().unwrap()),
+                None => break,
+            }
+        }
+        assert_eq!(batches, 15);
+        assert_eq!(expected, exported);
+
+        // A token is only valid for the state root it was created for.
+        let (_, token) = tries.trie_cursor(shard_uid, root, None).unwrap().next_batch(1).unwrap();
+        let other_root =
+            test_populate_trie(&tries, &root, shard_uid, vec![(b"key0".to_vec(), None)]);
+        assert!(tries.trie_cursor(shard_uid, other_root, token.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_get_at_block() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let (block1, block2) = (hash(b"block1"), hash(b"block2"));
+        let key = b"key".to_vec();
+        let root1 = test_populate_trie(
+            &tries,
+            &Trie::EMPTY_ROOT,
+            shard_uid,
+            vec![(key.clone(), Some(b"old".to_vec()))],
+        );
+        let root2 = test_populate_trie(
+            &tries,
+            &root1,
+            shard_uid,
+            vec![(key.clone(), Some(b"new".to_vec()))],
+        );
+
+        assert_eq!(
+            Some(b"old".to_vec()),
+            tries.get_at_block(shard_uid, root1, &block1, &key).unwrap()
+        );
+        assert_eq!(
+            Some(b"new".to_vec()),
+            tries.get_at_block(shard_uid, root2, &block2, &key).unwrap()
+        );
+        assert_eq!(None, tries.get_at_block(shard_uid, root1, &block1, b"missing").unwrap());
+    }
+
+    #[test]
+    fn test_traverse_to_depth() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        // Nibble paths 0-0, 0-1 and 1-0 give the following trie:
+        // Branch(0 -> Branch(0 -> Leaf, 1 -> Leaf), 1 -> Leaf)
+        let changes = vec![
+            (vec![0x00], Some(b"a".to_vec())),
+            (vec![0x01], Some(b"b".to_vec())),
+            (vec![0x10], Some(b"c".to_vec())),
+        ];
+        let root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes);
+
+        let traverse = |max_depth| {
+            let mut visited = vec![];
+            tries
+                .traverse_to_depth(shard_uid, root, max_depth, |prefix, hash| {
+                    visited.push((prefix.to_vec(), hash))
+                })
+                .unwrap();
+            visited
+        };
+        assert_eq!(vec![(vec![], root)], traverse(0));
+        let prefixes =
+            |max_depth| traverse(max_depth).into_iter().map(|(p, _)| p).collect::<Vec<_>>();
+        assert_eq!(vec![vec![], vec![0], vec![1]], prefixes(1));
+        assert_eq!(vec![vec![], vec![0], vec![0, 0], vec![0, 1], vec![1]], prefixes(2));
+        assert_eq!(5, traverse(10).len());
+
+        let mut empty_visits = 0;
+        tries.traverse_to_depth(shard_uid, Trie::EMPTY_ROOT, 10, |_, _| empty_visits += 1).unwrap();
+        assert_eq!(0, empty_visits);
+    }
+
+    #[cfg(feature = "protocol_feature_flat_state")]
+    #[test]
+    fn test_verify_flat_state_consistency() {
+        use crate::flat_state::{store_helper, Inconsistency};
+        use near_primitives::state::ValueRef;
+
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let changes: Vec<(Vec<u8>, Option<Vec<u8>>)> =
+            [&b"a"[..], b"b", b"c"].iter().map(|key| (key.to_vec(), Some(key.to_vec()))).collect();
+        let root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes.clone());
+
+        let mut store_update = tries.get_store().store_update();
+        for (key, value) in changes {
+            store_helper::set_ref(&mut store_update, key, Some(ValueRef::new(&value.unwrap())))
+                .unwrap();
+        }
+        store_update.commit().unwrap();
+        let sample = vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()];
+        assert_eq!(
+            Vec::<Inconsistency>::new(),
+            tries.verify_flat_state_consistency(shard_uid, root, &sample).unwrap()
+        );
+
+        // Corrupt the value of one key and add a key which is missing in the trie.
+        let corrupted = ValueRef::new(b"corrupted");
+        let mut store_update = tries.get_store().store_update();
+        store_helper::set_ref(&mut store_update, b"b".to_vec(), Some(corrupted.clone())).unwrap();
+        store_helper::set_ref(&mut store_update, b"d".to_vec(), Some(corrupted.clone())).unwrap();
+        store_update.commit().unwrap();
+        assert_eq!(
+            vec![
+                Inconsistency {
+                    key: b"b".to_vec(),
+                    flat_state: Some(corrupted.clone()),
+                    trie: Some(ValueRef::new(b"b")),
+                },
+                Inconsistency { key: b"d".to_vec(), flat_state: Some(corrupted), trie: None },
+            ],
+            tries.verify_flat_state_consistency(shard_uid, root, &sample).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_keys_with_prefix() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let changes: Vec<(Vec<u8>, Option<Vec<u8>>)> = [&b"aa1"[..], b"aa2", b"aa3", b"ab", b"b"]
+            .iter()
+            .map(|key| (key.to_vec(), Some(key.to_vec())))
+            .collect();
+        let root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes);
+
+        let keys = |prefix: &[u8], limit| {
+            tries
+                .keys_with_prefix(shard_uid, root, prefix, limit)
+                .unwrap()
+                .into_iter()
+                .map(|(key, value)| {
+                    assert_eq!(key, value);
+                    key
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![b"aa1".to_vec(), b"aa2".to_vec(), b"aa3".to_vec()], keys(b"aa", 10));
+        assert_eq!(vec![b"aa1".to_vec(), b"aa2".to_vec()], keys(b"aa", 2));
+        assert_eq!(vec![b"ab".to_vec()], keys(b"ab", 10));
+        assert_eq!(5, keys(b"", 10).len());
+        assert!(keys(b"aa", 0).is_empty());
+        assert!(keys(b"c", 10).is_empty());
+        assert!(keys(b"aa4", 10).is_empty());
+    }
+
+    #[test]
+    fn test_restart_dead_prefetcher() {
+        let shard_uid = ShardUId::single_shard();
+        let trie_config = TrieConfig { enable_receipt_prefetching: true, ..TrieConfig::default() };
+        let tries = shard_tries_for(trie_config, &[shard_uid]);
+        tries.get_trie_for_shard(shard_uid, Trie::EMPTY_ROOT);
+        {
+            let mut prefetchers = tries.0.prefetchers.write().unwrap();
+            let (_, handle) = prefetchers.get_mut(&shard_uid).unwrap();
+            handle.kill_io_thread();
+            assert!(handle.any_thread_dead());
+        }
+
+        // The next acquisition replaces the prefetcher with one whose threads all run.
+        tries.get_trie_for_shard(shard_uid, Trie::EMPTY_ROOT);
+        let key = TrieKey::Account { account_id: "alice.near".parse().unwrap() };
+        {
+            let prefetchers = tries.0.prefetchers.read().unwrap();
+            let (api, handle) = &prefetchers[&shard_uid];
+            assert!(!handle.any_thread_dead());
+            api.prefetch_trie_key(Trie::EMPTY_ROOT, key).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !tries.pending_prefetches(shard_uid).is_empty() {
+            assert!(Instant::now() < deadline, "prefetch requests not drained");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_active_prefetcher_shards() {
+        let shard0 = ShardUId { version: 1, shard_id: 0 };
+        let shard1 = ShardUId { version: 1, shard_id: 1 };
+        let trie_config = TrieConfig { enable_receipt_prefetching: true, ..TrieConfig::default() };
+        let tries = shard_tries_for(trie_config, &[shard0, shard1]);
+        assert!(tries.active_prefetcher_shards().is_empty());
+
+        tries.get_trie_for_shard(shard0, Trie::EMPTY_ROOT);
+        tries.get_view_trie_for_shard(shard1, Trie::EMPTY_ROOT);
+        assert_eq!(vec![shard0], tries.active_prefetcher_shards());
+
+        // Without prefetching enabled, no shard has a prefetcher.
+        let tries = shard_tries_for(TrieConfig::default(), &[shard0]);
+        tries.get_trie_for_shard(shard0, Trie::EMPTY_ROOT);
+        assert!(tries.active_prefetcher_shards().is_empty());
+    }
+
+    #[test]
+    fn test_trie_changes_into_with_format() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let changes = vec![(b"key".to_vec(), Some(b"value".to_vec()))];
+        let trie_changes =
+            tries.get_trie_for_shard(shard_uid, Trie::EMPTY_ROOT).update(changes).unwrap();
+        let persist = |block_hash: CryptoHash, format: SerializationFormat| {
+            let mut store_update = tries.get_store().store_update();
+            WrappedTrieChanges::new(
+                tries.clone(),
+                shard_uid,
+                trie_changes.clone(),
+                vec![],
+                block_hash,
+            )
+            .trie_changes_into_with_format(&mut store_update, format)
+            .unwrap();
+            store_update.commit().unwrap();
+            shard_layout::get_block_shard_uid(&block_hash, &shard_uid)
+        };
+        let store = tries.get_store();
+
+        let key = persist(hash(b"borsh"), SerializationFormat::Borsh);
+        assert_eq!(
+            Some(trie_changes.try_to_vec().unwrap()),
+            store.get(DBCol::TrieChanges, &key).unwrap().map(|value| value.to_vec())
+        );
+        assert!(store.get(DBCol::TrieChangesDebug, &key).unwrap().is_none());
+
+        #[cfg(feature = "debug_trie_changes")]
+        {
+            let key = persist(hash(b"json"), SerializationFormat::Json);
+            assert_eq!(
+                Some(trie_changes.clone()),
+                store.get_ser::<TrieChanges>(DBCol::TrieChanges, &key).unwrap()
+            );
+            let json = store.get(DBCol::TrieChangesDebug, &key).unwrap().unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+            assert_eq!(trie_changes.new_root.to_string(), json["new_root"]);
+            assert_eq!(trie_changes.insertions.len(), json["insertions"].as_array().unwrap().len());
+        }
+    }
+
+    #[test]
+    fn test_block_for_state_root() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let mut root = Trie::EMPTY_ROOT;
+        let mut roots = vec![];
+        for i in 0..3u32 {
+            let block_hash = hash(&i.to_le_bytes());
+            let changes = vec![(b"key".to_vec(), Some(i.to_le_bytes().to_vec()))];
+            let trie_changes = tries.get_trie_for_shard(shard_uid, root).update(changes).unwrap();
+            let (mut store_update, new_root) = tries.apply_all(&trie_changes, shard_uid);
+            let mut wrapped =
+                WrappedTrieChanges::new(tries.clone(), shard_uid, trie_changes, vec![], block_hash);
+            wrapped.trie_changes_into(&mut store_update).unwrap();
+            store_update.commit().unwrap();
+            wrapped.staged_state_root_block().record();
+            roots.push((new_root, block_hash));
+            root = new_root;
+        }
+        for (root, block_hash) in &roots {
+            assert_eq!(Some(*block_hash), tries.block_for_state_root(shard_uid, root));
+        }
+        let other_shard_uid = ShardUId { shard_id: 1, version: 0 };
+        assert_eq!(None, tries.block_for_state_root(other_shard_uid, &roots[0].0));
+
+        // Staged mappings are not remembered until they are recorded.
+        let changes = vec![(b"key".to_vec(), Some(b"staged".to_vec()))];
+        let trie_changes = tries.get_trie_for_shard(shard_uid, root).update(changes).unwrap();
+        let new_root = trie_changes.new_root;
+        let staged = WrappedTrieChanges::new(
+            tries.clone(),
+            shard_uid,
+            trie_changes,
+            vec![],
+            hash(b"staged"),
+        )
+        .staged_state_root_block();
+        assert_eq!(None, tries.block_for_state_root(shard_uid, &new_root));
+        staged.record();
+        assert_eq!(Some(hash(b"staged")), tries.block_for_state_root(shard_uid, &new_root));
+
+        // Old entries are evicted once the index is full.
+        for i in 0..STATE_ROOT_BLOCKS_CAPACITY as u32 {
+            let root = hash(&i.to_be_bytes());
+            tries.record_state_root_block(shard_uid, root, hash(b"block"));
+        }
+        assert_eq!(None, tries.block_for_state_root(shard_uid, &roots[0].0));
+    }
+
+    #[test]
+    fn test_persist() {
+        let tries = create_tries();
+        let shard_uid = ShardUId::single_shard();
+        let block_hash = hash(b"block");
+        let trie_key = TrieKey::Account { account_id: "alice.near".parse().unwrap() };
+        let value = b"account".to_vec();
+        let trie_changes = tries
+            .get_trie_for_shard(shard_uid, Trie::EMPTY_ROOT)
+            .update(vec![(trie_key.to_vec(), Some(value.clone()))])
+            .unwrap();
+        let new_root = trie_changes.new_root;
+        let state_changes = vec![RawStateChangesWithTrieKey {
+            trie_key: trie_key.clone(),
+            changes: vec![RawStateChange {
+                cause: StateChangeCause::InitialState,
+                data: Some(value.clone()),
+            }],
+        }];
+        let mut store_update = tries.get_store().store_update();
+        WrappedTrieChanges::new(
+            tries.clone(),
+            shard_uid,
+            trie_changes.clone(),
+            state_changes.clone(),
+            block_hash,
+        )
+        .persist(&mut store_update)
+        .unwrap();
+        store_update.commit().unwrap();
+
+        let store = tries.get_store();
+        assert_eq!(
+            Some(value),
+            tries.get_trie_for_shard(shard_uid, new_root).get(&trie_key.to_vec()).unwrap()
+        );
+        let saved: Option<TrieChanges> = store
+            .get_ser(
+                DBCol::TrieChanges,
+                &shard_layout::get_block_shard_uid(&block_hash, &shard_uid),
+            )
+            .unwrap();
+        assert_eq!(Some(trie_changes), saved);
+        let key = KeyForStateChanges::from_trie_key_for_shard(&block_hash, &trie_key, shard_uid);
+        let saved: Option<RawStateChangesWithTrieKey> =
+            store.get_ser(DBCol::StateChanges, key.as_ref()).unwrap();
+        assert_eq!(
+            Some(state_changes[0].try_to_vec().unwrap()),
+            saved.map(|changes| changes.try_to_vec().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_persist_skip_state_changes() {
+        let shard_uid = ShardUId::single_shard();
+        let trie_config = TrieConfig { skip_state_changes: true, ..TrieConfig::default() };
+        let tries = shard_tries_for(trie_config, &[shard_uid]);
+        let block_hash = hash(b"block");
+        let trie_key = TrieKey::Account { account_id: "alice.near".parse().unwrap() };
+        let value = b"account".to_vec();
+        let trie_changes = tries
+            .get_trie_for_shard(shard_uid, Trie::EMPTY_ROOT)
+            .update(vec![(trie_key.to_vec(), Some(value.clone()))])
+            .unwrap();
+        let new_root = trie_changes.new_root;
+        let state_changes = vec![RawStateChangesWithTrieKey {
+            trie_key: trie_key.clone(),
+            changes: vec![RawStateChange {
+                cause: StateChangeCause::InitialState,
+                data: Some(value.clone()),
+            }],
+        }];
+        let mut wrapped = WrappedTrieChanges::new(
+            tries.clone(),
+            shard_uid,
+            trie_changes.clone(),
+            state_changes,
+            block_hash,
+        );
+        let mut store_update = tries.get_store().store_update();
+        wrapped.state_changes_into(&mut store_update);
+        // The changes are drained even though they are not saved.
+        assert!(wrapped.state_changes().is_empty());
+        wrapped.persist(&mut store_update).unwrap();
+        store_update.commit().unwrap();
+
+        let store = tries.get_store();
+        assert_eq!(0, store.iter(DBCol::StateChanges).count());
+        // Trie nodes and trie changes are saved as usual.
+        assert_eq!(
+            Some(value),
+            tries.get_trie_for_shard(shard_uid, new_root).get(&trie_key.to_vec()).unwrap()
+        );
+        let saved: Option<TrieChanges> = store
+            .get_ser(
+                DBCol::TrieChanges,
+                &shard_layout::get_block_shard_uid(&block_hash, &shard_uid),
+            )
+            .unwrap();
+        assert_eq!(Some(trie_changes), saved);
+    }
+
+    #[test]
+    fn test_iter_decoded_state_changes() {
+        let store = create_test_store();
+        let block_hash = hash(b"block");
+        let trie_keys: Vec<TrieKey> = ["alice.near", "bob.near", "carol.near"]
+            .iter()
+            .map(|account_id| TrieKey::Account { account_id: account_id.parse().unwrap() })
+            .collect();
+
+        let mut store_update = store.store_update();
+        for trie_key in &trie_keys {
+            let changes = RawStateChangesWithTrieKey {
+                trie_key: trie_key.clone(),
+                changes: vec![RawStateChange {
+                    cause: StateChangeCause::InitialState,
+                    data: Some(trie_key.to_vec()),
+                }],
+            };
+            store_update.set(
+                DBCol::StateChanges,
+                KeyForStateChanges::from_trie_key(&block_hash, trie_key).as_ref(),
+                &changes.try_to_vec().unwrap(),
+            );
+        }
+        // An entry which cannot be deserialized.
+        store_update.set(
+            DBCol::StateChanges,
+            KeyForStateChanges::from_raw_key(&block_hash, b"garbage").as_ref(),
+            b"garbage",
+        );
+        // An entry stored under a key which doesn't match its embedded trie key.
+        let mismatched =
+            RawStateChangesWithTrieKey { trie_key: trie_keys[0].clone(), changes: vec![] };
+        store_update.set(
+            DBCol::StateChanges,
+            KeyForStateChanges::from_raw_key(&block_hash, b"mismatched").as_ref(),
+            &mismatched.try_to_vec().unwrap(),
+        );
+        store_update.commit().unwrap();
+
+        let malformed_before = metrics::STATE_CHANGES_MALFORMED_ENTRIES.get();
+        let decoded: Vec<_> = KeyForStateChanges::for_block(&block_hash)
+            .iter_decoded(&store)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            trie_keys,
+            decoded.iter().map(|(trie_key, _)| trie_key.clone()).collect::<Vec<_>>()
+        );
+        for (trie_key, changes) in &decoded {
+            assert_eq!(trie_key, &changes.trie_key);
+            assert_eq!(Some(trie_key.to_vec()), changes.changes[0].data);
+        }
+        assert!(metrics::STATE_CHANGES_MALFORMED_ENTRIES.get() >= malformed_before + 2);
+    }
+
+    #[test]
+    fn test_state_changes_for_shard() {
+        let shard_uids =
+            [ShardUId { version: 1, shard_id: 0 }, ShardUId { version: 1, shard_id: 1 }];
+        let tries = shard_tries_for(TrieConfig::default(), &shard_uids);
+        let store = tries.get_store();
+        let block_hash = hash(b"block");
+        let make_changes = |account_id: &str, data: &[u8]| RawStateChangesWithTrieKey {
+            trie_key: TrieKey::Account { account_id: account_id.parse().unwrap() },
+            changes: vec![RawStateChange {
+                cause: StateChangeCause::InitialState,
+                data: Some(data.to_vec()),
+            }],
+        };
+        // "shared.near" is changed in both shards, which used to be a key conflict.
+        let changes = [
+            vec![make_changes("alice.near", b"0"), make_changes("shared.near", b"0")],
+            vec![make_changes("bob.near", b"1"), make_changes("shared.near", b"1")],
+        ];
+        let mut store_update = store.store_update();
+        for (shard_uid, changes) in shard_uids.iter().zip(&changes) {
+            WrappedTrieChanges::new(
+                tries.clone(),
+                *shard_uid,
+                TrieChanges::empty(Trie::EMPTY_ROOT),
+                changes.clone(),
+                block_hash,
+            )
+            .state_changes_into(&mut store_update);
+        }
+        // An entry written by an older version, without the shard.
+        let legacy = make_changes("carol.near", b"legacy");
+        store_update.set(
+            DBCol::StateChanges,
+            KeyForStateChanges::from_trie_key(&block_hash, &legacy.trie_key).as_ref(),
+            &legacy.try_to_vec().unwrap(),
+        );
+        store_update.commit().unwrap();
+
+        let data = |changes: Vec<RawStateChangesWithTrieKey>| -> Vec<Vec<u8>> {
+            changes.into_iter().map(|c| c.changes[0].data.clone().unwrap()).collect()
+        };
+        let key = KeyForStateChanges::for_block(&block_hash);
+        for (shard_uid, expected) in shard_uids.iter().zip(&changes) {
+            let got: Vec<_> =
+                key.find_iter_for_shard(&store, *shard_uid).map(Result::unwrap).collect();
+            assert_eq!(data(expected.clone()), data(got));
+        }
+        let other_shard = ShardUId { version: 1, shard_id: 2 };
+        assert_eq!(0, key.find_iter_for_shard(&store, other_shard).count());
+
+        // Iteration over all shards returns the legacy entry as well.
+        assert_eq!(5, key.find_iter(&store).count());
+        assert_eq!(5, key.iter_decoded(&store).map(Result::unwrap).count());
+        // Lookups of a single trie key find the changes in every shard.
+        let shared = KeyForStateChanges::from_trie_key(&block_hash, &changes[0][1].trie_key);
+        let got: Vec<_> = shared.find_exact_iter(&store).map(Result::unwrap).collect();
+        assert_eq!(vec![b"0".to_vec(), b"1".to_vec()], data(got));
+    }
 }
@@ -257,9 +257,11 @@ mod tests {
 
     use near_primitives::hash::{hash, CryptoHash};
 
-    use crate::test_utils::{create_tries, gen_changes, test_populate_trie};
+    use crate::flat_state::FlatStateFactory;
+    use crate::test_utils::{create_test_store, create_tries, gen_changes, test_populate_trie};
     use crate::trie::iterator::CrumbStatus;
     use crate::trie::{TrieRefcountChange, ValueHandle};
+    use crate::{ShardTries, TrieConfig};
 
     use super::*;
     use near_primitives::shard_layout::ShardUId;
@@ -716,4 +718,43 @@ mod tests {
             }
         }
     }
+
+    /// State parts carry the original values, also when they are stored compressed,
+    /// so that they can be validated and applied by nodes with any compression setting.
+    #[test]
+    fn test_state_part_with_compressed_values() {
+        let shard_uid = ShardUId::single_shard();
+        let compressing_tries = || {
+            let store = create_test_store();
+            let trie_config =
+                TrieConfig { compress_values_above: Some(100), ..TrieConfig::default() };
+            ShardTries::new(store.clone(), trie_config, &[shard_uid], FlatStateFactory::new(store))
+        };
+        let large = vec![1; 10_000];
+        let changes = vec![
+            (b"small".to_vec(), Some(b"small".to_vec())),
+            (b"large".to_vec(), Some(large.clone())),
+        ];
+        let tries = compressing_tries();
+        let state_root = test_populate_trie(&tries, &Trie::EMPTY_ROOT, shard_uid, changes.clone());
+        let trie = tries.get_trie_for_shard(shard_uid, state_root);
+
+        let part_id = PartId::new(0, 1);
+        let part = trie.get_trie_nodes_for_part(part_id).unwrap();
+        assert!(part.0.iter().any(|item| **item == *large));
+        Trie::validate_trie_nodes_for_part(&state_root, part_id, part.clone())
+            .expect("validate ok");
+
+        for tries in [create_tries(), compressing_tries()] {
+            let trie_changes =
+                Trie::apply_state_part(&state_root, part_id, part.0.clone()).trie_changes;
+            let (store_update, root) = tries.apply_all(&trie_changes, shard_uid);
+            store_update.commit().unwrap();
+            assert_eq!(state_root, root);
+            let trie = tries.get_trie_for_shard(shard_uid, state_root);
+            for (key, value) in &changes {
+                assert_eq!(value, &trie.get(key).unwrap());
+            }
+        }
+    }
 }
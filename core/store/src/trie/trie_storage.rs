@@ -1,8 +1,9 @@
 use crate::db::refcount::decode_value_with_rc;
-use crate::trie::config::TrieConfig;
+use crate::trie::config::{SharedCacheBudgetConfig, TrieConfig};
 use crate::trie::prefetching_trie_storage::PrefetcherResult;
 use crate::trie::POISONED_LOCK_ERR;
 use crate::{metrics, DBCol, PrefetchApi, StorageError, Store};
+use crossbeam::channel::{Receiver, Sender, TrySendError};
 use lru::LruCache;
 use near_o11y::log_assert;
 use near_o11y::metrics::prometheus;
@@ -10,11 +11,16 @@ use near_o11y::metrics::prometheus::core::{GenericCounter, GenericGauge};
 use near_primitives::hash::CryptoHash;
 use near_primitives::shard_layout::ShardUId;
 use near_primitives::types::{ShardId, TrieCacheMode, TrieNodesCount};
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
 use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::ErrorKind;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Capacity of the channels returned by `TrieCache::watch`. Events which don't fit
+/// are dropped and counted in `near_shard_cache_watch_dropped`.
+const WATCH_CHANNEL_CAPACITY: usize = 1024;
 
 pub(crate) struct BoundedQueue<T> {
     queue: VecDeque<T>,
@@ -71,6 +77,8 @@ pub struct TrieCacheInner {
     total_size: u64,
     /// Upper bound for the total size.
     total_size_limit: u64,
+    /// Budget shared with the caches of other shards, which replaces `total_size_limit`.
+    budget: Option<(Arc<SharedCacheBudget>, ShardUId)>,
     /// Shard id of the nodes being cached.
     shard_id: ShardId,
     /// Whether cache is used for view calls execution.
@@ -78,6 +86,45 @@ pub struct TrieCacheInner {
     // Counters tracking operations happening inside the shard cache.
     // Stored here to avoid overhead of looking them up on hot paths.
     metrics: TrieCacheMetrics,
+    /// Subscriptions to evictions of specific keys, see `TrieCache::watch`.
+    watchers: Vec<EvictionWatcher>,
+    /// Insertion times of the cached nodes, if enabled with `track_entry_age`.
+    insertion_times: Option<HashMap<CryptoHash, Instant>>,
+}
+
+/// Tracks the sizes of the caches sharing a budget, see `SharedCacheBudgetConfig`.
+pub(crate) struct SharedCacheBudget {
+    config: SharedCacheBudgetConfig,
+    /// Current total size of the values in the cache of each shard.
+    used: Mutex<HashMap<ShardUId, u64>>,
+}
+
+impl SharedCacheBudget {
+    pub(crate) fn new(config: SharedCacheBudgetConfig) -> Self {
+        Self { config, used: Mutex::default() }
+    }
+
+    /// Upper bound for the total size of the cache of `shard_uid`: the part of the
+    /// budget not used by the other shards, with `min_shard_bytes` reserved for each
+    /// of them. It is never less than `min_shard_bytes`.
+    fn shard_limit(&self, shard_uid: ShardUId) -> u64 {
+        let used = self.used.lock().expect(POISONED_LOCK_ERR);
+        let reserved: u64 = used
+            .iter()
+            .filter(|(other, _)| **other != shard_uid)
+            .map(|(_, size)| (*size).max(self.config.min_shard_bytes))
+            .sum();
+        self.config.total_bytes.saturating_sub(reserved).max(self.config.min_shard_bytes)
+    }
+
+    fn set_used(&self, shard_uid: ShardUId, size: u64) {
+        self.used.lock().expect(POISONED_LOCK_ERR).insert(shard_uid, size);
+    }
+}
+
+struct EvictionWatcher {
+    keys: HashSet<CryptoHash>,
+    sender: Sender<CryptoHash>,
 }
 
 struct TrieCacheMetrics {
@@ -87,6 +134,7 @@ struct TrieCacheMetrics {
     shard_cache_pop_lru: GenericCounter<prometheus::core::AtomicU64>,
     shard_cache_gc_pop_misses: GenericCounter<prometheus::core::AtomicU64>,
     shard_cache_deletions_size: GenericGauge<prometheus::core::AtomicI64>,
+    shard_cache_watch_dropped: GenericCounter<prometheus::core::AtomicU64>,
 }
 
 impl TrieCacheInner {
@@ -114,15 +162,52 @@ impl TrieCacheInner {
                 .with_label_values(&metrics_labels),
             shard_cache_deletions_size: metrics::SHARD_CACHE_DELETIONS_SIZE
                 .with_label_values(&metrics_labels),
+            shard_cache_watch_dropped: metrics::SHARD_CACHE_WATCH_DROPPED
+                .with_label_values(&metrics_labels),
         };
         Self {
             cache: LruCache::new(cache_capacity),
             deletions: BoundedQueue::new(deletions_queue_capacity),
             total_size: 0,
             total_size_limit,
+            budget: None,
             shard_id,
             is_view,
             metrics,
+            watchers: Vec::new(),
+            insertion_times: None,
+        }
+    }
+
+    /// Makes the cache record when the nodes get inserted, see `oldest_entry_age`.
+    pub(crate) fn track_entry_age(mut self) -> Self {
+        self.insertion_times = Some(HashMap::new());
+        self
+    }
+
+    /// Makes the cache share `budget` with the caches of other shards, instead of
+    /// being limited by its own `total_size_limit`.
+    pub(crate) fn with_budget(
+        mut self,
+        budget: Arc<SharedCacheBudget>,
+        shard_uid: ShardUId,
+    ) -> Self {
+        budget.set_used(shard_uid, self.total_size);
+        self.budget = Some((budget, shard_uid));
+        self
+    }
+
+    fn total_size_limit(&self) -> u64 {
+        match &self.budget {
+            Some((budget, shard_uid)) => budget.shard_limit(*shard_uid),
+            None => self.total_size_limit,
+        }
+    }
+
+    /// Reports the current total size to the shared budget, if any.
+    fn update_budget(&self) {
+        if let Some((budget, shard_uid)) = &self.budget {
+            budget.set_used(*shard_uid, self.total_size);
         }
     }
 
@@ -131,19 +216,32 @@ impl TrieCacheInner {
     }
 
     pub(crate) fn clear(&mut self) {
+        if !self.watchers.is_empty() {
+            let keys: Vec<CryptoHash> = self.cache.iter().map(|(key, _)| *key).collect();
+            for key in keys {
+                self.notify_evicted(&key);
+            }
+        }
         self.total_size = 0;
         self.deletions.clear();
         self.cache.clear();
+        if let Some(times) = &mut self.insertion_times {
+            times.clear();
+        }
+        self.update_budget();
     }
 
     pub(crate) fn put(&mut self, key: CryptoHash, value: Arc<[u8]>) {
-        while self.total_size > self.total_size_limit || self.cache.len() == self.cache.cap() {
+        // Evictions only change the size of this cache, so the limit stays the same.
+        let total_size_limit = self.total_size_limit();
+        while self.total_size > total_size_limit || self.cache.len() == self.cache.cap() {
             // First, try to evict value using the key from deletions queue.
             match self.deletions.pop() {
                 Some(key) => match self.cache.pop(&key) {
                     Some(value) => {
                         self.metrics.shard_cache_pop_hits.inc();
                         self.total_size -= value.len() as u64;
+                        self.notify_evicted(&key);
                         continue;
                     }
                     None => {
@@ -155,9 +253,10 @@ impl TrieCacheInner {
 
             // Second, pop LRU value.
             self.metrics.shard_cache_pop_lru.inc();
-            let (_, value) =
+            let (evicted_key, value) =
                 self.cache.pop_lru().expect("Cannot fail because total size capacity is > 0");
             self.total_size -= value.len() as u64;
+            self.notify_evicted(&evicted_key);
         }
 
         // Add value to the cache.
@@ -169,6 +268,10 @@ impl TrieCacheInner {
             }
             None => {}
         };
+        if let Some(times) = &mut self.insertion_times {
+            times.insert(key, Instant::now());
+        }
+        self.update_budget();
     }
 
     // Adds key to the deletions queue if it is present in cache.
@@ -183,6 +286,8 @@ impl TrieCacheInner {
                     Some(evicted_value) => {
                         self.metrics.shard_cache_pop_hits.inc();
                         self.total_size -= evicted_value.len() as u64;
+                        self.notify_evicted(&key_to_delete);
+                        self.update_budget();
                         Some((key_to_delete, evicted_value))
                     }
                     None => {
@@ -198,6 +303,46 @@ impl TrieCacheInner {
         }
     }
 
+    /// Removes the key from the cache right away, bypassing the deletions queue.
+    pub(crate) fn remove(&mut self, key: &CryptoHash) -> Option<Arc<[u8]>> {
+        let value = self.cache.pop(key)?;
+        self.total_size -= value.len() as u64;
+        self.notify_evicted(key);
+        self.update_budget();
+        Some(value)
+    }
+
+    pub(crate) fn watch(&mut self, keys: HashSet<CryptoHash>) -> Receiver<CryptoHash> {
+        let (sender, receiver) = crossbeam::channel::bounded(WATCH_CHANNEL_CAPACITY);
+        self.watchers.push(EvictionWatcher { keys, sender });
+        receiver
+    }
+
+    /// Sends the evicted key to the watchers interested in it. Watchers whose
+    /// receiver has been dropped are unsubscribed.
+    fn notify_evicted(&mut self, key: &CryptoHash) {
+        if let Some(times) = &mut self.insertion_times {
+            times.remove(key);
+        }
+        if self.watchers.is_empty() {
+            return;
+        }
+        let dropped = &self.metrics.shard_cache_watch_dropped;
+        self.watchers.retain(|watcher| {
+            if !watcher.keys.contains(key) {
+                return true;
+            }
+            match watcher.sender.try_send(*key) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    dropped.inc();
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+
     pub fn len(&self) -> usize {
         self.cache.len()
     }
@@ -205,6 +350,14 @@ impl TrieCacheInner {
     pub fn current_total_size(&self) -> u64 {
         self.total_size
     }
+
+    /// Time since the insertion of the node which has been cached for the longest,
+    /// as of `now`. None if the cache is empty or doesn't track the insertion times.
+    /// Takes time linear in the number of cached nodes.
+    pub(crate) fn oldest_entry_age(&self, now: Instant) -> Option<Duration> {
+        let oldest = self.insertion_times.as_ref()?.values().min()?;
+        Some(now.saturating_duration_since(*oldest))
+    }
 }
 
 /// Wrapper over LruCache to handle concurrent access.
@@ -213,16 +366,34 @@ pub struct TrieCache(pub(crate) Arc<Mutex<TrieCacheInner>>);
 
 impl TrieCache {
     pub fn new(config: &TrieConfig, shard_uid: ShardUId, is_view: bool) -> Self {
+        Self::with_budget(config, shard_uid, is_view, None)
+    }
+
+    /// Creates a cache which shares `budget` with the caches of other shards, if given.
+    /// Otherwise the size of the cache is limited according to `config`.
+    pub(crate) fn with_budget(
+        config: &TrieConfig,
+        shard_uid: ShardUId,
+        is_view: bool,
+        budget: Option<Arc<SharedCacheBudget>>,
+    ) -> Self {
         let capacity = config.shard_cache_capacity(shard_uid, is_view);
         let total_size_limit = config.shard_cache_total_size_limit(shard_uid, is_view);
         let queue_capacity = config.deletions_queue_capacity();
-        Self(Arc::new(Mutex::new(TrieCacheInner::new(
+        let mut inner = TrieCacheInner::new(
             capacity as usize,
             queue_capacity,
             total_size_limit,
             shard_uid.shard_id(),
             is_view,
-        ))))
+        );
+        if let Some(budget) = budget {
+            inner = inner.with_budget(budget, shard_uid);
+        }
+        if config.shard_cache_tracks_entry_age(is_view) {
+            inner = inner.track_entry_age();
+        }
+        Self(Arc::new(Mutex::new(inner)))
     }
 
     pub fn get(&self, key: &CryptoHash) -> Option<Arc<[u8]>> {
@@ -233,13 +404,38 @@ impl TrieCache {
         self.0.lock().expect(POISONED_LOCK_ERR).clear()
     }
 
+    /// Removes the given keys from the cache. Returns the number of removed entries.
+    pub(crate) fn remove_all<'a>(&self, keys: impl IntoIterator<Item = &'a CryptoHash>) -> usize {
+        let mut guard = self.0.lock().expect(POISONED_LOCK_ERR);
+        keys.into_iter().filter(|key| guard.remove(key).is_some()).count()
+    }
+
+    /// Total size of the values currently held in the cache, in bytes.
+    pub fn current_total_size(&self) -> u64 {
+        self.0.lock().expect(POISONED_LOCK_ERR).current_total_size()
+    }
+
+    /// See `TrieCacheInner::oldest_entry_age`.
+    pub fn oldest_entry_age(&self, now: Instant) -> Option<Duration> {
+        self.0.lock().expect(POISONED_LOCK_ERR).oldest_entry_age(now)
+    }
+
+    /// Subscribes to evictions of the given keys. The hash of every watched key which
+    /// leaves the cache is sent over the returned channel, unless the channel is full,
+    /// in which case the event is dropped and counted in `near_shard_cache_watch_dropped`.
+    /// Dropping the receiver cancels the subscription.
+    pub fn watch(&self, keys: HashSet<CryptoHash>) -> Receiver<CryptoHash> {
+        self.0.lock().expect(POISONED_LOCK_ERR).watch(keys)
+    }
+
     pub fn update_cache(&self, ops: Vec<(CryptoHash, Option<&[u8]>)>) {
         let mut guard = self.0.lock().expect(POISONED_LOCK_ERR);
         for (hash, opt_value_rc) in ops {
             if let Some(value_rc) = opt_value_rc {
                 if let (Some(value), _rc) = decode_value_with_rc(&value_rc) {
+                    let value = TrieCachingStorage::decode_stored_value(&hash, value);
                     if value.len() < TrieConfig::max_cached_value_size() {
-                        guard.put(hash, value.into());
+                        guard.put(hash, Arc::from(&*value));
                     } else {
                         guard.metrics.shard_cache_too_large.inc();
                     }
@@ -257,6 +453,13 @@ impl TrieCache {
         let guard = self.0.lock().expect(POISONED_LOCK_ERR);
         guard.len()
     }
+
+    /// Copies of all cached entries. Doesn't affect the LRU order.
+    #[cfg(any(test, feature = "test_features"))]
+    pub(crate) fn entries(&self) -> Vec<(CryptoHash, Arc<[u8]>)> {
+        let guard = self.0.lock().expect(POISONED_LOCK_ERR);
+        guard.cache.iter().map(|(hash, value)| (*hash, value.clone())).collect()
+    }
 }
 
 pub trait TrieStorage {
@@ -300,7 +503,7 @@ impl TrieStorage for TrieRecordingStorage {
             .get(DBCol::State, key.as_ref())
             .map_err(|_| StorageError::StorageInternalError)?;
         if let Some(val) = val {
-            let val = Arc::from(val);
+            let val: Arc<[u8]> = Arc::from(&*TrieCachingStorage::decode_stored_value(hash, &val));
             self.recorded.borrow_mut().insert(*hash, Arc::clone(&val));
             Ok(val)
         } else {
@@ -342,6 +545,13 @@ impl TrieStorage for TrieMemoryPartialStorage {
     }
 }
 
+/// Prefix of the values which are stored in `DBCol::State` in compressed form.
+/// Serialized trie nodes never start with this byte.
+const COMPRESSED_VALUE_TAG: u8 = 0xff;
+
+/// zstd compression level of stored values.
+const VALUE_COMPRESSION_LEVEL: i32 = 3;
+
 pub struct TrieCachingStorage {
     pub(crate) store: Store,
     pub(crate) shard_uid: ShardUId,
@@ -357,6 +567,9 @@ pub struct TrieCachingStorage {
     /// Note that for both caches key is the hash of value, so for the fixed key the value is unique.
     pub(crate) chunk_cache: RefCell<HashMap<CryptoHash, Arc<[u8]>>>,
     pub(crate) cache_mode: Cell<TrieCacheMode>,
+    /// If set, values read from the DB are not inserted into `shard_cache`. Values already present there are still
+    /// served from it.
+    pub(crate) bypass_shard_cache: bool,
 
     /// The entry point for the runtime to submit prefetch requests.
     pub(crate) prefetch_api: Option<PrefetchApi>,
@@ -424,6 +637,7 @@ impl TrieCachingStorage {
             shard_uid,
             shard_cache,
             cache_mode: Cell::new(TrieCacheMode::CachingShard),
+            bypass_shard_cache: false,
             prefetch_api,
             chunk_cache: RefCell::new(Default::default()),
             db_read_nodes: Cell::new(0),
@@ -465,6 +679,46 @@ impl TrieCachingStorage {
     pub fn set_mode(&self, state: TrieCacheMode) {
         self.cache_mode.set(state);
     }
+
+    /// Converts a trie node or value into the form in which it is written to `DBCol::State`.
+    /// If `compress_above` is set and `value` is longer than that, the value is compressed
+    /// and prefixed with `COMPRESSED_VALUE_TAG`, unless compression doesn't make it shorter.
+    pub(crate) fn encode_stored_value(
+        value: &[u8],
+        compress_above: Option<usize>,
+    ) -> Cow<'_, [u8]> {
+        match compress_above {
+            Some(threshold) if value.len() > threshold => {}
+            _ => return Cow::Borrowed(value),
+        }
+        let mut encoded = vec![COMPRESSED_VALUE_TAG];
+        match zstd::stream::copy_encode(value, &mut encoded, VALUE_COMPRESSION_LEVEL) {
+            Ok(()) if encoded.len() < value.len() => Cow::Owned(encoded),
+            _ => Cow::Borrowed(value),
+        }
+    }
+
+    /// Inverse of `encode_stored_value`, `hash` being the hash of the original value.
+    /// Values written without compression, including the ones written before compression
+    /// was introduced, are returned as is. A value which happens to start with
+    /// `COMPRESSED_VALUE_TAG` is told apart from a compressed one by the hash check.
+    pub(crate) fn decode_stored_value<'a>(hash: &CryptoHash, stored: &'a [u8]) -> Cow<'a, [u8]> {
+        if let Some((&COMPRESSED_VALUE_TAG, compressed)) = stored.split_first() {
+            if let Ok(value) = zstd::stream::decode_all(compressed) {
+                if near_primitives::hash::hash(&value) == *hash {
+                    return Cow::Owned(value);
+                }
+            }
+        }
+        Cow::Borrowed(stored)
+    }
+
+    /// Makes the storage stop inserting values read from the DB into the shard cache, so that reading keys which
+    /// won't be needed again doesn't evict hot entries.
+    pub(crate) fn bypass_shard_cache(mut self) -> Self {
+        self.bypass_shard_cache = true;
+        self
+    }
 }
 
 impl TrieStorage for TrieCachingStorage {
@@ -548,7 +802,9 @@ impl TrieStorage for TrieCachingStorage {
                 // It is fine to have a size limit for shard cache and **not** have a limit for chunk cache, because key
                 // is always a value hash, so for each key there could be only one value, and it is impossible to have
                 // **different** values for the given key in shard and chunk caches.
-                if val.len() < TrieConfig::max_cached_value_size() {
+                if self.bypass_shard_cache {
+                    // Leave the shard cache untouched.
+                } else if val.len() < TrieConfig::max_cached_value_size() {
                     let mut guard = self.shard_cache.0.lock().expect(POISONED_LOCK_ERR);
                     guard.put(*hash, val.clone());
                 } else {
@@ -602,7 +858,7 @@ impl TrieCachingStorage {
             .ok_or_else(|| {
                 StorageError::StorageInconsistentState("Trie node missing".to_string())
             })?;
-        Ok(val.into())
+        Ok(Arc::from(&*Self::decode_stored_value(hash, &val)))
     }
 
     pub fn prefetch_api(&self) -> &Option<PrefetchApi> {
@@ -645,8 +901,12 @@ mod bounded_queue_tests {
 
 #[cfg(test)]
 mod trie_cache_tests {
-    use crate::trie::trie_storage::TrieCacheInner;
+    use crate::trie::config::SharedCacheBudgetConfig;
+    use crate::trie::trie_storage::{SharedCacheBudget, TrieCacheInner};
     use near_primitives::hash::hash;
+    use near_primitives::shard_layout::ShardUId;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     fn put_value(cache: &mut TrieCacheInner, value: &[u8]) {
         cache.put(hash(value), value.into());
@@ -686,6 +946,97 @@ mod trie_cache_tests {
         assert_eq!(cache.pop(&hash(&[1])), Some((hash(&[1]), vec![1].into())));
     }
 
+    #[test]
+    fn test_watch() {
+        let mut cache = TrieCacheInner::new(2, 100, 100, 0, false);
+        put_value(&mut cache, &[1]);
+        put_value(&mut cache, &[2]);
+        let watched = cache.watch([hash(&[1]), hash(&[2])].into_iter().collect());
+
+        // Exceeding the capacity evicts the LRU value.
+        put_value(&mut cache, &[3]);
+        assert_eq!(watched.try_recv(), Ok(hash(&[1])));
+        assert!(watched.try_recv().is_err());
+
+        // Evictions of unwatched keys are not reported.
+        put_value(&mut cache, &[4]);
+        assert_eq!(watched.try_recv(), Ok(hash(&[2])));
+        put_value(&mut cache, &[5]);
+        assert!(watched.try_recv().is_err());
+
+        // Dropping the receiver unsubscribes.
+        drop(watched);
+        put_value(&mut cache, &[1]);
+        assert!(cache.remove(&hash(&[1])).is_some());
+        assert!(cache.watchers.is_empty());
+    }
+
+    #[test]
+    fn test_shared_budget() {
+        let budget = Arc::new(SharedCacheBudget::new(SharedCacheBudgetConfig {
+            total_bytes: 10_000,
+            min_shard_bytes: 2_000,
+        }));
+        let mut caches: Vec<_> = (0..3)
+            .map(|shard_id| {
+                let shard_uid = ShardUId { version: 0, shard_id };
+                TrieCacheInner::new(1000, 100, 1, shard_id as u64, false)
+                    .with_budget(budget.clone(), shard_uid)
+            })
+            .collect();
+        let put_values = |cache: &mut TrieCacheInner, n: u32| {
+            for i in 0..n {
+                let mut value = vec![0; 100];
+                value[..4].copy_from_slice(&i.to_le_bytes());
+                value[4] = cache.shard_id as u8;
+                put_value(cache, &value);
+            }
+        };
+
+        // The hot shard takes everything which is not reserved for the others,
+        // which is way more than a fixed share of the budget.
+        put_values(&mut caches[0], 200);
+        put_values(&mut caches[1], 10);
+        let hot = caches[0].total_size;
+        assert!(hot > 10_000 / 3, "{hot}");
+        assert!(hot <= 10_000 - 2 * 2_000 + 100, "{hot}");
+        assert_eq!(caches[1].total_size, 1_000);
+
+        // The other shards can still fill their reserved part.
+        put_values(&mut caches[1], 100);
+        put_values(&mut caches[2], 100);
+        for cache in &caches[1..] {
+            assert!(cache.total_size >= 2_000, "{}", cache.total_size);
+        }
+    }
+
+    #[test]
+    fn test_oldest_entry_age() {
+        // Insertion times are not recorded unless enabled.
+        let mut cache = TrieCacheInner::new(2, 100, 100, 0, false);
+        put_value(&mut cache, &[1]);
+        assert_eq!(cache.oldest_entry_age(Instant::now()), None);
+
+        let mut cache = TrieCacheInner::new(2, 100, 100, 0, false).track_entry_age();
+        assert_eq!(cache.oldest_entry_age(Instant::now()), None);
+        let before_first = Instant::now();
+        put_value(&mut cache, &[1]);
+        let after_first = Instant::now();
+        put_value(&mut cache, &[2]);
+        let after_second = Instant::now();
+        let now = after_second + Duration::from_secs(10);
+        let age = cache.oldest_entry_age(now).unwrap();
+        assert!(age >= now - after_first && age <= now - before_first, "{age:?}");
+
+        // Once the first node is evicted, the second one is the oldest.
+        put_value(&mut cache, &[3]);
+        let age = cache.oldest_entry_age(now).unwrap();
+        assert!(age >= now - after_second && age <= now - after_first, "{age:?}");
+
+        cache.clear();
+        assert_eq!(cache.oldest_entry_age(now), None);
+    }
+
     #[test]
     fn test_cache_capacity() {
         let mut cache = TrieCacheInner::new(2, 100, 100, 0, false);
@@ -1256,6 +1256,12 @@ pub fn load_config(
         anyhow::ensure!(!config.tracked_shards.is_empty(),
                         "Validator must track all shards. Please change `tracked_shards` field in config.json to be any non-empty vector");
     }
+    // EXPERIMENTAL_changes RPC methods and archival queries read DBCol::StateChanges,
+    // which stays empty if state changes are skipped, so they would silently return nothing.
+    anyhow::ensure!(
+        !config.store.skip_state_changes || (!config.archive && config.rpc_addr().is_none()),
+        "`store.skip_state_changes` cannot be set on RPC and archival nodes. Please remove it from config.json"
+    );
 
     NearConfig::new(config, genesis, network_signer.into(), validator_signer)
 }
@@ -1332,6 +1338,42 @@ fn test_init_config_localnet() {
     );
 }
 
+#[test]
+fn test_skip_state_changes_requires_non_rpc_non_archival_node() {
+    let temp_dir = tempdir().unwrap();
+    init_configs(
+        &temp_dir.path(),
+        Some("localnet"),
+        None,
+        Some("seed1"),
+        1,
+        false,
+        None,
+        false,
+        None,
+        false,
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    let config_path = temp_dir.path().join(CONFIG_FILENAME);
+    let mut config = Config::from_file(&config_path).unwrap();
+    config.store.skip_state_changes = true;
+    #[cfg(feature = "json_rpc")]
+    {
+        config.write_to_file(&config_path).unwrap();
+        load_config(temp_dir.path(), GenesisValidationMode::UnsafeFast).unwrap_err();
+        config.rpc = None;
+    }
+    config.archive = true;
+    config.write_to_file(&config_path).unwrap();
+    load_config(temp_dir.path(), GenesisValidationMode::UnsafeFast).unwrap_err();
+    config.archive = false;
+    config.write_to_file(&config_path).unwrap();
+    load_config(temp_dir.path(), GenesisValidationMode::UnsafeFast).unwrap();
+}
+
 /// Tests that loading a config.json file works and results in values being
 /// correctly parsed and defaults being applied correctly applied.
 #[test]
@@ -126,6 +126,33 @@ fn open_storage(home_dir: &Path, near_config: &NearConfig) -> anyhow::Result<Nod
         update.commit()?;
     }
 
+    // Trie values are refcounted by their encoded form, so the compression
+    // threshold is fixed when the database is created.  Databases written
+    // before the setting existed hold no compressed values.
+    let key = near_store::db::TRIE_VALUE_COMPRESSION_THRESHOLD_KEY;
+    let configured =
+        near_config.config.store.trie_value_compression_threshold.map(|threshold| threshold as u64);
+    match hot.get_ser::<Option<u64>>(DBCol::BlockMisc, key)? {
+        Some(recorded) => anyhow::ensure!(
+            recorded == configured,
+            "The node is configured with store.trie_value_compression_threshold \
+             {configured:?} but the database has been written with {recorded:?}; \
+             the setting can't be changed for an existing database."
+        ),
+        None => {
+            let is_new_db = hot.get(DBCol::BlockMisc, near_store::db::HEAD_KEY)?.is_none();
+            anyhow::ensure!(
+                is_new_db || configured.is_none(),
+                "The node is configured with store.trie_value_compression_threshold \
+                 {configured:?} but the database has been written without compression; \
+                 the setting can only be enabled for a new database."
+            );
+            let mut update = hot.store_update();
+            update.set_ser(DBCol::BlockMisc, key, &configured)?;
+            update.commit()?;
+        }
+    }
+
     Ok(storage)
 }
 
@@ -48,7 +48,7 @@ use near_primitives::transaction::{Action, SignedTransaction};
 use near_primitives::trie_key::TrieKey;
 use near_primitives::types::AccountId;
 use near_primitives::types::StateRoot;
-use near_store::{PrefetchApi, Trie};
+use near_store::{PrefetchApi, PrefetchPriority, Trie};
 use sha2::Digest;
 use std::rc::Rc;
 use tracing::debug;
@@ -93,7 +93,7 @@ impl TriePrefetcher {
                 // general-purpose account prefetching
                 if self.prefetch_api.enable_receipt_prefetching {
                     let trie_key = TrieKey::Account { account_id: account_id.clone() };
-                    self.prefetch_trie_key(trie_key)?;
+                    self.prefetch_trie_key(trie_key, PrefetchPriority::High)?;
                 }
 
                 // SWEAT specific argument prefetcher
@@ -127,13 +127,13 @@ impl TriePrefetcher {
             for t in transactions {
                 let account_id = t.transaction.signer_id.clone();
                 let trie_key = TrieKey::Account { account_id };
-                self.prefetch_trie_key(trie_key)?;
+                self.prefetch_trie_key(trie_key, PrefetchPriority::High)?;
 
                 let trie_key = TrieKey::AccessKey {
                     account_id: t.transaction.signer_id.clone(),
                     public_key: t.transaction.public_key.clone(),
                 };
-                self.prefetch_trie_key(trie_key)?;
+                self.prefetch_trie_key(trie_key, PrefetchPriority::High)?;
             }
         }
         Ok(())
@@ -157,8 +157,11 @@ impl TriePrefetcher {
         self.prefetch_api.clear_data();
     }
 
-    fn prefetch_trie_key(&self, trie_key: TrieKey) -> Result<(), ()> {
-        let queue_full = self.prefetch_api.prefetch_trie_key(self.trie_root, trie_key).is_err();
+    /// Keys which are certainly accessed should be requested with `PrefetchPriority::High`,
+    /// so that they are not delayed by the predictive prefetching.
+    fn prefetch_trie_key(&self, trie_key: TrieKey, priority: PrefetchPriority) -> Result<(), ()> {
+        let queue_full =
+            self.prefetch_api.submit_with_priority(self.trie_root, trie_key, priority).is_err();
         if queue_full {
             self.prefetch_queue_full.inc();
             debug!(target: "prefetcher", "I/O scheduler input queue full, dropping prefetch request");
@@ -190,7 +193,7 @@ impl TriePrefetcher {
                                         key: key.to_vec(),
                                     };
                                     near_o11y::io_trace!(count: "prefetch");
-                                    self.prefetch_trie_key(trie_key)?;
+                                    self.prefetch_trie_key(trie_key, PrefetchPriority::Low)?;
                                 }
                             }
                         }
@@ -208,7 +211,7 @@ mod tests {
     use near_primitives::{trie_key::TrieKey, types::AccountId};
     use near_store::{
         test_utils::{create_test_store, test_populate_trie},
-        ShardTries, ShardUId, Trie, TrieConfig,
+        PrefetchPriority, ShardTries, ShardUId, Trie, TrieConfig,
     };
     use std::{rc::Rc, str::FromStr, time::Duration};
 
@@ -318,7 +321,7 @@ mod tests {
         assert_eq!(p.num_prefetched_and_staged(), 0);
 
         for trie_key in &prefetch_keys {
-            _ = prefetcher.prefetch_trie_key(trie_key.clone());
+            _ = prefetcher.prefetch_trie_key(trie_key.clone(), PrefetchPriority::High);
         }
         std::thread::yield_now();
 
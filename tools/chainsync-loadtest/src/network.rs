@@ -307,6 +307,8 @@ impl Handler<NetworkViewClientMessages> for FakeClientActor {
             NetworkViewClientMessages::AnnounceAccount(_) => {
                 return NetworkViewClientResponses::NoResponse;
             }
+            NetworkViewClientMessages::TxInclusionProofRequest { .. } => "TxInclusionProofRequest",
+            NetworkViewClientMessages::TxInclusionProofResponse(_) => "TxInclusionProofResponse",
             #[allow(unreachable_patterns)]
             _ => "unknown",
         };